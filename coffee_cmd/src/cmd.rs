@@ -1,10 +1,11 @@
 //! Coffee command line arguments definition.
 use std::fmt::Display;
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 
 use coffee_lib::error;
 use coffee_lib::errors::CoffeeError;
+use coffee_lib::types::response::ListOrder;
 
 /// Coffee main command line definition for the command line tools.
 #[derive(Debug, Parser)]
@@ -21,6 +22,41 @@ pub struct CoffeeArgs {
     pub data_dir: Option<String>,
     #[clap(short, long, action = clap::ArgAction::SetTrue)]
     pub skip_verify: bool,
+    /// Print the result of the command as JSON
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    pub json: bool,
+    /// Wait for another coffee instance to finish, instead of failing
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    pub wait: bool,
+    /// Abort the clone of a repository that takes longer than the seconds specified
+    #[clap(long, value_parser, name = "network-timeout")]
+    pub network_timeout: Option<u64>,
+    /// Abort the build of a plugin that takes longer than the seconds
+    /// specified, killing the processes of the build
+    #[clap(long, value_parser, name = "install-timeout")]
+    pub install_timeout: Option<u64>,
+    /// Path of the core lightning rpc socket, used to start and stop
+    /// the plugins without restarting core lightning
+    #[clap(long, value_parser, name = "rpc-path")]
+    pub rpc_path: Option<String>,
+    /// Install a plugin also when it needs a newer core lightning,
+    /// printing a warning instead of failing
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    pub allow_incompatible: bool,
+    /// Proxy of the network operations, like the clone of
+    /// the repositories and the build of the plugins
+    #[clap(long, value_parser)]
+    pub proxy: Option<String>,
+    /// Install the plugins without their dependencies, using only
+    /// what is already on the node
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    pub offline: bool,
+    /// Level of the logs: off, error, warn, info, debug or trace
+    #[clap(long, value_parser, name = "log-level")]
+    pub log_level: Option<String>,
+    /// File where the logs are appended, instead of the stderr
+    #[clap(long, value_parser, name = "log-file")]
+    pub log_file: Option<String>,
 }
 
 /// Coffee subcommand of the command line daemon.
@@ -33,7 +69,7 @@ pub enum CoffeeCommand {
     /// Unlink coffee from the core lightning configuration
     #[clap(arg_required_else_help = true)]
     Unlink { cln_conf: String },
-    /// Install a single by name.
+    /// Install a single by name, use `name@git_ref` to pin it to a git reference.
     #[clap(arg_required_else_help = true)]
     Install {
         plugin: String,
@@ -41,20 +77,85 @@ pub enum CoffeeCommand {
         verbose: bool,
         #[arg(short, long, action = clap::ArgAction::SetTrue)]
         dynamic: bool,
+        /// Log the commands that would be run, without installing the plugin.
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        dry_run: bool,
+        /// Build again the plugin if it is already installed.
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        force: bool,
+        /// Install the plugin once for all the networks managed by coffee.
+        #[arg(long, action = clap::ArgAction::SetTrue, conflicts_with_all = ["dynamic", "force"])]
+        global: bool,
+        /// Wait until core lightning runs the plugin, and roll back
+        /// the install when it refuses it, it needs `--rpc-path` and
+        /// fails for the important plugins.
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        verify: bool,
+    },
+    /// Build a plugin and start it inside the running core lightning
+    /// node, then stop it, without installing it.
+    #[clap(arg_required_else_help = true)]
+    Try {
+        plugin: String,
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        verbose: bool,
+        /// Install the plugin when core lightning accepts it.
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        install: bool,
     },
     /// upgrade a single repository.
     #[clap(arg_required_else_help = true)]
     Upgrade {
         repo: String,
+        /// Remove the untracked files and reset the local checkout
+        /// of the repository before the upgrade.
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        clean: bool,
         #[arg(short, long, action = clap::ArgAction::SetTrue)]
         verbose: bool,
     },
+    /// Check which plugins installed have newer commits upstream,
+    /// without upgrading them.
+    #[clap(arg_required_else_help = false)]
+    CheckUpdates,
+    /// Print the output of the last build of a plugin.
+    #[clap(arg_required_else_help = true)]
+    Logs { plugin: String },
+    /// Print the commits between the installed plugin and the
+    /// latest commit of its repository, before upgrading it.
+    #[clap(arg_required_else_help = true)]
+    Changelog { plugin: String },
     /// Print the list of plugins installed in cln.
     #[clap(arg_required_else_help = false)]
-    List {},
+    List {
+        /// Print also the plugins available across the remote repositories.
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        available: bool,
+        /// Print the disk space used by each plugin.
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        disk_usage: bool,
+        /// Order of the plugins installed.
+        #[arg(long, value_enum, default_value_t = SortBy::Name)]
+        sort: SortBy,
+    },
+    /// Print the status of a single plugin.
+    #[clap(arg_required_else_help = true)]
+    Status { plugin: String },
     /// Remove a plugin installed in cln.
     #[clap(arg_required_else_help = true)]
     Remove { plugin: String },
+    /// Remove a plugin installed in cln, together with its build log
+    /// and the options that it added to the cln configuration.
+    #[clap(arg_required_else_help = true)]
+    Purge {
+        plugin: String,
+        /// keep the options of the plugin inside the cln configuration
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        keep_config: bool,
+    },
+    /// Remove a plugin from the cln configuration, keeping its files on disk.
+    #[clap(arg_required_else_help = true)]
+    Detach { plugin: String },
     /// Manage Repository subcommand
     #[clap(arg_required_else_help = true)]
     Remote {
@@ -63,12 +164,31 @@ pub enum CoffeeCommand {
         #[arg(name = "remote-name", help = "The name of the remote repository")]
         name: Option<String>,
     },
+    /// Manage the core lightning nodes where coffee installs the plugins
+    #[clap(arg_required_else_help = true)]
+    Target {
+        #[clap(subcommand)]
+        action: TargetAction,
+    },
+    /// Print the core lightning configuration written by coffee,
+    /// merged with the one of the node.
+    #[clap(arg_required_else_help = false)]
+    Config,
     /// show the README file of the plugin
     #[clap(arg_required_else_help = true)]
     Show { plugin: String },
     /// search the remote repositories for a plugin
     #[clap(arg_required_else_help = true)]
     Search { plugin: String },
+    /// explain why a plugin can not be installed
+    #[clap(arg_required_else_help = true)]
+    Explain { plugin: String },
+    /// validate the coffee manifest of a plugin
+    #[clap(arg_required_else_help = true)]
+    Lint {
+        #[arg(help = "The plugin directory or the path of the manifest")]
+        dir: String,
+    },
     /// clean up remote repositories storage information
     #[clap(arg_required_else_help = false)]
     Nurse {
@@ -76,9 +196,43 @@ pub enum CoffeeCommand {
         #[arg(short, long, action = clap::ArgAction::SetTrue)]
         verify: bool,
     },
-    /// tipping a plugins developer.
+    /// remove the directories that coffee does not use anymore
+    #[clap(arg_required_else_help = false)]
+    Prune {
+        /// remove the directories without asking for confirmation
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        yes: bool,
+        /// remove also the packages downloaded by the builds
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        cache: bool,
+    },
+    /// check that the environment is able to run the plugins installed
+    #[clap(arg_required_else_help = false)]
+    Diagnose,
+    /// build again all the plugins installed, e.g. after an upgrade of coffee
     #[clap(arg_required_else_help = false)]
-    Tip { plugin: String, amount_msat: u64 },
+    Repair {
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        verbose: bool,
+    },
+    /// print the remotes and the plugins installed as JSON,
+    /// so they can be imported on another machine
+    #[clap(arg_required_else_help = false)]
+    Export,
+    /// add the remotes and install the plugins of a
+    /// file written by `coffee export`
+    #[clap(arg_required_else_help = true)]
+    Import {
+        /// path of the file written by `coffee export`
+        path: String,
+    },
+    /// tipping a plugins developer, without an amount
+    /// print where the developer accepts tips.
+    #[clap(arg_required_else_help = false)]
+    Tip {
+        plugin: String,
+        amount_msat: Option<u64>,
+    },
     /// Disable a plugin
     #[clap(arg_required_else_help = true)]
     Disable { plugin: String },
@@ -90,13 +244,77 @@ pub enum CoffeeCommand {
 #[derive(Debug, Subcommand)]
 pub enum RemoteAction {
     /// Add a remote repository to the plugin manager.
-    Add { name: String, url: String },
-    /// Remove a remote repository from the plugin manager.
-    Rm { name: String },
+    Add {
+        name: String,
+        url: String,
+        /// Update the url of the remote if it already exists.
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        update: bool,
+        /// Refuse the remote when it does not contain any plugin.
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        require_plugins: bool,
+    },
+    /// Remove a remote repository from the plugin manager,
+    /// together with the plugins installed from it.
+    Rm {
+        name: String,
+        /// Remove the plugins installed from the remote without asking for confirmation.
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        yes: bool,
+    },
     /// Inspect the plugins available in a remote repository.
     Inspect { name: String },
+    /// Fetch a remote repository, or all of them, to find the new
+    /// plugins without upgrading the plugins installed.
+    Refresh { name: Option<String> },
     /// List the remote repositories from the plugin manager.
-    List {},
+    List {
+        /// Print the disk space used by each repository.
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        disk_usage: bool,
+    },
+}
+
+/// The order of the plugins printed by `list`.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum SortBy {
+    Name,
+    Repo,
+    Date,
+}
+
+impl From<SortBy> for ListOrder {
+    fn from(value: SortBy) -> Self {
+        match value {
+            SortBy::Name => Self::Name,
+            SortBy::Repo => Self::Repository,
+            SortBy::Date => Self::InstallDate,
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum TargetAction {
+    /// Link coffee to another core lightning node, without using it.
+    Add { name: String, cln_path: String },
+    /// Install and remove the plugins on the node of the target.
+    Use { name: String },
+    /// List the core lightning nodes linked to coffee.
+    List,
+}
+
+impl CoffeeCommand {
+    /// The command runs with `--verbose`, so the
+    /// output of the builds is shown to the user.
+    pub fn verbose(&self) -> bool {
+        match self {
+            CoffeeCommand::Install { verbose, .. }
+            | CoffeeCommand::Try { verbose, .. }
+            | CoffeeCommand::Upgrade { verbose, .. }
+            | CoffeeCommand::Repair { verbose } => *verbose,
+            _ => false,
+        }
+    }
 }
 
 impl From<&CoffeeCommand> for coffee_core::CoffeeOperation {
@@ -108,9 +326,33 @@ impl From<&CoffeeCommand> for coffee_core::CoffeeOperation {
                 plugin,
                 verbose,
                 dynamic,
-            } => Self::Install(plugin.to_owned(), *verbose, *dynamic),
-            CoffeeCommand::Upgrade { repo, verbose } => Self::Upgrade(repo.to_owned(), *verbose),
-            CoffeeCommand::List {} => Self::List,
+                dry_run,
+                force,
+                global,
+                ..
+            } => Self::Install(
+                plugin.to_owned(),
+                *verbose,
+                *dynamic,
+                *dry_run,
+                *force,
+                *global,
+            ),
+            CoffeeCommand::Upgrade {
+                repo,
+                clean,
+                verbose,
+            } => Self::Upgrade(repo.to_owned(), *clean, *verbose),
+            CoffeeCommand::CheckUpdates => Self::CheckUpdates,
+            CoffeeCommand::Logs { plugin } => Self::Logs(plugin.to_owned()),
+            CoffeeCommand::Changelog { plugin } => Self::Changelog(plugin.to_owned()),
+            CoffeeCommand::Try {
+                plugin,
+                verbose,
+                install,
+            } => Self::Try(plugin.to_owned(), *verbose, *install),
+            CoffeeCommand::List { .. } => Self::List,
+            CoffeeCommand::Status { plugin } => Self::Status(plugin.to_owned()),
             CoffeeCommand::Remote { action, name } => {
                 if let Some(action) = action {
                     return Self::Remote(Some(action.into()), name.clone());
@@ -118,26 +360,54 @@ impl From<&CoffeeCommand> for coffee_core::CoffeeOperation {
                 Self::Remote(None, name.clone())
             }
             CoffeeCommand::Remove { plugin } => Self::Remove(plugin.to_owned()),
+            CoffeeCommand::Purge {
+                plugin,
+                keep_config,
+            } => Self::Purge(plugin.to_owned(), *keep_config),
+            CoffeeCommand::Detach { plugin } => Self::Detach(plugin.to_owned()),
+            CoffeeCommand::Target { action } => Self::Target(action.into()),
+            CoffeeCommand::Config => Self::Config,
             CoffeeCommand::Show { plugin } => Self::Show(plugin.to_owned()),
             CoffeeCommand::Search { plugin } => Self::Search(plugin.to_owned()),
+            CoffeeCommand::Explain { plugin } => Self::Explain(plugin.to_owned()),
+            CoffeeCommand::Lint { dir } => Self::Lint(dir.to_owned()),
             CoffeeCommand::Nurse { verify } => Self::Nurse(*verify),
+            CoffeeCommand::Prune { yes, cache } => Self::Prune(!*yes, *cache),
+            CoffeeCommand::Diagnose => Self::Diagnose,
+            CoffeeCommand::Repair { verbose } => Self::Repair(*verbose),
+            CoffeeCommand::Export => Self::Export,
+            CoffeeCommand::Import { path } => Self::Import(path.to_owned()),
             CoffeeCommand::Tip {
                 plugin,
                 amount_msat,
-            } => Self::Tip(plugin.to_owned(), amount_msat.clone()),
+            } => match amount_msat {
+                Some(amount_msat) => Self::Tip(plugin.to_owned(), *amount_msat),
+                None => Self::Funding(plugin.to_owned()),
+            },
             CoffeeCommand::Disable { plugin } => Self::Disable(plugin.to_owned()),
             CoffeeCommand::Enable { plugin } => Self::Enable(plugin.to_owned()),
         }
     }
 }
 
+impl From<&TargetAction> for coffee_core::TargetAction {
+    fn from(value: &TargetAction) -> Self {
+        match value {
+            TargetAction::Add { name, cln_path } => Self::Add(name.to_owned(), cln_path.to_owned()),
+            TargetAction::Use { name } => Self::Use(name.to_owned()),
+            TargetAction::List => Self::List,
+        }
+    }
+}
+
 impl From<&RemoteAction> for coffee_core::RemoteAction {
     fn from(value: &RemoteAction) -> Self {
         match value {
-            RemoteAction::Add { name, url } => Self::Add(name.to_owned(), url.to_owned()),
-            RemoteAction::Rm { name } => Self::Rm(name.to_owned()),
+            RemoteAction::Add { name, url, .. } => Self::Add(name.to_owned(), url.to_owned()),
+            RemoteAction::Rm { name, .. } => Self::Rm(name.to_owned()),
             RemoteAction::Inspect { name } => Self::Inspect(name.to_owned()),
-            RemoteAction::List {} => Self::List,
+            RemoteAction::Refresh { name } => Self::Refresh(name.to_owned()),
+            RemoteAction::List { .. } => Self::List,
         }
     }
 }
@@ -198,15 +468,58 @@ impl coffee_core::CoffeeArgs for CoffeeArgs {
             .clone()
             .ok_or_else(|| error!("Network is not defined"))
             .ok()?;
-        let validated_network = ClnNetwork::try_from(network.to_lowercase()).ok();
-
-        match validated_network {
-            Some(valid_network) => format!("{valid_network}").into(),
-            None => None,
+        // an unknown network is passed as it is, so coffee
+        // can report it instead of using the default one.
+        match ClnNetwork::try_from(network.to_lowercase()) {
+            Ok(valid_network) => Some(format!("{valid_network}")),
+            Err(_) => Some(network),
         }
     }
 
     fn skip_verify(&self) -> bool {
         self.skip_verify
     }
+
+    fn wait(&self) -> bool {
+        self.wait
+    }
+
+    fn network_timeout(&self) -> Option<u64> {
+        self.network_timeout
+    }
+
+    fn install_timeout(&self) -> Option<u64> {
+        self.install_timeout
+    }
+
+    fn rpc_path(&self) -> Option<String> {
+        self.rpc_path.clone()
+    }
+
+    fn allow_incompatible(&self) -> bool {
+        self.allow_incompatible
+    }
+
+    fn proxy(&self) -> Option<String> {
+        self.proxy.clone()
+    }
+
+    fn offline(&self) -> bool {
+        self.offline
+    }
+
+    fn log_level(&self) -> Option<String> {
+        // the steps of a dry run are logged at the info
+        // level, so they are shown unless a level is chosen.
+        let dry_run = matches!(self.command, CoffeeCommand::Install { dry_run: true, .. });
+        match &self.log_level {
+            Some(level) => Some(level.clone()),
+            None if dry_run && std::env::var("RUST_LOG").is_err() => Some("info".to_owned()),
+            None => None,
+        }
+    }
+
+    fn log_file(&self) -> Option<String> {
+        self.log_file.clone()
+    }
 }
@@ -1,3 +1,5 @@
 mod command_show;
+mod output;
 
 pub use command_show::*;
+pub use output::CoffeeOutput;
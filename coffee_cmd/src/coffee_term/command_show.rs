@@ -1,13 +1,21 @@
 //! Implementing the code function to show
 //! the command result on the terminal!
 
+use std::collections::HashMap;
+
 use radicle_term as term;
+use serde::Serialize;
 use term::table::TableOptions;
 use term::Element;
 
 use coffee_lib::error;
 use coffee_lib::errors::CoffeeError;
-use coffee_lib::types::response::{CoffeeList, CoffeeNurse, CoffeeRemote, CoffeeTip, NurseStatus};
+use coffee_lib::types::response::{
+    CoffeeChangelog, CoffeeCheckUpdates, CoffeeClnConfig, CoffeeDiagnose, CoffeeExplain,
+    CoffeeFunding, CoffeeImport, CoffeeList, CoffeeNurse, CoffeePrune, CoffeeRefresh, CoffeeRemote,
+    CoffeeRemoteRemoval, CoffeeRemove, CoffeeRepair, CoffeeSearch, CoffeeStatus, CoffeeTargets,
+    CoffeeTip, NurseStatus, UpgradeStatus,
+};
 
 pub fn show_list(coffee_list: Result<CoffeeList, CoffeeError>) -> Result<(), CoffeeError> {
     let remotes = coffee_list?;
@@ -21,8 +29,10 @@ pub fn show_list(coffee_list: Result<CoffeeList, CoffeeError>) -> Result<(), Cof
         term::format::dim(String::from("●")),
         term::format::bold(String::from("Language")),
         term::format::bold(String::from("Name")),
+        term::format::bold(String::from("Version")),
         term::format::bold(String::from("Enabled")),
         term::format::bold(String::from("Exec path")),
+        term::format::bold(String::from("Size")),
     ]);
     table.divider();
 
@@ -34,18 +44,104 @@ pub fn show_list(coffee_list: Result<CoffeeList, CoffeeError>) -> Result<(), Cof
             term::format::positive("●").into(),
             term::format::highlight(plugin.lang.to_string()),
             term::format::bold(plugin.name()),
+            term::format::highlight(plugin.version().unwrap_or_default()),
             if enabled {
                 term::format::positive("yes").into()
             } else {
                 term::format::negative("no").into()
             },
             term::format::highlight(plugin.exec_path.to_owned()),
+            term::format::dim(format_size(remotes.disk_usage.get(&plugin.name()).copied())),
         ])
     }
     table.print();
     Ok(())
 }
 
+/// Format the disk usage in MB, like `prune`, or `-`
+/// when it was not computed.
+fn format_size(size: Option<u64>) -> String {
+    match size {
+        Some(size) => format!("{:.1} MB", size as f64 / 1_000_000.0),
+        None => "-".to_owned(),
+    }
+}
+
+pub fn show_available(coffee_list: Result<CoffeeList, CoffeeError>) -> Result<(), CoffeeError> {
+    let list = coffee_list?;
+
+    term::println(
+        term::format::bold("●"),
+        term::format::tertiary("Plugins available"),
+    );
+    let mut table = radicle_term::Table::new(TableOptions::bordered());
+    table.push([
+        term::format::dim(String::from("●")),
+        term::format::bold(String::from("Language")),
+        term::format::bold(String::from("Name")),
+        term::format::bold(String::from("Repository")),
+        term::format::bold(String::from("Installed")),
+    ]);
+    table.divider();
+
+    for available in &list.available {
+        table.push([
+            term::format::positive("●").into(),
+            term::format::highlight(available.plugin.lang.to_string()),
+            term::format::bold(available.plugin.name()),
+            term::format::highlight(available.repository.to_owned()),
+            if available.installed {
+                term::format::positive("yes").into()
+            } else {
+                term::format::negative("no").into()
+            },
+        ])
+    }
+    table.print();
+    Ok(())
+}
+
+pub fn show_status(status: &CoffeeStatus) -> Result<(), CoffeeError> {
+    term::println(
+        term::format::bold("●"),
+        term::format::tertiary(format!("Plugin {}", status.plugin)),
+    );
+    let yes_no = |value: bool| {
+        if value {
+            term::format::positive(String::from("yes"))
+        } else {
+            term::format::negative(String::from("no"))
+        }
+    };
+    let mut table = radicle_term::Table::new(TableOptions::bordered());
+    table.push([
+        term::format::bold(String::from("Installed")),
+        yes_no(status.installed),
+    ]);
+    table.push([
+        term::format::bold(String::from("Enabled")),
+        yes_no(status.enabled),
+    ]);
+    table.push([
+        term::format::bold(String::from("Version")),
+        term::format::highlight(status.version.clone().unwrap_or_default()),
+    ]);
+    table.push([
+        term::format::bold(String::from("Commit")),
+        term::format::primary(status.commit.clone().unwrap_or_default()),
+    ]);
+    table.push([
+        term::format::bold(String::from("Language")),
+        term::format::highlight(status.lang.to_string()),
+    ]);
+    table.push([
+        term::format::bold(String::from("Exec path")),
+        term::format::highlight(status.exec_path.clone().unwrap_or_default()),
+    ]);
+    table.print();
+    Ok(())
+}
+
 pub fn show_remote_list(remote_list: Result<CoffeeRemote, CoffeeError>) -> Result<(), CoffeeError> {
     let repositories = remote_list?.remotes;
 
@@ -64,7 +160,10 @@ pub fn show_remote_list(remote_list: Result<CoffeeRemote, CoffeeError>) -> Resul
         term::format::bold(String::from("URL")),
         term::format::bold(String::from("N. Plugins")),
         term::format::bold(String::from("Git HEAD")),
+        term::format::bold(String::from("Git Ref")),
         term::format::bold(String::from("Last Update")),
+        term::format::bold(String::from("Last Fetch")),
+        term::format::bold(String::from("Size")),
     ]);
     table.divider();
 
@@ -72,17 +171,456 @@ pub fn show_remote_list(remote_list: Result<CoffeeRemote, CoffeeError>) -> Resul
         let mut commit_id = repository.commit_id.clone().unwrap_or_default();
         commit_id = commit_id.chars().take(7).collect::<String>();
         let date = repository.date.clone().unwrap_or_default();
+        let git_ref = repository.git_ref.clone().unwrap_or_default();
+        let fetched_at = repository.fetched_at.clone().unwrap_or_default();
         table.push([
             term::format::positive("●").into(),
             term::format::highlight(repository.local_name.to_owned()),
             term::format::bold(repository.url.to_owned()),
             term::format::highlight(repository.plugins.len().to_string()),
             term::format::primary(commit_id),
+            term::format::highlight(git_ref),
             term::format::bold(date),
+            term::format::dim(fetched_at),
+            term::format::dim(format_size(repository.disk_usage)),
+        ])
+    }
+    table.print();
+
+    Ok(())
+}
+
+pub fn show_search(search: &CoffeeSearch) -> Result<(), CoffeeError> {
+    term::println(
+        term::format::bold("●"),
+        term::format::tertiary("Plugins found"),
+    );
+    let mut table = radicle_term::Table::new(TableOptions::bordered());
+    table.push([
+        term::format::dim(String::from("●")),
+        term::format::bold(String::from("Name")),
+        term::format::bold(String::from("Repository")),
+        term::format::bold(String::from("Description")),
+    ]);
+    table.divider();
+
+    for plugin in &search.matches {
+        table.push([
+            term::format::positive("●").into(),
+            term::format::bold(plugin.name.to_owned()),
+            term::format::highlight(plugin.repository.to_owned()),
+            term::format::dim(plugin.description.clone().unwrap_or_default()),
+        ])
+    }
+    table.print();
+    Ok(())
+}
+
+pub fn show_updates(updates: &CoffeeCheckUpdates) -> Result<(), CoffeeError> {
+    if updates.updates.is_empty() {
+        term::success!("All the plugins installed are up to date");
+        return Ok(());
+    }
+    term::println(
+        term::format::bold("●"),
+        term::format::tertiary("Updates available"),
+    );
+    let mut table = radicle_term::Table::new(TableOptions::bordered());
+    table.push([
+        term::format::dim(String::from("●")),
+        term::format::bold(String::from("Name")),
+        term::format::bold(String::from("Current")),
+        term::format::bold(String::from("Latest")),
+    ]);
+    table.divider();
+
+    for update in &updates.updates {
+        let current = update
+            .current_commit
+            .as_ref()
+            .map(|commit| commit.chars().take(7).collect::<String>())
+            .unwrap_or_default();
+        table.push([
+            term::format::positive("●").into(),
+            term::format::bold(update.plugin.to_owned()),
+            term::format::dim(current),
+            term::format::highlight(update.latest_commit.chars().take(7).collect::<String>()),
         ])
     }
     table.print();
+    Ok(())
+}
+
+pub fn show_changelog(changelog: &CoffeeChangelog) -> Result<(), CoffeeError> {
+    if changelog.commits.is_empty() {
+        term::success!("Plugin `{}` is up to date", changelog.plugin);
+        return Ok(());
+    }
+    term::println(
+        term::format::bold("●"),
+        term::format::tertiary(format!(
+            "Changelog of {} ({} new commits)",
+            changelog.plugin,
+            changelog.commits.len()
+        )),
+    );
+    let mut table = radicle_term::Table::new(TableOptions::bordered());
+    table.push([
+        term::format::dim(String::from("●")),
+        term::format::bold(String::from("Commit")),
+        term::format::bold(String::from("Summary")),
+        term::format::bold(String::from("Author")),
+        term::format::bold(String::from("Date")),
+    ]);
+    table.divider();
+
+    for commit in &changelog.commits {
+        table.push([
+            term::format::positive("●").into(),
+            term::format::primary(commit.id.chars().take(7).collect::<String>()),
+            term::format::bold(commit.summary.to_owned()),
+            term::format::highlight(commit.author.to_owned()),
+            term::format::dim(commit.date.to_owned()),
+        ])
+    }
+    table.print();
+    Ok(())
+}
+
+pub fn show_refresh(
+    results: &HashMap<String, Result<CoffeeRefresh, CoffeeError>>,
+) -> Result<(), CoffeeError> {
+    let mut table = radicle_term::Table::new(TableOptions::bordered());
+    table.push([
+        term::format::dim(String::from("●")),
+        term::format::bold(String::from("Repository")),
+        term::format::bold(String::from("Status")),
+        term::format::bold(String::from("New Plugins")),
+    ]);
+    table.divider();
+
+    let mut names = results.keys().collect::<Vec<_>>();
+    names.sort();
+    for name in names {
+        let row = match &results[name] {
+            Ok(refresh) => {
+                let status = match &refresh.status {
+                    UpgradeStatus::UpToDate(..) => "up to date".to_owned(),
+                    UpgradeStatus::Updated(commit, _) => {
+                        format!("updated to {}", commit.chars().take(7).collect::<String>())
+                    }
+                };
+                [
+                    term::format::positive("●").into(),
+                    term::format::bold(name.to_owned()),
+                    term::format::dim(status),
+                    term::format::highlight(refresh.new_plugins.join(", ")),
+                ]
+            }
+            Err(err) => [
+                term::format::negative("●").into(),
+                term::format::bold(name.to_owned()),
+                term::format::negative(err.to_string()).into(),
+                term::format::dim(String::new()),
+            ],
+        };
+        table.push(row);
+    }
+    table.print();
+    Ok(())
+}
 
+pub fn show_prune(prune: &CoffeePrune) -> Result<(), CoffeeError> {
+    if prune.paths.is_empty() {
+        term::success!("Nothing to prune, coffee uses all the directories inside its home");
+        return Ok(());
+    }
+    let mut table = radicle_term::Table::new(TableOptions::bordered());
+    table.push([
+        term::format::dim(String::from("●")),
+        term::format::bold(String::from("Directory")),
+        term::format::bold(String::from("Size")),
+    ]);
+    table.divider();
+
+    for path in &prune.paths {
+        table.push([
+            term::format::negative("●").into(),
+            term::format::primary(path.path.to_owned()),
+            term::format::dim(format_size(Some(path.size))),
+        ])
+    }
+    table.print();
+    Ok(())
+}
+
+pub fn show_repair(repair: &CoffeeRepair) -> Result<(), CoffeeError> {
+    if repair.repaired.is_empty() && repair.failed.is_empty() {
+        term::success!("No plugins installed, nothing to repair");
+        return Ok(());
+    }
+    let mut table = radicle_term::Table::new(TableOptions::bordered());
+    table.push([
+        term::format::dim(String::from("●")),
+        term::format::bold(String::from("Plugin")),
+        term::format::bold(String::from("Result")),
+    ]);
+    table.divider();
+
+    for install in &repair.repaired {
+        table.push([
+            term::format::positive("●").into(),
+            term::format::highlight(install.plugin.to_owned()),
+            term::format::dim(install.exec_path.to_owned()),
+        ])
+    }
+    let mut failed = repair.failed.iter().collect::<Vec<_>>();
+    failed.sort();
+    for (plugin, err) in failed {
+        table.push([
+            term::format::negative("●").into(),
+            term::format::highlight(plugin.to_owned()),
+            term::format::negative(err.to_owned()).into(),
+        ])
+    }
+    table.print();
+    Ok(())
+}
+
+pub fn show_import(import: &CoffeeImport) -> Result<(), CoffeeError> {
+    let mut table = radicle_term::Table::new(TableOptions::bordered());
+    table.push([
+        term::format::dim(String::from("●")),
+        term::format::bold(String::from("Name")),
+        term::format::bold(String::from("Result")),
+    ]);
+    table.divider();
+
+    for remote in &import.remotes_added {
+        table.push([
+            term::format::positive("●").into(),
+            term::format::highlight(remote.to_owned()),
+            term::format::dim(String::from("remote added")),
+        ])
+    }
+    for plugin in &import.installed {
+        table.push([
+            term::format::positive("●").into(),
+            term::format::highlight(plugin.to_owned()),
+            term::format::dim(String::from("installed")),
+        ])
+    }
+    for name in &import.skipped {
+        table.push([
+            term::format::dim("●").into(),
+            term::format::highlight(name.to_owned()),
+            term::format::dim(String::from("already present")),
+        ])
+    }
+    // a remote and a plugin can have the same name
+    for failed in [&import.remotes_failed, &import.plugins_failed] {
+        let mut failed = failed.iter().collect::<Vec<_>>();
+        failed.sort();
+        for (name, err) in failed {
+            table.push([
+                term::format::negative("●").into(),
+                term::format::highlight(name.to_owned()),
+                term::format::negative(err.to_owned()).into(),
+            ])
+        }
+    }
+    table.print();
+    for conflict in &import.conflicts {
+        term::println(term::format::negative("●"), conflict.to_owned());
+    }
+    Ok(())
+}
+
+pub fn show_config(config: &CoffeeClnConfig) -> Result<(), CoffeeError> {
+    term::println(
+        term::format::bold("●"),
+        term::format::tertiary("Coffee configuration"),
+    );
+    term::println(term::format::dim("●"), config.coffee_config_path.to_owned());
+    match &config.cln_config_path {
+        Some(path) if config.included => {
+            term::println(term::format::positive("●"), format!("included by {path}"));
+        }
+        Some(path) => {
+            term::println(
+                term::format::negative("●"),
+                format!("not included by {path}, run `coffee link` again"),
+            );
+        }
+        None => {
+            term::println(
+                term::format::negative("●"),
+                "not linked to core lightning, run `coffee link`",
+            );
+        }
+    }
+    if config.merged.is_empty() {
+        return Ok(());
+    }
+    let mut table = radicle_term::Table::new(TableOptions::bordered());
+    table.push([
+        term::format::dim(String::from("●")),
+        term::format::bold(String::from("Option")),
+        term::format::bold(String::from("Value")),
+    ]);
+    table.divider();
+
+    // the options written by coffee are highlighted
+    for (key, values) in &config.merged {
+        for value in values {
+            let by_coffee = config
+                .coffee_config
+                .get(key)
+                .is_some_and(|values| values.contains(value));
+            table.push([
+                if by_coffee {
+                    term::format::positive("●").into()
+                } else {
+                    term::format::dim("●").into()
+                },
+                term::format::highlight(key.to_owned()),
+                term::format::primary(value.to_owned()),
+            ])
+        }
+    }
+    table.print();
+    Ok(())
+}
+
+pub fn show_purge(purge: &CoffeeRemove) -> Result<(), CoffeeError> {
+    term::success!(
+        "Plugin {} purged! {:.1} MB freed",
+        purge.plugin.name(),
+        purge.freed_bytes as f64 / 1_000_000.0
+    );
+    for path in &purge.removed_paths {
+        term::println(term::format::negative("●"), path.to_owned());
+    }
+    for (key, value) in &purge.reverted_options {
+        term::println(term::format::negative("●"), format!("{key}={value}"));
+    }
+    Ok(())
+}
+
+pub fn show_targets(targets: &CoffeeTargets) -> Result<(), CoffeeError> {
+    term::println(
+        term::format::bold("●"),
+        term::format::tertiary("List of targets"),
+    );
+    let mut table = radicle_term::Table::new(TableOptions::bordered());
+    table.push([
+        term::format::dim(String::from("●")),
+        term::format::bold(String::from("Target")),
+        term::format::bold(String::from("CLN Path")),
+        term::format::bold(String::from("N. Plugins")),
+    ]);
+    table.divider();
+
+    for target in &targets.targets {
+        table.push([
+            if target.active {
+                term::format::positive("●").into()
+            } else {
+                term::format::dim("●").into()
+            },
+            term::format::highlight(target.name.to_owned()),
+            term::format::bold(target.cln_root.clone().unwrap_or_default()),
+            term::format::highlight(target.plugins.to_string()),
+        ])
+    }
+    table.print();
+
+    Ok(())
+}
+
+pub fn show_remote_removal(removal: &CoffeeRemoteRemoval) -> Result<(), CoffeeError> {
+    term::println(
+        term::format::bold("●"),
+        term::format::tertiary(format!(
+            "Plugins installed from {} that are removed with it",
+            removal.remote
+        )),
+    );
+    let mut table = radicle_term::Table::new(TableOptions::bordered());
+    table.push([
+        term::format::dim(String::from("●")),
+        term::format::bold(String::from("Name")),
+        term::format::bold(String::from("Exec path")),
+    ]);
+    table.divider();
+
+    for plugin in &removal.plugins {
+        table.push([
+            term::format::negative("●").into(),
+            term::format::bold(plugin.name()),
+            term::format::highlight(plugin.exec_path.to_owned()),
+        ])
+    }
+    table.print();
+    Ok(())
+}
+
+pub fn show_diagnose(diagnose: &CoffeeDiagnose) -> Result<(), CoffeeError> {
+    if diagnose.is_healthy() {
+        term::success!("No issues found, coffee is able to run the plugins installed");
+        return Ok(());
+    }
+    term::println(
+        term::format::bold("●"),
+        term::format::tertiary("Issues found"),
+    );
+    for issue in &diagnose.issues {
+        term::println(term::format::negative("●"), issue.to_string());
+    }
+    Ok(())
+}
+
+pub fn show_explain(explain: &CoffeeExplain) -> Result<(), CoffeeError> {
+    let plugin = &explain.plugin;
+    if let Some(lang) = &explain.lang {
+        let repository = explain.repository.clone().unwrap_or_default();
+        term::info!("Plugin `{plugin}` found inside `{repository}`, written in {lang}");
+    }
+    if explain.is_installable() {
+        term::success!("Plugin `{plugin}` can be installed");
+        return Ok(());
+    }
+    term::println(
+        term::format::bold("●"),
+        term::format::tertiary(format!("Plugin `{plugin}` can not be installed")),
+    );
+    for reason in &explain.reasons {
+        term::println(term::format::negative("●"), reason.to_string());
+    }
+    if explain.suggestions.is_empty() {
+        return Ok(());
+    }
+    term::println(
+        term::format::bold("●"),
+        term::format::tertiary("Did you mean"),
+    );
+    let mut table = radicle_term::Table::new(TableOptions::bordered());
+    table.push([
+        term::format::dim(String::from("●")),
+        term::format::bold(String::from("Name")),
+        term::format::bold(String::from("Repository")),
+        term::format::bold(String::from("Description")),
+    ]);
+    table.divider();
+    for suggestion in &explain.suggestions {
+        table.push([
+            term::format::positive("●").into(),
+            term::format::bold(suggestion.name.to_owned()),
+            term::format::highlight(suggestion.repository.to_owned()),
+            term::format::dim(suggestion.description.clone().unwrap_or_default()),
+        ])
+    }
+    table.print();
     Ok(())
 }
 
@@ -101,7 +639,7 @@ pub fn show_nurse_result(
             table.push([
                 term::format::dim(String::from("●")),
                 term::format::bold(String::from("Actions Taken")),
-                term::format::bold(String::from("Affected repositories")),
+                term::format::bold(String::from("Affected")),
             ]);
             table.divider();
 
@@ -111,10 +649,18 @@ pub fn show_nurse_result(
                     NurseStatus::RepositoryLocallyRemoved(_) => {
                         "Removed from local storage".to_string()
                     }
+                    NurseStatus::PluginConfigurationRemoved(_) => {
+                        "Removed from cln configuration".to_string()
+                    }
+                    NurseStatus::CorruptedRepositoryRemoved(_) => {
+                        "Removed as not loadable".to_string()
+                    }
                 };
                 let repos_str = match status {
                     NurseStatus::RepositoryLocallyRestored(repos)
-                    | NurseStatus::RepositoryLocallyRemoved(repos) => repos.join(", "),
+                    | NurseStatus::RepositoryLocallyRemoved(repos)
+                    | NurseStatus::PluginConfigurationRemoved(repos)
+                    | NurseStatus::CorruptedRepositoryRemoved(repos) => repos.join(", "),
                 };
 
                 table.push([
@@ -155,3 +701,22 @@ pub fn show_tips(coffee_tip: &CoffeeTip) -> Result<(), CoffeeError> {
     table.print();
     Ok(())
 }
+
+pub fn show_funding(funding: &CoffeeFunding) -> Result<(), CoffeeError> {
+    let Some(address) = &funding.funding else {
+        term::info!("Plugin `{}` has no tipping info", funding.plugin);
+        return Ok(());
+    };
+    term::println(
+        term::format::bold("●"),
+        term::format::tertiary(format!("Tip the author of {}", funding.plugin)),
+    );
+    term::println(term::format::positive("●"), address);
+    Ok(())
+}
+
+pub fn show_json<T: Serialize>(value: &T) -> Result<(), CoffeeError> {
+    let value = serde_json::to_string_pretty(value).map_err(|err| error!("{err}"))?;
+    println!("{value}");
+    Ok(())
+}
@@ -0,0 +1,305 @@
+//! The result of a command, that is rendered on the
+//! terminal or printed as JSON.
+
+use std::collections::HashMap;
+
+use radicle_term as term;
+
+use coffee_lib::errors::CoffeeError;
+use coffee_lib::plugin_conf::Manifest;
+use coffee_lib::types::response::{
+    ChainOfResponsibilityStatus, CoffeeAddRemote, CoffeeBuildLog, CoffeeChangelog,
+    CoffeeCheckUpdates, CoffeeClnConfig, CoffeeDiagnose, CoffeeExplain, CoffeeFunding,
+    CoffeeImport, CoffeeInstall, CoffeeList, CoffeeListRemote, CoffeeNurse, CoffeePrune,
+    CoffeeRefresh, CoffeeRemote, CoffeeRemoteRemoval, CoffeeRemove, CoffeeRepair, CoffeeSearch,
+    CoffeeShow, CoffeeState, CoffeeStatus, CoffeeTargets, CoffeeTip, CoffeeTrial, CoffeeUpgrade,
+    ReloadStatus, UpgradeStatus,
+};
+
+use super::*;
+
+/// The outcome of a command, the same value is shown as a
+/// table on the terminal, or printed as JSON with `--json`.
+pub enum CoffeeOutput {
+    /// The command has nothing to report.
+    Nothing,
+    /// The command has nothing to report, except a message
+    /// on the terminal.
+    Success(String),
+    Install {
+        install: CoffeeInstall,
+        dry_run: bool,
+    },
+    Remove(CoffeeRemove),
+    Purge(CoffeeRemove),
+    Detach(CoffeeRemove),
+    List(CoffeeList),
+    Available(CoffeeList),
+    Status(CoffeeStatus),
+    Upgrade {
+        upgrade: CoffeeUpgrade,
+        clean: bool,
+    },
+    Updates(CoffeeCheckUpdates),
+    Logs(CoffeeBuildLog),
+    Trial {
+        trial: CoffeeTrial,
+        /// The install that follows the trial, when requested.
+        install: Option<CoffeeInstall>,
+    },
+    Changelog(CoffeeChangelog),
+    RemoteAdded {
+        remote: CoffeeAddRemote,
+        /// The remote existed already, and its url was updated.
+        update: bool,
+    },
+    RemoteRemoval(CoffeeRemoteRemoval),
+    Refresh {
+        name: String,
+        refresh: CoffeeRefresh,
+    },
+    RefreshAll(HashMap<String, Result<CoffeeRefresh, CoffeeError>>),
+    Remotes(CoffeeRemote),
+    Remote(CoffeeListRemote),
+    Targets {
+        targets: CoffeeTargets,
+        /// The message of a change of the targets, on the terminal
+        /// it is shown instead of the targets.
+        message: Option<String>,
+    },
+    Config(CoffeeClnConfig),
+    Show(CoffeeShow),
+    Search(CoffeeSearch),
+    Explain(CoffeeExplain),
+    NurseVerify(ChainOfResponsibilityStatus),
+    Nurse(CoffeeNurse),
+    Prune(CoffeePrune),
+    Diagnose(CoffeeDiagnose),
+    Repair(CoffeeRepair),
+    Export(CoffeeState),
+    Import(CoffeeImport),
+    Tip(CoffeeTip),
+    Funding(CoffeeFunding),
+    Lint(Manifest),
+}
+
+impl CoffeeOutput {
+    /// Print the output as JSON when `json` is true, otherwise
+    /// render it on the terminal.
+    pub fn render(self, json: bool) -> Result<(), CoffeeError> {
+        if json {
+            self.show_json()
+        } else {
+            self.show()
+        }
+    }
+
+    fn show_json(&self) -> Result<(), CoffeeError> {
+        match self {
+            Self::Nothing | Self::Success(_) => show_json(&()),
+            Self::Install { install, .. } => show_json(install),
+            Self::Remove(remove) | Self::Purge(remove) | Self::Detach(remove) => show_json(remove),
+            Self::List(list) | Self::Available(list) => show_json(list),
+            Self::Status(status) => show_json(status),
+            Self::Upgrade { upgrade, .. } => show_json(upgrade),
+            Self::Updates(updates) => show_json(updates),
+            Self::Logs(logs) => show_json(logs),
+            Self::Trial { trial, .. } => show_json(trial),
+            Self::Changelog(changelog) => show_json(changelog),
+            Self::RemoteAdded { remote, .. } => show_json(remote),
+            Self::RemoteRemoval(removal) => show_json(removal),
+            Self::Refresh { refresh, .. } => show_json(refresh),
+            Self::RefreshAll(results) => show_json(results),
+            Self::Remotes(remotes) => show_json(remotes),
+            Self::Remote(remote) => show_json(remote),
+            Self::Targets { targets, .. } => show_json(targets),
+            Self::Config(config) => show_json(config),
+            Self::Show(show) => show_json(show),
+            Self::Search(search) => show_json(search),
+            Self::Explain(explain) => show_json(explain),
+            Self::NurseVerify(status) => show_json(status),
+            Self::Nurse(nurse) => show_json(nurse),
+            Self::Prune(prune) => show_json(prune),
+            Self::Diagnose(diagnose) => show_json(diagnose),
+            Self::Repair(repair) => show_json(repair),
+            Self::Export(state) => show_json(state),
+            Self::Import(import) => show_json(import),
+            Self::Tip(tip) => show_json(tip),
+            Self::Funding(funding) => show_json(funding),
+            Self::Lint(manifest) => show_json(manifest),
+        }
+    }
+
+    fn show(self) -> Result<(), CoffeeError> {
+        match self {
+            Self::Nothing => {}
+            Self::Success(message) => term::success!("{message}"),
+            Self::Install { install, dry_run } => {
+                if dry_run {
+                    term::success!("Dry run of plugin {} completed", install.plugin);
+                } else {
+                    term::success!("Plugin {} Compiled and Installed", install.plugin);
+                    term::info!("executable: {}", install.exec_path);
+                }
+            }
+            Self::Remove(remove) => term::success!(
+                "Plugin uninstalled! {:.1} MB freed",
+                remove.freed_bytes as f64 / 1_000_000.0
+            ),
+            Self::Purge(purge) => show_purge(&purge)?,
+            Self::Detach(detach) => term::success!(
+                "Plugin {} detached, its files are in {}",
+                detach.plugin.name(),
+                detach.plugin.root_path
+            ),
+            Self::List(list) => show_list(Ok(list))?,
+            Self::Available(list) => show_available(Ok(list))?,
+            Self::Status(status) => show_status(&status)?,
+            Self::Upgrade { upgrade, clean } => show_upgrade(&upgrade, clean),
+            Self::Updates(updates) => show_updates(&updates)?,
+            Self::Logs(logs) => print!("{}", logs.log),
+            Self::Trial { trial, install } => {
+                term::success!("core lightning accepted {}", trial.plugin);
+                if let Some(install) = install {
+                    term::success!("Plugin {} Compiled and Installed", install.plugin);
+                    term::info!("executable: {}", install.exec_path);
+                }
+            }
+            Self::Changelog(changelog) => show_changelog(&changelog)?,
+            Self::RemoteAdded { remote, update } => {
+                if update {
+                    term::success!("Remote updated!");
+                } else {
+                    term::success!("Remote added!");
+                }
+                if remote.plugins.is_empty() {
+                    term::info!(
+                        "Remote `{}` does not contain any plugin, check the url {}",
+                        remote.name,
+                        remote.url
+                    );
+                }
+            }
+            Self::RemoteRemoval(removal) => show_remote_removal(&removal)?,
+            Self::Refresh { name, refresh } => show_refresh(&HashMap::from([(name, Ok(refresh))]))?,
+            Self::RefreshAll(results) => show_refresh(&results)?,
+            Self::Remotes(remotes) => show_remote_list(Ok(remotes))?,
+            // shown in the same way as the list of the remotes
+            Self::Remote(remote) => show_remote_list(Ok(CoffeeRemote {
+                remotes: Some(vec![remote]),
+            }))?,
+            Self::Targets { targets, message } => match message {
+                Some(message) => term::success!("{message}"),
+                None => show_targets(&targets)?,
+            },
+            Self::Config(config) => show_config(&config)?,
+            Self::Show(show) => show_plugin(&show),
+            Self::Search(search) => show_search(&search)?,
+            Self::Explain(explain) => show_explain(&explain)?,
+            Self::NurseVerify(status) => {
+                term::info!("{}", status);
+                if !status.is_sane() {
+                    term::info!("Coffee local directory is damaged, please run `coffee nurse` to try to fix it");
+                }
+            }
+            Self::Nurse(nurse) => show_nurse_result(Ok(nurse))?,
+            Self::Prune(prune) if prune.dry_run => show_prune(&prune)?,
+            Self::Prune(prune) => term::success!(
+                "Directories removed! {:.1} MB freed",
+                prune.reclaimed() as f64 / 1_000_000.0
+            ),
+            Self::Diagnose(diagnose) => show_diagnose(&diagnose)?,
+            Self::Repair(repair) => show_repair(&repair)?,
+            // the state is always printed as JSON, so it can be saved in a file
+            Self::Export(state) => show_json(&state)?,
+            Self::Import(import) => show_import(&import)?,
+            Self::Tip(tip) => show_tips(&tip)?,
+            Self::Funding(funding) => show_funding(&funding)?,
+            Self::Lint(manifest) => {
+                let plugins = manifest
+                    .confs()
+                    .iter()
+                    .map(|conf| conf.plugin.name.clone())
+                    .collect::<Vec<_>>();
+                term::success!("Coffee manifest valid, plugins: {}", plugins.join(", "));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn show_upgrade(upgrade: &CoffeeUpgrade, clean: bool) {
+    if !upgrade.dirty.is_empty() {
+        if clean {
+            term::info!(
+                "Local changes removed from `{}`: {}",
+                upgrade.repo,
+                upgrade.dirty.join(", ")
+            );
+        } else {
+            term::info!(
+                "The local checkout of `{}` is dirty: {}, run with `--clean` to reset it",
+                upgrade.repo,
+                upgrade.dirty.join(", ")
+            );
+        }
+    }
+    match upgrade.status {
+        UpgradeStatus::UpToDate(_, _) => {
+            term::info!(
+                "Remote repository `{}` is already up to date!",
+                upgrade.repo
+            )
+        }
+        UpgradeStatus::Updated(_, _) => {
+            term::success!(
+                "Remote repository `{}` was successfully upgraded!",
+                upgrade.repo
+            )
+        }
+    }
+    for reload in &upgrade.reloads {
+        match reload {
+            ReloadStatus::Reloaded(plugin) => {
+                term::success!("Plugin `{plugin}` reloaded inside core lightning")
+            }
+            ReloadStatus::RestartNeeded(plugin) => {
+                term::info!("Restart core lightning to run the new version of `{plugin}`")
+            }
+        }
+    }
+    let mut failed = upgrade.failed.iter().collect::<Vec<_>>();
+    failed.sort();
+    for (plugin, err) in failed {
+        term::error(format!(
+            "Plugin `{plugin}` was not upgraded, the installed build is kept: {err}"
+        ));
+    }
+}
+
+fn show_plugin(show: &CoffeeShow) {
+    let metadata = [
+        ("author", &show.author),
+        ("license", &show.license),
+        ("homepage", &show.homepage),
+    ];
+    for (key, value) in metadata {
+        if let Some(value) = value {
+            term::info!("{key}: {value}");
+        }
+    }
+    if let Some(commit) = &show.commit {
+        term::info!("commit: {commit}");
+    }
+    if let Some(checksum) = &show.checksum {
+        term::info!("sha256: {checksum}");
+    }
+    if let Some(signed_by) = &show.signed_by {
+        term::info!("signed by: {signed_by}");
+    }
+    if let Some(min_cln_version) = &show.min_cln_version {
+        term::info!("requires core lightning: {min_cln_version}");
+    }
+    // FIXME: modify the radicle_term markdown
+    term::markdown(show.readme.as_str());
+}
@@ -1,197 +1,438 @@
 mod cmd;
 mod coffee_term;
 
+use std::future::Future;
+
 use clap::Parser;
 use radicle_term as term;
 
 use coffee_core::coffee::CoffeeManager;
+use coffee_core::logger;
 use coffee_lib::error;
-use coffee_lib::errors::CoffeeError;
+use coffee_lib::errors::{CoffeeError, CoffeeErrorKind};
+use coffee_lib::plugin_conf::validate_manifest;
 use coffee_lib::plugin_manager::PluginManager;
-use coffee_lib::types::response::{CoffeeRemote, UpgradeStatus};
+use coffee_lib::progress::ProgressEvent;
+use coffee_lib::types::response::{CoffeeState, CoffeeStatus};
 
 use crate::cmd::CoffeeArgs;
 use crate::cmd::CoffeeCommand;
 use crate::cmd::RemoteAction;
+use crate::cmd::TargetAction;
+use crate::coffee_term::CoffeeOutput;
+
+/// Return the status of the plugin, or an error
+/// when the plugin is unknown to coffee.
+async fn plugin_status(coffee: &CoffeeManager, plugin: &str) -> Result<CoffeeStatus, CoffeeError> {
+    coffee.status(plugin).await?.ok_or_else(|| {
+        error!("plugin `{plugin}` is not installed and not inside the repositories").with_kind(
+            CoffeeErrorKind::PluginNotFound {
+                name: plugin.to_owned(),
+            },
+        )
+    })
+}
+
+/// Ask the user to confirm the `question` on the terminal,
+/// anything different from `y` or `yes` is a no.
+fn confirm(question: &str) -> Result<bool, CoffeeError> {
+    use std::io::Write;
+
+    print!("{question} [y/N] ");
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Read the state written by `coffee export` at `path`.
+async fn read_state(path: &str) -> Result<CoffeeState, CoffeeError> {
+    let content = tokio::fs::read_to_string(path).await?;
+    serde_json::from_str(&content)
+        .map_err(|err| error!("`{path}` is not a state exported by coffee: {err}"))
+}
 
-async fn run(args: CoffeeArgs, mut coffee: CoffeeManager) -> Result<(), CoffeeError> {
-    match args.command {
+/// Run `action` under a spinner with `message`, the spinner
+/// is shown only when the output goes on the terminal.
+async fn spin<T>(
+    json: bool,
+    message: impl ToString,
+    action: impl Future<Output = Result<T, CoffeeError>>,
+) -> Result<T, CoffeeError> {
+    if json {
+        return action.await;
+    }
+    let spinner = term::spinner(message);
+    let result = action.await;
+    match &result {
+        Ok(_) => spinner.finish(),
+        Err(_) => spinner.failed(),
+    }
+    result
+}
+
+/// Run the command and return its outcome, that is rendered on the
+/// terminal or printed as JSON.
+///
+/// The confirmations are asked only on the terminal, with `--json`
+/// there is no prompt, so without `--yes` nothing is removed.
+async fn dispatch(
+    command: CoffeeCommand,
+    coffee: &mut CoffeeManager,
+    json: bool,
+) -> Result<CoffeeOutput, CoffeeError> {
+    let output = match command {
         CoffeeCommand::Link { cln_conf } => {
             // FIXME: read the core lightning config
             // and the coffee script
             coffee.link(&cln_conf).await?;
+            CoffeeOutput::Nothing
         }
         CoffeeCommand::Unlink { cln_conf } => {
             coffee.unlink(&cln_conf).await?;
+            CoffeeOutput::Nothing
         }
         CoffeeCommand::Install {
             plugin,
             verbose,
             dynamic,
+            dry_run,
+            force,
+            global,
+            verify,
         } => {
-            let spinner = if !verbose {
-                Some(term::spinner("Compiling and installing"))
-            } else {
-                None
-            };
-            match coffee.install(&plugin, verbose, dynamic).await {
-                Ok(_) => {
-                    spinner.and_then(|spinner| Some(spinner.finish()));
-                    term::success!("Plugin {plugin} Compiled and Installed")
+            coffee.set_verify_start(verify);
+            let mut spinner =
+                (!json && !verbose && !dry_run).then(|| term::spinner("Compiling and installing"));
+            let mut events = spinner.as_ref().map(|_| coffee.subscribe());
+            let install = async {
+                if global {
+                    coffee.install_global(&plugin, verbose, dry_run).await
+                } else {
+                    coffee
+                        .install(&plugin, verbose, dynamic, dry_run, force)
+                        .await
                 }
-                Err(err) => {
-                    spinner.and_then(|spinner| Some(spinner.failed()));
-                    term::error(format!("{err}"))
+            };
+            let result = match (spinner.as_mut(), events.as_mut()) {
+                (Some(spinner), Some(events)) => {
+                    // show the progress of the install inside the spinner
+                    tokio::pin!(install);
+                    loop {
+                        tokio::select! {
+                            result = &mut install => break result,
+                            Some(event) = events.recv() => spinner.message(format!("{event}")),
+                        }
+                    }
                 }
+                _ => install.await,
+            };
+            match (spinner, &result) {
+                (Some(spinner), Ok(_)) => spinner.finish(),
+                (Some(spinner), Err(_)) => spinner.failed(),
+                (None, _) => {}
             }
+            let install = result?;
+            CoffeeOutput::Install { install, dry_run }
         }
         CoffeeCommand::Remove { plugin } => {
-            let mut spinner = term::spinner(format!("Uninstalling plugin {plugin}"));
-            let result = coffee.remove(&plugin).await;
-            if let Err(err) = &result {
-                spinner.error(format!("Error while uninstalling the plugin: {err}"));
-                return Ok(());
+            let remove = spin(
+                json,
+                format!("Uninstalling plugin {plugin}"),
+                coffee.remove(&plugin),
+            )
+            .await?;
+            CoffeeOutput::Remove(remove)
+        }
+        CoffeeCommand::Purge {
+            plugin,
+            keep_config,
+        } => CoffeeOutput::Purge(coffee.purge(&plugin, keep_config).await?),
+        CoffeeCommand::Detach { plugin } => CoffeeOutput::Detach(coffee.detach(&plugin).await?),
+        CoffeeCommand::List {
+            available,
+            disk_usage,
+            sort,
+        } => {
+            coffee.set_disk_usage(disk_usage);
+            coffee.set_list_order(sort.into());
+            if available {
+                CoffeeOutput::Available(coffee.list_available().await?)
+            } else {
+                CoffeeOutput::List(coffee.list().await?)
             }
-            spinner.message("Plugin uninstalled!");
-            spinner.finish();
         }
-        CoffeeCommand::List {} => {
-            let remotes = coffee.list().await;
-            coffee_term::show_list(remotes)?;
+        CoffeeCommand::Status { plugin } => {
+            CoffeeOutput::Status(plugin_status(coffee, &plugin).await?)
+        }
+        CoffeeCommand::Upgrade {
+            repo,
+            clean,
+            verbose,
+        } => {
+            let upgrade = spin(
+                json || verbose,
+                "Upgrading",
+                coffee.upgrade(&repo, clean, verbose),
+            )
+            .await?;
+            CoffeeOutput::Upgrade { upgrade, clean }
         }
-        CoffeeCommand::Upgrade { repo, verbose } => {
-            let spinner = if !verbose {
-                Some(term::spinner("Upgrading"))
+        CoffeeCommand::CheckUpdates => {
+            let updates = spin(json, "Checking for updates", coffee.check_updates()).await?;
+            CoffeeOutput::Updates(updates)
+        }
+        CoffeeCommand::Logs { plugin } => CoffeeOutput::Logs(coffee.logs(&plugin).await?),
+        CoffeeCommand::Try {
+            plugin,
+            verbose,
+            install,
+        } => {
+            let trial = spin(
+                json,
+                format!("Trying {plugin} inside core lightning"),
+                coffee.try_plugin(&plugin, verbose),
+            )
+            .await?;
+            let install = if install {
+                Some(
+                    coffee
+                        .install(&plugin, verbose, false, false, false)
+                        .await?,
+                )
             } else {
                 None
             };
-            match coffee.upgrade(&repo, verbose).await {
-                Ok(res) => {
-                    spinner.and_then(|splinner| Some(splinner.finish()));
-                    match res.status {
-                        UpgradeStatus::UpToDate(_, _) => {
-                            term::info!("Remote repository `{}` is already up to date!", res.repo)
-                        }
-                        UpgradeStatus::Updated(_, _) => {
-                            term::success!(
-                                "Remote repository `{}` was successfully upgraded!",
-                                res.repo
-                            )
-                        }
+            CoffeeOutput::Trial { trial, install }
+        }
+        CoffeeCommand::Changelog { plugin } => {
+            let changelog = spin(
+                json,
+                format!("Fetching the changelog of {plugin}"),
+                coffee.changelog(&plugin),
+            )
+            .await?;
+            CoffeeOutput::Changelog(changelog)
+        }
+        CoffeeCommand::Remote { action, name } => match action {
+            Some(RemoteAction::Add {
+                name,
+                url,
+                update,
+                require_plugins,
+            }) => {
+                coffee.set_require_plugins(require_plugins);
+                let result = if update {
+                    spin(
+                        json,
+                        format!("Fetch remote from {url}"),
+                        coffee.update_remote(&name, &url),
+                    )
+                    .await
+                } else {
+                    spin(
+                        json,
+                        format!("Fetch remote from {url}"),
+                        coffee.add_remote(&name, &url),
+                    )
+                    .await
+                };
+                if let Err(err) = &result {
+                    if let (false, Some(CoffeeErrorKind::RemoteAlreadyExists { name, .. })) =
+                        (json, err.kind())
+                    {
+                        term::info!(
+                            "Run `coffee remote add --update {name} {url}` to update its url"
+                        );
                     }
                 }
-                Err(err) => {
-                    spinner.and_then(|spinner| Some(spinner.failed()));
-                    return Err(err);
+                CoffeeOutput::RemoteAdded {
+                    remote: result?,
+                    update,
                 }
             }
-        }
-        CoffeeCommand::Remote { action, name } => {
-            match action {
-                Some(RemoteAction::Add { name, url }) => {
-                    let mut spinner = term::spinner(format!("Fetch remote from {url}"));
-                    let result = coffee.add_remote(&name, &url).await;
-                    if let Err(err) = &result {
-                        spinner.error(format!("Error while add remote: {err}"));
-                        return result;
+            Some(RemoteAction::Rm { name, yes }) => {
+                let removal = coffee.rm_remote_preview(&name).await?;
+                if !yes {
+                    if json {
+                        return Ok(CoffeeOutput::RemoteRemoval(removal));
                     }
-                    spinner.message("Remote added!");
-                    spinner.finish();
-                }
-                Some(RemoteAction::Rm { name }) => {
-                    let mut spinner = term::spinner(format!("Removing remote {name}"));
-                    let result = coffee.rm_remote(&name).await;
-                    if let Err(err) = &result {
-                        spinner.error(format!("Error while removing the repository: {err}"));
-                        return result;
+                    if !removal.plugins.is_empty() {
+                        coffee_term::show_remote_removal(&removal)?;
+                        if !confirm("Remove the remote and its plugins?")? {
+                            return Ok(CoffeeOutput::Nothing);
+                        }
                     }
-                    spinner.message("Remote removed!");
-                    spinner.finish();
-                }
-                Some(RemoteAction::Inspect { name }) => {
-                    let result = coffee.get_plugins_in_remote(&name).await;
-                    coffee_term::show_list(result)?;
                 }
-                Some(RemoteAction::List {}) => {
-                    let remotes = coffee.list_remotes().await;
-                    coffee_term::show_remote_list(remotes)?;
+                spin(
+                    json,
+                    format!("Removing remote {name}"),
+                    coffee.rm_remote(&name),
+                )
+                .await?;
+                CoffeeOutput::Success("Remote removed!".to_owned())
+            }
+            Some(RemoteAction::Inspect { name }) => {
+                CoffeeOutput::List(coffee.get_plugins_in_remote(&name).await?)
+            }
+            Some(RemoteAction::Refresh { name }) => match name {
+                Some(name) => {
+                    let refresh = spin(
+                        json,
+                        format!("Fetching the remote repository {name}"),
+                        coffee.refresh_remote(&name),
+                    )
+                    .await?;
+                    CoffeeOutput::Refresh { name, refresh }
                 }
                 None => {
-                    // This is the case when the user does not provides the
-                    // plugins flag, so we just show the remote repository
-                    // information
-
-                    // The name will be always Some because of the
-                    // arg_required_else_help = true in the clap
-                    // attribute
-                    let name = name.ok_or_else(|| error!("No remote repository name provided"))?;
-                    let remotes = coffee.list_remotes().await?;
-                    let remotes = remotes
-                        .remotes
-                        .ok_or_else(|| error!("Couldn't get the remote repositories"))?;
-                    let remote = remotes
-                        .iter()
-                        .find(|remote| remote.local_name == name)
-                        .ok_or_else(|| error!("Couldn't find the remote repository"))?;
-                    // A workaround to show the remote repository information
-                    // in the same way as the list command
-                    let remote = Ok(CoffeeRemote {
-                        remotes: Some(vec![remote.clone()]),
-                    });
-                    coffee_term::show_remote_list(remote)?;
+                    let results = spin(
+                        json,
+                        "Fetching the remote repositories",
+                        coffee.refresh_all(),
+                    )
+                    .await?;
+                    CoffeeOutput::RefreshAll(results)
                 }
+            },
+            Some(RemoteAction::List { disk_usage }) => {
+                coffee.set_disk_usage(disk_usage);
+                CoffeeOutput::Remotes(coffee.list_remotes().await?)
             }
-        }
-        CoffeeCommand::Show { plugin } => {
-            let val = coffee.show(&plugin).await?;
+            None => {
+                // This is the case when the user does not provides the
+                // plugins flag, so we just show the remote repository
+                // information
 
-            // FIXME: modify the radicle_term markdown
-            let val = val.readme.as_str();
-            term::markdown(val);
-        }
-        CoffeeCommand::Search { plugin } => {
-            let val = coffee.search(&plugin).await?;
-            let repository_url = val.repository_url.as_str();
-            term::success!("found plugin {plugin} in remote repository {repository_url}");
+                // The name will be always Some because of the
+                // arg_required_else_help = true in the clap
+                // attribute
+                let name = name.ok_or_else(|| error!("No remote repository name provided"))?;
+                let remotes = coffee.list_remotes().await?;
+                let remote = remotes
+                    .remotes
+                    .unwrap_or_default()
+                    .into_iter()
+                    .find(|remote| remote.local_name == name)
+                    .ok_or_else(|| error!("Couldn't find the remote repository"))?;
+                CoffeeOutput::Remote(remote)
+            }
+        },
+        CoffeeCommand::Target { action } => {
+            let message = match action {
+                TargetAction::Add { name, cln_path } => {
+                    coffee.add_target(&name, &cln_path).await?;
+                    Some(format!("Target {name} linked to {cln_path}"))
+                }
+                TargetAction::Use { name } => {
+                    coffee.use_target(&name).await?;
+                    Some(format!("Using target {name}"))
+                }
+                TargetAction::List => None,
+            };
+            let targets = coffee.list_targets().await?;
+            CoffeeOutput::Targets { targets, message }
         }
+        CoffeeCommand::Config => CoffeeOutput::Config(coffee.show_config().await?),
+        CoffeeCommand::Show { plugin } => CoffeeOutput::Show(coffee.show(&plugin).await?),
+        CoffeeCommand::Search { plugin } => CoffeeOutput::Search(coffee.search(&plugin).await?),
+        CoffeeCommand::Explain { plugin } => CoffeeOutput::Explain(coffee.explain(&plugin).await?),
+        CoffeeCommand::Lint { dir } => CoffeeOutput::Lint(validate_manifest(&dir).await?),
         CoffeeCommand::Nurse { verify } => {
             if verify {
-                let result = coffee.nurse_verify().await?;
-                term::info!("{}", result);
-                if !result.is_sane() {
-                    term::info!("Coffee local directory is damaged, please run `coffee nurse` to try to fix it");
-                }
+                CoffeeOutput::NurseVerify(coffee.nurse_verify().await?)
             } else {
-                let nurse_result = coffee.nurse().await;
-                coffee_term::show_nurse_result(nurse_result)?;
+                CoffeeOutput::Nurse(coffee.nurse().await?)
             }
         }
+        CoffeeCommand::Prune { yes, cache } => {
+            if json {
+                return Ok(CoffeeOutput::Prune(coffee.prune(!yes, cache).await?));
+            }
+            let prune = coffee.prune(true, cache).await?;
+            if prune.paths.is_empty() {
+                return Ok(CoffeeOutput::Prune(prune));
+            }
+            coffee_term::show_prune(&prune)?;
+            if !yes && !confirm("Remove the directories?")? {
+                return Ok(CoffeeOutput::Nothing);
+            }
+            CoffeeOutput::Prune(coffee.prune(false, cache).await?)
+        }
+        CoffeeCommand::Diagnose => CoffeeOutput::Diagnose(coffee.diagnose().await?),
+        CoffeeCommand::Repair { verbose } => {
+            CoffeeOutput::Repair(coffee.repair_all(verbose).await?)
+        }
+        CoffeeCommand::Export => CoffeeOutput::Export(coffee.export_state().await?),
+        CoffeeCommand::Import { path } => {
+            let state = read_state(&path).await?;
+            let import = spin(json, "Importing", coffee.import_state(&state, false)).await?;
+            CoffeeOutput::Import(import)
+        }
         CoffeeCommand::Tip {
             plugin,
             amount_msat,
-        } => {
-            let tip_result = coffee.tip(&plugin, amount_msat).await?;
-            coffee_term::show_tips(&tip_result)?;
-        }
+        } => match amount_msat {
+            Some(amount_msat) => CoffeeOutput::Tip(coffee.tip(&plugin, amount_msat).await?),
+            None => CoffeeOutput::Funding(coffee.funding(&plugin).await?),
+        },
         CoffeeCommand::Disable { plugin } => {
             coffee.disable(&plugin).await?;
-            term::success!("Plugin {plugin} disabled");
+            CoffeeOutput::Success(format!("Plugin {plugin} disabled"))
         }
         CoffeeCommand::Enable { plugin } => {
             coffee.enable(&plugin).await?;
-            term::success!("Plugin {plugin} enabled");
+            CoffeeOutput::Success(format!("Plugin {plugin} enabled"))
         }
     };
-    Ok(())
+    Ok(output)
 }
 
 #[tokio::main]
 async fn main() -> Result<(), CoffeeError> {
-    env_logger::init();
     let args = CoffeeArgs::parse();
-    let coffee = CoffeeManager::new(&args).await?;
-    if let Err(err) = run(args, coffee).await {
-        term::error(format!("{err}"))
+    let json = args.json;
+    // the manifest does not need a coffee home, so it is
+    // validated without building the plugin manager
+    let result = if let CoffeeCommand::Lint { dir } = &args.command {
+        match logger::init(args.log_level.as_deref(), args.log_file.as_deref()) {
+            Ok(()) => validate_manifest(dir).await.map(CoffeeOutput::Lint),
+            Err(err) => Err(err),
+        }
+    } else {
+        // the plugin manager initializes the logger with its configuration
+        match CoffeeManager::new(&args).await {
+            Ok(mut coffee) => {
+                // the library does not print, so the output of the
+                // builds of a verbose command comes from the progress.
+                let printer = (!json && args.command.verbose()).then(|| {
+                    let mut events = coffee.subscribe();
+                    tokio::spawn(async move {
+                        while let Some(event) = events.recv().await {
+                            if let ProgressEvent::Output(line) = event {
+                                println!("{line}");
+                            }
+                        }
+                    })
+                });
+                let result = dispatch(args.command, &mut coffee, json).await;
+                // the events end when the plugin manager is dropped
+                drop(coffee);
+                if let Some(printer) = printer {
+                    let _ = printer.await;
+                }
+                result
+            }
+            Err(err) => Err(err),
+        }
+    };
+    if let Err(err) = result.and_then(|output| output.render(json)) {
+        if json {
+            coffee_term::show_json(&err)?;
+        } else {
+            term::error(format!("{err}"))
+        }
+        std::process::exit(err.exit_code());
     }
     Ok(())
 }
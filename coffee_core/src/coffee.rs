@@ -1,8 +1,15 @@
 //! Coffee mod implementation
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt::Debug;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
 use std::vec::Vec;
 use tokio::fs;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use tokio::sync::Semaphore;
+use tokio::task::{Id as TaskId, JoinError, JoinSet};
 
 use async_trait::async_trait;
 use clightningrpc_common::client::Client;
@@ -12,54 +19,534 @@ use log;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use tokio::process::Command;
 
+use coffee_github::gitlab::Gitlab;
+use coffee_github::local::Local;
 use coffee_github::repository::Github;
-use coffee_lib::errors::CoffeeError;
+use coffee_github::GitBinary;
+use coffee_lib::errors::{CoffeeError, CoffeeErrorKind};
+use coffee_lib::plugin::{Plugin, PluginLang, PluginScope};
+use coffee_lib::plugin_conf::{Manifest, MANIFEST_FILES};
 use coffee_lib::plugin_manager::PluginManager;
+use coffee_lib::progress::{emit, ProgressEvent, ProgressSender};
 use coffee_lib::repository::Repository;
 use coffee_lib::types::response::*;
-use coffee_lib::url::URL;
-use coffee_lib::{commit_id, error, get_repo_info, sh};
+use coffee_lib::url::{is_git_url, URL};
+use coffee_lib::utils::{
+    binary_in_path, cln_version_satisfies, copy_dir, disk_usage, gpg_verify, lightningd_version,
+    resolve_path, sha256sum,
+};
+use coffee_lib::{commit_id, error, get_repo_info};
+use coffee_storage::credentials::{Credentials, RepositoryToken};
 use coffee_storage::model::repository::{Kind, Repository as RepositoryInfo};
-use coffee_storage::nosql_db::NoSQlStorage;
-use coffee_storage::storage::StorageManager;
+use coffee_storage::storage::{Storage, StorageBackend, StorageManager};
 
 use super::config;
-use crate::config::CoffeeConf;
+use crate::config::{ClnTarget, CoffeeConf, Forge, CLN_NETWORKS, DEFAULT_TARGET};
+use crate::lock::CoffeeLock;
+use crate::logger;
 use crate::nurse::chain::RecoveryChainOfResponsibility;
 use crate::CoffeeArgs;
 
 pub type RepoName = String;
 
 #[derive(Serialize, Deserialize)]
-/// FIXME: move the list of plugin
-/// and the list of repository inside this struct.
 pub struct CoffeeStorageInfo {
     pub config: config::CoffeeConf,
     pub repositories: HashMap<RepoName, RepositoryInfo>,
+    /// all the plugins installed with coffee, restored
+    /// by the inventory.
+    #[serde(default)]
+    pub plugins: Vec<Plugin>,
+}
+
+/// Copy the core lightning configuration file in a timestamped
+/// `.bak` file, before coffee overwrites it, and return its path.
+///
+/// If the backup can not be written we return an error, so the
+/// original file is never overwritten without a copy.
+async fn backup_cln_conf(path: &str) -> Result<Option<String>, CoffeeError> {
+    if !fs::try_exists(path).await? {
+        return Ok(None);
+    }
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|err| error!("{err}"))?
+        .as_nanos();
+    // the name is reserved with `create_new`, so a backup never
+    // replaces another one taken at the same time.
+    let mut counter = 0;
+    let backup = loop {
+        let backup = match counter {
+            0 => format!("{path}.{timestamp}.bak"),
+            _ => format!("{path}.{timestamp}.{counter}.bak"),
+        };
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&backup)
+            .await
+        {
+            Ok(_) => break backup,
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => counter += 1,
+            Err(err) => return Err(error!("unable to backup `{path}` in `{backup}`: {err}")),
+        }
+    };
+    fs::copy(path, &backup)
+        .await
+        .map_err(|err| error!("unable to backup `{path}` in `{backup}`: {err}"))?;
+    log::info!("core lightning configuration backup stored in {backup}");
+    Ok(Some(backup))
+}
+
+/// Path of the log file with the output of the last build of `plugin`.
+fn build_log_path(root_path: &str, plugin: &str) -> String {
+    format!("{root_path}/logs/{plugin}.log")
+}
+
+/// Path of the directory with the downloads shared by the builds.
+fn cache_path(root_path: &str) -> String {
+    format!("{root_path}/cache")
+}
+
+/// The disk space used by `path`, the walk of the directory runs
+/// on the blocking threads so it does not stall the runtime.
+async fn disk_usage_of(path: &str) -> u64 {
+    let path = path.to_owned();
+    tokio::task::spawn_blocking(move || disk_usage(Path::new(&path)))
+        .await
+        .unwrap_or_default()
+}
+
+/// Return the token inside `COFFEE_GIT_TOKEN` when the remote `url` is on
+/// the host inside `COFFEE_GIT_TOKEN_HOST`, so the token is never sent
+/// to the other hosts.
+fn git_token(url: &str) -> Option<String> {
+    let token = std::env::var("COFFEE_GIT_TOKEN").ok()?;
+    let Ok(token_host) = std::env::var("COFFEE_GIT_TOKEN_HOST") else {
+        log::warn!("`COFFEE_GIT_TOKEN` is ignored because `COFFEE_GIT_TOKEN_HOST` is not set");
+        return None;
+    };
+    let url = url.split(',').next().unwrap_or_default().trim();
+    let host = URL::new("", url, "").ok()?.host()?;
+    if host != token_host.trim().to_lowercase() {
+        log::debug!("`COFFEE_GIT_TOKEN` is not used for the host `{host}`");
+        return None;
+    }
+    Some(token)
+}
+
+/// The host of the first url of a remote, where its token is
+/// valid, the urls after the first one are the mirrors.
+fn remote_host(url: &str, name: &str) -> Result<Option<String>, CoffeeError> {
+    let url = url.split(',').next().unwrap_or_default().trim();
+    Ok(URL::new("", url, name)?.host())
+}
+
+/// Return the passphrase used to encrypt the tokens of
+/// the private repositories, when the user set it.
+fn credentials_passphrase() -> Option<String> {
+    std::env::var("COFFEE_CREDENTIALS_PASSPHRASE")
+        .ok()
+        .filter(|passphrase| !passphrase.is_empty())
+}
+
+/// Error returned when there is no repository with `name`.
+fn repository_not_found(name: &str) -> CoffeeError {
+    error!("repository with name: {name} not found").with_kind(
+        CoffeeErrorKind::RepositoryNotFound {
+            name: name.to_owned(),
+        },
+    )
+}
+
+/// Error returned when the plugin `name` is not installed.
+fn plugin_not_installed(name: &str) -> CoffeeError {
+    error!("No plugin with name `{name}` found in the plugins installed").with_kind(
+        CoffeeErrorKind::PluginNotFound {
+            name: name.to_owned(),
+        },
+    )
+}
+
+/// Error returned when the configuration at `path` can not be written.
+fn config_write_failed<E: Into<CoffeeError>>(path: &str, err: E) -> CoffeeError {
+    err.into().with_kind(CoffeeErrorKind::ConfigWriteFailed {
+        path: path.to_owned(),
+    })
+}
+
+/// Rank how well the plugin matches the (lowercase) search term,
+/// lower is better, and `None` means that the plugin does not match.
+fn search_rank(plugin: &Plugin, term: &str) -> Option<u8> {
+    let name = plugin.name().to_lowercase();
+    if name == term {
+        Some(0)
+    } else if name.starts_with(term) {
+        Some(1)
+    } else if name.contains(term) {
+        Some(2)
+    } else if plugin
+        .description()
+        .is_some_and(|description| description.to_lowercase().contains(term))
+    {
+        Some(3)
+    } else {
+        None
+    }
+}
+
+/// Edit distance between the two names, used to suggest
+/// the plugins with a name similar to the one requested.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let cost = usize::from(a != *b);
+            let distance = (previous[j] + cost)
+                .min(previous[j + 1] + 1)
+                .min(current[j] + 1);
+            current.push(distance);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Explain why the directory `root` of the repository `repo`
+/// is not indexed as a plugin, with the language detected.
+fn unindexed_reason(repo: &str, root: &Path) -> (PluginLang, Option<ExplainReason>) {
+    let lang = PluginLang::detect(root);
+    let invalid =
+        |err: CoffeeError| ExplainReason::InvalidManifest(repo.to_owned(), err.msg().to_owned());
+    let manifest = MANIFEST_FILES
+        .iter()
+        .find_map(|file| std::fs::read_to_string(root.join(file)).ok());
+    let Some(manifest) = manifest else {
+        let reason = match lang {
+            PluginLang::Unknown => Some(ExplainReason::UnknownLang(repo.to_owned())),
+            // the exec path of these languages is guessed by the indexing
+            PluginLang::PyPip | PluginLang::PyPoetry | PluginLang::Go | PluginLang::Rust => None,
+            _ => Some(ExplainReason::MissingManifest(
+                repo.to_owned(),
+                lang.clone(),
+            )),
+        };
+        return (lang, reason);
+    };
+    let manifest = match Manifest::parse(&manifest).and_then(|manifest| {
+        manifest.validate()?;
+        Ok(manifest)
+    }) {
+        Ok(manifest) => manifest,
+        Err(err) => return (lang, Some(invalid(err))),
+    };
+    let root_path = root.to_string_lossy();
+    for conf in manifest.confs() {
+        let conf_lang = match PluginLang::from_conf(&conf.plugin.lang) {
+            Ok(conf_lang) => conf_lang,
+            Err(err) => return (lang, Some(invalid(err))),
+        };
+        if let Err(err) = resolve_path(&root_path, &conf.plugin.main) {
+            return (conf_lang, Some(invalid(err)));
+        }
+    }
+    (lang, None)
+}
+
+/// File left inside the directory of a detached plugin,
+/// with the plugin information at the time of the detach.
+const DETACHED_BUILD: &str = ".coffee-detached.json";
+
+/// Directory used in place of the network one by the
+/// plugins installed with the global scope.
+const GLOBAL_PLUGINS: &str = "global";
+
+/// Directory used in place of the network one by the
+/// plugins built to be tried before the install.
+const TRIAL_PLUGINS: &str = "trial";
+
+/// How many times the running plugins are listed before
+/// reporting that core lightning did not run the plugin.
+const VERIFY_START_ATTEMPTS: u32 = 10;
+
+/// Time between two lists of the running plugins.
+const VERIFY_START_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Verify the signature of the manifest of the plugin with the
+/// `trusted_keys`, and return the fingerprint of the key that signed it.
+///
+/// Without trusted keys the verification is disabled.
+async fn verify_signature(
+    plugin: &Plugin,
+    trusted_keys: &[String],
+) -> Result<Option<String>, CoffeeError> {
+    if trusted_keys.is_empty() {
+        return Ok(None);
+    }
+    let name = plugin.name();
+    let Some(manifest) = MANIFEST_FILES
+        .iter()
+        .map(|file| format!("{}/{file}", plugin.root_path))
+        .find(|manifest| Path::new(manifest).exists())
+    else {
+        return Err(error!(
+            "plugin `{name}` does not have a coffee manifest, so it can not be verified"
+        ));
+    };
+    let signature = format!("{manifest}.asc");
+    if !fs::try_exists(&signature).await? {
+        return Err(error!(
+            "plugin `{name}` is not signed, `{signature}` does not exist"
+        ));
+    }
+    let fingerprints = gpg_verify(&manifest, &signature).await?;
+    let Some(key) = fingerprints
+        .into_iter()
+        .find(|fingerprint| trusted_keys.contains(fingerprint))
+    else {
+        return Err(error!(
+            "the manifest of plugin `{name}` is not signed by a trusted key"
+        ));
+    };
+    log::info!("manifest of plugin `{name}` signed by {key}");
+    Ok(Some(key))
+}
+
+/// Copy the plugin inside the network plugins directory and build it,
+/// returning the plugin and the path of the runnable plugin.
+///
+/// With `trusted_keys` the plugin is built only when its manifest
+/// has a valid signature of one of them.
+///
+/// This does not touch the coffee state, so it is possible to build
+/// multiple plugins concurrently.
+#[allow(clippy::too_many_arguments)]
+async fn build_plugin(
+    mut plugin: Plugin,
+    root_path: String,
+    network: String,
+    verbose: bool,
+    dry_run: bool,
+    progress: Option<ProgressSender>,
+    trusted_keys: Vec<String>,
+    proxy: Option<String>,
+    offline: bool,
+    timeout: Option<Duration>,
+) -> Result<(Plugin, String), CoffeeError> {
+    plugin.signed_by = verify_signature(&plugin, &trusted_keys).await?;
+    // old_root_path is the path where the plugin is cloned and currently stored
+    // eg. ~/.coffee/repositories/<repo_name>/<plugin_name>
+    let old_root_path = plugin.root_path.clone();
+    // new_root_path is the path where the plugin will be installed specific to the network
+    // eg. ~/.coffee/<network>/plugins/<plugin_name>
+    let new_root_path = format!("{root_path}/{network}/plugins/{}", plugin.name());
+
+    log::debug!(
+        "Start! copying directory from {} inside the new one {}",
+        old_root_path,
+        new_root_path
+    );
+    if !dry_run && fs::try_exists(&new_root_path).await? {
+        // a stale build, e.g. a detached plugin built from another commit.
+        fs::remove_dir_all(&new_root_path).await?;
+    }
+    emit(progress.as_ref(), ProgressEvent::Copying(plugin.name()));
+    if dry_run {
+        log::info!("[dry-run] copy {old_root_path} inside {new_root_path}");
+    } else {
+        copy_dir(Path::new(&old_root_path), Path::new(&new_root_path))
+            .await
+            .map_err(|err| {
+                error!("unable to copy {old_root_path} inside {new_root_path}: {err}")
+            })?;
+    }
+    log::debug!(
+        "Done! copying directory from {} inside the new one {}",
+        old_root_path,
+        new_root_path
+    );
+
+    let Some(relative_path) = plugin.exec_path.strip_prefix(&old_root_path) else {
+        return Err(error!("exec path not found"));
+    };
+    plugin.exec_path = format!("{}{}", new_root_path, relative_path);
+    plugin.root_path = new_root_path;
+
+    log::debug!("plugin: {:?}", plugin);
+    emit(progress.as_ref(), ProgressEvent::Building(plugin.name()));
+    // the log keeps only the output of the last build of the plugin
+    let log_path = build_log_path(&root_path, &plugin.name());
+    if !dry_run {
+        fs::create_dir_all(format!("{root_path}/logs")).await?;
+        fs::write(&log_path, "").await?;
+    }
+    let log = (!dry_run).then_some(log_path.as_str());
+    // the downloads are shared between the plugins and the reinstalls
+    let cache = cache_path(&root_path);
+    let name = plugin.name();
+    let root = plugin.root_path.clone();
+    let configure = plugin.configure(
+        verbose,
+        dry_run,
+        progress.as_ref(),
+        log,
+        Some(&cache),
+        proxy.as_deref(),
+        offline,
+    );
+    // the processes of the build are killed when the configure is dropped
+    let result = match timeout {
+        Some(timeout) => match tokio::time::timeout(timeout, configure).await {
+            Ok(result) => result,
+            Err(_) => {
+                // the build is left halfway, so nothing of it is kept
+                if !dry_run {
+                    let _ = fs::remove_dir_all(&root).await;
+                }
+                return Err(error!(
+                    "the build of the plugin `{name}` timed out after {}s",
+                    timeout.as_secs()
+                )
+                .with_kind(CoffeeErrorKind::BuildTimeout { plugin: name }));
+            }
+        },
+        None => configure.await,
+    };
+    let path =
+        result.map_err(|err| err.with_kind(CoffeeErrorKind::BuildFailed { plugin: name }))?;
+    log::debug!("runnable plugin path {path}");
+    Ok((plugin, path))
+}
+
+/// Walk the dependencies of `plugin` depth first, and push inside
+/// `dependencies` the ones that are not `installed`, each one after
+/// its own dependencies. `lookup` finds a plugin inside the repositories.
+fn visit_dependencies(
+    plugin: &Plugin,
+    installed: &[Plugin],
+    lookup: &dyn Fn(&str) -> Option<Plugin>,
+    visiting: &mut Vec<String>,
+    dependencies: &mut Vec<Plugin>,
+) -> Result<(), CoffeeError> {
+    for name in plugin.dependencies() {
+        if visiting.contains(&name) {
+            visiting.push(name);
+            return Err(error!(
+                "dependency cycle detected: {}",
+                visiting.join(" -> ")
+            ));
+        }
+        let is_installed = installed.iter().any(|p| p.name() == name);
+        if is_installed || dependencies.iter().any(|p| p.name() == name) {
+            continue;
+        }
+        let Some(dependency) = lookup(&name) else {
+            return Err(error!(
+                "dependency `{name}` of plugin `{}` is not present inside the repositories",
+                plugin.name()
+            ));
+        };
+        visiting.push(name);
+        visit_dependencies(&dependency, installed, lookup, visiting, dependencies)?;
+        visiting.pop();
+        dependencies.push(dependency);
+    }
+    Ok(())
+}
+
+/// Remove the build of a plugin that is not registered,
+/// nothing points to it anymore.
+async fn remove_build(plugin: &Plugin) {
+    if let Err(err) = fs::remove_dir_all(&plugin.root_path).await {
+        log::warn!("unable to remove the build of `{}`: {err}", plugin.name());
+    }
+}
+
+/// Check if the build at `path` is used by a plugin installed on any
+/// target, all the targets build inside the same plugins directory.
+fn build_in_use(config: &CoffeeConf, path: &str) -> bool {
+    let prefix = format!("{path}/");
+    config
+        .plugins
+        .iter()
+        .chain(
+            config
+                .targets
+                .values()
+                .flat_map(|target| target.plugins.iter()),
+        )
+        .any(|plugin| plugin.root_path == path || plugin.exec_path.starts_with(&prefix))
+}
+
+/// Pair the outcome of a task with the name it was spawned for,
+/// a task that panicked or was cancelled fails only its own name
+/// with the error built by `failed`.
+fn task_outcome<T>(
+    names: &HashMap<TaskId, String>,
+    task: Result<(String, Result<T, CoffeeError>), JoinError>,
+    failed: impl FnOnce(&str, JoinError) -> CoffeeError,
+) -> (String, Result<T, CoffeeError>) {
+    match task {
+        Ok(outcome) => outcome,
+        Err(err) => {
+            let name = names
+                .get(&err.id())
+                .cloned()
+                .unwrap_or_else(|| err.id().to_string());
+            let err = failed(&name, err);
+            (name, Err(err))
+        }
+    }
+}
+
+/// Pair the outcome of a build task with the plugin it was building,
+/// a task that panicked or was cancelled fails only its own plugin.
+fn build_outcome<T>(
+    names: &HashMap<TaskId, String>,
+    build: Result<(String, Result<T, CoffeeError>), JoinError>,
+) -> (String, Result<T, CoffeeError>) {
+    task_outcome(names, build, |name, err| {
+        error!("build of `{name}` did not complete: {err}").with_kind(
+            CoffeeErrorKind::BuildFailed {
+                plugin: name.to_owned(),
+            },
+        )
+    })
+}
+
+/// Pair the outcome of a clone task with the remote it was cloning,
+/// a task that panicked or was cancelled fails only its own remote.
+fn clone_outcome<T>(
+    names: &HashMap<TaskId, String>,
+    clone: Result<(String, Result<T, CoffeeError>), JoinError>,
+) -> (String, Result<T, CoffeeError>) {
+    task_outcome(names, clone, |name, err| {
+        error!("clone of the remote `{name}` did not complete: {err}")
+    })
 }
 
 impl From<&CoffeeManager> for CoffeeStorageInfo {
     fn from(value: &CoffeeManager) -> Self {
-        let mut repos = HashMap::new();
-        // FIXME: improve the down cast
-        for (name, repo) in value.repos.iter() {
-            let repo = if let Some(git) = repo.as_any().downcast_ref::<Github>() {
-                RepositoryInfo::from(git)
-            } else {
-                panic!("this should never happens")
-            };
-            repos.insert(name.to_string(), repo);
-        }
+        let repositories = value
+            .repos
+            .iter()
+            .map(|(name, repo)| (name.to_owned(), repo.to_info()))
+            .collect();
 
         CoffeeStorageInfo {
             config: value.config.to_owned(),
-            repositories: repos, // FIXME: find a way to downcast
+            repositories,
+            plugins: value.config.plugins.clone(),
         }
     }
 }
 
+/// Number of repositories cloned at the same time by default.
+pub const CLONE_CONCURRENCY: usize = 4;
+
 pub struct CoffeeManager {
     pub config: config::CoffeeConf,
     pub repos: HashMap<String, Box<dyn Repository + Send + Sync>>,
@@ -70,29 +557,156 @@ pub struct CoffeeManager {
     pub cln_config: Option<CLNConf>,
     /// storage instance to make all the plugin manager
     /// information persistent on disk
-    pub storage: NoSQlStorage,
+    pub storage: Storage,
     /// core lightning rpc connection
     pub rpc: Option<Client>,
     /// Recovery Strategies for the nurse command.
     pub recovery_strategies: RecoveryChainOfResponsibility,
+    /// Repositories stored that coffee is not able to load, they are
+    /// kept as they are on disk until the nurse removes them.
+    pub corrupted_repos: HashMap<RepoName, serde_json::Value>,
+    /// Listener of the progress of the long operations.
+    progress: Option<ProgressSender>,
+    /// Maximum number of repositories cloned at the same time.
+    clone_concurrency: usize,
+    /// How long a network operation can take before it is aborted.
+    network_timeout: Option<Duration>,
+    /// How long the build of a plugin can take before it is aborted.
+    install_timeout: Option<Duration>,
+    /// Compute the disk usage inside the lists.
+    disk_usage: bool,
+    /// Order of the plugins inside the lists.
+    list_order: ListOrder,
+    /// Wait until core lightning runs the plugins installed.
+    verify_start: bool,
+    /// Refuse the remotes without any plugin.
+    require_plugins: bool,
+    /// Wait for another instance to release the lock, instead of failing.
+    lock_wait: bool,
+    /// Lock of the coffee directory held by the running operation, it is
+    /// shared with the nested operations and released when the outer ends.
+    held_lock: Mutex<Weak<CoffeeLock>>,
 }
 
 impl CoffeeManager {
     pub async fn new(conf: &dyn CoffeeArgs) -> Result<Self, CoffeeError> {
+        Self::with_storage(conf, StorageBackend::default()).await
+    }
+
+    /// Subscribe to the progress events of the long operations,
+    /// like the install of a plugin. Only the last subscriber
+    /// receives the events.
+    pub fn subscribe(&mut self) -> UnboundedReceiver<ProgressEvent> {
+        let (sender, receiver) = unbounded_channel();
+        self.set_progress(Some(sender));
+        receiver
+    }
+
+    /// Set the maximum number of repositories cloned at the same time
+    /// by `add_remotes`, by default `CLONE_CONCURRENCY`.
+    pub fn set_clone_concurrency(&mut self, concurrency: usize) {
+        self.clone_concurrency = concurrency.max(1);
+    }
+
+    /// Set how long a clone of a repository can take before it is aborted.
+    pub fn set_network_timeout(&mut self, timeout: Option<Duration>) {
+        self.network_timeout = timeout;
+    }
+
+    /// Set how long the build of a plugin can take before its
+    /// processes are killed and the install is aborted.
+    pub fn set_install_timeout(&mut self, timeout: Option<Duration>) {
+        self.install_timeout = timeout;
+    }
+
+    /// Take the lock of the coffee directory for an operation that changes
+    /// the stored state, so a long running instance (e.g. the plugin or the
+    /// daemon) holds it only while it changes something.
+    ///
+    /// The lock held by an outer operation is reused.
+    async fn lock(&self) -> Result<Arc<CoffeeLock>, CoffeeError> {
+        let held = self
+            .held_lock
+            .lock()
+            .map_err(|err| error!("{err}"))?
+            .upgrade();
+        if let Some(lock) = held {
+            return Ok(lock);
+        }
+        let lock = Arc::new(CoffeeLock::acquire(&self.config.root_path, self.lock_wait).await?);
+        *self.held_lock.lock().map_err(|err| error!("{err}"))? = Arc::downgrade(&lock);
+        Ok(lock)
+    }
+
+    /// The git executable configured in the defaults file, if any.
+    fn git_binary(&self) -> Option<GitBinary> {
+        self.config.startup.git_path.as_ref().map(|path| GitBinary {
+            path: path.clone(),
+            clone_args: self.config.startup.git_clone_args.clone(),
+        })
+    }
+
+    /// Build the plugin manager with the storage backend specified.
+    pub async fn with_storage(
+        conf: &dyn CoffeeArgs,
+        backend: StorageBackend,
+    ) -> Result<Self, CoffeeError> {
+        let lock_wait = conf.wait();
+        let network_timeout = conf.network_timeout().map(Duration::from_secs);
+        let install_timeout = conf.install_timeout().map(Duration::from_secs);
         let conf = CoffeeConf::new(conf).await?;
+        logger::init(
+            conf.startup.log_level.as_deref(),
+            conf.startup.log_file.as_deref(),
+        )?;
         let mut coffee = CoffeeManager {
             config: conf.clone(),
             coffee_cln_config: CLNConf::new(conf.config_path, true),
             repos: HashMap::new(),
-            storage: NoSQlStorage::new(&conf.root_path).await?,
+            storage: Storage::new(backend, &conf.root_path).await?,
             cln_config: None,
             rpc: None,
             recovery_strategies: RecoveryChainOfResponsibility::new().await?,
+            corrupted_repos: HashMap::new(),
+            progress: None,
+            clone_concurrency: CLONE_CONCURRENCY,
+            network_timeout,
+            install_timeout,
+            disk_usage: false,
+            list_order: ListOrder::default(),
+            verify_start: false,
+            require_plugins: false,
+            lock_wait,
+            held_lock: Mutex::new(Weak::new()),
         };
         coffee.inventory().await?;
+        coffee.apply_defaults().await?;
         Ok(coffee)
     }
 
+    /// Apply the defaults of the configuration file that change the
+    /// stored state: link core lightning when coffee is not linked,
+    /// and add the remotes when coffee does not have any.
+    async fn apply_defaults(&mut self) -> Result<(), CoffeeError> {
+        let defaults = self.config.startup.defaults.clone();
+        if let Some(cln_path) = &defaults.cln_path {
+            if self.config.cln_root.is_none() {
+                log::info!("linking coffee with core lightning at {cln_path}");
+                self.link_with_cln(cln_path).await?;
+                self.flush().await?;
+            }
+        }
+        if self.repos.is_empty() && self.corrupted_repos.is_empty() {
+            for (name, url) in &defaults.remotes {
+                // a remote that can not be cloned now is retried at the next run
+                if let Err(err) = self.add_remote(name, url).await {
+                    log::warn!("unable to add the default remote `{name}`: {err}");
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// when coffee is configured, run an inventory to collect all the necessary information
     /// about the coffee ecosystem.
     async fn inventory(&mut self) -> Result<(), CoffeeError> {
@@ -101,22 +715,91 @@ impl CoffeeManager {
             .load::<CoffeeStorageInfo>(&self.config.network)
             .await
             .map(|store| {
+                // the options given at startup are not part of the stored state
+                let startup = std::mem::take(&mut self.config.startup);
                 self.config = store.config;
+                self.config.plugins = store.plugins;
+                self.config.startup = startup;
             });
+        // the configuration file of a target is not the one
+        // given at startup, that belongs to the default target.
+        if self.config.target != DEFAULT_TARGET
+            && self.coffee_cln_config.path != self.config.config_path
+        {
+            self.coffee_cln_config = CLNConf::new(self.config.config_path.clone(), true);
+        }
+        let tokens = self.load_tokens().await.unwrap_or_else(|err| {
+            log::warn!("unable to load the tokens of the private repositories: {err}");
+            HashMap::new()
+        });
         // FIXME: check if this exist in a better wai
-        let _ = self
+        let stored = self
             .storage
-            .load::<HashMap<RepoName, RepositoryInfo>>("repositories")
+            .load::<HashMap<RepoName, serde_json::Value>>("repositories")
             .await
-            .map(|item| {
-                log::debug!("repositories in store {:?}", item);
-                item.iter().for_each(|repo| match repo.1.kind {
-                    Kind::Git => {
-                        let repo = Github::from(repo.1);
-                        self.repos.insert(repo.name(), Box::new(repo));
-                    }
-                });
+            .unwrap_or_default();
+        // a single repository that we are not able to load must
+        // not stop coffee from loading all the others.
+        let mut repositories = HashMap::new();
+        for (name, value) in stored {
+            match serde_json::from_value::<RepositoryInfo>(value.clone()) {
+                Ok(repo) => {
+                    repositories.insert(name, repo);
+                }
+                Err(err) => {
+                    log::warn!(
+                        "unable to load the repository `{name}`: {err}, run `coffee nurse` to remove it"
+                    );
+                    self.corrupted_repos.insert(name, value);
+                }
+            }
+        }
+        log::debug!("repositories in store {:?}", repositories);
+        // the plugins index is stored with the repository, so we
+        // index the repository again only when its HEAD changed.
+        let mut reindexed = false;
+        for (name, repo) in repositories.iter() {
+            // a token is used only on the host where it was stored
+            let host = repo.url.host();
+            let token = tokens.get(name).and_then(|token| {
+                let valid = token.for_host(host.as_deref(), host.as_deref());
+                if valid.is_none() {
+                    log::warn!("the stored token of `{name}` is not valid for the host of its url");
+                }
+                valid
             });
+            let repo: Box<dyn Repository + Send + Sync> = match repo.kind {
+                Kind::Git => {
+                    let mut repo = Github::from(repo)
+                        .with_token(token)
+                        .with_clone_timeout(self.network_timeout)
+                        .with_git(self.git_binary())
+                        .with_proxy(self.config.startup.proxy.clone());
+                    reindexed |= repo.refresh_index().await.unwrap_or_else(|err| {
+                        log::warn!("unable to refresh the index of `{name}`: {err}");
+                        false
+                    });
+                    Box::new(repo)
+                }
+                Kind::GitLab => {
+                    let mut repo = Gitlab::from(repo)
+                        .with_token(token)
+                        .with_clone_timeout(self.network_timeout)
+                        .with_git(self.git_binary())
+                        .with_proxy(self.config.startup.proxy.clone());
+                    reindexed |= repo.refresh_index().await.unwrap_or_else(|err| {
+                        log::warn!("unable to refresh the index of `{name}`: {err}");
+                        false
+                    });
+                    Box::new(repo)
+                }
+                Kind::Local => Box::new(Local::from(repo)),
+            };
+            self.repos.insert(repo.name(), repo);
+        }
+        if reindexed {
+            self.flush().await?;
+        }
 
         if let Err(err) = self.coffee_cln_config.parse() {
             log::error!("{}", err.cause);
@@ -180,258 +863,2334 @@ impl CoffeeManager {
         Ok(())
     }
 
-    pub fn storage_info(&self) -> CoffeeStorageInfo {
-        CoffeeStorageInfo::from(self)
+    pub fn storage_info(&self) -> Result<CoffeeStorageInfo, CoffeeError> {
+        Ok(CoffeeStorageInfo::from(self))
     }
 
-    pub async fn flush(&self) -> Result<(), CoffeeError> {
-        let store_info = self.storage_info();
-        self.storage
-            .store(&self.config.network, &store_info)
-            .await?;
-        self.storage
-            .store("repositories", &store_info.repositories)
-            .await?;
-        Ok(())
+    /// Derive the key of the credentials from the `passphrase`, and
+    /// migrate to it the `encrypted` tokens, when they are encrypted
+    /// with the key stored in the clear by the previous versions.
+    async fn credentials(
+        &self,
+        passphrase: &str,
+        encrypted: &mut HashMap<RepoName, String>,
+    ) -> Result<Credentials, CoffeeError> {
+        let root_path = &self.config.root_path;
+        let credentials = Credentials::new(root_path, passphrase).await?;
+        let legacy = match Credentials::legacy(root_path).await {
+            Ok(Some(legacy)) => legacy,
+            Ok(None) => return Ok(credentials),
+            Err(err) => {
+                log::warn!("unable to migrate the stored tokens to the passphrase: {err}");
+                return Ok(credentials);
+            }
+        };
+        match credentials.migrate(&legacy, encrypted) {
+            Ok(migrated) => {
+                self.storage.store("credentials", &migrated).await?;
+                Credentials::remove_legacy(root_path).await?;
+                log::info!("the stored tokens are now encrypted with the passphrase");
+                *encrypted = migrated;
+            }
+            Err(err) => {
+                log::warn!("unable to migrate the stored tokens to the passphrase: {err}")
+            }
+        }
+        Ok(credentials)
     }
 
-    pub async fn update_conf(&self) -> Result<(), CoffeeError> {
-        self.coffee_cln_config.flush()?;
-        log::debug!("stored all the cln info in {}", self.coffee_cln_config);
-        Ok(())
+    /// Load the tokens of the private repositories.
+    async fn load_tokens(&self) -> Result<HashMap<RepoName, RepositoryToken>, CoffeeError> {
+        let Ok(mut encrypted) = self
+            .storage
+            .load::<HashMap<RepoName, String>>("credentials")
+            .await
+        else {
+            return Ok(HashMap::new());
+        };
+        let Some(passphrase) = credentials_passphrase() else {
+            log::warn!(
+                "`COFFEE_CREDENTIALS_PASSPHRASE` is not set, the stored tokens are not used"
+            );
+            return Ok(HashMap::new());
+        };
+        let credentials = self.credentials(&passphrase, &mut encrypted).await?;
+        let mut tokens = HashMap::new();
+        for (name, token) in encrypted {
+            tokens.insert(name, credentials.decrypt_token(&token)?);
+        }
+        Ok(tokens)
     }
 
-    pub async fn load_cln_conf(&mut self) -> Result<(), CoffeeError> {
-        if self.config.cln_config_path.is_none() {
-            return Ok(());
-        }
-        let root = self.config.cln_root.clone().unwrap();
-        // We check if there is some problem we the path that we know
-        if !fs::try_exists(root.clone()).await? {
-            return Err(error!("lightning root path `{}` do not exist", root));
-        } else if !fs::try_exists(format!("{root}/{}", self.config.network)).await? {
-            return Err(error!(
-                "lightning network path `{root}/{}` do not exist",
-                self.config.network
-            ));
+    /// Store encrypted the token of a private repository with the host
+    /// where it is valid, or remove it when `token` is `None`.
+    async fn store_token(
+        &self,
+        name: &str,
+        token: Option<&RepositoryToken>,
+    ) -> Result<(), CoffeeError> {
+        let mut encrypted = self
+            .storage
+            .load::<HashMap<RepoName, String>>("credentials")
+            .await
+            .unwrap_or_default();
+        match token {
+            Some(token) => {
+                let Some(passphrase) = credentials_passphrase() else {
+                    log::warn!(
+                        "`COFFEE_CREDENTIALS_PASSPHRASE` is not set, the token of `{name}` is not stored"
+                    );
+                    return Ok(());
+                };
+                let credentials = self.credentials(&passphrase, &mut encrypted).await?;
+                encrypted.insert(name.to_owned(), credentials.encrypt_token(token)?);
+            }
+            None => {
+                if encrypted.remove(name).is_none() {
+                    return Ok(());
+                }
+            }
         }
-        // All safe, we can move with the logic
-        let rpc = Client::new(format!("{root}/{}/lightning-rpc", self.config.network));
-        self.rpc = Some(rpc);
-        let path = self.config.cln_config_path.clone().unwrap();
-        let mut file = CLNConf::new(path.clone(), true);
-        log::info!("looking for the cln config: {path}");
-        file.parse()
-            .map_err(|err| CoffeeError::new(err.core, &err.cause))?;
-        log::trace!("{:?}", file.fields);
-        self.cln_config = Some(file);
-        Ok(())
+        self.storage.store("credentials", &encrypted).await
     }
 
-    pub async fn link_with_cln(&mut self, cln_dir: &str) -> Result<(), CoffeeError> {
-        if self.cln_config.is_some() {
-            log::warn!("you are overriding the previous set up");
+    /// Build the remote repository, without cloning it.
+    fn new_remote(
+        &self,
+        name: &str,
+        url: &str,
+        token: Option<String>,
+    ) -> Result<Box<dyn Repository + Send + Sync>, CoffeeError> {
+        if let Some(repo) = self.repos.get(name) {
+            return Err(CoffeeError::remote_already_exists(
+                name,
+                &repo.url().url_string,
+            ));
         }
-        let path_with_network = format!("{cln_dir}/{}/config", self.config.network);
-        log::info!("configure coffee in the following cln config {path_with_network}");
-        self.config.cln_config_path = Some(path_with_network);
-        self.config.cln_root = Some(cln_dir.to_owned());
-        self.load_cln_conf().await?;
-        let mut conf = self.cln_config.clone().unwrap();
-        conf.add_subconf(self.coffee_cln_config.clone())
-            .map_err(|err| error!("{}", &err.cause))?;
-        conf.flush()?;
-        Ok(())
+        // the urls after the first one are the mirrors
+        let mut urls = url.split(',').map(str::trim).filter(|url| !url.is_empty());
+        let url = URL::new(
+            &self.config.root_path,
+            urls.next().unwrap_or_default(),
+            name,
+        )?;
+        let mirrors = urls
+            .map(|mirror| URL::new(&self.config.root_path, mirror, name).map(|url| url.url_string))
+            .collect::<Result<Vec<_>, _>>()?;
+        log::debug!("remote adding: {} {}", name, &url.url_string);
+        emit(
+            self.progress.as_ref(),
+            ProgressEvent::Cloning(url.url_string.clone()),
+        );
+        let repo: Box<dyn Repository + Send + Sync> = if url.url_string.starts_with("file://") {
+            Box::new(Local::new(name, &url))
+        } else {
+            let forge = url
+                .host()
+                .map(|host| Forge::of_host(&self.config.startup.forges, &host));
+            match forge {
+                Some(Forge::GitLab) => Box::new(
+                    Gitlab::new(name, &url)
+                        .with_token(token)
+                        .with_clone_timeout(self.network_timeout)
+                        .with_progress(self.progress.clone())
+                        .with_git(self.git_binary())
+                        .with_proxy(self.config.startup.proxy.clone())
+                        .with_mirrors(mirrors),
+                ),
+                _ => Box::new(
+                    Github::new(name, &url)
+                        .with_token(token)
+                        .with_clone_timeout(self.network_timeout)
+                        .with_progress(self.progress.clone())
+                        .with_git(self.git_binary())
+                        .with_proxy(self.config.startup.proxy.clone())
+                        .with_mirrors(mirrors),
+                ),
+            }
+        };
+        Ok(repo)
     }
 
-    /// Unlink coffee from the core lightning configuration file
-    pub async fn unlink_from_cln(&mut self, cln_dir: &str) -> Result<(), CoffeeError> {
-        if self.cln_config.is_none() {
-            return Err(error!("no cln configuration found"));
+    /// Add more remote repositories, cloning them concurrently
+    /// with at most `clone_concurrency` clones at the same time.
+    ///
+    /// An error while adding a remote does not stop the others,
+    /// so the result of each remote is returned.
+    pub async fn add_remotes(
+        &mut self,
+        remotes: &[(&str, &str)],
+    ) -> Result<HashMap<String, Result<(), CoffeeError>>, CoffeeError> {
+        let _lock = self.lock().await?;
+        let mut results = HashMap::new();
+        let semaphore = Arc::new(Semaphore::new(self.clone_concurrency));
+        let mut clones = JoinSet::new();
+        let mut names = HashMap::new();
+        for (name, url) in remotes {
+            if results.contains_key(*name) {
+                continue;
+            }
+            let mut repo = match self.new_remote(name, url, git_token(url)) {
+                Ok(repo) => repo,
+                Err(err) => {
+                    results.insert(name.to_string(), Err(err));
+                    continue;
+                }
+            };
+            // mark the remote as seen, the result will be replaced
+            // when the clone finishes.
+            results.insert(name.to_string(), Ok(()));
+            let semaphore = semaphore.clone();
+            let task_name = name.to_string();
+            let task = clones.spawn(async move {
+                let _permit = semaphore.acquire_owned().await;
+                let result = repo.init().await.map(|()| repo);
+                (task_name, result)
+            });
+            names.insert(task.id(), name.to_string());
         }
-        let path_with_network = format!("{cln_dir}/{}/config", self.config.network);
-        log::info!("teardown coffee in the following cln config {path_with_network}");
-        let mut conf = self.cln_config.clone().unwrap();
-        conf.rm_subconf(&self.coffee_cln_config.clone().path)
-            .map_err(|err| error!("{}", &err.cause))?;
-        conf.flush()?;
-        Ok(())
+        while let Some(clone) = clones.join_next().await {
+            let (name, result) = clone_outcome(&names, clone);
+            let result = match result {
+                Ok(repo) => self.track_remote(repo).await,
+                Err(err) => Err(err),
+            };
+            if let Err(err) = &result {
+                log::error!("error while adding the remote `{name}`: {err}");
+            }
+            results.insert(name, result);
+        }
+        Ok(results)
     }
-}
 
-#[async_trait]
-impl PluginManager for CoffeeManager {
-    async fn configure(&mut self) -> Result<(), CoffeeError> {
-        log::debug!("plugin configured");
+    /// Track the remote cloned by `add_remotes`, once it is
+    /// verified that it contains some plugins.
+    async fn track_remote(
+        &mut self,
+        repo: Box<dyn Repository + Send + Sync>,
+    ) -> Result<(), CoffeeError> {
+        self.check_remote_plugins(repo.as_ref()).await?;
+        let name = repo.name();
+        log::debug!("remote added: {} {}", name, repo.url().url_string);
+        let token = git_token(&repo.url().url_string)
+            .map(|token| RepositoryToken::new(&token, repo.url().host()));
+        self.repos.insert(name.clone(), repo);
+        self.store_repository(&name).await?;
+        if let Some(token) = token {
+            self.store_token(&name, Some(&token)).await?;
+        }
         Ok(())
     }
 
-    async fn install(
+    /// Add a remote repository, the token is used to
+    /// authenticate when the repository is private.
+    pub async fn add_remote_with_token(
         &mut self,
-        plugin: &str,
-        verbose: bool,
-        try_dynamic: bool,
-    ) -> Result<(), CoffeeError> {
-        let mut plugins = self.config.plugins.clone();
-        plugins.retain(|p| p.name().eq(plugin));
+        name: &str,
+        url: &str,
+        token: Option<String>,
+    ) -> Result<CoffeeAddRemote, CoffeeError> {
+        // FIXME: we should allow some error here like
+        // for the add remote command the no found error for the `repository`
+        // directory is fine.
 
-        if !plugins.is_empty() {
-            return Err(error!("Plugin with name `{plugin}` already installed"));
+        let mut repo = self.new_remote(name, url, token.clone())?;
+        // the token is valid for the host of the first url
+        let token_host = remote_host(url, name)?;
+        repo.init().await?;
+        self.check_remote_plugins(repo.as_ref()).await?;
+        let plugins = repo
+            .list()
+            .await?
+            .iter()
+            .map(|plugin| plugin.name())
+            .collect();
+        // the url can be a mirror, when the first url is not reachable
+        let url = repo.url().url_string;
+        log::debug!("remote added: {name} {url}");
+        self.repos.insert(repo.name(), repo);
+        if let Some(token) = token {
+            let token = RepositoryToken::new(&token, token_host);
+            self.store_token(name, Some(&token)).await?;
         }
+        self.store_repository(name).await?;
+        Ok(CoffeeAddRemote {
+            name: name.to_owned(),
+            url,
+            plugins,
+        })
+    }
 
-        log::debug!("installing plugin: {plugin}");
-        // keep track if the plugin is successfully installed
+    /// Check the local checkout of each repository, sorted by name.
+    pub async fn repositories_health(&self) -> Result<Vec<RepositoryHealth>, CoffeeError> {
+        let mut health = vec![];
         for repo in self.repos.values() {
-            if let Some(mut plugin) = repo.get_plugin_by_name(plugin) {
-                log::trace!("{:?}", plugin);
-
-                if try_dynamic && plugin.important() {
-                    return Err(error!(
-                        "plugin is important, can't be dynamically installed"
-                    ));
-                }
-
-                // old_root_path is the path where the plugin is cloned and currently stored
-                // eg. ~/.coffee/repositories/<repo_name>/<plugin_name>
-                let old_root_path = plugin.root_path.clone();
-                // new_root_path is the path where the plugin will be installed specific to the network
-                // eg. ~/.coffee/<network>/plugins/<plugin_name>
-                let new_root_path = format!(
-                    "{}/{}/plugins/{}",
-                    self.config.root_path,
-                    self.config.network,
-                    plugin.name()
-                );
-
-                log::debug!(
-                    "Start! copying directory from {} inside the new one {}",
-                    old_root_path,
-                    new_root_path
-                );
-                let script = format!("cp -r {old_root_path} {new_root_path}");
-                sh!(self.config.root_path.clone(), script, verbose);
-                log::debug!(
-                    "Done! copying directory from {} inside the new one {}",
-                    old_root_path,
-                    new_root_path
-                );
-                let old_exec_path = plugin.exec_path.clone();
+            health.push(repo.health_check().await?);
+        }
+        health.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(health)
+    }
 
-                let plugin_conf_key = if plugin.important() {
-                    "important-plugin"
-                } else {
-                    "plugin"
+    /// Make sure that the remote just cloned contains at least one
+    /// plugin, the index is the one built by the init of the remote.
+    ///
+    /// An empty remote is only reported with a warning, unless the
+    /// remotes without plugins are refused, in which case the clone
+    /// is removed.
+    async fn check_remote_plugins(
+        &self,
+        repo: &(dyn Repository + Send + Sync),
+    ) -> Result<(), CoffeeError> {
+        if !repo.list().await?.is_empty() {
+            return Ok(());
+        }
+        let name = repo.name();
+        let url = repo.url();
+        if !self.require_plugins {
+            log::warn!(
+                "remote `{name}` does not contain any plugin, check the url {}",
+                url.url_string
+            );
+            return Ok(());
+        }
+        let cloned_repositories_path = format!("{}/repositories", self.config.root_path);
+        if url.path_string.starts_with(&cloned_repositories_path) {
+            fs::remove_dir_all(&url.path_string).await?;
+        }
+        Err(error!(
+            "remote `{name}` does not contain any plugin, check the url {}",
+            url.url_string
+        ))
+    }
+
+    /// Update the url of an existing remote, the plugins
+    /// installed from the remote are kept as they are.
+    pub async fn update_remote(
+        &mut self,
+        name: &str,
+        url: &str,
+    ) -> Result<CoffeeAddRemote, CoffeeError> {
+        let _lock = self.lock().await?;
+        let Some(repo) = self.repos.remove(name) else {
+            return Err(repository_not_found(name));
+        };
+        // the stored token is reused only when the remote stays on the host
+        // of the token, otherwise it is removed with the old url.
+        let old_host = repo.url().host();
+        let new_host = remote_host(url, name)?;
+        let token = match git_token(url) {
+            Some(token) => Some(token),
+            None => self
+                .load_tokens()
+                .await?
+                .remove(name)
+                .and_then(|token| token.for_host(new_host.as_deref(), old_host.as_deref())),
+        };
+        let keep_token = token.is_some();
+        // keep the old clone around until the new one is ready,
+        // so we can restore it if something goes wrong.
+        let repo_path = repo.url().path_string;
+        let backup_path = format!("{repo_path}.old");
+        let cloned_repositories_path = format!("{}/repositories", self.config.root_path);
+        let is_cloned = repo_path.starts_with(&cloned_repositories_path);
+        if is_cloned {
+            fs::rename(&repo_path, &backup_path).await?;
+        }
+        let update = match self.add_remote_with_token(name, url, token).await {
+            Ok(update) => update,
+            Err(err) => {
+                if is_cloned {
+                    let _ = fs::remove_dir_all(&repo_path).await;
+                    fs::rename(&backup_path, &repo_path).await?;
+                }
+                self.repos.insert(name.to_owned(), repo);
+                return Err(err);
+            }
+        };
+        if is_cloned {
+            fs::remove_dir_all(&backup_path).await?;
+        }
+        if !keep_token {
+            self.store_token(name, None).await?;
+        }
+        log::debug!("remote updated: {name} {url}");
+        Ok(update)
+    }
+
+    /// Install the plugin resolved, after the plugins that it depends on.
+    async fn install_with_dependencies(
+        &mut self,
+        plugin: Plugin,
+        verbose: bool,
+        try_dynamic: bool,
+        dry_run: bool,
+    ) -> Result<CoffeeInstall, CoffeeError> {
+        for dependency in self.resolve_dependencies(&plugin)? {
+            if try_dynamic && dependency.important() {
+                return Err(error!(
+                    "dependency `{}` is important, can't be dynamically installed",
+                    dependency.name()
+                ));
+            }
+            log::info!(
+                "installing `{}` required by `{}`",
+                dependency.name(),
+                plugin.name()
+            );
+            emit(
+                self.progress.as_ref(),
+                ProgressEvent::InstallingDependency(dependency.name(), plugin.name()),
+            );
+            self.install_plugin(dependency, verbose, try_dynamic, dry_run)
+                .await?;
+        }
+        self.install_plugin(plugin, verbose, try_dynamic, dry_run)
+            .await
+    }
+
+    /// Install the plugin for all the networks, see `install_global`.
+    ///
+    /// With `remote` the plugin is looked up only inside that remote.
+    async fn install_global_from(
+        &mut self,
+        plugin: &str,
+        remote: Option<&str>,
+        verbose: bool,
+        dry_run: bool,
+    ) -> Result<CoffeeInstall, CoffeeError> {
+        let networks = self.other_networks().await;
+        for network in &networks {
+            let store = self.storage.load::<CoffeeStorageInfo>(network).await?;
+            if store.plugins.iter().any(|p| p.name() == plugin) {
+                return Err(error!(
+                    "Plugin with name `{plugin}` already installed on {network}"
+                ));
+            }
+        }
+        let mut plugin = self.resolve_plugin(plugin, remote, false)?;
+        if !self.resolve_dependencies(&plugin)?.is_empty() {
+            return Err(error!(
+                "plugin `{}` depends on other plugins, so it can not be installed globally",
+                plugin.name()
+            ));
+        }
+        self.check_cln_version(&plugin).await?;
+        plugin.scope = PluginScope::Global;
+        let root_path = self.config.root_path.clone();
+        if !dry_run {
+            fs::create_dir_all(format!("{root_path}/{GLOBAL_PLUGINS}/plugins")).await?;
+        }
+        let (plugin, path) = build_plugin(
+            plugin,
+            root_path,
+            GLOBAL_PLUGINS.to_owned(),
+            verbose,
+            dry_run,
+            self.progress.clone(),
+            self.config.startup.trusted_keys.clone(),
+            self.config.startup.proxy.clone(),
+            self.config.startup.offline,
+            self.install_timeout,
+        )
+        .await?;
+        let install = CoffeeInstall::new(&plugin, &path);
+        if dry_run {
+            log::debug!("dry-run: plugin `{}` not installed", plugin.name());
+            return Ok(install);
+        }
+        self.register_plugin(plugin, &path, false).await?;
+        let Some(plugin) = self
+            .config
+            .plugins
+            .iter()
+            .find(|p| p.name() == install.plugin)
+            .cloned()
+        else {
+            return Err(error!("plugin `{}` not registered", install.plugin));
+        };
+        for network in networks {
+            // the plugin already runs on the network of coffee, so
+            // a failure on another network is only reported.
+            if let Err(err) = self.share_plugin(&network, &plugin).await {
+                log::warn!(
+                    "unable to install the plugin `{}` on {network}: {err}",
+                    plugin.name()
+                );
+            }
+        }
+        Ok(install)
+    }
+
+    /// Install the plugin `name` as it is at the `git_ref` of its
+    /// repository. The reference is stored with the plugin, so the
+    /// upgrades of the repository do not rebuild it.
+    ///
+    /// With `force` a plugin already installed is replaced, and with
+    /// `remote` the plugin is looked up only inside that remote.
+    #[allow(clippy::too_many_arguments)]
+    async fn install_pinned(
+        &mut self,
+        name: &str,
+        remote: Option<&str>,
+        git_ref: &str,
+        verbose: bool,
+        try_dynamic: bool,
+        dry_run: bool,
+        force: bool,
+    ) -> Result<CoffeeInstall, CoffeeError> {
+        let installed = self
+            .config
+            .plugins
+            .iter()
+            .find(|installed| installed.name() == name)
+            .cloned();
+        if installed.is_some() && !force {
+            return Err(error!("Plugin with name `{name}` already installed"));
+        }
+        let mut plugin = self.find_plugin_in(name, remote, try_dynamic)?;
+        let Some(repo_name) = self
+            .repos
+            .iter()
+            .filter(|(repo_name, _)| remote.is_none() || remote == Some(repo_name.as_str()))
+            .find(|(_, repo)| repo.get_plugin_by_name(name).is_some())
+            .map(|(repo_name, _)| repo_name.to_owned())
+        else {
+            return Err(error!("no repository contains the plugin `{name}`"));
+        };
+        let repo = self
+            .repos
+            .get_mut(&repo_name)
+            .ok_or_else(|| repository_not_found(&repo_name))?;
+        log::debug!("installing plugin `{name}` pinned to {git_ref}");
+        let commit = repo.checkout(Some(git_ref)).await?;
+        plugin.commit = Some(commit);
+        // the manifest describes the head of the repository,
+        // so the reference is the best guess of the version.
+        plugin.version = Some(git_ref.to_owned());
+        plugin.git_ref = Some(git_ref.to_owned());
+
+        // the plugin installed is replaced only when the new
+        // build succeeds, so a failure leaves it as it was.
+        let result = match installed {
+            Some(_) => {
+                self.reinstall_plugin(plugin, verbose, try_dynamic, dry_run)
+                    .await
+            }
+            None => {
+                self.install_with_dependencies(plugin, verbose, try_dynamic, dry_run)
+                    .await
+            }
+        };
+        // the other plugins are built from the head of the repository,
+        // a failure of the restore must not hide the result of the install.
+        if let Some(repo) = self.repos.get_mut(&repo_name) {
+            if let Err(err) = repo.checkout(None).await {
+                log::error!(
+                    "unable to restore the repository `{repo_name}` after the checkout of {git_ref}: {err}"
+                );
+            }
+        }
+        result
+    }
+
+    /// Install the plugin of the git repository at `url`, the repository
+    /// is added as a remote named after it, unless a remote with the
+    /// same url already exists.
+    ///
+    /// The repository must contain a single plugin, or a plugin
+    /// with the same name of the repository. With `force` a plugin
+    /// already installed is replaced.
+    async fn install_from_url(
+        &mut self,
+        url: &str,
+        verbose: bool,
+        try_dynamic: bool,
+        dry_run: bool,
+        force: bool,
+    ) -> Result<CoffeeInstall, CoffeeError> {
+        let parsed = URL::new(&self.config.root_path, url, "")?;
+        let existing = self
+            .repos
+            .values()
+            .find(|repo| {
+                let remote = repo.url();
+                remote.url_string == parsed.url_string && remote.git_ref == parsed.git_ref
+            })
+            .map(|repo| repo.name());
+        let (name, added) = match existing {
+            Some(name) => (name, false),
+            None => {
+                let name = self.free_remote_name(&parsed.repo_name);
+                self.add_remote(&name, url).await?;
+                (name, true)
+            }
+        };
+        let result = self
+            .install_from_remote(&name, verbose, try_dynamic, dry_run, force)
+            .await;
+        // the remote is only needed by the plugin installed, so
+        // we do not leave it around when nothing was installed.
+        if added && (dry_run || result.is_err()) {
+            if let Err(err) = self.rm_remote(&name).await {
+                log::warn!("unable to remove the remote `{name}`: {err}");
+            }
+        }
+        result
+    }
+
+    /// The name of a new remote derived from `name`, that is
+    /// followed by a number when a remote has the same name.
+    fn free_remote_name(&self, name: &str) -> String {
+        let taken =
+            |name: &str| self.repos.contains_key(name) || self.corrupted_repos.contains_key(name);
+        if !taken(name) {
+            return name.to_owned();
+        }
+        (2..)
+            .map(|index| format!("{name}-{index}"))
+            .find(|name| !taken(name))
+            .unwrap_or_default()
+    }
+
+    /// Install the plugin of the remote repository `name`, see `install_from_url`.
+    async fn install_from_remote(
+        &mut self,
+        name: &str,
+        verbose: bool,
+        try_dynamic: bool,
+        dry_run: bool,
+        force: bool,
+    ) -> Result<CoffeeInstall, CoffeeError> {
+        let repo = self
+            .repos
+            .get(name)
+            .ok_or_else(|| repository_not_found(name))?;
+        let mut plugins = repo.list().await?;
+        let plugin = match plugins.iter().position(|plugin| plugin.name() == name) {
+            Some(index) => plugins.swap_remove(index),
+            None if plugins.len() == 1 => plugins.remove(0),
+            None if plugins.is_empty() => {
+                return Err(error!("no plugins found inside the repository `{name}`"))
+            }
+            None => {
+                let names = plugins
+                    .iter()
+                    .map(|plugin| plugin.name())
+                    .collect::<Vec<_>>();
+                return Err(error!(
+                    "the repository `{name}` contains more plugins, install one of them by name: {}",
+                    names.join(", ")
+                ));
+            }
+        };
+        let installed = self
+            .config
+            .plugins
+            .iter()
+            .any(|p| p.name() == plugin.name());
+        if installed && !force {
+            return Err(error!(
+                "Plugin with name `{}` already installed",
+                plugin.name()
+            ));
+        }
+        if try_dynamic && plugin.important() {
+            return Err(error!(
+                "plugin is important, can't be dynamically installed"
+            ));
+        }
+        if installed {
+            return self
+                .reinstall_plugin(plugin, verbose, try_dynamic, dry_run)
+                .await;
+        }
+        self.install_with_dependencies(plugin, verbose, try_dynamic, dry_run)
+            .await
+    }
+
+    /// Look up the plugin by name inside the repositories, or only
+    /// inside the `remote` one, and make sure that it can be installed.
+    fn resolve_plugin(
+        &self,
+        plugin: &str,
+        remote: Option<&str>,
+        try_dynamic: bool,
+    ) -> Result<Plugin, CoffeeError> {
+        if self.config.plugins.iter().any(|p| p.name().eq(plugin)) {
+            return Err(error!("Plugin with name `{plugin}` already installed"));
+        }
+
+        log::debug!("installing plugin: {plugin}");
+        self.find_plugin_in(plugin, remote, try_dynamic)
+    }
+
+    /// Look up the plugin by name inside the repositories.
+    fn find_plugin(&self, plugin: &str, try_dynamic: bool) -> Result<Plugin, CoffeeError> {
+        self.find_plugin_in(plugin, None, try_dynamic)
+    }
+
+    /// Look up the plugin by name inside the repositories, or
+    /// only inside the `remote` one when it is specified.
+    fn find_plugin_in(
+        &self,
+        plugin: &str,
+        remote: Option<&str>,
+        try_dynamic: bool,
+    ) -> Result<Plugin, CoffeeError> {
+        if let Some(remote) = remote {
+            if !self.repos.contains_key(remote) {
+                return Err(repository_not_found(remote));
+            }
+        }
+        let repos = self
+            .repos
+            .iter()
+            .filter(|(name, _)| remote.is_none() || remote == Some(name.as_str()))
+            .map(|(_, repo)| repo);
+        for repo in repos {
+            if let Some(plugin) = repo.get_plugin_by_name(plugin) {
+                log::trace!("{:?}", plugin);
+                if try_dynamic && plugin.important() {
+                    return Err(error!(
+                        "plugin is important, can't be dynamically installed"
+                    ));
+                }
+                return Ok(plugin);
+            }
+        }
+        Err(
+            error!("plugin `{plugin}` are not present inside the repositories").with_kind(
+                CoffeeErrorKind::PluginNotFound {
+                    name: plugin.to_owned(),
+                },
+            ),
+        )
+    }
+
+    /// Look for the plugins that need to be installed before `plugin`,
+    /// returned in the order in which they need to be installed.
+    ///
+    /// The plugins already installed are skipped.
+    fn resolve_dependencies(&self, plugin: &Plugin) -> Result<Vec<Plugin>, CoffeeError> {
+        let lookup = |name: &str| {
+            self.repos
+                .values()
+                .find_map(|repo| repo.get_plugin_by_name(name))
+        };
+        let mut dependencies = Vec::new();
+        let mut visiting = vec![plugin.name()];
+        visit_dependencies(
+            plugin,
+            &self.config.plugins,
+            &lookup,
+            &mut visiting,
+            &mut dependencies,
+        )?;
+        Ok(dependencies)
+    }
+
+    /// Build the plugin and store it inside the coffee configuration.
+    async fn install_plugin(
+        &mut self,
+        plugin: Plugin,
+        verbose: bool,
+        try_dynamic: bool,
+        dry_run: bool,
+    ) -> Result<CoffeeInstall, CoffeeError> {
+        if let Some((plugin, path)) = self.prepare_build(&plugin, dry_run).await? {
+            let install = CoffeeInstall::new(&plugin, &path);
+            self.register_plugin(plugin, &path, try_dynamic).await?;
+            return Ok(install);
+        }
+        let (plugin, path) = build_plugin(
+            plugin,
+            self.config.root_path.clone(),
+            self.config.network.clone(),
+            verbose,
+            dry_run,
+            self.progress.clone(),
+            self.config.startup.trusted_keys.clone(),
+            self.config.startup.proxy.clone(),
+            self.config.startup.offline,
+            self.install_timeout,
+        )
+        .await?;
+        let install = CoffeeInstall::new(&plugin, &path);
+        if dry_run {
+            // nothing is stored, so the coffee state and the cln
+            // configuration are left untouched.
+            log::debug!("dry-run: plugin `{}` not installed", plugin.name());
+            return Ok(install);
+        }
+        self.register_plugin(plugin, &path, try_dynamic).await?;
+        Ok(install)
+    }
+
+    /// Run the checks that come before the build of a plugin, shared
+    /// by all the installs, and return the detached build of the plugin
+    /// when it can be registered without building it again.
+    async fn prepare_build(
+        &self,
+        plugin: &Plugin,
+        dry_run: bool,
+    ) -> Result<Option<(Plugin, String)>, CoffeeError> {
+        self.check_cln_version(plugin).await?;
+        if dry_run {
+            return Ok(None);
+        }
+        if self.verify_start {
+            self.check_verifiable(plugin)?;
+        }
+        let detached = self.detached_build(plugin).await;
+        if let Some((plugin, _)) = &detached {
+            log::info!("reusing the detached build of `{}`", plugin.name());
+        }
+        Ok(detached)
+    }
+
+    /// Make sure that the start of the plugin can be verified, so
+    /// `--verify` never installs a plugin without checking it.
+    fn check_verifiable(&self, plugin: &Plugin) -> Result<(), CoffeeError> {
+        let name = plugin.name();
+        if self.rpc.is_none() {
+            return Err(error!(
+                "unable to verify the start of `{name}` without the rpc socket of core lightning, use `--rpc-path` or link coffee to the node"
+            ));
+        }
+        if plugin.important() {
+            return Err(error!(
+                "`{name}` is an important plugin, core lightning loads it only at startup so its start can not be verified"
+            ));
+        }
+        Ok(())
+    }
+
+    /// The version of the core lightning node, asked with `getinfo`
+    /// when the rpc is available, or to the `lightningd` binary.
+    async fn cln_version(&self) -> Option<String> {
+        if self.rpc.is_some() {
+            match self
+                .cln::<serde_json::Value, serde_json::Value>("getinfo", json!({}))
+                .await
+            {
+                Ok(info) => {
+                    if let Some(version) = info.get("version").and_then(|v| v.as_str()) {
+                        return Some(version.to_owned());
+                    }
+                }
+                Err(err) => log::debug!("unable to ask the version to core lightning: {err}"),
+            }
+        }
+        lightningd_version().await
+    }
+
+    /// Make sure that the node runs the minimum core lightning
+    /// version required by the plugin, unless the incompatible
+    /// plugins are allowed, that only print a warning.
+    async fn check_cln_version(&self, plugin: &Plugin) -> Result<(), CoffeeError> {
+        let Some(required) = plugin.min_cln_version() else {
+            return Ok(());
+        };
+        let name = plugin.name();
+        let Some(running) = self.cln_version().await else {
+            log::warn!(
+                "unable to know the core lightning version, plugin `{name}` needs at least {required}"
+            );
+            return Ok(());
+        };
+        match cln_version_satisfies(&required, &running) {
+            Some(true) => Ok(()),
+            Some(false) if self.config.startup.allow_incompatible => {
+                log::warn!(
+                    "plugin `{name}` needs core lightning {required}, but the node runs {running}"
+                );
+                Ok(())
+            }
+            Some(false) => Err(error!(
+                "plugin `{name}` needs core lightning {required}, but the node runs {running}, use `--allow-incompatible` to install it anyway"
+            )),
+            None => {
+                log::warn!(
+                    "unable to compare the core lightning version {running} with {required} required by `{name}`"
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Build again a plugin that is already installed from the `plugin`
+    /// resolved, replacing its executable and the stored entry only when
+    /// the build succeeds. The cln configuration keeps pointing to the
+    /// same path.
+    async fn reinstall_plugin(
+        &mut self,
+        mut plugin: Plugin,
+        verbose: bool,
+        try_dynamic: bool,
+        dry_run: bool,
+    ) -> Result<CoffeeInstall, CoffeeError> {
+        let name = plugin.name();
+        let Some(index) = self.config.plugins.iter().position(|p| p.name() == name) else {
+            return Err(error!("plugin `{name}` is not installed").with_kind(
+                CoffeeErrorKind::PluginNotFound {
+                    name: name.to_owned(),
+                },
+            ));
+        };
+        let installed = self.config.plugins[index].clone();
+        plugin.scope = installed.scope;
+        if dry_run {
+            return self
+                .install_plugin(plugin, verbose, try_dynamic, dry_run)
+                .await;
+        }
+        self.check_cln_version(&plugin).await?;
+        log::debug!("reinstalling plugin: {name}");
+        // keep the old build around until the new one is ready,
+        // so we can restore it if the build fails.
+        let cloned_repositories_path = format!("{}/repositories", self.config.root_path);
+        let backup_path = format!("{}.old", installed.root_path);
+        let has_backup = !installed.root_path.contains(&cloned_repositories_path);
+        if has_backup {
+            fs::rename(&installed.root_path, &backup_path).await?;
+        }
+        let network = match installed.scope {
+            PluginScope::Network => self.config.network.clone(),
+            PluginScope::Global => GLOBAL_PLUGINS.to_owned(),
+        };
+        let build = build_plugin(
+            plugin,
+            self.config.root_path.clone(),
+            network,
+            verbose,
+            dry_run,
+            self.progress.clone(),
+            self.config.startup.trusted_keys.clone(),
+            self.config.startup.proxy.clone(),
+            self.config.startup.offline,
+            self.install_timeout,
+        )
+        .await;
+        let (mut plugin, path) = match build {
+            Ok(build) => build,
+            Err(err) => {
+                if has_backup {
+                    let _ = fs::remove_dir_all(&installed.root_path).await;
+                    fs::rename(&backup_path, &installed.root_path).await?;
+                }
+                return Err(err);
+            }
+        };
+        if has_backup {
+            fs::remove_dir_all(&backup_path).await?;
+        }
+        plugin.enabled = installed.enabled;
+        self.move_exec_path(&installed, &path).await?;
+        if installed.cln_options() != plugin.cln_options() {
+            self.rm_cln_options(&installed);
+            self.add_cln_options(&plugin)?;
+            self.update_conf().await?;
+        }
+        let install = CoffeeInstall::new(&plugin, &path);
+        self.config.plugins[index] = plugin;
+        self.flush().await?;
+        if try_dynamic && installed.enabled != Some(false) {
+            if let Err(err) = self.stop_plugin(&installed.exec_path).await {
+                log::warn!("{err}");
+            }
+            self.start_plugin(&path).await?;
+        }
+        emit(self.progress.as_ref(), ProgressEvent::Done(name.to_owned()));
+        Ok(install)
+    }
+
+    /// Remove the `disable-plugin` entries of the plugin at `exec_path`,
+    /// core lightning accepts both its path and its file name.
+    fn rm_disable_entries(&mut self, exec_path: &str) {
+        let file_name = Path::new(exec_path)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(exec_path);
+        let entries = self
+            .coffee_cln_config
+            .fields
+            .get("disable-plugin")
+            .cloned()
+            .unwrap_or_default();
+        for entry in entries
+            .iter()
+            .filter(|entry| entry.as_str() == exec_path || entry.as_str() == file_name)
+        {
+            if let Err(err) = self
+                .coffee_cln_config
+                .rm_conf("disable-plugin", Some(entry))
+            {
+                log::debug!("`disable-plugin={entry}` not removed: {}", err.cause);
+            }
+        }
+    }
+
+    /// Point the cln configuration of the `installed` plugin to the
+    /// executable at `path`, when a new build moved it.
+    async fn move_exec_path(&mut self, installed: &Plugin, path: &str) -> Result<(), CoffeeError> {
+        if path == installed.exec_path {
+            return Ok(());
+        }
+        // the manifest changed where the executable is,
+        // so the cln configuration needs to follow it.
+        let plugin_conf_key = if installed.important() {
+            "important-plugin"
+        } else {
+            "plugin"
+        };
+        let mut keys = vec![plugin_conf_key];
+        if installed.enabled == Some(false) {
+            keys.push("disable-plugin");
+        }
+        for key in keys {
+            if self
+                .coffee_cln_config
+                .rm_conf(key, Some(&installed.exec_path))
+                .is_ok()
+            {
+                self.coffee_cln_config
+                    .add_conf(key, &path.to_owned())
+                    .map_err(|err| error!("{}", err.cause))?;
+            }
+        }
+        self.update_conf().await
+    }
+
+    /// Run the build of the installed plugin `name` again from its
+    /// directory. The sources are not touched, so a pinned plugin
+    /// stays at its reference and running it twice is harmless.
+    async fn repair_plugin(
+        &mut self,
+        name: &str,
+        verbose: bool,
+    ) -> Result<CoffeeInstall, CoffeeError> {
+        let Some(index) = self.config.plugins.iter().position(|p| p.name() == name) else {
+            return Err(plugin_not_installed(name));
+        };
+        let installed = self.config.plugins[index].clone();
+        let network = match installed.scope {
+            PluginScope::Network => self.config.network.clone(),
+            PluginScope::Global => GLOBAL_PLUGINS.to_owned(),
+        };
+        let Some(relative_path) = installed.exec_path.strip_prefix(&installed.root_path) else {
+            return Err(error!("exec path not found"));
+        };
+        // the installed directory is moved aside and used as the sources
+        // of the build, without its stale build that is kept apart, so
+        // both are restored when the new build fails.
+        let backup_path = format!("{}.repair", installed.root_path);
+        let artifacts_path = format!("{}.repair-artifacts", installed.root_path);
+        for path in [&backup_path, &artifacts_path] {
+            if fs::try_exists(path).await? {
+                // left by a repair that was interrupted
+                fs::remove_dir_all(path).await?;
+            }
+        }
+        fs::rename(&installed.root_path, &backup_path).await?;
+        fs::create_dir_all(&artifacts_path).await?;
+        let mut moved = vec![];
+        for artifact in installed.lang.build_artifacts(&backup_path) {
+            if !fs::try_exists(&artifact).await? {
+                continue;
+            }
+            let Some(dir) = Path::new(&artifact).file_name() else {
+                continue;
+            };
+            let aside = format!("{artifacts_path}/{}", dir.to_string_lossy());
+            fs::rename(&artifact, &aside).await?;
+            moved.push((artifact, aside));
+        }
+        let mut plugin = installed.clone();
+        plugin.exec_path = format!("{backup_path}{relative_path}");
+        plugin.root_path = backup_path.clone();
+        let build = build_plugin(
+            plugin,
+            self.config.root_path.clone(),
+            network,
+            verbose,
+            false,
+            self.progress.clone(),
+            self.config.startup.trusted_keys.clone(),
+            self.config.startup.proxy.clone(),
+            self.config.startup.offline,
+            self.install_timeout,
+        )
+        .await;
+        let (mut plugin, path) = match build {
+            Ok(build) => build,
+            Err(err) => {
+                // every step of the restore is tried, so a failure
+                // does not leave the rest of the plugin behind.
+                let mut failures = vec![];
+                if fs::try_exists(&installed.root_path).await.unwrap_or(true) {
+                    if let Err(err) = fs::remove_dir_all(&installed.root_path).await {
+                        failures.push(format!("{}: {err}", installed.root_path));
+                    }
+                }
+                for (artifact, aside) in moved {
+                    if let Err(err) = fs::rename(&aside, &artifact).await {
+                        failures.push(format!("{artifact}: {err}"));
+                    }
+                }
+                if let Err(err) = fs::rename(&backup_path, &installed.root_path).await {
+                    failures.push(format!("{}: {err}", installed.root_path));
+                }
+                if failures.is_empty() {
+                    let _ = fs::remove_dir_all(&artifacts_path).await;
+                    return Err(err);
+                }
+                log::warn!(
+                    "unable to restore the build of `{name}`: {}",
+                    failures.join(", ")
+                );
+                return Err(error!(
+                    "{}, and the previous build was not restored: {}",
+                    err.msg(),
+                    failures.join(", ")
+                )
+                .with_kind(CoffeeErrorKind::BuildFailed {
+                    plugin: name.to_owned(),
+                }));
+            }
+        };
+        fs::remove_dir_all(&backup_path).await?;
+        fs::remove_dir_all(&artifacts_path).await?;
+        plugin.enabled = installed.enabled;
+        self.move_exec_path(&installed, &path).await?;
+        let install = CoffeeInstall::new(&plugin, &path);
+        self.config.plugins[index] = plugin;
+        self.flush().await?;
+        emit(self.progress.as_ref(), ProgressEvent::Done(name.to_owned()));
+        Ok(install)
+    }
+
+    /// Remove the plugin from the cln configuration and from the
+    /// plugins tracked by coffee, without touching its files.
+    async fn untrack_plugin(
+        &mut self,
+        plugin: &str,
+        keep_options: bool,
+    ) -> Result<(Plugin, BTreeMap<String, String>), CoffeeError> {
+        let plugins = &mut self.config.plugins;
+        let Some(index) = plugins.iter().position(|x| x.name() == plugin) else {
+            return Err(
+                error!("plugin `{plugin}` is already not installed").with_kind(
+                    CoffeeErrorKind::PluginNotFound {
+                        name: plugin.to_owned(),
+                    },
+                ),
+            );
+        };
+        let plugin = plugins.remove(index);
+        let exec_path = plugin.exec_path.clone();
+        log::debug!("runnable plugin path: {exec_path}");
+        log::debug!("coffee cln config: {}", self.coffee_cln_config);
+        let plugin_conf_key = if plugin.important() {
+            "important-plugin"
+        } else {
+            "plugin"
+        };
+        let remove_config = self
+            .coffee_cln_config
+            .rm_conf(plugin_conf_key, Some(&exec_path.to_owned()));
+        match remove_config {
+            // the plugin is stopped also inside the running core lightning,
+            // unless it was disabled by the user.
+            Ok(_) if self.config.startup.rpc_path.is_some() && plugin.enabled != Some(false) => {
+                if let Err(err) = self.stop_plugin(&exec_path).await {
+                    log::warn!("unable to stop the plugin `{}`: {err}", plugin.name());
+                }
+            }
+            Ok(_) => {}
+            // if this is true, we are probably a dynamic plugin:
+            Err(err) if err.cause.contains("field with `plugin` not present") => {
+                if let Err(e) = self.stop_plugin(&exec_path).await {
+                    log::warn!("{}", e);
                 };
+            }
+            Err(err) => return Err(error!("{}", &err.cause)),
+        }
+        // a disabled plugin has also the `disable-plugin` entry
+        // that is not meaningful anymore.
+        self.rm_disable_entries(&exec_path);
+        let reverted_options = if keep_options {
+            BTreeMap::new()
+        } else {
+            self.rm_cln_options(&plugin)
+        };
+        self.flush().await?;
+        self.update_conf().await?;
+        Ok((plugin, reverted_options))
+    }
+
+    /// Look for the build left on disk by `detach`, that can be
+    /// reused only when it was built from the same commit of `plugin`.
+    async fn detached_build(&self, plugin: &Plugin) -> Option<(Plugin, String)> {
+        let root_path = format!(
+            "{}/{}/plugins/{}",
+            self.config.root_path,
+            self.config.network,
+            plugin.name()
+        );
+        let note = format!("{root_path}/{DETACHED_BUILD}");
+        let content = fs::read_to_string(&note).await.ok()?;
+        let mut detached = serde_json::from_str::<Plugin>(&content).ok()?;
+        if detached.commit != plugin.commit
+            || !fs::try_exists(&detached.exec_path).await.unwrap_or(false)
+        {
+            return None;
+        }
+        // the build can be older than the verification of the signatures
+        let trusted_keys = &self.config.startup.trusted_keys;
+        if !trusted_keys.is_empty()
+            && !detached
+                .signed_by
+                .as_ref()
+                .is_some_and(|key| trusted_keys.contains(key))
+        {
+            return None;
+        }
+        // the executable could be changed by hand in the meantime
+        if let Some(checksum) = &detached.checksum {
+            if sha256sum(&detached.exec_path).await.ok().as_ref() != Some(checksum) {
+                return None;
+            }
+        }
+        let _ = fs::remove_file(&note).await;
+        detached.enabled = None;
+        let path = detached.exec_path.clone();
+        Some((detached, path))
+    }
+
+    /// Add the options declared in the manifest of the plugin to the
+    /// cln configuration managed by coffee.
+    ///
+    /// An option already present in the cln configuration is kept
+    /// as it is, so the value chosen by the user is not overridden.
+    fn add_cln_options(&mut self, plugin: &Plugin) -> Result<(), CoffeeError> {
+        for (key, value) in plugin.cln_options() {
+            let conflict = self.coffee_cln_config.fields.contains_key(&key)
+                || self
+                    .cln_config
+                    .as_ref()
+                    .is_some_and(|conf| conf.fields.contains_key(&key));
+            if conflict {
+                log::warn!(
+                    "option `{key}` of plugin `{}` is already inside the cln configuration, skipping `{key}={value}`",
+                    plugin.name()
+                );
+                continue;
+            }
+            self.coffee_cln_config
+                .add_conf(&key, &value)
+                .map_err(|err| error!("{}", err.cause))?;
+        }
+        Ok(())
+    }
+
+    /// Remove the options declared in the manifest of the plugin
+    /// from the cln configuration managed by coffee, and return
+    /// the ones that were removed.
+    fn rm_cln_options(&mut self, plugin: &Plugin) -> BTreeMap<String, String> {
+        let mut removed = BTreeMap::new();
+        for (key, value) in plugin.cln_options() {
+            match self.coffee_cln_config.rm_conf(&key, Some(&value)) {
+                Ok(_) => {
+                    removed.insert(key, value);
+                }
+                Err(err) => log::debug!("option `{key}={value}` not removed: {}", err.cause),
+            }
+        }
+        removed
+    }
+
+    /// Remove the plugin from cln and delete the files created by its
+    /// build, see `PluginManager::remove` and `PluginManager::purge`.
+    async fn remove_plugin(
+        &mut self,
+        plugin: &str,
+        purge: bool,
+        keep_options: bool,
+    ) -> Result<CoffeeRemove, CoffeeError> {
+        let (plugin, reverted_options) = self.untrack_plugin(plugin, keep_options).await?;
+        if plugin.scope == PluginScope::Global {
+            // the files are shared, so the other networks can not keep the plugin
+            for network in self.other_networks().await {
+                if let Err(err) = self.unshare_plugin(&network, &plugin.name()).await {
+                    log::warn!(
+                        "unable to remove the plugin `{}` from {network}: {err}",
+                        plugin.name()
+                    );
+                }
+            }
+        }
+        let root_path = plugin.root_path.clone();
+        if self.used_by_other_target(&root_path) {
+            // another node runs the same build, so we keep the files.
+            log::info!(
+                "plugin `{}` is installed on another target, its files are kept",
+                plugin.name()
+            );
+            return Ok(CoffeeRemove {
+                plugin,
+                freed_bytes: 0,
+                removed_paths: vec![],
+                reverted_options,
+            });
+        }
+        let cloned_repositories_path = format!("{}/repositories", self.config.root_path,);
+        let mut paths = plugin.venv.iter().cloned().collect::<Vec<_>>();
+        // make sure that we are not deleting the cloned repositories,
+        // where we can delete only what the build created.
+        if !root_path.contains(&cloned_repositories_path) {
+            paths.push(root_path);
+        } else {
+            paths.extend(plugin.lang.build_artifacts(&root_path));
+            if purge {
+                // the launcher is written by the build inside the repository
+                let launcher = format!("{root_path}/{}-launcher.sh", plugin.name());
+                if Path::new(&launcher).exists() {
+                    paths.push(launcher);
+                }
+            }
+        }
+        let log_path = build_log_path(&self.config.root_path, &plugin.name());
+        if purge && Path::new(&log_path).exists() {
+            paths.push(log_path);
+        }
+        // the downloads in the shared cache are reused by the other
+        // builds, so they go away only with the last plugin.
+        let installed = !self.config.plugins.is_empty()
+            || self
+                .config
+                .targets
+                .values()
+                .any(|target| !target.plugins.is_empty());
+        let cache_dir = cache_path(&self.config.root_path);
+        if !installed && Path::new(&cache_dir).exists() {
+            paths.push(cache_dir);
+        }
+        let mut freed_bytes = 0;
+        let mut removed_paths = vec![];
+        for path in paths {
+            if !fs::try_exists(&path).await? {
+                // e.g. the plugin directory was deleted by hand.
+                log::warn!("`{path}` of plugin `{}` is already gone", plugin.name());
+                continue;
+            }
+            freed_bytes += disk_usage_of(&path).await;
+            if fs::metadata(&path).await?.is_dir() {
+                fs::remove_dir_all(&path).await?;
+            } else {
+                fs::remove_file(&path).await?;
+            }
+            removed_paths.push(path);
+        }
+        log::info!(
+            "removed plugin `{}`, {freed_bytes} bytes freed",
+            plugin.name()
+        );
+        Ok(CoffeeRemove {
+            plugin,
+            freed_bytes,
+            removed_paths,
+            reverted_options,
+        })
+    }
+
+    /// Store the plugin built with `build_plugin` inside the coffee
+    /// configuration and enable it inside core lightning.
+    async fn register_plugin(
+        &mut self,
+        mut plugin: Plugin,
+        path: &str,
+        try_dynamic: bool,
+    ) -> Result<(), CoffeeError> {
+        let name = plugin.name();
+        plugin.installed_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()
+            .map(|duration| duration.as_secs());
+        if !try_dynamic {
+            let plugin_conf_key = if plugin.important() {
+                "important-plugin"
+            } else {
+                "plugin"
+            };
+            // mark the plugin enabled
+            plugin.enabled = Some(true);
+            log::debug!("path coffee conf: {}", self.coffee_cln_config.path);
+            self.coffee_cln_config
+                .add_conf(plugin_conf_key, &path.to_owned())
+                .map_err(|err| error!("{}", err.cause))?;
+            if let Err(err) = self.add_cln_options(&plugin) {
+                self.rm_cln_options(&plugin);
+                let _ = self.coffee_cln_config.rm_conf(plugin_conf_key, Some(path));
+                return Err(err);
+            }
+            self.config.plugins.push(plugin);
+            log::debug!("coffee conf updated: {}", self.coffee_cln_config);
+            // the storage and the cln configuration are committed together,
+            // so a failure in one of them must not leave the other with the plugin.
+            let result = match self.flush().await {
+                Ok(_) => self.update_conf().await,
+                Err(err) => Err(err),
+            };
+            if let Err(err) = result {
+                if let Some(plugin) = self.config.plugins.pop() {
+                    self.rm_cln_options(&plugin);
+                    remove_build(&plugin).await;
+                }
+                let _ = self.coffee_cln_config.rm_conf(plugin_conf_key, Some(path));
+                self.rollback(true).await;
+                return Err(err);
+            }
+            // `prepare_build` already refused the plugins that can not be verified
+            if self.verify_start {
+                if let Err(err) = self.start_verified(&name, path).await {
+                    if let Some(plugin) = self.config.plugins.pop() {
+                        self.rm_cln_options(&plugin);
+                        remove_build(&plugin).await;
+                    }
+                    let _ = self.coffee_cln_config.rm_conf(plugin_conf_key, Some(path));
+                    self.rollback(true).await;
+                    return Err(err);
+                }
+            } else {
+                self.hot_start(&name, path, plugin_conf_key).await;
+            }
+        } else {
+            self.config.plugins.push(plugin);
+            let result = match self.flush().await {
+                Ok(_) => self.start_plugin(path).await,
+                Err(err) => Err(err),
+            };
+            if let Err(err) = result {
+                if let Some(plugin) = self.config.plugins.pop() {
+                    remove_build(&plugin).await;
+                }
+                self.rollback(false).await;
+                return Err(err);
+            }
+        }
+        emit(self.progress.as_ref(), ProgressEvent::Done(name));
+        Ok(())
+    }
+
+    /// The networks, other than the one of coffee, that have a stored
+    /// state, where the plugins with the global scope are installed too.
+    async fn other_networks(&self) -> Vec<String> {
+        let mut networks = vec![];
+        for network in CLN_NETWORKS {
+            if network == self.config.network {
+                continue;
+            }
+            if self
+                .storage
+                .load::<CoffeeStorageInfo>(network)
+                .await
+                .is_ok()
+            {
+                networks.push(network.to_owned());
+            }
+        }
+        networks
+    }
+
+    /// Load the cln configuration managed by coffee at `path`, the
+    /// file can be missing when the network was never linked.
+    async fn network_cln_conf(path: &str) -> Result<CLNConf, CoffeeError> {
+        let mut cln_config = CLNConf::new(path.to_owned(), true);
+        if fs::try_exists(path).await? {
+            cln_config.parse().map_err(|err| error!("{}", err.cause))?;
+        }
+        Ok(cln_config)
+    }
+
+    /// Add the global `plugin`, installed on the network of coffee,
+    /// to the stored state and to the cln configuration of `network`.
+    async fn share_plugin(&self, network: &str, plugin: &Plugin) -> Result<(), CoffeeError> {
+        let mut store = self.storage.load::<CoffeeStorageInfo>(network).await?;
+        let mut cln_config = Self::network_cln_conf(&store.config.config_path).await?;
+        let plugin_conf_key = if plugin.important() {
+            "important-plugin"
+        } else {
+            "plugin"
+        };
+        cln_config
+            .add_conf(plugin_conf_key, &plugin.exec_path)
+            .map_err(|err| error!("{}", err.cause))?;
+        for (key, value) in plugin.cln_options() {
+            if cln_config.fields.contains_key(&key) {
+                log::warn!("option `{key}` is already inside the cln configuration of {network}");
+                continue;
+            }
+            cln_config
+                .add_conf(&key, &value)
+                .map_err(|err| error!("{}", err.cause))?;
+        }
+        cln_config
+            .flush()
+            .map_err(|err| config_write_failed(&cln_config.path, err))?;
+        store.plugins.push(plugin.clone());
+        self.storage.store(network, &store).await?;
+        log::info!("plugin `{}` installed also on {network}", plugin.name());
+        Ok(())
+    }
+
+    /// Remove the global plugin `name` from the stored state
+    /// and from the cln configuration of `network`.
+    async fn unshare_plugin(&self, network: &str, name: &str) -> Result<(), CoffeeError> {
+        let mut store = self.storage.load::<CoffeeStorageInfo>(network).await?;
+        let Some(index) = store.plugins.iter().position(|p| p.name() == name) else {
+            return Ok(());
+        };
+        let plugin = store.plugins.remove(index);
+        let mut cln_config = Self::network_cln_conf(&store.config.config_path).await?;
+        let plugin_conf_key = if plugin.important() {
+            "important-plugin"
+        } else {
+            "plugin"
+        };
+        let mut entries = vec![(plugin_conf_key.to_owned(), plugin.exec_path.clone())];
+        if plugin.enabled == Some(false) {
+            entries.push(("disable-plugin".to_owned(), plugin.exec_path.clone()));
+        }
+        entries.extend(plugin.cln_options());
+        for (key, value) in entries {
+            if let Err(err) = cln_config.rm_conf(&key, Some(&value)) {
+                log::debug!("`{key}={value}` not removed from {network}: {}", err.cause);
+            }
+        }
+        cln_config
+            .flush()
+            .map_err(|err| config_write_failed(&cln_config.path, err))?;
+        self.storage.store(network, &store).await?;
+        log::info!("plugin `{name}` removed also from {network}");
+        Ok(())
+    }
+
+    /// Start the plugin at `path` inside the running core lightning,
+    /// when the rpc socket is configured. The plugin is already inside
+    /// the configuration, so a failure is only reported as a warning.
+    async fn hot_start(&self, name: &str, path: &str, plugin_conf_key: &str) {
+        if self.config.startup.rpc_path.is_none() {
+            return;
+        }
+        // core lightning loads the important plugins only at startup
+        if plugin_conf_key == "important-plugin" {
+            log::warn!("plugin `{name}` is important, restart core lightning to load it");
+            return;
+        }
+        if let Err(err) = self.start_plugin(path).await {
+            log::warn!(
+                "unable to start the plugin `{name}`, restart core lightning to load it: {err}"
+            );
+        }
+    }
+
+    /// Start the plugin at `path` inside the running core lightning, and
+    /// wait until it is listed as active. The error contains the reason
+    /// of core lightning when the plugin is refused.
+    async fn start_verified(&self, name: &str, path: &str) -> Result<(), CoffeeError> {
+        let start_failed = |msg: String| {
+            error!("{msg}").with_kind(CoffeeErrorKind::StartFailed {
+                plugin: name.to_owned(),
+            })
+        };
+        self.start_plugin(path).await.map_err(|err| {
+            start_failed(format!(
+                "core lightning refused to start the plugin `{name}`: {}",
+                err.msg()
+            ))
+        })?;
+        for _ in 0..VERIFY_START_ATTEMPTS {
+            if self.is_running(path).await? {
+                return Ok(());
+            }
+            tokio::time::sleep(VERIFY_START_INTERVAL).await;
+        }
+        if let Err(err) = self.stop_plugin(path).await {
+            log::debug!("unable to stop the plugin `{name}`: {err}");
+        }
+        Err(start_failed(format!(
+            "the plugin `{name}` is not running inside core lightning after the start"
+        )))
+    }
+
+    /// Check if the plugin at `exec_path` is active inside core lightning.
+    async fn is_running(&self, exec_path: &str) -> Result<bool, CoffeeError> {
+        let mut payload = json_utils::init_payload();
+        json_utils::add_str(&mut payload, "subcommand", "list");
+        let list = self
+            .cln::<serde_json::Value, serde_json::Value>("plugin", payload)
+            .await?;
+        Ok(list["plugins"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .any(|running| {
+                running["name"].as_str() == Some(exec_path)
+                    && running["active"].as_bool().unwrap_or(false)
+            }))
+    }
+
+    /// Check if the plugin installed is running inside core lightning,
+    /// a failure of the rpc call means that a restart is needed.
+    async fn reload_status(&self, plugin: &str) -> ReloadStatus {
+        let Some(exec_path) = self
+            .config
+            .plugins
+            .iter()
+            .find(|installed| installed.name() == plugin)
+            .map(|installed| installed.exec_path.clone())
+        else {
+            return ReloadStatus::RestartNeeded(plugin.to_owned());
+        };
+        let running = match self.is_running(&exec_path).await {
+            Ok(running) => running,
+            Err(err) => {
+                log::warn!("unable to list the plugins running inside core lightning: {err}");
+                false
+            }
+        };
+        if running {
+            ReloadStatus::Reloaded(plugin.to_owned())
+        } else {
+            ReloadStatus::RestartNeeded(plugin.to_owned())
+        }
+    }
 
-                match old_exec_path.strip_prefix(&old_root_path) {
-                    Some(relative_path) => {
-                        let new_exec_path = format!("{}{}", new_root_path, relative_path);
-                        plugin.root_path = new_root_path;
-                        plugin.exec_path = new_exec_path;
-
-                        log::debug!("plugin: {:?}", plugin);
-                        let path = plugin.configure(verbose).await?;
-                        log::debug!("runnable plugin path {path}");
-                        if !try_dynamic {
-                            // mark the plugin enabled
-                            plugin.enabled = Some(true);
-                            self.config.plugins.push(plugin);
-                            log::debug!("path coffee conf: {}", self.coffee_cln_config.path);
-                            self.coffee_cln_config
-                                .add_conf(plugin_conf_key, &path.to_owned())
-                                .map_err(|err| error!("{}", err.cause))?;
-                            log::debug!("coffee conf updated: {}", self.coffee_cln_config);
-                            self.flush().await?;
-                            self.update_conf().await?;
-                        } else {
-                            self.config.plugins.push(plugin);
-                            self.flush().await?;
-                            self.start_plugin(&path).await?;
-                        }
-                        return Ok(());
+    /// Store again the state in memory after that a staged
+    /// change was reverted, the failure here is only logged
+    /// because we are already returning the original error.
+    async fn rollback(&self, cln_conf: bool) {
+        if let Err(err) = self.flush().await {
+            log::error!("error while rolling back the storage: {err}");
+        }
+        if !cln_conf {
+            return;
+        }
+        if let Err(err) = self.update_conf().await {
+            log::error!("error while rolling back the cln configuration: {err}");
+        }
+    }
+
+    /// Build the plugin inside the trial directory, without touching the
+    /// coffee state and the cln configuration, so it can be started
+    /// inside the node before installing it.
+    pub async fn build_trial(
+        &mut self,
+        plugin: &str,
+        verbose: bool,
+    ) -> Result<CoffeeInstall, CoffeeError> {
+        let plugin = self.find_plugin(plugin, false)?;
+        self.check_cln_version(&plugin).await?;
+        let root_path = self.config.root_path.clone();
+        fs::create_dir_all(format!("{root_path}/{TRIAL_PLUGINS}/plugins")).await?;
+        let (plugin, path) = build_plugin(
+            plugin,
+            root_path,
+            TRIAL_PLUGINS.to_owned(),
+            verbose,
+            false,
+            self.progress.clone(),
+            self.config.startup.trusted_keys.clone(),
+            self.config.startup.proxy.clone(),
+            self.config.startup.offline,
+            self.install_timeout,
+        )
+        .await?;
+        Ok(CoffeeInstall::new(&plugin, &path))
+    }
+
+    /// Remove the builds left by `build_trial`.
+    pub async fn clean_trial(&self) -> Result<(), CoffeeError> {
+        let path = format!("{}/{TRIAL_PLUGINS}", self.config.root_path);
+        if fs::try_exists(&path).await? {
+            fs::remove_dir_all(path).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn flush(&self) -> Result<(), CoffeeError> {
+        let _lock = self.lock().await?;
+        let store_info = self.storage_info()?;
+        self.storage
+            .store(&self.config.network, &store_info)
+            .await?;
+        let mut repositories = self.corrupted_repos.clone();
+        for (name, repo) in store_info.repositories {
+            let repo = serde_json::to_value(repo).map_err(|err| error!("{err}"))?;
+            repositories.insert(name, repo);
+        }
+        self.storage.store("repositories", &repositories).await?;
+        Ok(())
+    }
+
+    /// The disk space used by each installed plugin, only
+    /// when the disk usage is enabled.
+    async fn plugins_disk_usage(&self) -> HashMap<String, u64> {
+        let mut usage = HashMap::new();
+        if !self.disk_usage {
+            return usage;
+        }
+        for plugin in &self.config.plugins {
+            usage.insert(plugin.name(), disk_usage_of(&plugin.root_path).await);
+        }
+        usage
+    }
+
+    /// The installed plugins in the order of `list_order`, the
+    /// sort is stable so the output is the same across the runs.
+    async fn sorted_plugins(&self) -> Result<Vec<Plugin>, CoffeeError> {
+        let mut plugins = self.config.plugins.clone();
+        plugins.sort_by_key(|plugin| plugin.name());
+        match self.list_order {
+            ListOrder::Name => {}
+            ListOrder::InstallDate => plugins.sort_by_key(|plugin| plugin.installed_at),
+            ListOrder::Repository => {
+                // a plugin with the same name in two repositories
+                // belongs to the first one in alphabetical order.
+                let mut names = self.repos.keys().cloned().collect::<Vec<_>>();
+                names.sort();
+                let mut repositories = HashMap::new();
+                for name in names {
+                    for plugin in self.repos[&name].list().await? {
+                        repositories
+                            .entry(plugin.name())
+                            .or_insert_with(|| name.clone());
                     }
-                    None => return Err(error!("exec path not found")),
-                };
+                }
+                // the plugins without a repository go at the end
+                plugins.sort_by_key(|plugin| {
+                    let repository = repositories.get(&plugin.name()).cloned();
+                    (repository.is_none(), repository)
+                });
             }
         }
-        Err(error!(
-            "plugin `{plugin}` are not present inside the repositories"
-        ))
+        Ok(plugins)
     }
 
-    async fn remove(&mut self, plugin: &str) -> Result<CoffeeRemove, CoffeeError> {
-        log::debug!("removing plugin: {plugin}");
-        let plugins = &mut self.config.plugins;
-        if let Some(index) = plugins.iter().position(|x| x.name() == plugin) {
-            let plugin = plugins[index].clone();
-            let exec_path = plugin.exec_path.clone();
-            let root_path = plugin.root_path.clone();
-            let cloned_repositories_path = format!("{}/repositories", self.config.root_path,);
-            // make sure that we are not deleting the cloned repositories
-            if !root_path.contains(&cloned_repositories_path) {
-                fs::remove_dir_all(root_path).await?;
-            }
-            log::debug!("runnable plugin path: {exec_path}");
-            plugins.remove(index);
-            log::debug!("coffee cln config: {}", self.coffee_cln_config);
-            let plugin_conf_key = if plugin.important() {
-                "important-plugin"
-            } else {
-                "plugin"
+    /// Store only the repository `name`, instead of the whole
+    /// state like `flush`, so adding a remote does not rewrite
+    /// all the others.
+    async fn store_repository(&self, name: &str) -> Result<(), CoffeeError> {
+        let _lock = self.lock().await?;
+        match self.repos.get(name) {
+            Some(repo) => self.storage.upsert_repository(&repo.to_info()).await,
+            None => self.storage.remove_repository(name).await,
+        }
+    }
+
+    pub async fn update_conf(&self) -> Result<(), CoffeeError> {
+        let _lock = self.lock().await?;
+        self.coffee_cln_config
+            .flush()
+            .map_err(|err| config_write_failed(&self.coffee_cln_config.path, err))?;
+        log::debug!("stored all the cln info in {}", self.coffee_cln_config);
+        Ok(())
+    }
+
+    pub async fn load_cln_conf(&mut self) -> Result<(), CoffeeError> {
+        // the rpc socket can belong to a node that coffee does
+        // not manage, so it is available also without the link.
+        if let Some(rpc_path) = &self.config.startup.rpc_path {
+            self.rpc = Some(Client::new(rpc_path.clone()));
+        }
+        if self.config.cln_config_path.is_none() {
+            return Ok(());
+        }
+        let root = self.config.cln_root.clone().unwrap();
+        // We check if there is some problem we the path that we know
+        if !fs::try_exists(root.clone()).await? {
+            return Err(error!("lightning root path `{}` do not exist", root));
+        } else if !fs::try_exists(format!("{root}/{}", self.config.network)).await? {
+            return Err(error!(
+                "lightning network path `{root}/{}` do not exist",
+                self.config.network
+            ));
+        }
+        // All safe, we can move with the logic
+        if self.rpc.is_none() {
+            let rpc = Client::new(format!("{root}/{}/lightning-rpc", self.config.network));
+            self.rpc = Some(rpc);
+        }
+        let path = self.config.cln_config_path.clone().unwrap();
+        let mut file = CLNConf::new(path.clone(), true);
+        log::info!("looking for the cln config: {path}");
+        file.parse()
+            .map_err(|err| CoffeeError::new(err.core, &err.cause))?;
+        log::trace!("{:?}", file.fields);
+        self.cln_config = Some(file);
+        Ok(())
+    }
+
+    pub async fn link_with_cln(&mut self, cln_dir: &str) -> Result<(), CoffeeError> {
+        if self.cln_config.is_some() {
+            log::warn!("you are overriding the previous set up");
+        }
+        let path_with_network = format!("{cln_dir}/{}/config", self.config.network);
+        log::info!("configure coffee in the following cln config {path_with_network}");
+        self.config.cln_config_path = Some(path_with_network);
+        self.config.cln_root = Some(cln_dir.to_owned());
+        self.load_cln_conf().await?;
+        let mut conf = self.cln_config.clone().unwrap();
+        // the coffee configuration can be already included by a previous
+        // link, so we replace it instead of including it twice.
+        if conf.rm_subconf(&self.coffee_cln_config.path).is_ok() {
+            log::debug!("coffee configuration already included, replacing it");
+        }
+        conf.add_subconf(self.coffee_cln_config.clone())
+            .map_err(|err| error!("{}", &err.cause))?;
+        backup_cln_conf(&conf.path).await?;
+        conf.flush()
+            .map_err(|err| config_write_failed(&conf.path, err))?;
+        Ok(())
+    }
+
+    /// Unlink coffee from the core lightning configuration file
+    pub async fn unlink_from_cln(&mut self, cln_dir: &str) -> Result<(), CoffeeError> {
+        if self.cln_config.is_none() {
+            return Err(error!("no cln configuration found"));
+        }
+        let path_with_network = format!("{cln_dir}/{}/config", self.config.network);
+        log::info!("teardown coffee in the following cln config {path_with_network}");
+        let mut conf = self.cln_config.clone().unwrap();
+        conf.rm_subconf(&self.coffee_cln_config.clone().path)
+            .map_err(|err| error!("{}", &err.cause))?;
+        backup_cln_conf(&conf.path).await?;
+        conf.flush()
+            .map_err(|err| config_write_failed(&conf.path, err))?;
+        Ok(())
+    }
+
+    /// Whether a core lightning target that is not in use
+    /// runs the plugin built inside `root_path`.
+    fn used_by_other_target(&self, root_path: &str) -> bool {
+        self.config
+            .targets
+            .values()
+            .flat_map(|target| target.plugins.iter())
+            .any(|plugin| plugin.root_path == root_path)
+    }
+}
+
+#[async_trait]
+impl PluginManager for CoffeeManager {
+    fn set_progress(&mut self, progress: Option<ProgressSender>) {
+        self.progress = progress;
+    }
+
+    fn set_disk_usage(&mut self, enabled: bool) {
+        self.disk_usage = enabled;
+    }
+
+    fn set_list_order(&mut self, order: ListOrder) {
+        self.list_order = order;
+    }
+
+    fn set_verify_start(&mut self, enabled: bool) {
+        self.verify_start = enabled;
+    }
+
+    fn set_require_plugins(&mut self, enabled: bool) {
+        self.require_plugins = enabled;
+    }
+
+    async fn configure(&mut self) -> Result<(), CoffeeError> {
+        log::debug!("plugin configured");
+        Ok(())
+    }
+
+    async fn install(
+        &mut self,
+        plugin: &str,
+        verbose: bool,
+        try_dynamic: bool,
+        dry_run: bool,
+        force: bool,
+    ) -> Result<CoffeeInstall, CoffeeError> {
+        let _lock = self.lock().await?;
+        if is_git_url(plugin) {
+            return self
+                .install_from_url(plugin, verbose, try_dynamic, dry_run, force)
+                .await;
+        }
+        if let Some((name, git_ref)) = plugin.split_once('@') {
+            return self
+                .install_pinned(name, None, git_ref, verbose, try_dynamic, dry_run, force)
+                .await;
+        }
+        if force && self.config.plugins.iter().any(|p| p.name() == plugin) {
+            let plugin = self.find_plugin(plugin, try_dynamic)?;
+            return self
+                .reinstall_plugin(plugin, verbose, try_dynamic, dry_run)
+                .await;
+        }
+        let plugin = self.resolve_plugin(plugin, None, try_dynamic)?;
+        self.install_with_dependencies(plugin, verbose, try_dynamic, dry_run)
+            .await
+    }
+
+    async fn install_many(
+        &mut self,
+        plugins: &[&str],
+        verbose: bool,
+        try_dynamic: bool,
+    ) -> Result<HashMap<String, Result<(), CoffeeError>>, CoffeeError> {
+        let _lock = self.lock().await?;
+        let mut results = HashMap::new();
+        let workers = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+        let semaphore = Arc::new(Semaphore::new(workers));
+        let mut builds = JoinSet::new();
+        let mut names = HashMap::new();
+        for name in plugins {
+            if results.contains_key(*name) {
+                continue;
+            }
+            // a pinned plugin checks out its repository,
+            // so it can not be built concurrently.
+            if name.contains('@') {
+                let result = self
+                    .install(name, verbose, try_dynamic, false, false)
+                    .await
+                    .map(|_| ());
+                results.insert(name.to_string(), result);
+                continue;
+            }
+            let plugin = match self.resolve_plugin(name, None, try_dynamic) {
+                Ok(plugin) => plugin,
+                Err(err) => {
+                    results.insert(name.to_string(), Err(err));
+                    continue;
+                }
             };
-            let remove_config = self
-                .coffee_cln_config
-                .rm_conf(plugin_conf_key, Some(&exec_path.to_owned()));
-            if let Err(err) = remove_config {
-                // if this is true, we are probably a dynamic plugin:
-                if err.cause.contains("field with `plugin` not present") {
-                    if let Err(e) = self.stop_plugin(&exec_path).await {
-                        log::warn!("{}", e);
-                    };
-                } else {
-                    return Err(error!("{}", &err.cause));
+            // the dependencies need to be installed before the plugin,
+            // so we can not build it concurrently.
+            if !matches!(self.resolve_dependencies(&plugin), Ok(deps) if deps.is_empty()) {
+                let result = self
+                    .install(name, verbose, try_dynamic, false, false)
+                    .await
+                    .map(|_| ());
+                results.insert(name.to_string(), result);
+                continue;
+            }
+            match self.prepare_build(&plugin, false).await {
+                Ok(Some((plugin, path))) => {
+                    let result = self.register_plugin(plugin, &path, try_dynamic).await;
+                    results.insert(name.to_string(), result);
+                    continue;
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    results.insert(name.to_string(), Err(err));
+                    continue;
+                }
+            }
+            // mark the plugin as seen, the result will be replaced
+            // when the build is finished.
+            results.insert(name.to_string(), Ok(()));
+            let semaphore = semaphore.clone();
+            let root_path = self.config.root_path.clone();
+            let network = self.config.network.clone();
+            let progress = self.progress.clone();
+            let trusted_keys = self.config.startup.trusted_keys.clone();
+            let proxy = self.config.startup.proxy.clone();
+            let offline = self.config.startup.offline;
+            let install_timeout = self.install_timeout;
+            let name = name.to_string();
+            let task_name = name.clone();
+            let task = builds.spawn(async move {
+                let _permit = semaphore.acquire_owned().await;
+                let result = build_plugin(
+                    plugin,
+                    root_path,
+                    network,
+                    verbose,
+                    false,
+                    progress,
+                    trusted_keys,
+                    proxy,
+                    offline,
+                    install_timeout,
+                )
+                .await;
+                (task_name, result)
+            });
+            names.insert(task.id(), name);
+        }
+
+        // the build of the plugins is independent, but the storage
+        // and the cln configuration are shared, so the plugins are
+        // registered one by one.
+        while let Some(build) = builds.join_next().await {
+            let (name, result) = build_outcome(&names, build);
+            let result = match result {
+                // the plugin can be already installed as
+                // dependency of another plugin.
+                Ok(_) if self.config.plugins.iter().any(|p| p.name() == name) => Ok(()),
+                Ok((plugin, path)) => self.register_plugin(plugin, &path, try_dynamic).await,
+                Err(err) => Err(err),
+            };
+            if let Err(err) = &result {
+                log::error!("error while installing `{name}`: {err}");
+            }
+            results.insert(name, result);
+        }
+        Ok(results)
+    }
+
+    async fn install_global(
+        &mut self,
+        plugin: &str,
+        verbose: bool,
+        dry_run: bool,
+    ) -> Result<CoffeeInstall, CoffeeError> {
+        let _lock = self.lock().await?;
+        self.install_global_from(plugin, None, verbose, dry_run)
+            .await
+    }
+
+    async fn try_plugin(
+        &mut self,
+        plugin: &str,
+        verbose: bool,
+    ) -> Result<CoffeeTrial, CoffeeError> {
+        if self.rpc.is_none() {
+            return Err(error!(
+                "the trial needs the rpc socket of core lightning, use `--rpc-path` or link coffee to the node"
+            ));
+        }
+        let build = self.build_trial(plugin, verbose).await?;
+        let result = self.start_verified(&build.plugin, &build.exec_path).await;
+        if result.is_ok() {
+            if let Err(err) = self.stop_plugin(&build.exec_path).await {
+                log::warn!("unable to stop the trial of `{plugin}`: {err}");
+            }
+        }
+        if let Err(err) = self.clean_trial().await {
+            log::warn!("unable to remove the trial build of `{plugin}`: {err}");
+        }
+        result?;
+        Ok(CoffeeTrial {
+            plugin: build.plugin,
+            exec_path: build.exec_path,
+        })
+    }
+
+    async fn repair_all(&mut self, verbose: bool) -> Result<CoffeeRepair, CoffeeError> {
+        let _lock = self.lock().await?;
+        let mut names = self
+            .config
+            .plugins
+            .iter()
+            .map(|plugin| plugin.name())
+            .collect::<Vec<_>>();
+        names.sort();
+        let mut repair = CoffeeRepair::default();
+        for name in names {
+            // a plugin that fails does not stop the repair of the others
+            match self.repair_plugin(&name, verbose).await {
+                Ok(install) => repair.repaired.push(install),
+                Err(err) => {
+                    log::warn!("unable to repair the plugin `{name}`: {err}");
+                    repair.failed.insert(name, err.msg().to_owned());
                 }
             }
-            self.flush().await?;
-            self.update_conf().await?;
-            Ok(CoffeeRemove { plugin })
-        } else {
-            return Err(error!("plugin `{plugin}` is already not installed"));
         }
+        Ok(repair)
+    }
+
+    async fn remove(&mut self, plugin: &str) -> Result<CoffeeRemove, CoffeeError> {
+        let _lock = self.lock().await?;
+        log::debug!("removing plugin: {plugin}");
+        self.remove_plugin(plugin, false, false).await
+    }
+
+    async fn purge(
+        &mut self,
+        plugin: &str,
+        keep_config: bool,
+    ) -> Result<CoffeeRemove, CoffeeError> {
+        let _lock = self.lock().await?;
+        log::debug!("purging plugin: {plugin}");
+        self.remove_plugin(plugin, true, keep_config).await
+    }
+
+    async fn detach(&mut self, plugin: &str) -> Result<CoffeeRemove, CoffeeError> {
+        let _lock = self.lock().await?;
+        log::debug!("detaching plugin: {plugin}");
+        let (plugin, reverted_options) = self.untrack_plugin(plugin, false).await?;
+        // leave a note of the build next to it, so a later
+        // install can reuse the build if nothing changed.
+        let note = serde_json::to_string(&plugin).map_err(|err| error!("{err}"))?;
+        fs::write(format!("{}/{DETACHED_BUILD}", plugin.root_path), note).await?;
+        log::info!(
+            "plugin `{}` files left in {}",
+            plugin.name(),
+            plugin.root_path
+        );
+        Ok(CoffeeRemove {
+            plugin,
+            freed_bytes: 0,
+            removed_paths: vec![],
+            reverted_options,
+        })
     }
 
     async fn list(&mut self) -> Result<CoffeeList, CoffeeError> {
         Ok(CoffeeList {
-            plugins: self.config.plugins.clone(),
+            plugins: self.sorted_plugins().await?,
+            available: vec![],
+            disk_usage: self.plugins_disk_usage().await,
+        })
+    }
+
+    async fn list_available(&mut self) -> Result<CoffeeList, CoffeeError> {
+        let mut available = vec![];
+        for (name, repo) in &self.repos {
+            for plugin in repo.list().await? {
+                let installed = self
+                    .config
+                    .plugins
+                    .iter()
+                    .any(|installed| installed.name() == plugin.name());
+                available.push(CoffeeAvailablePlugin {
+                    repository: name.to_owned(),
+                    plugin,
+                    installed,
+                });
+            }
+        }
+        available.sort_by(|a, b| {
+            a.plugin
+                .name()
+                .cmp(&b.plugin.name())
+                .then_with(|| a.repository.cmp(&b.repository))
+        });
+        Ok(CoffeeList {
+            plugins: self.sorted_plugins().await?,
+            available,
+            disk_usage: self.plugins_disk_usage().await,
         })
     }
 
-    async fn upgrade(&mut self, repo: &str, verbose: bool) -> Result<CoffeeUpgrade, CoffeeError> {
+    async fn status(&self, plugin: &str) -> Result<Option<CoffeeStatus>, CoffeeError> {
+        if let Some(installed) = self
+            .config
+            .plugins
+            .iter()
+            .find(|installed| installed.name() == plugin)
+        {
+            return Ok(Some(CoffeeStatus {
+                plugin: installed.name(),
+                installed: true,
+                // If enabled is None, the plugin is enabled by default
+                // for backward compatibility.
+                enabled: installed.enabled.unwrap_or(true),
+                version: installed.version(),
+                commit: installed.commit.clone(),
+                lang: installed.lang.clone(),
+                exec_path: Some(installed.exec_path.clone()),
+            }));
+        }
+        let status = self
+            .repos
+            .values()
+            .find_map(|repo| repo.get_plugin_by_name(plugin))
+            .map(|available| CoffeeStatus {
+                plugin: available.name(),
+                installed: false,
+                enabled: false,
+                version: available.version(),
+                commit: available.commit.clone(),
+                lang: available.lang.clone(),
+                exec_path: None,
+            });
+        Ok(status)
+    }
+
+    async fn upgrade(
+        &mut self,
+        repo: &str,
+        clean: bool,
+        verbose: bool,
+    ) -> Result<CoffeeUpgrade, CoffeeError> {
+        let _lock = self.lock().await?;
         // TODO: upgrade should now be able to upgrade a single plugin
         // without affecting other plugins installed from the same repo
-        let repository = self
-            .repos
-            .get_mut(repo)
-            .ok_or_else(|| error!("Repository with name: `{}` not found", repo))?;
+        let repository = self.repos.get_mut(repo).ok_or_else(|| {
+            error!("Repository with name: `{}` not found", repo).with_kind(
+                CoffeeErrorKind::RepositoryNotFound {
+                    name: repo.to_owned(),
+                },
+            )
+        })?;
 
-        let status = repository.upgrade(&self.config.plugins, verbose).await?;
+        let mut status = repository
+            .upgrade(&self.config.plugins, clean, verbose)
+            .await?;
 
         // if status is not up to date, we need to update the plugins as well
-        match status.status {
-            UpgradeStatus::Updated(_, _) => {
-                for plugins in status.plugins_effected.iter() {
-                    self.remove(plugins).await?;
-                    self.install(plugins, verbose, false).await?;
+        if let UpgradeStatus::Updated(_, _) = status.status {
+            let dynamic_reload = self.config.startup.rpc_path.is_some();
+            for plugin in status.plugins_effected.iter() {
+                let Some(installed) = self
+                    .config
+                    .plugins
+                    .iter()
+                    .find(|installed| installed.name() == *plugin)
+                else {
+                    continue;
+                };
+                if let Some(git_ref) = &installed.git_ref {
+                    log::info!("plugin `{plugin}` is pinned to {git_ref}, skipping it");
+                    continue;
+                }
+                // the plugin disabled by the user stays disabled, and an
+                // important plugin can not be restarted dynamically.
+                let enabled = installed.enabled != Some(false);
+                let try_dynamic = dynamic_reload && enabled && !installed.important();
+                // the new build replaces the installed one only when it
+                // succeeds, and a plugin that fails does not stop the others.
+                let result = match self.find_plugin_in(plugin, Some(repo), false) {
+                    Ok(upgraded) => {
+                        self.reinstall_plugin(upgraded, verbose, try_dynamic, false)
+                            .await
+                    }
+                    Err(err) => Err(err),
+                };
+                match result {
+                    Ok(_) if try_dynamic => status.reloads.push(self.reload_status(plugin).await),
+                    Ok(_) if dynamic_reload && enabled => status
+                        .reloads
+                        .push(ReloadStatus::RestartNeeded(plugin.to_owned())),
+                    Ok(_) => {}
+                    Err(err) => {
+                        log::warn!("unable to upgrade the plugin `{plugin}`: {err}");
+                        status
+                            .failed
+                            .insert(plugin.to_owned(), err.msg().to_owned());
+                    }
                 }
             }
-            _ => {}
         }
 
         self.flush().await?;
         Ok(status)
     }
 
+    async fn check_updates(&self) -> Result<CoffeeCheckUpdates, CoffeeError> {
+        let mut updates = vec![];
+        for repo in self.repos.values() {
+            let installed = self
+                .config
+                .plugins
+                .iter()
+                .filter(|plugin| repo.get_plugin_by_name(&plugin.name()).is_some())
+                // a pinned plugin is not upgraded with its repository
+                .filter(|plugin| plugin.git_ref.is_none())
+                .collect::<Vec<_>>();
+            if installed.is_empty() {
+                continue;
+            }
+            let Some(latest_commit) = repo.latest_commit().await? else {
+                continue;
+            };
+            for plugin in installed {
+                if let Some(commit) = &plugin.commit {
+                    if *commit == latest_commit {
+                        continue;
+                    }
+                    // a new commit of the repository that does not touch the
+                    // plugin directory is not an update of the plugin, when the
+                    // diff is not available (e.g. a shallow history) it is.
+                    let changed = repo
+                        .plugin_changed(&plugin.name(), commit, &latest_commit)
+                        .await
+                        .unwrap_or(true);
+                    if !changed {
+                        continue;
+                    }
+                }
+                updates.push(CoffeePluginUpdate {
+                    plugin: plugin.name(),
+                    current_commit: plugin.commit.clone(),
+                    latest_commit: latest_commit.clone(),
+                });
+            }
+        }
+        Ok(CoffeeCheckUpdates { updates })
+    }
+
+    async fn logs(&self, plugin: &str) -> Result<CoffeeBuildLog, CoffeeError> {
+        let path = build_log_path(&self.config.root_path, plugin);
+        let Ok(log) = fs::read_to_string(&path).await else {
+            return Err(
+                error!("no build log found for the plugin `{plugin}`").with_kind(
+                    CoffeeErrorKind::PluginNotFound {
+                        name: plugin.to_owned(),
+                    },
+                ),
+            );
+        };
+        Ok(CoffeeBuildLog {
+            plugin: plugin.to_owned(),
+            path,
+            log,
+        })
+    }
+
+    async fn changelog(&self, plugin: &str) -> Result<CoffeeChangelog, CoffeeError> {
+        let installed = self
+            .config
+            .plugins
+            .iter()
+            .find(|installed| installed.name() == plugin)
+            .ok_or_else(|| plugin_not_installed(plugin))?;
+        let Some(current_commit) = installed.commit.clone() else {
+            return Err(error!(
+                "plugin `{plugin}` was installed without tracking its commit, reinstall it to see the changelog"
+            ));
+        };
+        let repo = self
+            .repos
+            .values()
+            .find(|repo| repo.get_plugin_by_name(plugin).is_some())
+            .ok_or_else(|| {
+                error!("no repository contains the plugin `{plugin}`").with_kind(
+                    CoffeeErrorKind::PluginNotFound {
+                        name: plugin.to_owned(),
+                    },
+                )
+            })?;
+        let latest_commit = repo
+            .latest_commit()
+            .await?
+            .unwrap_or_else(|| current_commit.clone());
+        let commits = if latest_commit == current_commit {
+            vec![]
+        } else {
+            repo.log(&current_commit, &latest_commit).await?
+        };
+        Ok(CoffeeChangelog {
+            plugin: installed.name(),
+            current_commit,
+            latest_commit,
+            commits,
+        })
+    }
+
     async fn link(&mut self, cln_dir: &str) -> Result<(), CoffeeError> {
+        let _lock = self.lock().await?;
         self.link_with_cln(cln_dir).await?;
         log::info!("cln configured");
         self.flush().await?;
@@ -439,73 +3198,409 @@ impl PluginManager for CoffeeManager {
     }
 
     async fn unlink(&mut self, cln_dir: &str) -> Result<(), CoffeeError> {
+        let _lock = self.lock().await?;
         self.unlink_from_cln(cln_dir).await?;
         log::info!("cln configuration removed");
         self.flush().await?;
         Ok(())
     }
 
-    async fn add_remote(&mut self, name: &str, url: &str) -> Result<(), CoffeeError> {
-        // FIXME: we should allow some error here like
-        // for the add remote command the no found error for the `repository`
-        // directory is fine.
+    async fn add_target(&mut self, name: &str, cln_dir: &str) -> Result<(), CoffeeError> {
+        let _lock = self.lock().await?;
+        let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        if name.is_empty() || !name.chars().all(valid) {
+            return Err(error!(
+                "target name `{name}` can contain only letters, digits, `-` and `_`"
+            ));
+        }
+        if name == self.config.target || self.config.targets.contains_key(name) {
+            return Err(error!("target `{name}` already exists"));
+        }
+        let network = self.config.network.clone();
+        if !fs::try_exists(format!("{cln_dir}/{network}")).await? {
+            return Err(error!(
+                "lightning network path `{cln_dir}/{network}` do not exist"
+            ));
+        }
+        // each target has its own coffee configuration, so the
+        // plugins of a node are not started by the others.
+        let targets_dir = format!("{}/{network}/targets", self.config.root_path);
+        fs::create_dir_all(&targets_dir).await?;
+        let config_path = format!("{targets_dir}/{name}.conf");
+        let coffee_conf = CLNConf::new(config_path.clone(), true);
+        coffee_conf
+            .flush()
+            .map_err(|err| config_write_failed(&config_path, err))?;
 
-        if self.repos.contains_key(name) {
-            return Err(error!("repository with name: {name} already exists"));
+        let cln_config_path = format!("{cln_dir}/{network}/config");
+        let mut conf = CLNConf::new(cln_config_path.clone(), true);
+        conf.parse()
+            .map_err(|err| CoffeeError::new(err.core, &err.cause))?;
+        if conf.rm_subconf(&config_path).is_ok() {
+            log::debug!("target configuration already included, replacing it");
         }
-        let url = URL::new(&self.config.root_path, url, name);
-        log::debug!("remote adding: {} {}", name, &url.url_string);
-        let mut repo = Github::new(name, &url);
-        repo.init().await?;
-        self.repos.insert(repo.name(), Box::new(repo));
-        log::debug!("remote added: {} {}", name, &url.url_string);
+        conf.add_subconf(coffee_conf)
+            .map_err(|err| error!("{}", &err.cause))?;
+        backup_cln_conf(&conf.path).await?;
+        conf.flush()
+            .map_err(|err| config_write_failed(&conf.path, err))?;
+
+        self.config.targets.insert(
+            name.to_owned(),
+            ClnTarget {
+                cln_config_path: Some(cln_config_path),
+                cln_root: Some(cln_dir.to_owned()),
+                config_path,
+                plugins: vec![],
+            },
+        );
+        self.flush().await?;
+        Ok(())
+    }
+
+    async fn use_target(&mut self, name: &str) -> Result<(), CoffeeError> {
+        let _lock = self.lock().await?;
+        if name == self.config.target {
+            return Ok(());
+        }
+        let Some(target) = self.config.targets.remove(name) else {
+            return Err(error!("target `{name}` not found"));
+        };
+        // the target in use is stored with the others, so
+        // switching back to it restores its plugins.
+        let current = ClnTarget {
+            cln_config_path: self.config.cln_config_path.take(),
+            cln_root: self.config.cln_root.take(),
+            config_path: self.config.config_path.clone(),
+            plugins: std::mem::take(&mut self.config.plugins),
+        };
+        let previous = std::mem::replace(&mut self.config.target, name.to_owned());
+        self.config.targets.insert(previous, current);
+        self.config.cln_config_path = target.cln_config_path;
+        self.config.cln_root = target.cln_root;
+        self.config.config_path = target.config_path;
+        self.config.plugins = target.plugins;
+
+        self.coffee_cln_config = CLNConf::new(self.config.config_path.clone(), true);
+        if let Err(err) = self.coffee_cln_config.parse() {
+            log::error!("{}", err.cause);
+        }
+        self.cln_config = None;
+        self.rpc = None;
+        self.load_cln_conf().await?;
         self.flush().await?;
+        log::info!("using the core lightning target `{name}`");
         Ok(())
     }
 
+    async fn list_targets(&self) -> Result<CoffeeTargets, CoffeeError> {
+        let active = CoffeeTarget {
+            name: self.config.target.clone(),
+            cln_root: self.config.cln_root.clone(),
+            config_path: self.config.config_path.clone(),
+            plugins: self.config.plugins.len(),
+            active: true,
+        };
+        let mut targets = self
+            .config
+            .targets
+            .iter()
+            .map(|(name, target)| CoffeeTarget {
+                name: name.to_owned(),
+                cln_root: target.cln_root.clone(),
+                config_path: target.config_path.clone(),
+                plugins: target.plugins.len(),
+                active: false,
+            })
+            .chain(std::iter::once(active))
+            .collect::<Vec<_>>();
+        targets.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(CoffeeTargets { targets })
+    }
+
+    async fn show_config(&self) -> Result<CoffeeClnConfig, CoffeeError> {
+        let coffee_config = self
+            .coffee_cln_config
+            .fields
+            .clone()
+            .into_iter()
+            .collect::<BTreeMap<_, _>>();
+        let mut merged = BTreeMap::new();
+        let mut included = false;
+        if let Some(cln_config) = &self.cln_config {
+            merged.extend(cln_config.fields.clone());
+            // the include is found by removing it from a copy
+            included = cln_config
+                .clone()
+                .rm_subconf(&self.coffee_cln_config.path)
+                .is_ok();
+        }
+        for (key, values) in &coffee_config {
+            merged
+                .entry(key.to_owned())
+                .or_insert_with(Vec::new)
+                .extend(values.iter().cloned());
+        }
+        Ok(CoffeeClnConfig {
+            cln_config_path: self.config.cln_config_path.clone(),
+            coffee_config_path: self.coffee_cln_config.path.clone(),
+            included,
+            coffee_config,
+            merged,
+        })
+    }
+
+    async fn export_state(&self) -> Result<CoffeeState, CoffeeError> {
+        let mut state = CoffeeState::default();
+        for repo in self.repos.values() {
+            let url = repo.url();
+            state.remotes.push(CoffeeStateRemote {
+                name: repo.name(),
+                url: url.url_string,
+                git_ref: url.git_ref,
+                mirrors: repo.to_info().mirrors,
+            });
+        }
+        state.remotes.sort_by(|a, b| a.name.cmp(&b.name));
+        for installed in &self.config.plugins {
+            let mut remote = None;
+            for repo in self.repos.values() {
+                if repo.get_plugin_by_name(&installed.name()).is_some() {
+                    remote = Some(repo.name());
+                    break;
+                }
+            }
+            state.plugins.push(CoffeeStatePlugin {
+                name: installed.name(),
+                remote,
+                git_ref: installed.git_ref.clone(),
+                enabled: installed.enabled.unwrap_or(true),
+                scope: installed.scope,
+            });
+        }
+        state.plugins.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(state)
+    }
+
+    async fn import_state(
+        &mut self,
+        state: &CoffeeState,
+        verbose: bool,
+    ) -> Result<CoffeeImport, CoffeeError> {
+        let _lock = self.lock().await?;
+        let mut import = CoffeeImport::default();
+        for remote in &state.remotes {
+            if let Some(repo) = self.repos.get(&remote.name) {
+                let url = repo.url();
+                if url.url_string == remote.url && url.git_ref == remote.git_ref {
+                    import.skipped.push(remote.name.to_owned());
+                } else {
+                    import.conflicts.push(format!(
+                        "remote `{}` already exists with the url `{}`",
+                        remote.name, url.url_string
+                    ));
+                }
+                continue;
+            }
+            // the same syntax of `remote add`, the mirrors follow the url
+            let mut url = remote.url.to_owned();
+            if let Some(git_ref) = &remote.git_ref {
+                url += &format!("#{git_ref}");
+            }
+            for mirror in &remote.mirrors {
+                url += &format!(",{mirror}");
+            }
+            match self.add_remote(&remote.name, &url).await {
+                Ok(_) => import.remotes_added.push(remote.name.to_owned()),
+                Err(err) => {
+                    log::warn!("unable to add the remote `{}`: {err}", remote.name);
+                    import
+                        .remotes_failed
+                        .insert(remote.name.to_owned(), err.msg().to_owned());
+                }
+            }
+        }
+        for plugin in &state.plugins {
+            // a dependency can be installed by a previous plugin of the state
+            if let Some(installed) = self.config.plugins.iter().find(|p| p.name() == plugin.name) {
+                if installed.git_ref == plugin.git_ref && installed.scope == plugin.scope {
+                    import.skipped.push(plugin.name.to_owned());
+                } else {
+                    import.conflicts.push(format!(
+                        "plugin `{}` is already installed with a different git reference or scope",
+                        plugin.name
+                    ));
+                }
+                continue;
+            }
+            // the plugin is installed from the remote where it was exported from,
+            // another remote can contain a plugin with the same name.
+            let remote = plugin.remote.as_deref();
+            let result = match (&plugin.scope, &plugin.git_ref) {
+                (PluginScope::Global, _) => {
+                    self.install_global_from(&plugin.name, remote, verbose, false)
+                        .await
+                }
+                (PluginScope::Network, Some(git_ref)) => {
+                    self.install_pinned(&plugin.name, remote, git_ref, verbose, false, false, false)
+                        .await
+                }
+                (PluginScope::Network, None) => {
+                    match self.resolve_plugin(&plugin.name, remote, false) {
+                        Ok(found) => {
+                            self.install_with_dependencies(found, verbose, false, false)
+                                .await
+                        }
+                        Err(err) => Err(err),
+                    }
+                }
+            };
+            let result = match result {
+                Ok(_) if !plugin.enabled => self.disable(&plugin.name).await,
+                result => result.map(|_| ()),
+            };
+            match result {
+                Ok(()) => import.installed.push(plugin.name.to_owned()),
+                Err(err) => {
+                    log::warn!("unable to install the plugin `{}`: {err}", plugin.name);
+                    import
+                        .plugins_failed
+                        .insert(plugin.name.to_owned(), err.msg().to_owned());
+                }
+            }
+        }
+        Ok(import)
+    }
+
+    async fn add_remote(&mut self, name: &str, url: &str) -> Result<CoffeeAddRemote, CoffeeError> {
+        let _lock = self.lock().await?;
+        self.add_remote_with_token(name, url, git_token(url)).await
+    }
+
     async fn rm_remote(&mut self, name: &str) -> Result<(), CoffeeError> {
+        let _lock = self.lock().await?;
         log::debug!("remote removing: {}", name);
+        let removal = self.rm_remote_preview(name).await?;
         match self.repos.get(name) {
             Some(repo) => {
-                let remote_repo = repo.list().await?;
                 let repo_path = repo.url().path_string;
-                let plugins = self.config.plugins.clone();
-                for plugin in &remote_repo {
-                    if let Some(ind) = plugins
-                        .iter()
-                        .position(|elem| elem.name() == *plugin.name())
-                    {
-                        let plugin_name = &plugins[ind].name().clone();
-                        match self.remove(plugin_name).await {
-                            Ok(_) => {}
-                            Err(err) => return Err(err),
-                        }
-                    }
+                for plugin in &removal.plugins {
+                    self.remove(&plugin.name()).await?;
+                }
+                // make sure that we are deleting only the repositories
+                // cloned by coffee, and not a local directory of the user.
+                let cloned_repositories_path = format!("{}/repositories", self.config.root_path);
+                if repo_path.starts_with(&cloned_repositories_path) {
+                    fs::remove_dir_all(repo_path).await?;
                 }
-                fs::remove_dir_all(repo_path).await?;
                 self.repos.remove(name);
+                self.store_token(name, None).await?;
                 log::debug!("remote removed: {}", name);
-                self.flush().await?;
+                self.store_repository(name).await?;
             }
             None => {
-                return Err(error!("repository with name: {name} not found"));
+                return Err(repository_not_found(name));
             }
         };
         Ok(())
     }
 
+    async fn rm_remote_preview(&self, name: &str) -> Result<CoffeeRemoteRemoval, CoffeeError> {
+        let repo = self
+            .repos
+            .get(name)
+            .ok_or_else(|| repository_not_found(name))?;
+        let remote_plugins = repo.list().await?;
+        let plugins = self
+            .config
+            .plugins
+            .iter()
+            .filter(|installed| {
+                remote_plugins
+                    .iter()
+                    .any(|plugin| plugin.name() == installed.name())
+            })
+            .cloned()
+            .collect();
+        Ok(CoffeeRemoteRemoval {
+            remote: name.to_owned(),
+            plugins,
+        })
+    }
+
+    async fn refresh_remote(&mut self, name: &str) -> Result<CoffeeRefresh, CoffeeError> {
+        let _lock = self.lock().await?;
+        let repo = self
+            .repos
+            .get_mut(name)
+            .ok_or_else(|| repository_not_found(name))?;
+        let known = repo
+            .list()
+            .await?
+            .iter()
+            .map(|plugin| plugin.name())
+            .collect::<HashSet<_>>();
+        let status = repo.refresh().await?;
+        let new_plugins = repo
+            .list()
+            .await?
+            .iter()
+            .map(|plugin| plugin.name())
+            .filter(|plugin| !known.contains(plugin))
+            .collect();
+        self.flush().await?;
+        Ok(CoffeeRefresh {
+            repo: name.to_owned(),
+            status,
+            new_plugins,
+        })
+    }
+
+    async fn refresh_all(
+        &mut self,
+    ) -> Result<HashMap<String, Result<CoffeeRefresh, CoffeeError>>, CoffeeError> {
+        let _lock = self.lock().await?;
+        let names = self.repos.keys().cloned().collect::<Vec<_>>();
+        let mut results = HashMap::new();
+        for name in names {
+            let result = self.refresh_remote(&name).await;
+            if let Err(err) = &result {
+                log::error!("error while refreshing `{name}`: {err}");
+            }
+            results.insert(name, result);
+        }
+        Ok(results)
+    }
+
     async fn list_remotes(&mut self) -> Result<CoffeeRemote, CoffeeError> {
         let mut remote_list = Vec::new();
         for repo in self.repos.values() {
-            let repository = git2::Repository::open(repo.url().path_string.as_str())
-                .map_err(|err| error!("{}", err.message()))?;
-            let (commit, date) = get_repo_info!(repository);
+            // a local repository is not required to be a git repository
+            let (commit, date) = match git2::Repository::open(repo.url().path_string.as_str()) {
+                Ok(repository) => {
+                    let (commit, date) = get_repo_info!(repository);
+                    (Some(commit), Some(date))
+                }
+                Err(err) => {
+                    log::debug!("{} is not a git repository: {}", repo.name(), err.message());
+                    (None, None)
+                }
+            };
+            let repo_usage = if self.disk_usage {
+                Some(disk_usage_of(&repo.url().path_string).await)
+            } else {
+                None
+            };
             remote_list.push(CoffeeListRemote {
                 local_name: repo.name(),
                 url: repo.url().url_string,
                 plugins: repo.list().await?,
-                commit_id: Some(commit),
-                date: Some(date),
+                commit_id: commit,
+                date,
+                git_ref: repo.url().git_ref,
+                fetched_at: repo.fetched_at(),
+                mirrors: repo.to_info().mirrors,
+                disk_usage: repo_usage,
             });
         }
         Ok(CoffeeRemote {
@@ -518,9 +3613,13 @@ impl PluginManager for CoffeeManager {
         let repo = self
             .repos
             .get(name)
-            .ok_or_else(|| error!("repository with name: {name} not found"))?;
+            .ok_or_else(|| repository_not_found(name))?;
         let plugins = repo.list().await?;
-        Ok(CoffeeList { plugins })
+        Ok(CoffeeList {
+            plugins,
+            available: vec![],
+            disk_usage: HashMap::new(),
+        })
     }
 
     async fn show(&mut self, plugin: &str) -> Result<CoffeeShow, CoffeeError> {
@@ -528,28 +3627,315 @@ impl PluginManager for CoffeeManager {
             if let Some(plugin) = repo.get_plugin_by_name(plugin) {
                 // FIXME: there are more README file options?
                 let readme_path = format!("{}/README.md", plugin.root_path);
-                let contents = fs::read_to_string(readme_path).await?;
-                return Ok(CoffeeShow { readme: contents });
+                let installed = self
+                    .config
+                    .plugins
+                    .iter()
+                    .find(|installed| installed.name() == plugin.name());
+                // the README of an installed plugin is the one of the
+                // installed commit, that can be older than the HEAD.
+                let installed_commit = installed
+                    .and_then(|installed| installed.commit.clone())
+                    .filter(|commit| plugin.commit.as_ref() != Some(commit));
+                let installed_readme = match installed_commit {
+                    Some(commit) => {
+                        let repo_path = repo.url().path_string;
+                        let file = readme_path
+                            .strip_prefix(&repo_path)
+                            .unwrap_or(&readme_path)
+                            .trim_start_matches('/');
+                        // e.g. the README was added after the installed commit
+                        match repo.read_file(&commit, file).await {
+                            Ok(contents) => Some((contents, Some(commit))),
+                            Err(err) => {
+                                log::warn!(
+                                    "unable to read the README of `{}` at {commit}, showing the one of the HEAD: {err}",
+                                    plugin.name()
+                                );
+                                None
+                            }
+                        }
+                    }
+                    None => None,
+                };
+                let (contents, commit) = match installed_readme {
+                    Some(readme) => readme,
+                    None => (
+                        fs::read_to_string(readme_path).await?,
+                        plugin.commit.clone(),
+                    ),
+                };
+                let checksum = installed.and_then(|installed| installed.checksum.clone());
+                let signed_by = installed.and_then(|installed| installed.signed_by.clone());
+                return Ok(CoffeeShow {
+                    readme: contents,
+                    commit,
+                    author: plugin.author(),
+                    license: plugin.license(),
+                    homepage: plugin.homepage(),
+                    checksum,
+                    signed_by,
+                    min_cln_version: plugin.min_cln_version(),
+                });
             }
         }
         let err = error!(
             "{}",
             &format!("plugin `{plugin}` are not present inside the repositories"),
-        );
+        )
+        .with_kind(CoffeeErrorKind::PluginNotFound {
+            name: plugin.to_owned(),
+        });
         Err(err)
     }
 
     async fn search(&mut self, plugin: &str) -> Result<CoffeeSearch, CoffeeError> {
+        let term = plugin.to_lowercase();
+        let mut matches = Vec::new();
         for repo in self.repos.values() {
-            if let Some(plugin) = repo.get_plugin_by_name(plugin) {
-                return Ok(CoffeeSearch {
-                    repository_url: repo.url().url_string,
-                    plugin,
-                });
+            for candidate in repo.list().await? {
+                let Some(rank) = search_rank(&candidate, &term) else {
+                    continue;
+                };
+                matches.push((rank, candidate, repo.name(), repo.url().url_string));
             }
         }
-        let err = CoffeeError::new(404, &format!("unable to locate plugin `{plugin}`"));
-        Err(err)
+        matches.sort_by(|(a_rank, a, ..), (b_rank, b, ..)| {
+            a_rank.cmp(b_rank).then_with(|| a.name().cmp(&b.name()))
+        });
+
+        let Some((_, best, _, repository_url)) = matches.first().cloned() else {
+            let err = error!("unable to locate plugin `{plugin}`").with_kind(
+                CoffeeErrorKind::PluginNotFound {
+                    name: plugin.to_owned(),
+                },
+            );
+            return Err(err);
+        };
+        let matches = matches
+            .into_iter()
+            .map(
+                |(_, plugin, repository, repository_url)| CoffeeSearchMatch {
+                    name: plugin.name(),
+                    description: plugin.description(),
+                    repository,
+                    repository_url,
+                },
+            )
+            .collect();
+        Ok(CoffeeSearch {
+            repository_url,
+            plugin: best,
+            matches,
+        })
+    }
+
+    async fn explain(&self, plugin: &str) -> Result<CoffeeExplain, CoffeeError> {
+        let mut explain = CoffeeExplain {
+            plugin: plugin.to_owned(),
+            lang: None,
+            repository: None,
+            suggestions: vec![],
+            reasons: vec![],
+        };
+        if self.config.plugins.iter().any(|p| p.name() == plugin) {
+            explain.reasons.push(ExplainReason::AlreadyInstalled);
+        }
+        if self.repos.is_empty() {
+            explain.reasons.push(ExplainReason::NoRemotes);
+            return Ok(explain);
+        }
+
+        if let Ok(found) = self.find_plugin(plugin, false) {
+            explain.lang = Some(found.lang.clone());
+            explain.repository = self
+                .repos
+                .values()
+                .find(|repo| repo.get_plugin_by_name(plugin).is_some())
+                .map(|repo| repo.name());
+            for binary in found.required_binaries() {
+                if !binary_in_path(binary) {
+                    explain.reasons.push(ExplainReason::MissingBinary(
+                        binary.to_owned(),
+                        found.lang.clone(),
+                    ));
+                }
+            }
+            if let Err(err) = self.resolve_dependencies(&found) {
+                explain
+                    .reasons
+                    .push(ExplainReason::Dependencies(err.msg().to_owned()));
+            }
+            if let Err(err) = self.check_cln_version(&found).await {
+                explain
+                    .reasons
+                    .push(ExplainReason::IncompatibleCln(err.msg().to_owned()));
+            }
+            return Ok(explain);
+        }
+
+        // the plugin is not indexed, so we look for its directory
+        // inside the remotes to tell why it is skipped.
+        for repo in self.repos.values() {
+            let root = Path::new(&repo.url().path_string).join(plugin);
+            if !root.is_dir() {
+                continue;
+            }
+            let (lang, reason) = unindexed_reason(&repo.name(), &root);
+            explain.lang = Some(lang);
+            explain.repository = Some(repo.name());
+            explain.reasons.extend(reason);
+            break;
+        }
+        if explain.repository.is_none() {
+            explain.reasons.push(ExplainReason::NotFound);
+        }
+
+        let term = plugin.to_lowercase();
+        let mut suggestions = vec![];
+        for repo in self.repos.values() {
+            for candidate in repo.list().await? {
+                let name = candidate.name().to_lowercase();
+                let distance = edit_distance(&name, &term);
+                let similar =
+                    distance <= 2 || search_rank(&candidate, &term).is_some_and(|rank| rank <= 2);
+                if !similar {
+                    continue;
+                }
+                suggestions.push((
+                    distance,
+                    CoffeeSearchMatch {
+                        name: candidate.name(),
+                        description: candidate.description(),
+                        repository: repo.name(),
+                        repository_url: repo.url().url_string,
+                    },
+                ));
+            }
+        }
+        suggestions.sort_by(|(a_distance, a), (b_distance, b)| {
+            a_distance.cmp(b_distance).then_with(|| a.name.cmp(&b.name))
+        });
+        explain.suggestions = suggestions
+            .into_iter()
+            .take(5)
+            .map(|(_, suggestion)| suggestion)
+            .collect();
+        Ok(explain)
+    }
+
+    async fn diagnose(&self) -> Result<CoffeeDiagnose, CoffeeError> {
+        let mut issues = vec![];
+
+        let mut binaries: Vec<(&str, Vec<String>)> = vec![];
+        for plugin in self.config.plugins.iter() {
+            for binary in plugin.required_binaries() {
+                match binaries.iter_mut().find(|(name, _)| *name == binary) {
+                    Some((_, plugins)) => plugins.push(plugin.name()),
+                    None => binaries.push((binary, vec![plugin.name()])),
+                }
+            }
+        }
+        for (binary, plugins) in binaries {
+            if !binary_in_path(binary) {
+                issues.push(DiagnoseIssue::MissingBinary(binary.to_owned(), plugins));
+            }
+        }
+
+        let config_paths = [
+            Some(self.config.config_path.clone()),
+            self.config.cln_config_path.clone(),
+        ];
+        for path in config_paths.into_iter().flatten() {
+            // opening the file in append mode does not change it
+            let writable = std::fs::OpenOptions::new().append(true).open(&path).is_ok();
+            if !writable {
+                issues.push(DiagnoseIssue::ConfigNotWritable(path));
+            }
+        }
+
+        for plugin in self.config.plugins.iter() {
+            let executable = std::fs::metadata(&plugin.exec_path)
+                .is_ok_and(|metadata| metadata.is_file() && metadata.mode() & 0o111 != 0);
+            if !executable {
+                issues.push(DiagnoseIssue::PluginNotExecutable(
+                    plugin.name(),
+                    plugin.exec_path.clone(),
+                ));
+            }
+        }
+
+        for health in self.repositories_health().await? {
+            if !health.exists {
+                issues.push(DiagnoseIssue::RepositoryAbsent(health.name));
+            } else if !health.valid {
+                issues.push(DiagnoseIssue::RepositoryNotValid(health.name));
+            } else if !health.head_matches() {
+                issues.push(DiagnoseIssue::RepositoryHeadMismatch(
+                    health.name,
+                    health.expected.unwrap_or_default(),
+                    health.head.unwrap_or_default(),
+                ));
+            }
+        }
+        Ok(CoffeeDiagnose { issues })
+    }
+
+    async fn prune(&mut self, dry_run: bool, cache: bool) -> Result<CoffeePrune, CoffeeError> {
+        let _lock = self.lock().await?;
+        let root_path = &self.config.root_path;
+        let mut tracked = self
+            .repos
+            .values()
+            .map(|repo| repo.url().path_string)
+            .collect::<HashSet<_>>();
+        // a corrupted repository is repaired by the nurse, not removed here
+        tracked.extend(
+            self.corrupted_repos
+                .keys()
+                .map(|name| format!("{root_path}/repositories/{name}")),
+        );
+        let dirs = [
+            format!("{root_path}/repositories"),
+            format!("{root_path}/{}/plugins", self.config.network),
+        ];
+        let mut paths = vec![];
+        let cache_dir = cache_path(root_path);
+        if cache && fs::try_exists(&cache_dir).await? {
+            let size = disk_usage_of(&cache_dir).await;
+            paths.push(CoffeePrunedPath {
+                path: cache_dir,
+                size,
+            });
+        }
+        for dir in dirs {
+            let Ok(mut entries) = fs::read_dir(&dir).await else {
+                continue;
+            };
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path().to_string_lossy().to_string();
+                if !entry.file_type().await?.is_dir() || tracked.contains(&path) {
+                    continue;
+                }
+                let installed = build_in_use(&self.config, &path);
+                // a detached build is kept to be reused by the next install
+                let detached = fs::try_exists(format!("{path}/{DETACHED_BUILD}"))
+                    .await
+                    .unwrap_or(false);
+                if installed || detached {
+                    continue;
+                }
+                let size = disk_usage_of(&path).await;
+                paths.push(CoffeePrunedPath { path, size });
+            }
+        }
+        if !dry_run {
+            for pruned in &paths {
+                fs::remove_dir_all(&pruned.path).await?;
+                log::info!("removed {} ({} bytes)", pruned.path, pruned.size);
+            }
+        }
+        Ok(CoffeePrune { paths, dry_run })
     }
 
     async fn nurse_verify(&self) -> Result<ChainOfResponsibilityStatus, CoffeeError> {
@@ -557,6 +3943,7 @@ impl PluginManager for CoffeeManager {
     }
 
     async fn nurse(&mut self) -> Result<CoffeeNurse, CoffeeError> {
+        let _lock = self.lock().await?;
         let status = self.recovery_strategies.scan(self).await?;
         let mut nurse_actions: Vec<NurseStatus> = vec![];
         for defect in status.defects.iter() {
@@ -566,6 +3953,16 @@ impl PluginManager for CoffeeManager {
                     let mut actions = self.patch_repository_locally_absent(repos.to_vec()).await?;
                     nurse_actions.append(&mut actions);
                 }
+                Defect::PluginExecutableAbsent(plugins) => {
+                    let mut actions = self
+                        .patch_plugin_executable_absent(plugins.to_vec())
+                        .await?;
+                    nurse_actions.append(&mut actions);
+                }
+                Defect::RepositoryCorrupted(repos) => {
+                    let mut actions = self.patch_repository_corrupted(repos.to_vec()).await?;
+                    nurse_actions.append(&mut actions);
+                }
             }
         }
         let mut nurse = CoffeeNurse {
@@ -575,6 +3972,26 @@ impl PluginManager for CoffeeManager {
         Ok(nurse)
     }
 
+    async fn patch_repository_corrupted(
+        &mut self,
+        repos: Vec<String>,
+    ) -> Result<Vec<NurseStatus>, CoffeeError> {
+        // we do not know anything of a repository that we are not
+        // able to load, so we can only remove it, with its clone.
+        let cloned_repositories_path = format!("{}/repositories", self.config.root_path);
+        for repo_name in repos.iter() {
+            self.corrupted_repos.remove(repo_name);
+            let repo_path = format!("{cloned_repositories_path}/{repo_name}");
+            // This shouldn't return an error if the repository
+            // is not present locally.
+            let _ = fs::remove_dir_all(repo_path).await;
+            self.store_token(repo_name, None).await?;
+            log::info!("removing repository {repo_name}");
+        }
+        self.flush().await?;
+        Ok(vec![NurseStatus::CorruptedRepositoryRemoved(repos)])
+    }
+
     async fn patch_repository_locally_absent(
         &mut self,
         repos: Vec<String>,
@@ -592,7 +4009,7 @@ impl PluginManager for CoffeeManager {
             let repo = self
                 .repos
                 .get_mut(repo_name)
-                .ok_or_else(|| error!("repository with name: {repo_name} not found"))?;
+                .ok_or_else(|| repository_not_found(repo_name))?;
 
             match repo.recover().await {
                 Ok(_) => {
@@ -616,7 +4033,7 @@ impl PluginManager for CoffeeManager {
                     log::info!("removing repository {}", repo_name.clone());
                     self.repos.remove(repo_name);
                     log::debug!("remote removed: {}", repo_name);
-                    self.flush().await?;
+                    self.store_repository(repo_name).await?;
                     nurse_actions.push(NurseStatus::RepositoryLocallyRemoved(vec![
                         repo_name.clone()
                     ]));
@@ -626,6 +4043,41 @@ impl PluginManager for CoffeeManager {
         Ok(nurse_actions)
     }
 
+    async fn patch_plugin_executable_absent(
+        &mut self,
+        plugins: Vec<String>,
+    ) -> Result<Vec<NurseStatus>, CoffeeError> {
+        // the executable does not exist anymore, so there is nothing
+        // to recover, we can only remove the plugin from the configuration
+        // otherwise core lightning will refuse to start.
+        for exec_path in plugins.iter() {
+            for key in ["plugin", "important-plugin"] {
+                if let Err(err) = self.coffee_cln_config.rm_conf(key, Some(exec_path)) {
+                    log::debug!("`{key}={exec_path}` not removed: {}", err.cause);
+                }
+            }
+            self.rm_disable_entries(exec_path);
+            // cln refuses the options of a plugin that is not loaded
+            let removed = self
+                .config
+                .plugins
+                .iter()
+                .filter(|plugin| plugin.exec_path == *exec_path)
+                .cloned()
+                .collect::<Vec<_>>();
+            for plugin in &removed {
+                self.rm_cln_options(plugin);
+            }
+            self.config
+                .plugins
+                .retain(|plugin| plugin.exec_path != *exec_path);
+            log::info!("plugin {exec_path} removed from configuration");
+        }
+        self.flush().await?;
+        self.update_conf().await?;
+        Ok(vec![NurseStatus::PluginConfigurationRemoved(plugins)])
+    }
+
     async fn tip(&mut self, plugin: &str, amount_msat: u64) -> Result<CoffeeTip, CoffeeError> {
         let plugins = self
             .config
@@ -633,11 +4085,15 @@ impl PluginManager for CoffeeManager {
             .iter()
             .filter(|repo_plugin| plugin == repo_plugin.name())
             .collect::<Vec<_>>();
-        let plugin = plugins.first().ok_or(error!(
-            "No plugin with name `{plugin}` found in the plugins installed"
-        ))?;
+        let plugin = plugins.first().ok_or(plugin_not_installed(plugin))?;
 
-        let Some(tipping) = plugin.tipping_info() else {
+        // a lightning address can not be paid with an offer, so when
+        // the funding is not a BOLT12 offer we fall back to the `tipping` one.
+        let Some(offer) = plugin
+            .funding_info()
+            .filter(|funding| funding.starts_with("lno"))
+            .or_else(|| plugin.tipping_info().map(|tipping| tipping.bolt12))
+        else {
             return Err(error!("Plugin `{plugin}` has no tipping information"));
         };
         // FIXME write a tip_plugin method as method
@@ -649,7 +4105,7 @@ impl PluginManager for CoffeeManager {
             .cln(
                 "fetchinvoice",
                 json!({
-                    "offer": tipping.bolt12,
+                    "offer": offer,
                     "amount_msat": amount_msat,
                 }),
             )
@@ -674,7 +4130,34 @@ impl PluginManager for CoffeeManager {
         Ok(tip)
     }
 
+    async fn funding(&self, plugin: &str) -> Result<CoffeeFunding, CoffeeError> {
+        let found = self
+            .config
+            .plugins
+            .iter()
+            .find(|installed| installed.name() == plugin)
+            .cloned()
+            .or_else(|| {
+                self.repos
+                    .values()
+                    .find_map(|repo| repo.get_plugin_by_name(plugin))
+            });
+        let Some(found) = found else {
+            return Err(error!(
+                "plugin `{plugin}` is not installed and not inside the repositories"
+            )
+            .with_kind(CoffeeErrorKind::PluginNotFound {
+                name: plugin.to_owned(),
+            }));
+        };
+        Ok(CoffeeFunding {
+            plugin: found.name(),
+            funding: found.funding_info(),
+        })
+    }
+
     async fn disable(&mut self, plugin: &str) -> Result<(), CoffeeError> {
+        let _lock = self.lock().await?;
         log::debug!("disabling plugin: {plugin}");
 
         let plugin = self
@@ -682,9 +4165,7 @@ impl PluginManager for CoffeeManager {
             .plugins
             .iter_mut()
             .find(|repo_plugin| plugin == repo_plugin.name())
-            .ok_or(error!(
-                "No plugin with name `{plugin}` found in the plugins installed"
-            ))?;
+            .ok_or(plugin_not_installed(plugin))?;
         log::debug!("plugin: {:?}", plugin);
         if plugin.enabled == Some(false) {
             return Err(error!("Plugin `{plugin}` is already disabled"));
@@ -705,6 +4186,7 @@ impl PluginManager for CoffeeManager {
     }
 
     async fn enable(&mut self, plugin: &str) -> Result<(), CoffeeError> {
+        let _lock = self.lock().await?;
         log::debug!("enabling plugin: {plugin}");
 
         let plugin = self
@@ -712,23 +4194,17 @@ impl PluginManager for CoffeeManager {
             .plugins
             .iter_mut()
             .find(|repo_plugin| plugin == repo_plugin.name())
-            .ok_or(error!(
-                "No plugin with name `{plugin}` found in the plugins installed"
-            ))?;
+            .ok_or(plugin_not_installed(plugin))?;
         log::debug!("plugin: {:?}", plugin);
         if plugin.enabled.is_none() || plugin.enabled == Some(true) {
             return Err(error!(
                 "Plugin `{plugin}` is already enabled or enabled by default"
             ));
         }
-        self.coffee_cln_config
-            .rm_conf("disable-plugin", Some(&plugin.exec_path))
-            .map_err(|err| error!("{}", err.cause))?;
-        log::debug!(
-            "Plugin {} was added to CLN configuration successfully",
-            plugin.name()
-        );
         plugin.enabled = Some(true);
+        let exec_path = plugin.exec_path.clone();
+        self.rm_disable_entries(&exec_path);
+        log::debug!("Plugin {exec_path} was added to CLN configuration successfully");
 
         self.flush().await?;
         self.update_conf().await?;
@@ -737,9 +4213,188 @@ impl PluginManager for CoffeeManager {
     }
 }
 
-// FIXME: we need to move on but this is not safe and with the coffee
-// implementation is not true!
-unsafe impl Send for CoffeeManager {}
-unsafe impl Sync for CoffeeManager {}
-unsafe impl Send for CoffeeStorageInfo {}
-unsafe impl Sync for CoffeeStorageInfo {}
+// The coffee manager is shared across async tasks, so fail
+// the build if one of its fields stops being thread safe.
+const _: () = {
+    fn assert_send_sync<T: Send + Sync>() {}
+    #[allow(dead_code)]
+    fn assert_coffee_send_sync() {
+        assert_send_sync::<CoffeeManager>();
+        assert_send_sync::<CoffeeStorageInfo>();
+    }
+};
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use tokio::task::JoinSet;
+
+    use coffee_lib::errors::CoffeeErrorKind;
+    use coffee_lib::plugin::{Plugin, PluginLang};
+    use coffee_lib::plugin_conf::Conf;
+
+    use super::{backup_cln_conf, build_in_use, build_outcome, visit_dependencies};
+    use crate::config::CoffeeConf;
+
+    /// A plugin that declares `dependencies` inside its manifest.
+    fn plugin_with(name: &str, dependencies: &[&str]) -> Plugin {
+        let conf = serde_json::json!({
+            "plugin": {
+                "name": name,
+                "version": "0.1.0",
+                "lang": "pypip",
+                "main": format!("{name}.py"),
+                "dependencies": dependencies,
+            }
+        });
+        Plugin::new(
+            name,
+            &format!("/tmp/{name}"),
+            &format!("/tmp/{name}/{name}.py"),
+            PluginLang::PyPip,
+            Some(serde_json::from_value::<Conf>(conf).unwrap()),
+            None,
+            None,
+        )
+    }
+
+    /// Resolve the dependencies of `name` among the `available`
+    /// plugins, and return their names in the install order.
+    fn resolve(
+        name: &str,
+        available: &[Plugin],
+        installed: &[Plugin],
+    ) -> Result<Vec<String>, String> {
+        let plugin = available.iter().find(|p| p.name() == name).unwrap();
+        let lookup = |name: &str| available.iter().find(|p| p.name() == name).cloned();
+        let mut visiting = vec![plugin.name()];
+        let mut dependencies = vec![];
+        visit_dependencies(plugin, installed, &lookup, &mut visiting, &mut dependencies)
+            .map_err(|err| err.msg().to_owned())?;
+        Ok(dependencies.iter().map(|p| p.name()).collect())
+    }
+
+    #[tokio::test]
+    async fn test_backup_cln_conf_does_not_collide() {
+        let dir = std::env::temp_dir().join(format!("coffee-backup-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config");
+        let path = path.to_str().unwrap();
+        assert_eq!(backup_cln_conf(path).await.unwrap(), None);
+
+        std::fs::write(path, "plugin=/a").unwrap();
+        let first = backup_cln_conf(path).await.unwrap().unwrap();
+        std::fs::write(path, "plugin=/b").unwrap();
+        let second = backup_cln_conf(path).await.unwrap().unwrap();
+        assert_ne!(first, second);
+        assert_eq!(std::fs::read_to_string(first).unwrap(), "plugin=/a");
+        assert_eq!(std::fs::read_to_string(second).unwrap(), "plugin=/b");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_build_outcome_keeps_the_batch() {
+        let mut builds = JoinSet::new();
+        let mut names = HashMap::new();
+        for name in ["ok", "panic"] {
+            let task = builds.spawn(async move {
+                if name == "panic" {
+                    panic!("build panicked");
+                }
+                (name.to_string(), Ok(()))
+            });
+            names.insert(task.id(), name.to_string());
+        }
+
+        let mut results = HashMap::new();
+        while let Some(build) = builds.join_next().await {
+            let (name, result) = build_outcome(&names, build);
+            results.insert(name, result);
+        }
+        assert_eq!(results.len(), 2);
+        assert!(results["ok"].is_ok());
+        let err = results["panic"].as_ref().unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            Some(CoffeeErrorKind::BuildFailed { plugin }) if plugin == "panic"
+        ));
+    }
+
+    #[test]
+    fn test_build_in_use_by_other_target() {
+        let root = "/tmp/coffee/regtest/plugins";
+        let summary = Plugin::new(
+            "summary",
+            &format!("{root}/summary"),
+            &format!("{root}/summary/summary.py"),
+            PluginLang::PyPip,
+            None,
+            None,
+            None,
+        );
+        // the plugin is installed only on the target not in use
+        let config: CoffeeConf = serde_json::from_value(serde_json::json!({
+            "network": "regtest",
+            "config_path": "/tmp/coffee/regtest/coffee.conf",
+            "cln_config_path": null,
+            "cln_root": null,
+            "targets": {
+                "bob": {
+                    "cln_config_path": null,
+                    "cln_root": null,
+                    "config_path": "/tmp/coffee/regtest/targets/bob.conf",
+                    "plugins": [summary],
+                },
+            },
+            "root_path": "/tmp/coffee",
+            "skip_verify": false,
+        }))
+        .unwrap();
+        assert!(config.plugins.is_empty());
+        assert!(build_in_use(&config, &format!("{root}/summary")));
+        assert!(!build_in_use(&config, &format!("{root}/helpme")));
+    }
+
+    #[test]
+    fn test_resolve_dependencies_order() {
+        let available = [
+            plugin_with("app", &["db", "log"]),
+            plugin_with("db", &["log"]),
+            plugin_with("log", &[]),
+        ];
+        assert_eq!(resolve("app", &available, &[]).unwrap(), vec!["log", "db"]);
+        assert!(resolve("log", &available, &[]).unwrap().is_empty());
+
+        // the plugins already installed are skipped
+        let installed = [plugin_with("log", &[])];
+        assert_eq!(resolve("app", &available, &installed).unwrap(), vec!["db"]);
+    }
+
+    #[test]
+    fn test_resolve_dependencies_cycle() {
+        let available = [
+            plugin_with("a", &["b"]),
+            plugin_with("b", &["c"]),
+            plugin_with("c", &["a"]),
+        ];
+        let err = resolve("a", &available, &[]).unwrap_err();
+        assert!(err.contains("a -> b -> c -> a"), "{err}");
+
+        // a plugin that depends on itself
+        let available = [plugin_with("a", &["a"])];
+        let err = resolve("a", &available, &[]).unwrap_err();
+        assert!(err.contains("a -> a"), "{err}");
+    }
+
+    #[test]
+    fn test_resolve_dependencies_missing() {
+        let available = [plugin_with("app", &["db"]), plugin_with("db", &["log"])];
+        let err = resolve("app", &available, &[]).unwrap_err();
+        assert!(
+            err.contains("dependency `log` of plugin `db` is not present"),
+            "{err}"
+        );
+    }
+}
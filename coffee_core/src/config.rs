@@ -1,13 +1,231 @@
 //! Coffee configuration utils.
 use log::info;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::env;
+use std::io::ErrorKind;
+use tokio::fs;
 
 use crate::CoffeeOperation;
+use coffee_lib::error;
+use coffee_lib::errors::{CoffeeError, CoffeeErrorKind};
+use coffee_lib::plugin::Plugin;
 use coffee_lib::utils::check_dir_or_make_if_missing;
-use coffee_lib::{errors::CoffeeError, plugin::Plugin};
 
 use crate::CoffeeArgs;
+
+/// The networks supported by core lightning, that are
+/// also the name of the network directories.
+pub const CLN_NETWORKS: [&str; 5] = ["bitcoin", "testnet", "signet", "regtest", "liquid"];
+
+/// Check that the network is one supported by core lightning,
+/// and return the name of its directory.
+pub fn validate_network(network: &str) -> Result<String, CoffeeError> {
+    let network = network.to_lowercase();
+    // core lightning calls the mainnet `bitcoin`
+    if network == "mainnet" {
+        return Ok("bitcoin".to_owned());
+    }
+    if !CLN_NETWORKS.contains(&network.as_str()) {
+        return Err(error!(
+            "network `{network}` is not supported, use one of: {}",
+            CLN_NETWORKS.join(", ")
+        )
+        .with_kind(CoffeeErrorKind::UnsupportedNetwork { network }));
+    }
+    Ok(network)
+}
+
+/// Resolve the data directory to an absolute path, expanding the `~`
+/// to the `home` directory and following the relative paths from the
+/// current directory. The directory must exist, because the coffee
+/// home is created inside it.
+pub fn resolve_data_dir(data_dir: &str, home: &str) -> Result<String, CoffeeError> {
+    let expanded = match data_dir.strip_prefix('~') {
+        Some("") => home.to_owned(),
+        Some(rest) if rest.starts_with('/') => format!("{home}{rest}"),
+        _ => data_dir.to_owned(),
+    };
+    let resolved = std::fs::canonicalize(&expanded)
+        .map_err(|err| error!("data directory `{data_dir}` is not valid: {err}"))?;
+    if !resolved.is_dir() {
+        return Err(error!("data directory `{data_dir}` is not a directory"));
+    }
+    Ok(resolved.to_string_lossy().to_string())
+}
+
+/// Normalize a gpg key fingerprint, that can be
+/// written in groups separated by spaces.
+pub fn fingerprint(key: &str) -> String {
+    key.split_whitespace().collect::<String>().to_uppercase()
+}
+
+/// The hosting service of a git repository, that decides
+/// how coffee talks with the repository.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Forge {
+    GitHub,
+    GitLab,
+}
+
+impl Forge {
+    /// Parse the forge of the configuration file, e.g. `gitlab`.
+    pub fn parse(forge: &str) -> Option<Self> {
+        match forge.to_lowercase().as_str() {
+            "github" => Some(Forge::GitHub),
+            "gitlab" => Some(Forge::GitLab),
+            _ => None,
+        }
+    }
+
+    /// The forge declared for the `host` inside `forges`, otherwise
+    /// it is guessed from the name of the host, that is GitHub
+    /// unless the host starts with `gitlab.`.
+    pub fn of_host(forges: &BTreeMap<String, Forge>, host: &str) -> Self {
+        let host = host.to_lowercase();
+        if let Some(forge) = forges.get(&host) {
+            return *forge;
+        }
+        if host.starts_with("gitlab.") {
+            Forge::GitLab
+        } else {
+            Forge::GitHub
+        }
+    }
+}
+
+/// The defaults of coffee, read from the `coffee.conf` file inside the
+/// data directory. The file uses the `key=value` format of the core
+/// lightning configuration, and the command line arguments override it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CoffeeDefaults {
+    pub network: Option<String>,
+    /// used only when the data directory is not
+    /// specified on the command line.
+    pub data_dir: Option<String>,
+    /// core lightning root directory, that coffee links
+    /// when it is not linked yet.
+    pub cln_path: Option<String>,
+    pub rpc_path: Option<String>,
+    /// (name, url) of the remotes added when coffee has none.
+    pub remotes: Vec<(String, String)>,
+    /// fingerprints of the gpg keys allowed to sign the plugins.
+    pub trusted_keys: Vec<String>,
+    /// git executable used in place of libgit2 to clone,
+    /// and in place of the `git` inside the `PATH` to upgrade.
+    pub git_path: Option<String>,
+    /// extra arguments of the `git clone` command.
+    pub git_clone_args: Vec<String>,
+    /// proxy of the network operations, e.g. `http://proxy.local:3128`.
+    pub proxy: Option<String>,
+    /// install the plugins without their dependencies.
+    pub offline: bool,
+    /// level of the logs, e.g. `debug`.
+    pub log_level: Option<String>,
+    /// file where the logs are appended.
+    pub log_file: Option<String>,
+    /// the forge of the hosts, e.g. of a self-hosted GitLab.
+    pub forges: BTreeMap<String, Forge>,
+}
+
+impl CoffeeDefaults {
+    /// Parse the `content` of the configuration file at `path`.
+    pub fn parse(path: &str, content: &str) -> Result<Self, CoffeeError> {
+        let mut defaults = CoffeeDefaults::default();
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(error!(
+                    "{path}:{}: `{line}` is not a `key=value` option",
+                    index + 1
+                ));
+            };
+            let value = value.trim().to_owned();
+            match key.trim() {
+                "network" => defaults.network = Some(value),
+                "data-dir" => defaults.data_dir = Some(value),
+                "cln-path" => defaults.cln_path = Some(value),
+                "rpc-path" => defaults.rpc_path = Some(value),
+                "trusted-key" => defaults.trusted_keys.push(fingerprint(&value)),
+                "git-path" => defaults.git_path = Some(value),
+                "proxy" => defaults.proxy = Some(value),
+                "log-level" => defaults.log_level = Some(value),
+                "log-file" => defaults.log_file = Some(value),
+                "offline" => {
+                    defaults.offline = value.parse().map_err(|_| {
+                        error!(
+                            "{path}:{}: `offline` is `true` or `false`, found `{value}`",
+                            index + 1
+                        )
+                    })?
+                }
+                "git-clone-arg" => defaults
+                    .git_clone_args
+                    .extend(value.split_whitespace().map(str::to_owned)),
+                "forge" => {
+                    let forge = value
+                        .split_once(char::is_whitespace)
+                        .and_then(|(host, forge)| Some((host, Forge::parse(forge.trim())?)));
+                    let Some((host, forge)) = forge else {
+                        return Err(error!(
+                            "{path}:{}: a forge needs a host and `github` or `gitlab`, found `{value}`",
+                            index + 1
+                        ));
+                    };
+                    defaults.forges.insert(host.to_lowercase(), forge);
+                }
+                "remote" => {
+                    let Some((name, url)) = value.split_once(char::is_whitespace) else {
+                        return Err(error!(
+                            "{path}:{}: a remote needs a name and an url, found `{value}`",
+                            index + 1
+                        ));
+                    };
+                    defaults
+                        .remotes
+                        .push((name.to_owned(), url.trim().to_owned()));
+                }
+                key => return Err(error!("{path}:{}: unknown option `{key}`", index + 1)),
+            }
+        }
+        Ok(defaults)
+    }
+
+    /// Load the configuration file at `path`, a missing
+    /// file means that there are no defaults.
+    pub async fn load(path: &str) -> Result<Self, CoffeeError> {
+        match fs::read_to_string(path).await {
+            Ok(content) => Self::parse(path, &content),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+/// The target used before coffee supported more than one
+/// core lightning node, that is the one linked by `coffee link`.
+pub const DEFAULT_TARGET: &str = "default";
+
+fn default_target() -> String {
+    DEFAULT_TARGET.to_owned()
+}
+
+/// A core lightning node linked to coffee that is not in use, its
+/// state is restored inside the `CoffeeConf` when coffee switches to it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ClnTarget {
+    pub cln_config_path: Option<String>,
+    pub cln_root: Option<String>,
+    /// path of the configuration file managed by coffee.
+    pub config_path: String,
+    /// the plugins installed on the node.
+    #[serde(default)]
+    pub plugins: Vec<Plugin>,
+}
+
 /// Custom coffee configuration, given by a command line list of arguments
 /// or a coffee configuration file.
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -24,27 +242,81 @@ pub struct CoffeeConf {
     pub cln_config_path: Option<String>,
     /// root cln directory path
     pub cln_root: Option<String>,
+    /// name of the core lightning target in use.
+    #[serde(default = "default_target")]
+    pub target: String,
+    /// the core lightning targets that are not in use.
+    #[serde(default)]
+    pub targets: BTreeMap<String, ClnTarget>,
     /// root path plugin manager
     pub root_path: String,
     /// all plugins that are installed
     /// with the plugin manager.
+    ///
+    /// They are stored in the `CoffeeStorageInfo`.
+    #[serde(skip)]
     pub plugins: Vec<Plugin>,
     /// A flag that indicates if the
     /// user wants to skip the verification
     /// of nurse.
     pub skip_verify: bool,
+    /// the options given at startup, from the command line
+    /// or the configuration file.
+    #[serde(skip)]
+    pub startup: CoffeeStartup,
+}
+
+/// The options that coffee receives at startup, they are not
+/// part of the stored state, so they survive the load of the storage.
+#[derive(Clone, Debug, Default)]
+pub struct CoffeeStartup {
+    /// path of the core lightning rpc socket, when it is
+    /// specified the plugins are started and stopped at
+    /// runtime, other than being written in the configuration.
+    pub rpc_path: Option<String>,
+    /// fingerprints of the gpg keys of the maintainers allowed to
+    /// sign the plugins, when it is not empty a plugin is installed
+    /// only with a valid signature of its manifest.
+    pub trusted_keys: Vec<String>,
+    /// install the plugins that need a newer core lightning
+    /// with a warning, instead of refusing them.
+    pub allow_incompatible: bool,
+    /// git executable used to clone and upgrade the repositories,
+    /// when missing the clone uses libgit2 and the upgrade the
+    /// `git` inside the `PATH`.
+    pub git_path: Option<String>,
+    /// extra arguments of the `git clone` command, used
+    /// only with a custom `git_path`.
+    pub git_clone_args: Vec<String>,
+    /// proxy used by the clones, the upgrades and the
+    /// package managers that build the plugins.
+    pub proxy: Option<String>,
+    /// install the plugins without installing their dependencies,
+    /// using only what is already on the node.
+    pub offline: bool,
+    /// level of the logs, when missing `RUST_LOG` is used.
+    pub log_level: Option<String>,
+    /// file where the logs are appended, instead of the stderr.
+    pub log_file: Option<String>,
+    /// the forge of the hosts, when a host is missing
+    /// the forge is guessed from its name.
+    pub forges: BTreeMap<String, Forge>,
+    /// defaults of the configuration file, that
+    /// are applied after loading the storage.
+    pub defaults: CoffeeDefaults,
 }
 
 impl CoffeeConf {
     /// Create a new instance of the coffee configuration from the args.
     pub async fn new(conf: &dyn CoffeeArgs) -> Result<Self, CoffeeError> {
         #[allow(deprecated)]
-        let mut def_path = env::home_dir().unwrap().to_str().unwrap().to_string();
-        if let Some(data_dir) = &conf.data_dir() {
-            def_path = data_dir.to_owned();
-        }
-
-        def_path = def_path.strip_suffix('/').unwrap_or(&def_path).to_string();
+        let home = env::home_dir().unwrap().to_str().unwrap().to_string();
+        let data_dir = resolve_data_dir(&conf.data_dir().unwrap_or(home.clone()), &home)?;
+        let defaults = CoffeeDefaults::load(&format!("{data_dir}/.coffee/coffee.conf")).await?;
+        let mut def_path = match (conf.data_dir(), &defaults.data_dir) {
+            (None, Some(data_dir)) => resolve_data_dir(data_dir, &home)?,
+            _ => data_dir,
+        };
         def_path += "/.coffee";
         check_dir_or_make_if_missing(def_path.to_string()).await?;
         info!("creating coffee home at {def_path}");
@@ -56,31 +328,45 @@ impl CoffeeConf {
             plugins: vec![],
             cln_config_path: None,
             cln_root: None,
+            target: default_target(),
+            targets: BTreeMap::new(),
             skip_verify: false,
+            startup: CoffeeStartup::default(),
         };
 
-        // check the command line arguments and bind them
-        // inside the coffee conf
+        // the command line arguments override the
+        // defaults of the configuration file.
+        coffee.bind_defaults(defaults)?;
         coffee.bind_cmd_line_params(conf)?;
 
         check_dir_or_make_if_missing(format!("{def_path}/{}", coffee.network)).await?;
         check_dir_or_make_if_missing(format!("{def_path}/{}/plugins", coffee.network)).await?;
         check_dir_or_make_if_missing(format!("{def_path}/repositories")).await?;
-        // after we know all the information regarding
-        // the configuration we try to see if there is
-        // something stored already to the disk.
-        coffee.load_from_file().await?;
 
         Ok(coffee)
     }
 
-    async fn load_from_file(&mut self) -> Result<(), CoffeeError> {
+    fn bind_defaults(&mut self, defaults: CoffeeDefaults) -> Result<(), CoffeeError> {
+        if let Some(network) = &defaults.network {
+            self.network = validate_network(network)?;
+            self.config_path = format!("{}/{}/coffee.conf", self.root_path, self.network);
+        }
+        self.startup.rpc_path = defaults.rpc_path.clone();
+        self.startup.trusted_keys = defaults.trusted_keys.clone();
+        self.startup.git_path = defaults.git_path.clone();
+        self.startup.git_clone_args = defaults.git_clone_args.clone();
+        self.startup.proxy = defaults.proxy.clone();
+        self.startup.offline = defaults.offline;
+        self.startup.log_level = defaults.log_level.clone();
+        self.startup.log_file = defaults.log_file.clone();
+        self.startup.forges = defaults.forges.clone();
+        self.startup.defaults = defaults;
         Ok(())
     }
 
     fn bind_cmd_line_params(&mut self, conf: &dyn CoffeeArgs) -> Result<(), CoffeeError> {
         if let Some(network) = &conf.network() {
-            self.network = network.to_owned();
+            self.network = validate_network(network)?;
             self.config_path = format!("{}/{}/coffee.conf", self.root_path, self.network);
         }
 
@@ -88,12 +374,28 @@ impl CoffeeConf {
             self.config_path = config.to_owned();
         }
 
+        if let Some(rpc_path) = conf.rpc_path() {
+            self.startup.rpc_path = Some(rpc_path);
+        }
+        self.startup.allow_incompatible = conf.allow_incompatible();
+        if let Some(proxy) = conf.proxy() {
+            self.startup.proxy = Some(proxy);
+        }
+        self.startup.offline |= conf.offline();
+        if let Some(log_level) = conf.log_level() {
+            self.startup.log_level = Some(log_level);
+        }
+        if let Some(log_file) = conf.log_file() {
+            self.startup.log_file = Some(log_file);
+        }
+
         // If the command is nurse we skip the verification
         // because nurse is the command that needs
-        // to solve the configuration problems.
+        // to solve the configuration problems, and diagnose
+        // needs to run also with a damaged configuration.
         if !conf.skip_verify() {
             match conf.command() {
-                CoffeeOperation::Nurse(_) => {
+                CoffeeOperation::Nurse(_) | CoffeeOperation::Diagnose => {
                     self.skip_verify = true;
                 }
                 _ => {
@@ -108,3 +410,120 @@ impl CoffeeConf {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use std::collections::BTreeMap;
+
+    use super::{resolve_data_dir, validate_network, CoffeeDefaults, Forge};
+
+    #[test]
+    fn test_validate_network() {
+        assert_eq!(validate_network("regtest").unwrap(), "regtest");
+        assert_eq!(validate_network("Testnet").unwrap(), "testnet");
+        assert_eq!(validate_network("mainnet").unwrap(), "bitcoin");
+        assert!(validate_network("bitcoin-testnet").is_err());
+        assert!(validate_network("").is_err());
+    }
+
+    #[test]
+    fn test_resolve_data_dir() {
+        let home = env::temp_dir().join("coffee-resolve-home");
+        fs::create_dir_all(home.join("coffee")).unwrap();
+        let home = fs::canonicalize(home).unwrap();
+        let home_str = home.to_str().unwrap();
+        let expected = home.join("coffee").to_string_lossy().to_string();
+
+        assert_eq!(resolve_data_dir("~/coffee", home_str).unwrap(), expected);
+        assert_eq!(resolve_data_dir("~/coffee/", home_str).unwrap(), expected);
+        assert_eq!(resolve_data_dir("~", home_str).unwrap(), home_str);
+        assert_eq!(
+            resolve_data_dir(&format!("{home_str}/coffee/../coffee"), home_str).unwrap(),
+            expected
+        );
+        let cwd = env::current_dir().unwrap().to_string_lossy().to_string();
+        assert_eq!(
+            resolve_data_dir(".", home_str).unwrap(),
+            fs::canonicalize(cwd).unwrap().to_string_lossy()
+        );
+        // `~user` is not expanded
+        assert!(resolve_data_dir("~coffee", home_str).is_err());
+        assert!(resolve_data_dir("~/missing", home_str).is_err());
+        fs::remove_dir_all(home).unwrap();
+    }
+
+    #[test]
+    fn test_parse_defaults() {
+        let content = "# coffee defaults
+network=regtest
+rpc-path = /tmp/l1/regtest/lightning-rpc
+
+remote=lightningd https://github.com/lightningd/plugins.git
+trusted-key=1234 abcd 5678 EF90
+git-path=/opt/git/bin/git
+git-clone-arg=--config http.proxy=http://127.0.0.1:3128
+proxy=http://proxy.local:3128
+offline=true
+log-level=debug
+log-file=/var/log/coffee.log
+forge=Git.Example.org gitlab
+";
+        let defaults = CoffeeDefaults::parse("coffee.conf", content).unwrap();
+        assert_eq!(defaults.network.as_deref(), Some("regtest"));
+        assert_eq!(
+            defaults.rpc_path.as_deref(),
+            Some("/tmp/l1/regtest/lightning-rpc")
+        );
+        assert_eq!(defaults.data_dir, None);
+        assert_eq!(defaults.trusted_keys, vec!["1234ABCD5678EF90".to_owned()]);
+        assert_eq!(defaults.git_path.as_deref(), Some("/opt/git/bin/git"));
+        assert_eq!(defaults.proxy.as_deref(), Some("http://proxy.local:3128"));
+        assert!(defaults.offline);
+        assert_eq!(defaults.log_level.as_deref(), Some("debug"));
+        assert_eq!(defaults.log_file.as_deref(), Some("/var/log/coffee.log"));
+        assert_eq!(
+            defaults.forges,
+            BTreeMap::from([("git.example.org".to_owned(), Forge::GitLab)])
+        );
+        assert_eq!(
+            defaults.git_clone_args,
+            vec![
+                "--config".to_owned(),
+                "http.proxy=http://127.0.0.1:3128".to_owned()
+            ]
+        );
+        assert_eq!(
+            defaults.remotes,
+            vec![(
+                "lightningd".to_owned(),
+                "https://github.com/lightningd/plugins.git".to_owned()
+            )]
+        );
+
+        assert!(CoffeeDefaults::parse("coffee.conf", "network").is_err());
+        assert!(CoffeeDefaults::parse("coffee.conf", "remote=lightningd").is_err());
+        assert!(CoffeeDefaults::parse("coffee.conf", "color=blue").is_err());
+        assert!(CoffeeDefaults::parse("coffee.conf", "forge=git.example.org").is_err());
+        assert!(CoffeeDefaults::parse("coffee.conf", "forge=git.example.org gitea").is_err());
+    }
+
+    #[test]
+    fn test_forge_of_host() {
+        let forges = BTreeMap::from([
+            ("git.example.org".to_owned(), Forge::GitLab),
+            ("gitlab.example.org".to_owned(), Forge::GitHub),
+        ]);
+        // the forge declared wins over the name of the host
+        assert_eq!(Forge::of_host(&forges, "Git.Example.org"), Forge::GitLab);
+        assert_eq!(Forge::of_host(&forges, "gitlab.example.org"), Forge::GitHub);
+        assert_eq!(Forge::of_host(&forges, "gitlab.com"), Forge::GitLab);
+        assert_eq!(Forge::of_host(&forges, "github.com"), Forge::GitHub);
+        assert_eq!(
+            Forge::of_host(&BTreeMap::new(), "git.example.org"),
+            Forge::GitHub
+        );
+    }
+}
@@ -1,6 +1,8 @@
 pub mod coffee;
 pub mod config;
 
+mod lock;
+pub mod logger;
 mod nurse;
 
 pub use coffee_lib as lib;
@@ -11,25 +13,54 @@ pub enum CoffeeOperation {
     Link(String),
     /// Unlink coffee from the lightning configuration file
     Unlink(String),
-    /// Install(plugin name, verbose run, dynamic installation)
-    Install(String, bool, bool),
+    /// Install(plugin name, verbose run, dynamic installation, dry run, force, global)
+    Install(String, bool, bool, bool, bool, bool),
     /// List
     List,
-    // Upgrade(name of the repository, verbose run)
-    Upgrade(String, bool),
+    /// Status(plugin name)
+    Status(String),
+    // Upgrade(name of the repository, clean the checkout, verbose run)
+    Upgrade(String, bool, bool),
+    CheckUpdates,
+    /// Logs(plugin name)
+    Logs(String),
+    /// Changelog(plugin name)
+    Changelog(String),
+    /// Try(plugin name, verbose run, install on success)
+    Try(String, bool, bool),
     Remove(String),
+    /// Purge(plugin name, keep the cln options)
+    Purge(String, bool),
     /// Remote(name repository, url of the repository)
     Remote(Option<RemoteAction>, Option<String>),
+    Target(TargetAction),
+    Config,
+    Export,
+    /// Import(path of the state exported)
+    Import(String),
     Show(String),
     /// Search(plugin name)
     Search(String),
+    /// Explain(plugin name)
+    Explain(String),
+    /// Lint(plugin directory)
+    Lint(String),
     Nurse(bool),
+    /// Prune(dry run, remove the cache)
+    Prune(bool, bool),
+    Diagnose,
+    /// Repair(verbose)
+    Repair(bool),
     /// Tip operation
     ///
     /// (plugin_name, amount_msat)
     Tip(String, u64),
+    /// Funding(plugin name)
+    Funding(String),
     /// Disable a plugin(plugin name)
     Disable(String),
+    /// Detach a plugin(plugin name)
+    Detach(String),
     /// Enable a plugin(plugin name)
     Enable(String),
 }
@@ -39,6 +70,16 @@ pub enum RemoteAction {
     Add(String, String),
     Rm(String),
     Inspect(String),
+    /// Refresh(name of the repository, all of them when missing)
+    Refresh(Option<String>),
+    List,
+}
+
+#[derive(Clone, Debug)]
+pub enum TargetAction {
+    /// Add(name of the target, core lightning root directory)
+    Add(String, String),
+    Use(String),
     List,
 }
 
@@ -53,4 +94,45 @@ pub trait CoffeeArgs: Send + Sync {
     fn data_dir(&self) -> Option<String>;
     /// return the skip verify flag
     fn skip_verify(&self) -> bool;
+    /// return true when coffee needs to wait for another
+    /// instance to release the lock, instead of failing
+    fn wait(&self) -> bool {
+        false
+    }
+    /// return the timeout in seconds of the network operations
+    fn network_timeout(&self) -> Option<u64> {
+        None
+    }
+    /// return the timeout in seconds of the build of a plugin
+    fn install_timeout(&self) -> Option<u64> {
+        None
+    }
+    /// return the path of the core lightning rpc socket, that
+    /// coffee uses to start and stop the plugins without a restart
+    fn rpc_path(&self) -> Option<String> {
+        None
+    }
+    /// return true when a plugin that needs a newer core lightning
+    /// can be installed anyway, with a warning
+    fn allow_incompatible(&self) -> bool {
+        false
+    }
+    /// return the proxy of the network operations, like
+    /// the clone of a repository or the build of a plugin
+    fn proxy(&self) -> Option<String> {
+        None
+    }
+    /// return true when the plugins are installed without
+    /// their dependencies, e.g. on a node without network
+    fn offline(&self) -> bool {
+        false
+    }
+    /// return the level of the logs, e.g. `debug`
+    fn log_level(&self) -> Option<String> {
+        None
+    }
+    /// return the file where the logs are appended
+    fn log_file(&self) -> Option<String> {
+        None
+    }
 }
@@ -0,0 +1,71 @@
+//! Lock of the coffee directory, so only one coffee instance
+//! at time changes the storage and the cln configuration.
+use std::fs::{File, OpenOptions};
+
+use fs2::FileExt;
+
+use coffee_lib::error;
+use coffee_lib::errors::CoffeeError;
+
+/// Name of the lock file inside the coffee root directory.
+const LOCK_FILE: &str = "coffee.lock";
+
+/// Advisory lock held by the plugin manager while an operation
+/// changes the stored state, the lock is released when it is dropped.
+pub struct CoffeeLock {
+    file: File,
+}
+
+impl CoffeeLock {
+    /// Acquire the lock of the coffee directory `root_path`.
+    ///
+    /// When another instance holds the lock, we return an error,
+    /// unless `wait` is true, where we wait for the lock instead.
+    pub async fn acquire(root_path: &str, wait: bool) -> Result<Self, CoffeeError> {
+        let path = format!("{root_path}/{LOCK_FILE}");
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .open(&path)
+            .map_err(|err| error!("unable to open the lock file `{path}`: {err}"))?;
+        if file.try_lock_exclusive().is_ok() {
+            return Ok(Self { file });
+        }
+        if !wait {
+            return Err(error!(
+                "another coffee instance is using `{root_path}`, run the command again with `--wait` to wait for it"
+            ));
+        }
+        log::info!("waiting for another coffee instance to release `{path}`");
+        let file = tokio::task::spawn_blocking(move || file.lock_exclusive().map(|_| file))
+            .await
+            .map_err(|err| error!("{err}"))??;
+        Ok(Self { file })
+    }
+}
+
+impl Drop for CoffeeLock {
+    fn drop(&mut self) {
+        if let Err(err) = self.file.unlock() {
+            log::warn!("unable to release the coffee lock: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CoffeeLock;
+
+    #[tokio::test]
+    async fn test_lock_held() {
+        let dir = std::env::temp_dir().join("coffee-lock");
+        std::fs::create_dir_all(&dir).unwrap();
+        let root_path = dir.to_str().unwrap();
+
+        let lock = CoffeeLock::acquire(root_path, false).await.unwrap();
+        assert!(CoffeeLock::acquire(root_path, false).await.is_err());
+        drop(lock);
+        let lock = CoffeeLock::acquire(root_path, false).await;
+        assert!(lock.is_ok());
+    }
+}
@@ -57,9 +57,7 @@ impl Handler for GitRepositoryLocallyAbsentStrategy {
         let coffee_repos = &coffee.repos;
         for repo in coffee_repos.values() {
             log::debug!("Checking if repository {} exists locally", repo.name());
-            let repo_path = repo.url().path_string;
-            let repo_path = Path::new(&repo_path);
-            if !repo_path.exists() {
+            if !repo.health_check().await?.exists {
                 log::debug!("Repository {} is missing locally", repo.name());
                 repos.push(repo.name().to_string());
             }
@@ -74,3 +72,70 @@ impl Handler for GitRepositoryLocallyAbsentStrategy {
         }
     }
 }
+
+/// Strategy for handling the situation when a plugin is present in the core
+/// lightning configuration managed by coffee, but its executable is absent
+/// from the local storage.
+///
+/// This can happens when the plugin directory is removed by hand, and
+/// core lightning will refuse to start with a plugin that does not exist.
+pub struct PluginExecutableAbsentStrategy;
+
+#[async_trait]
+impl Handler for PluginExecutableAbsentStrategy {
+    /// Determines if a plugin inside the core lightning configuration
+    /// managed by coffee points to an executable that does not exist.
+    async fn can_be_applied(
+        self: Arc<Self>,
+        coffee: &CoffeeManager,
+    ) -> Result<Option<Defect>, CoffeeError> {
+        let mut plugins: Vec<String> = Vec::new();
+        for key in ["plugin", "important-plugin"] {
+            let Some(paths) = coffee.coffee_cln_config.fields.get(key) else {
+                continue;
+            };
+            for path in paths {
+                log::debug!("Checking if plugin executable {path} exists locally");
+                if !Path::new(path).exists() {
+                    log::debug!("Plugin executable {path} is missing locally");
+                    plugins.push(path.to_owned());
+                }
+            }
+        }
+
+        if plugins.is_empty() {
+            log::debug!("No plugin executables missing locally");
+            Ok(None)
+        } else {
+            log::debug!("Found {} plugin executables missing locally", plugins.len());
+            Ok(Some(Defect::PluginExecutableAbsent(plugins)))
+        }
+    }
+}
+
+/// Strategy for handling the situation when a repository is stored
+/// in the coffee storage, but coffee is not able to load it.
+///
+/// This can happens when the stored entry is damaged, e.g: it was
+/// changed by hand or written by an incompatible version of coffee.
+pub struct RepositoryCorruptedStrategy;
+
+#[async_trait]
+impl Handler for RepositoryCorruptedStrategy {
+    /// Determines if some repository was skipped during the inventory
+    /// because coffee was not able to load it.
+    async fn can_be_applied(
+        self: Arc<Self>,
+        coffee: &CoffeeManager,
+    ) -> Result<Option<Defect>, CoffeeError> {
+        let mut repos = coffee.corrupted_repos.keys().cloned().collect::<Vec<_>>();
+        if repos.is_empty() {
+            log::debug!("No repositories corrupted");
+            Ok(None)
+        } else {
+            repos.sort();
+            log::debug!("Found {} repositories corrupted", repos.len());
+            Ok(Some(Defect::RepositoryCorrupted(repos)))
+        }
+    }
+}
@@ -0,0 +1,175 @@
+//! Gitlab repository implementation
+//!
+//! From the coffee point of view a gitlab repository is a git
+//! repository like the github one, so we reuse the same
+//! implementation and we only change the kind of repository
+//! that we store on disk.
+use std::any::Any;
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use coffee_lib::errors::CoffeeError;
+use coffee_lib::plugin::Plugin;
+use coffee_lib::progress::ProgressSender;
+use coffee_lib::repository::Repository;
+use coffee_lib::types::response::{CoffeeCommit, CoffeeUpgrade, RepositoryHealth, UpgradeStatus};
+use coffee_lib::url::URL;
+use coffee_storage::model::repository::Kind;
+use coffee_storage::model::repository::Repository as StorageRepository;
+
+use crate::repository::Github;
+use crate::utils::GitBinary;
+
+pub struct Gitlab {
+    inner: Github,
+}
+
+impl Gitlab {
+    /// Create a new instance of the Repository
+    /// with a name and a url
+    pub fn new(name: &str, url: &URL) -> Self {
+        Gitlab {
+            inner: Github::new(name, url),
+        }
+    }
+
+    /// Index the repository again when the HEAD on disk changed.
+    pub async fn refresh_index(&mut self) -> Result<bool, CoffeeError> {
+        self.inner.refresh_index().await
+    }
+
+    /// Set the token used to access a private repository.
+    pub fn with_token(self, token: Option<String>) -> Self {
+        Gitlab {
+            inner: self.inner.with_token(token),
+        }
+    }
+
+    /// Set how long a clone can take before it is aborted.
+    pub fn with_clone_timeout(self, timeout: Option<Duration>) -> Self {
+        Gitlab {
+            inner: self.inner.with_clone_timeout(timeout),
+        }
+    }
+
+    /// Set the urls tried in order when the repository url is not reachable.
+    pub fn with_mirrors(self, mirrors: Vec<String>) -> Self {
+        Gitlab {
+            inner: self.inner.with_mirrors(mirrors),
+        }
+    }
+
+    /// Set the listener of the output of the clone.
+    pub fn with_progress(self, progress: Option<ProgressSender>) -> Self {
+        Gitlab {
+            inner: self.inner.with_progress(progress),
+        }
+    }
+
+    /// Set the git executable used to clone and upgrade the repository.
+    pub fn with_git(self, git: Option<GitBinary>) -> Self {
+        Gitlab {
+            inner: self.inner.with_git(git),
+        }
+    }
+
+    /// Set the proxy used to reach the remote repository.
+    pub fn with_proxy(self, proxy: Option<String>) -> Self {
+        Gitlab {
+            inner: self.inner.with_proxy(proxy),
+        }
+    }
+}
+
+#[async_trait]
+impl Repository for Gitlab {
+    async fn init(&mut self) -> Result<(), CoffeeError> {
+        self.inner.init().await
+    }
+
+    async fn upgrade(
+        &mut self,
+        plugins: &Vec<Plugin>,
+        clean: bool,
+        verbose: bool,
+    ) -> Result<CoffeeUpgrade, CoffeeError> {
+        self.inner.upgrade(plugins, clean, verbose).await
+    }
+
+    async fn refresh(&mut self) -> Result<UpgradeStatus, CoffeeError> {
+        self.inner.refresh().await
+    }
+
+    async fn latest_commit(&self) -> Result<Option<String>, CoffeeError> {
+        self.inner.latest_commit().await
+    }
+
+    async fn plugin_changed(&self, name: &str, from: &str, to: &str) -> Result<bool, CoffeeError> {
+        self.inner.plugin_changed(name, from, to).await
+    }
+
+    async fn read_file(&self, commit: &str, path: &str) -> Result<String, CoffeeError> {
+        self.inner.read_file(commit, path).await
+    }
+
+    async fn log(&self, from: &str, to: &str) -> Result<Vec<CoffeeCommit>, CoffeeError> {
+        self.inner.log(from, to).await
+    }
+
+    async fn checkout(&mut self, git_ref: Option<&str>) -> Result<String, CoffeeError> {
+        self.inner.checkout(git_ref).await
+    }
+
+    async fn recover(&mut self) -> Result<(), CoffeeError> {
+        self.inner.recover().await
+    }
+
+    async fn health_check(&self) -> Result<RepositoryHealth, CoffeeError> {
+        self.inner.health_check().await
+    }
+
+    async fn list(&self) -> Result<Vec<Plugin>, CoffeeError> {
+        self.inner.list().await
+    }
+
+    fn name(&self) -> String {
+        self.inner.name()
+    }
+
+    fn url(&self) -> URL {
+        self.inner.url()
+    }
+
+    fn fetched_at(&self) -> Option<String> {
+        self.inner.fetched_at()
+    }
+
+    fn get_plugin_by_name(&self, name: &str) -> Option<Plugin> {
+        self.inner.get_plugin_by_name(name)
+    }
+
+    fn to_info(&self) -> StorageRepository {
+        StorageRepository::from(self)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl From<&StorageRepository> for Gitlab {
+    fn from(value: &StorageRepository) -> Self {
+        Gitlab {
+            inner: Github::from(value),
+        }
+    }
+}
+
+impl From<&Gitlab> for StorageRepository {
+    fn from(value: &Gitlab) -> Self {
+        let mut repo = StorageRepository::from(&value.inner);
+        repo.kind = Kind::GitLab;
+        repo
+    }
+}
@@ -1,16 +1,30 @@
 //! Github repository implementation
 
+pub mod gitlab;
+pub mod local;
 pub mod repository;
 mod utils;
 
+pub use utils::GitBinary;
+
 #[cfg(test)]
 mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
     use std::{path::Path, sync::Once};
 
+    use tokio::io::{copy_bidirectional, AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
     use coffee_lib::repository::Repository;
+    use coffee_lib::types::response::UpgradeStatus;
     use coffee_lib::url::URL;
-    use std::fs::remove_dir_all;
+    use std::fs::{create_dir_all, remove_dir_all, write};
 
+    use coffee_storage::model::repository::{Kind, Repository as StorageRepository};
+
+    use crate::gitlab::Gitlab;
+    use crate::local::Local;
     use crate::repository::Github;
 
     static INIT: Once = Once::new();
@@ -30,11 +44,263 @@ mod tests {
             "/tmp",
             "https://github.com/lightningd/plugins",
             "lightningd_plugins",
-        );
+        )
+        .unwrap();
         let mut repo = Github::new(name, &url);
         let repo = repo.init().await;
         assert!(repo.is_ok());
         assert!(Path::new(&url.path_string).exists());
         remove_dir_all(&url.path_string).unwrap();
     }
+
+    #[tokio::test]
+    async fn repository_is_cloned_through_proxy() {
+        init();
+        // a stub of an http proxy, that supports only the CONNECT
+        // tunnel and counts the tunnels opened.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = format!("http://{}", listener.local_addr().unwrap());
+        let tunnels = Arc::new(AtomicUsize::new(0));
+        let counter = tunnels.clone();
+        tokio::spawn(async move {
+            while let Ok((mut client, _)) = listener.accept().await {
+                let counter = counter.clone();
+                tokio::spawn(async move {
+                    let mut head = vec![];
+                    let mut byte = [0u8; 1];
+                    while !head.ends_with(b"\r\n\r\n") {
+                        if client.read(&mut byte).await.unwrap_or(0) == 0 {
+                            return;
+                        }
+                        head.push(byte[0]);
+                    }
+                    let head = String::from_utf8_lossy(&head).to_string();
+                    let Some(target) = head
+                        .strip_prefix("CONNECT ")
+                        .and_then(|line| line.split_whitespace().next())
+                    else {
+                        return;
+                    };
+                    let Ok(mut server) = TcpStream::connect(target).await else {
+                        return;
+                    };
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    let _ = client
+                        .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                        .await;
+                    let _ = copy_bidirectional(&mut client, &mut server).await;
+                });
+            }
+        });
+
+        let url = URL::new(
+            "/tmp",
+            "https://github.com/lightningd/plugins",
+            "proxy_plugins",
+        )
+        .unwrap();
+        let mut repo = Github::new("proxy_plugins", &url).with_proxy(Some(proxy));
+        let result = repo.init().await;
+        assert!(result.is_ok(), "{:?}", result);
+        assert!(tunnels.load(Ordering::SeqCst) > 0);
+        remove_dir_all(&url.path_string).unwrap();
+    }
+
+    #[test]
+    fn gitlab_repository_is_stored_with_its_kind() {
+        init();
+        let url = URL::new(
+            "/tmp",
+            "https://gitlab.com/lightningd/plugins",
+            "gitlab_plugins",
+        )
+        .unwrap();
+        let repo = Gitlab::new("gitlab_plugins", &url);
+        let info = StorageRepository::from(&repo);
+        assert!(matches!(info.kind, Kind::GitLab));
+
+        let repo = Gitlab::from(&info);
+        assert_eq!(repo.name(), "gitlab_plugins");
+        assert_eq!(repo.url().url_string, url.url_string);
+    }
+
+    #[tokio::test]
+    async fn local_repository_is_indexed() {
+        init();
+        let dir = std::env::temp_dir().join("coffee-local-repository");
+        create_dir_all(dir.join("folgore")).unwrap();
+        write(dir.join("folgore/requirements.txt"), "").unwrap();
+
+        let url = URL::new(
+            "/tmp",
+            &format!("file://{}", dir.to_str().unwrap()),
+            "local_plugins",
+        )
+        .unwrap();
+        let mut repo = Local::new("local_plugins", &url);
+        assert_eq!(repo.url().path_string, dir.to_str().unwrap());
+        let result = repo.init().await;
+        assert!(result.is_ok(), "{:?}", result);
+        assert!(repo.get_plugin_by_name("folgore").is_some());
+        remove_dir_all(&dir).unwrap();
+
+        // the directory does not exist anymore
+        let mut repo = Local::new("local_plugins", &url);
+        assert!(repo.init().await.is_err());
+    }
+
+    /// Commit all the files of `repo` on top of its HEAD.
+    fn commit_all(repo: &git2::Repository, message: &str) -> String {
+        let mut index = repo.index().unwrap();
+        index
+            .add_all(["*"].iter(), git2::IndexAddOption::DEFAULT, None)
+            .unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("coffee", "coffee@localhost").unwrap();
+        let parent = repo.head().ok().and_then(|head| head.peel_to_commit().ok());
+        let parents = parent.iter().collect::<Vec<_>>();
+        repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            message,
+            &tree,
+            &parents,
+        )
+        .unwrap()
+        .to_string()
+    }
+
+    #[tokio::test]
+    async fn plugin_changed_only_inside_its_directory() {
+        init();
+        let dir =
+            std::env::temp_dir().join(format!("coffee-plugin-changed-{}", std::process::id()));
+        let _ = remove_dir_all(&dir);
+        for plugin in ["folgore", "summary"] {
+            create_dir_all(dir.join(plugin)).unwrap();
+            write(dir.join(plugin).join("requirements.txt"), "").unwrap();
+        }
+        let git = git2::Repository::init(&dir).unwrap();
+        let first = commit_all(&git, "init");
+        write(dir.join("folgore/requirements.txt"), "pyln-client").unwrap();
+        let second = commit_all(&git, "folgore: add pyln-client");
+
+        let url = URL::new(
+            "/tmp",
+            &format!("file://{}", dir.to_str().unwrap()),
+            "changed_plugins",
+        )
+        .unwrap();
+        let mut repo = Local::new("changed_plugins", &url);
+        repo.init().await.unwrap();
+        assert!(repo
+            .plugin_changed("folgore", &first, &second)
+            .await
+            .unwrap());
+        assert!(!repo
+            .plugin_changed("summary", &first, &second)
+            .await
+            .unwrap());
+        // a plugin that is not inside the repository is always changed
+        assert!(repo
+            .plugin_changed("unknown", &first, &second)
+            .await
+            .unwrap());
+        // a commit that is not inside the history is an error
+        assert!(repo
+            .plugin_changed("summary", &"0".repeat(40), &second)
+            .await
+            .is_err());
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn local_repository_upgrades_only_the_changed_plugins() {
+        init();
+        let dir = std::env::temp_dir().join(format!("coffee-local-upgrade-{}", std::process::id()));
+        let _ = remove_dir_all(&dir);
+        for plugin in ["folgore", "summary"] {
+            create_dir_all(dir.join(plugin)).unwrap();
+            write(dir.join(plugin).join("requirements.txt"), "").unwrap();
+        }
+        let url = URL::new(
+            "/tmp",
+            &format!("file://{}", dir.to_str().unwrap()),
+            "upgrade_plugins",
+        )
+        .unwrap();
+        let mut repo = Local::new("upgrade_plugins", &url);
+        repo.init().await.unwrap();
+
+        // both the plugins are installed after the last change
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let mut installed = ["folgore", "summary"]
+            .iter()
+            .map(|name| {
+                let mut plugin = repo.get_plugin_by_name(name).unwrap();
+                plugin.installed_at = Some(now + 60);
+                plugin
+            })
+            .collect::<Vec<_>>();
+        let upgrade = repo.upgrade(&installed, false, false).await.unwrap();
+        assert!(
+            matches!(upgrade.status, UpgradeStatus::UpToDate(..)),
+            "{:?}",
+            upgrade
+        );
+        assert!(upgrade.plugins_effected.is_empty());
+
+        // folgore was changed after its install
+        installed[0].installed_at = Some(now - 60);
+        let upgrade = repo.upgrade(&installed, false, false).await.unwrap();
+        assert!(
+            matches!(upgrade.status, UpgradeStatus::Updated(..)),
+            "{:?}",
+            upgrade
+        );
+        assert_eq!(upgrade.plugins_effected, vec!["folgore"]);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn local_repository_is_refreshed_only_when_changed() {
+        init();
+        let dir = std::env::temp_dir().join(format!("coffee-local-refresh-{}", std::process::id()));
+        let _ = remove_dir_all(&dir);
+        create_dir_all(dir.join("folgore")).unwrap();
+        write(dir.join("folgore/requirements.txt"), "").unwrap();
+        let url = URL::new(
+            "/tmp",
+            &format!("file://{}", dir.to_str().unwrap()),
+            "refresh_plugins",
+        )
+        .unwrap();
+        let mut repo = Local::new("refresh_plugins", &url);
+        repo.init().await.unwrap();
+        let status = repo.refresh().await.unwrap();
+        assert!(
+            matches!(status, UpgradeStatus::UpToDate(..)),
+            "{:?}",
+            status
+        );
+
+        // a new plugin changes the index
+        create_dir_all(dir.join("summary")).unwrap();
+        write(dir.join("summary/requirements.txt"), "").unwrap();
+        let status = repo.refresh().await.unwrap();
+        assert!(matches!(status, UpgradeStatus::Updated(..)), "{:?}", status);
+        assert!(repo.get_plugin_by_name("summary").is_some());
+        let status = repo.refresh().await.unwrap();
+        assert!(
+            matches!(status, UpgradeStatus::UpToDate(..)),
+            "{:?}",
+            status
+        );
+        remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,243 @@
+//! Local repository implementation
+//!
+//! A local repository is a directory on the local machine
+//! that contains some plugins, this is useful to develop
+//! a plugin without pushing it on a remote git repository.
+use std::any::Any;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use async_trait::async_trait;
+use walkdir::WalkDir;
+
+use coffee_lib::errors::CoffeeError;
+use coffee_lib::macros::error;
+use coffee_lib::plugin::Plugin;
+use coffee_lib::repository::Repository;
+use coffee_lib::types::response::{CoffeeCommit, CoffeeUpgrade, RepositoryHealth, UpgradeStatus};
+use coffee_lib::url::URL;
+use coffee_storage::model::repository::Kind;
+use coffee_storage::model::repository::Repository as StorageRepository;
+
+use crate::repository::Github;
+use crate::utils::today;
+
+pub struct Local {
+    inner: Github,
+}
+
+impl Local {
+    /// Create a new instance of the Repository
+    /// with a name and a `file://` url.
+    pub fn new(name: &str, url: &URL) -> Self {
+        let mut url = url.clone();
+        // the plugins are not cloned inside the coffee
+        // directory, we use directly the local directory.
+        url.path_string = url
+            .url_string
+            .strip_prefix("file://")
+            .unwrap_or(&url.url_string)
+            .to_owned();
+        Local {
+            inner: Github::new(name, &url),
+        }
+    }
+}
+
+/// Return the newest modification time of the sources of the
+/// `plugin`, in seconds since the unix epoch. The hidden files
+/// and the build artifacts are not sources, so they are skipped.
+fn newest_mtime(plugin: &Plugin) -> Option<u64> {
+    let artifacts = plugin.lang.build_artifacts(&plugin.root_path);
+    WalkDir::new(&plugin.root_path)
+        .into_iter()
+        .filter_entry(|entry| {
+            let hidden = entry.depth() > 0 && entry.file_name().to_string_lossy().starts_with('.');
+            !hidden
+                && !artifacts
+                    .iter()
+                    .any(|artifact| entry.path() == Path::new(artifact))
+        })
+        .filter_map(|entry| entry.ok()?.metadata().ok()?.modified().ok())
+        .filter_map(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+        .map(|mtime| mtime.as_secs())
+        .max()
+}
+
+#[async_trait]
+impl Repository for Local {
+    /// Init the repository by checking that the local
+    /// directory exists, and index the plugins inside it.
+    async fn init(&mut self) -> Result<(), CoffeeError> {
+        let path = self.inner.url().path_string;
+        if !Path::new(&path).is_dir() {
+            return Err(error!("local repository path `{path}` does not exist"));
+        }
+        self.inner.index_repository().await
+    }
+
+    /// A local repository does not have any upstream, so we
+    /// index it again and we mark as effected the plugins installed
+    /// from it that have some files changed after their install.
+    async fn upgrade(
+        &mut self,
+        plugins: &Vec<Plugin>,
+        _: bool,
+        _: bool,
+    ) -> Result<CoffeeUpgrade, CoffeeError> {
+        self.inner.index_repository().await?;
+        let plugins_effected = self
+            .inner
+            .list()
+            .await?
+            .iter()
+            .filter(|plugin| {
+                let Some(installed) = plugins
+                    .iter()
+                    .find(|installed| installed.name() == plugin.name())
+                else {
+                    return false;
+                };
+                // without the time of the install we can not know
+                // if the plugin is older than its sources.
+                let Some(installed_at) = installed.installed_at else {
+                    return true;
+                };
+                newest_mtime(plugin).map_or(true, |mtime| mtime >= installed_at)
+            })
+            .map(|plugin| plugin.name())
+            .collect::<Vec<_>>();
+        let date = today();
+        let status = if plugins_effected.is_empty() {
+            UpgradeStatus::UpToDate(String::new(), date)
+        } else {
+            UpgradeStatus::Updated(String::new(), date)
+        };
+        Ok(CoffeeUpgrade {
+            repo: self.name(),
+            status,
+            plugins_effected,
+            reloads: vec![],
+            dirty: vec![],
+            failed: HashMap::new(),
+        })
+    }
+
+    /// A local repository does not have any upstream, so the
+    /// refresh only indexes the directory again, and it is
+    /// updated only when the plugins indexed changed.
+    async fn refresh(&mut self) -> Result<UpgradeStatus, CoffeeError> {
+        let indexed = |plugins: Vec<Plugin>| {
+            let mut plugins = plugins
+                .iter()
+                .map(|plugin| format!("{plugin:?}"))
+                .collect::<Vec<_>>();
+            plugins.sort();
+            plugins
+        };
+        let before = indexed(self.inner.list().await?);
+        self.inner.index_repository().await?;
+        let after = indexed(self.inner.list().await?);
+        let date = today();
+        if before == after {
+            return Ok(UpgradeStatus::UpToDate(String::new(), date));
+        }
+        Ok(UpgradeStatus::Updated(String::new(), date))
+    }
+
+    /// A local repository does not have any upstream
+    /// to compare with.
+    async fn latest_commit(&self) -> Result<Option<String>, CoffeeError> {
+        Ok(None)
+    }
+
+    async fn plugin_changed(&self, name: &str, from: &str, to: &str) -> Result<bool, CoffeeError> {
+        self.inner.plugin_changed(name, from, to).await
+    }
+
+    async fn read_file(&self, commit: &str, path: &str) -> Result<String, CoffeeError> {
+        self.inner.read_file(commit, path).await
+    }
+
+    async fn log(&self, from: &str, to: &str) -> Result<Vec<CoffeeCommit>, CoffeeError> {
+        self.inner.log(from, to).await
+    }
+
+    /// The working tree belongs to the user, so coffee
+    /// does not check out anything inside it.
+    async fn checkout(&mut self, git_ref: Option<&str>) -> Result<String, CoffeeError> {
+        Err(error!(
+            "local repository `{}` can not be checked out to `{}`, check it out by hand",
+            self.name(),
+            git_ref.unwrap_or("HEAD")
+        ))
+    }
+
+    /// There is nothing that we can clone again, so
+    /// a local repository can not be recovered.
+    async fn recover(&mut self) -> Result<(), CoffeeError> {
+        Err(error!(
+            "local repository `{}` is missing at `{}`",
+            self.name(),
+            self.inner.url().path_string
+        ))
+    }
+
+    /// A local directory does not need to be a git repository,
+    /// and coffee does not track its commit, so only its
+    /// existence is checked.
+    async fn health_check(&self) -> Result<RepositoryHealth, CoffeeError> {
+        Ok(RepositoryHealth {
+            name: self.name(),
+            exists: Path::new(&self.inner.url().path_string).is_dir(),
+            valid: true,
+            head: None,
+            expected: None,
+        })
+    }
+
+    async fn list(&self) -> Result<Vec<Plugin>, CoffeeError> {
+        self.inner.list().await
+    }
+
+    fn name(&self) -> String {
+        self.inner.name()
+    }
+
+    fn url(&self) -> URL {
+        self.inner.url()
+    }
+
+    fn fetched_at(&self) -> Option<String> {
+        self.inner.fetched_at()
+    }
+
+    fn get_plugin_by_name(&self, name: &str) -> Option<Plugin> {
+        self.inner.get_plugin_by_name(name)
+    }
+
+    fn to_info(&self) -> StorageRepository {
+        StorageRepository::from(self)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl From<&StorageRepository> for Local {
+    fn from(value: &StorageRepository) -> Self {
+        Local {
+            inner: Github::from(value),
+        }
+    }
+}
+
+impl From<&Local> for StorageRepository {
+    fn from(value: &Local) -> Self {
+        let mut repo = StorageRepository::from(&value.inner);
+        repo.kind = Kind::Local;
+        repo
+    }
+}
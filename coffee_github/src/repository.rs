@@ -1,4 +1,6 @@
 use std::any::Any;
+use std::collections::HashMap;
+use std::time::Duration;
 
 use async_trait::async_trait;
 use git2;
@@ -12,16 +14,31 @@ use coffee_lib::errors::CoffeeError;
 use coffee_lib::macros::{commit_id, error, get_repo_info};
 use coffee_lib::plugin::Plugin;
 use coffee_lib::plugin::PluginLang;
-use coffee_lib::plugin_conf::Conf;
+use coffee_lib::plugin_conf::{Manifest, MANIFEST_FILES};
+use coffee_lib::progress::ProgressSender;
 use coffee_lib::repository::Repository;
-use coffee_lib::types::response::CoffeeUpgrade;
+use coffee_lib::types::response::{CoffeeCommit, CoffeeUpgrade, RepositoryHealth, UpgradeStatus};
 use coffee_lib::url::URL;
-use coffee_lib::utils::get_plugin_info_from_path;
+use coffee_lib::utils::resolve_path;
 use coffee_storage::model::repository::Kind;
 use coffee_storage::model::repository::Repository as StorageRepository;
 
 use crate::utils::clone_recursive_fix;
+use crate::utils::git_changed_paths;
+use crate::utils::git_checkout;
+use crate::utils::git_checkout_ref;
+use crate::utils::git_clone;
+use crate::utils::git_clone_with;
+use crate::utils::git_dirty_files;
+use crate::utils::git_head;
+use crate::utils::git_latest_commit;
+use crate::utils::git_log;
+use crate::utils::git_read_file;
+use crate::utils::git_set_origin;
 use crate::utils::git_upgrade;
+use crate::utils::now;
+use crate::utils::GitBinary;
+use crate::utils::CLONE_ATTEMPTS;
 
 pub struct Github {
     /// the url of the repository to be able
@@ -40,6 +57,27 @@ pub struct Github {
     git_head: Option<String>,
     /// the latest commit date of the repository
     last_activity: Option<String>,
+    /// the last time that the repository was fetched
+    fetched_at: Option<String>,
+    /// the token used to authenticate with a private repository,
+    /// it is never stored with the repository information.
+    token: Option<String>,
+    /// the host where the token is valid, the token is never
+    /// sent to a mirror hosted somewhere else.
+    token_host: Option<String>,
+    /// how many times the clone is attempted when
+    /// it fails for a network error.
+    clone_attempts: u32,
+    /// how long a clone can take before it is aborted.
+    clone_timeout: Option<Duration>,
+    /// listener of the output of the clone.
+    progress: Option<ProgressSender>,
+    /// git executable used in place of libgit2.
+    git: Option<GitBinary>,
+    /// proxy of the network operations.
+    proxy: Option<String>,
+    /// the urls tried in order when the `url` is not reachable.
+    mirrors: Vec<String>,
 }
 
 // FIXME: move this inside a utils dir craters
@@ -63,9 +101,265 @@ impl Github {
             branch: "".to_owned(),
             git_head: None,
             last_activity: None,
+            fetched_at: None,
+            token: None,
+            token_host: None,
+            clone_attempts: CLONE_ATTEMPTS,
+            clone_timeout: None,
+            progress: None,
+            git: None,
+            proxy: None,
+            mirrors: vec![],
         }
     }
 
+    /// Set the token used to access a private repository.
+    ///
+    /// The token is valid only for the host of the current url.
+    pub fn with_token(mut self, token: Option<String>) -> Self {
+        self.token_host = token.as_ref().and_then(|_| self.url.host());
+        self.token = token;
+        self
+    }
+
+    /// The token of the repository, when the url in use
+    /// is on the host where the token is valid.
+    fn token(&self) -> Option<&str> {
+        match &self.token_host {
+            Some(host) if self.url.host().as_ref() == Some(host) => self.token.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Set how many times the clone is attempted when
+    /// it fails for a network error.
+    pub fn with_clone_attempts(mut self, attempts: u32) -> Self {
+        self.clone_attempts = attempts.max(1);
+        self
+    }
+
+    /// Set how long a clone can take before it is aborted.
+    pub fn with_clone_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.clone_timeout = timeout;
+        self
+    }
+
+    /// Set the listener of the output of the clone.
+    pub fn with_progress(mut self, progress: Option<ProgressSender>) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Set the git executable used to clone and upgrade the repository.
+    pub fn with_git(mut self, git: Option<GitBinary>) -> Self {
+        self.git = git;
+        self
+    }
+
+    /// Set the proxy used to reach the remote repository.
+    pub fn with_proxy(mut self, proxy: Option<String>) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    /// Set the urls tried in order when the repository url is not reachable.
+    pub fn with_mirrors(mut self, mirrors: Vec<String>) -> Self {
+        self.mirrors = mirrors;
+        self
+    }
+
+    /// Make the `mirror` the url of the repository, the `previous`
+    /// url becomes the first mirror.
+    fn promote_mirror(&mut self, previous: String, mirror: &str) {
+        debug!("repository {} now uses the mirror {mirror}", self.name);
+        self.url.url_string = mirror.to_owned();
+        self.mirrors.retain(|url| url != mirror);
+        self.mirrors.insert(0, previous);
+    }
+
+    /// Clone the repository like `clone_repository`, trying the
+    /// mirrors in order when the url is not reachable.
+    ///
+    /// When all of them fail the error of the url is returned.
+    async fn clone_with_mirrors(&mut self, shallow: bool) -> Result<git2::Repository, CoffeeError> {
+        // a clone that fails because the directory is already there
+        // fails also with the mirrors, and the directory is not ours.
+        let existed = std::path::Path::new(&self.url.path_string).exists();
+        let err = match self.clone_repository(shallow).await {
+            Ok(repo) => return Ok(repo),
+            Err(err) if existed => return Err(err),
+            Err(err) => err,
+        };
+        for mirror in self.mirrors.clone() {
+            log::warn!(
+                "unable to clone `{}`: {err}, trying the mirror `{mirror}`",
+                self.url.url_string
+            );
+            // the failed clone can leave a partial checkout behind
+            let _ = tokio::fs::remove_dir_all(&self.url.path_string).await;
+            let previous = std::mem::replace(&mut self.url.url_string, mirror.clone());
+            match self.clone_repository(shallow).await {
+                Ok(repo) => {
+                    self.promote_mirror(previous, &mirror);
+                    return Ok(repo);
+                }
+                Err(err) => {
+                    log::warn!("unable to clone the mirror `{mirror}`: {err}");
+                    self.url.url_string = previous;
+                }
+            }
+        }
+        Err(err)
+    }
+
+    /// Clone the repository with the git executable when it is set,
+    /// otherwise with libgit2. The token authenticates only with
+    /// libgit2, so a private repository always uses it.
+    async fn clone_repository(&self, shallow: bool) -> Result<git2::Repository, CoffeeError> {
+        match (&self.git, self.token()) {
+            (Some(git), None) => {
+                git_clone_with(
+                    git,
+                    &self.url.url_string,
+                    &self.url.path_string,
+                    self.proxy.as_deref(),
+                    shallow,
+                    self.clone_attempts,
+                    self.clone_timeout,
+                    self.progress.clone(),
+                )
+                .await
+            }
+            _ => {
+                git_clone(
+                    &self.url.url_string,
+                    &self.url.path_string,
+                    self.token(),
+                    self.proxy.as_deref(),
+                    shallow,
+                    self.clone_attempts,
+                    self.clone_timeout,
+                    self.progress.clone(),
+                )
+                .await
+            }
+        }
+    }
+
+    /// Move the local checkout to the latest upstream commit, and
+    /// index the repository again when its content changed.
+    ///
+    /// With `clean` the untracked files are removed before.
+    async fn pull(&mut self, clean: bool, verbose: bool) -> Result<UpgradeStatus, CoffeeError> {
+        let status = self.upgrade_with_mirrors(clean, verbose).await?;
+        self.git_head = Some(status.commit_id());
+        self.last_activity = Some(status.date());
+        self.fetched_at = Some(now());
+        if let UpgradeStatus::Updated(..) = &status {
+            self.index_repository().await?;
+        }
+        Ok(status)
+    }
+
+    /// Upgrade the local checkout from the url of the repository, trying
+    /// the mirrors in order when it is not reachable.
+    ///
+    /// When all of them fail the error of the url is returned.
+    async fn upgrade_with_mirrors(
+        &mut self,
+        clean: bool,
+        verbose: bool,
+    ) -> Result<UpgradeStatus, CoffeeError> {
+        let err = match self.upgrade_checkout(clean, verbose).await {
+            Ok(status) => return Ok(status),
+            Err(err) => err,
+        };
+        for mirror in self.mirrors.clone() {
+            log::warn!(
+                "unable to upgrade `{}` from `{}`: {err}, trying the mirror `{mirror}`",
+                self.name,
+                self.url.url_string
+            );
+            git_set_origin(&self.url.path_string, &mirror)?;
+            // the url in use decides if the token can be sent
+            let previous = std::mem::replace(&mut self.url.url_string, mirror.clone());
+            match self.upgrade_checkout(clean, verbose).await {
+                Ok(status) => {
+                    self.promote_mirror(previous, &mirror);
+                    return Ok(status);
+                }
+                Err(err) => {
+                    log::warn!("unable to upgrade from the mirror `{mirror}`: {err}");
+                    self.url.url_string = previous;
+                }
+            }
+        }
+        if !self.mirrors.is_empty() {
+            git_set_origin(&self.url.path_string, &self.url.url_string)?;
+        }
+        Err(err)
+    }
+
+    /// Fetch the origin of the local checkout and move it to the
+    /// latest upstream commit.
+    async fn upgrade_checkout(
+        &self,
+        clean: bool,
+        verbose: bool,
+    ) -> Result<UpgradeStatus, CoffeeError> {
+        git_upgrade(
+            &self.url.path_string,
+            &self.branch,
+            self.url.git_ref.as_deref(),
+            self.token(),
+            self.proxy.as_deref(),
+            self.git.as_ref(),
+            clean,
+            verbose,
+        )
+        .await
+    }
+
+    /// Return true when the directory of the plugin `name` changed
+    /// between the commits `from` and `to`, a plugin that is not
+    /// inside the repository anymore is always changed.
+    fn plugin_dir_changed(&self, name: &str, from: &str, to: &str) -> Result<bool, CoffeeError> {
+        let Some(plugin) = self.get_plugin_by_name(name) else {
+            return Ok(true);
+        };
+        let plugin_dir = plugin
+            .root_path
+            .strip_prefix(&self.url.path_string)
+            .unwrap_or(&plugin.root_path)
+            .trim_start_matches('/')
+            .to_owned();
+        let changes = git_changed_paths(&self.url.path_string, from, to)?;
+        Ok(changes.iter().any(|path| path.starts_with(&plugin_dir)))
+    }
+
+    /// Index the repository again when the HEAD on disk is not the
+    /// one that was indexed, e.g: the repository was changed by hand.
+    ///
+    /// Return true if the repository was indexed again.
+    pub async fn refresh_index(&mut self) -> Result<bool, CoffeeError> {
+        // a missing repository is a defect that is fixed by the nurse.
+        let Ok(repo) = git2::Repository::open(&self.url.path_string) else {
+            return Ok(false);
+        };
+        let (commit, date) = get_repo_info!(repo);
+        if self.git_head.as_ref() == Some(&commit) {
+            return Ok(false);
+        }
+        debug!(
+            "repository {} HEAD changed from {:?} to {commit}, indexing it again",
+            self.name, self.git_head
+        );
+        self.git_head = Some(commit);
+        self.last_activity = Some(date);
+        self.index_repository().await?;
+        Ok(true)
+    }
+
     /// Index the repository to store information
     /// related to the plugins
     pub async fn index_repository(&mut self) -> Result<(), CoffeeError> {
@@ -76,6 +370,7 @@ impl Github {
             .filter_entry(|dir_entry| !is_hidden(dir_entry));
         let commit_id = &self.git_head;
 
+        self.plugins.clear();
         for plugin_dir in target_dirs {
             match plugin_dir {
                 Ok(plugin_path) => {
@@ -85,106 +380,110 @@ impl Github {
                         .to_os_string()
                         .to_string_lossy()
                         .to_string();
-                    let mut exec_path = None;
-                    let mut plugin_name = None;
-                    let mut plugin_lang = PluginLang::Unknown;
-
                     // check if the plugin has the custom configuration to read.
-                    let mut conf = None;
-                    for file in ["coffee.yaml", "coffee.yml"] {
+                    let mut manifest = None;
+                    for file in MANIFEST_FILES {
                         let conf_path = format!("{}/{}", root_path, file);
                         if let Ok(mut conf_file) = File::open(conf_path).await {
                             let mut conf_str = String::new();
                             conf_file.read_to_string(&mut conf_str).await?;
                             debug!("found plugin configuration: {}", conf_str);
 
-                            let conf_file = serde_yaml::from_str::<Conf>(&conf_str)
-                                .map_err(|err| error!("Coffee manifest malformed: {err}"))?;
-                            plugin_name = Some(conf_file.plugin.name.to_string());
-                            let conf_lang = conf_file.plugin.lang.to_owned();
-                            match conf_lang.as_str() {
-                                "pypip" => plugin_lang = PluginLang::PyPip,
-                                "pypoetry" => plugin_lang = PluginLang::PyPoetry,
-                                "go" => plugin_lang = PluginLang::Go,
-                                "rs" | "rust" => plugin_lang = PluginLang::Rust,
-                                "dart" => plugin_lang = PluginLang::Dart,
-                                "js" => plugin_lang = PluginLang::JavaScript,
-                                "ts" => plugin_lang = PluginLang::TypeScript,
-                                "java" | "kotlin" | "scala" => plugin_lang = PluginLang::JVM,
-                                _ => {
-                                    return Err(error!("language {conf_lang} not supported"));
-                                }
-                            };
-
-                            exec_path = Some(format!("{root_path}/{}", conf_file.plugin.main));
-                            conf = Some(conf_file);
+                            let conf_file = Manifest::parse(&conf_str)?;
+                            manifest = Some(conf_file);
                             break;
                         }
                     }
 
-                    // check if there was a coffee configuration file
-                    if conf.is_none() {
-                        debug!("conf file not found, so we try to guess the language");
-                        // try to understand the language from the file
-                        let files = WalkDir::new(plugin_path.path()).max_depth(1);
-                        for file in files {
-                            let file_dir = file.unwrap().clone();
-                            let (derived_root_path, derived_name) =
-                                get_plugin_info_from_path(file_dir.path())?;
-
-                            plugin_name = Some(derived_name.to_string());
-                            debug!("looking for {derived_name} in {derived_root_path}");
-                            let file_name = file_dir.file_name().to_str().unwrap();
-                            plugin_lang = match file_name {
-                                "requirements.txt" => {
-                                    exec_path =
-                                        Some(format!("{derived_root_path}/{derived_name}.py"));
-                                    PluginLang::PyPip
-                                }
-                                "pyproject.toml" => {
-                                    exec_path =
-                                        Some(format!("{derived_root_path}/{derived_name}.py"));
-                                    PluginLang::PyPoetry
+                    // a manifest can define a collection of plugins that
+                    // share the same directory, so we index all of them.
+                    if let Some(manifest) = manifest {
+                        let confs = manifest.confs();
+                        if confs.is_empty() {
+                            return Err(error!(
+                                "Coffee manifest malformed: no plugin defined in {root_path}"
+                            ));
+                        }
+                        for conf in confs {
+                            let plugin_lang = PluginLang::from_conf(&conf.plugin.lang)?;
+                            let plugin_name = conf.plugin.name.clone();
+                            let exec_path = match resolve_path(&root_path, &conf.plugin.main) {
+                                Ok(exec_path) => exec_path,
+                                Err(err) => {
+                                    log::warn!("plugin {plugin_name} is not indexed: {err}");
+                                    continue;
                                 }
-                                // We dot have any information on standard pattern on where to find the
-                                // plugin exec path, so for now we skip the indexing!
-                                //
-                                // N.B: The plugin should use the coffee manifest, period.
-                                "go.mod" => PluginLang::Go,
-                                "cargo.toml" => PluginLang::Rust,
-                                "pubspec.yaml" => PluginLang::Dart,
-                                "package.json" => PluginLang::JavaScript,
-                                "tsconfig.json" => PluginLang::TypeScript,
-                                _ => PluginLang::Unknown,
                             };
-                            if plugin_lang != PluginLang::Unknown {
-                                break;
-                            }
+                            debug!("exec path is {exec_path}");
+                            let plugin = Plugin::new(
+                                &plugin_name,
+                                &root_path,
+                                &exec_path,
+                                plugin_lang,
+                                Some(conf),
+                                commit_id.clone(),
+                                // The plugin for now is not installed, so it's
+                                // neither enabled or disabled
+                                None,
+                            );
+                            debug!("new plugin: {:?}", plugin);
+                            self.plugins.push(plugin);
                         }
-                    }
-                    debug!("possible plugin language: {:?}", plugin_lang);
-                    if exec_path.is_none() {
-                        let name = plugin_name.clone().unwrap();
-                        log::warn!("we are not able to find the exec path for the plugin {name} written in {:?}, so we do not index it", plugin_lang);
-                        log::info!("we are not able to detect the exec path for the plugin {name}");
                         continue;
                     }
 
+                    debug!("conf file not found, so we try to guess the language");
+                    // try to understand the language from the project files
+                    let derived_root_path = root_path.clone();
+                    let plugin_name = plugin_path.file_name().to_string_lossy().to_string();
+                    debug!("looking for {plugin_name} in {derived_root_path}");
+                    let plugin_lang = PluginLang::detect(plugin_path.path());
+                    debug!("possible plugin language: {:?}", plugin_lang);
+                    let exec_path = match plugin_lang {
+                        PluginLang::PyPip | PluginLang::PyPoetry => {
+                            Some(format!("{derived_root_path}/{plugin_name}.py"))
+                        }
+                        // `go build -o <name>` put the binary inside the plugin root.
+                        PluginLang::Go => Some(format!("{derived_root_path}/{plugin_name}")),
+                        // `cargo build --release` put the binary inside the target dir,
+                        // so we can compute the exec path without building the plugin.
+                        PluginLang::Rust => Some(
+                            PluginLang::Rust
+                                .default_install(
+                                    &derived_root_path,
+                                    &plugin_name,
+                                    false,
+                                    false,
+                                    false,
+                                    None,
+                                    None,
+                                    None,
+                                    None,
+                                )
+                                .await?,
+                        ),
+                        // We dot have any information on standard pattern on where to find the
+                        // plugin exec path, so for now we skip the indexing!
+                        //
+                        // N.B: The plugin should use the coffee manifest, period.
+                        _ => None,
+                    };
                     let Some(exec_path) = exec_path else {
-                        return Err(error!(
-                            "exec path not known, but we should know at this point."
-                        ));
+                        log::warn!("we are not able to find the exec path for the plugin {plugin_name} written in {:?}, so we do not index it", plugin_lang);
+                        log::info!(
+                            "we are not able to detect the exec path for the plugin {plugin_name}"
+                        );
+                        continue;
                     };
 
                     debug!("exec path is {exec_path}");
 
-                    // The language is already contained inside the configuration file.
                     let plugin = Plugin::new(
-                        &plugin_name.unwrap(),
+                        &plugin_name,
                         &root_path,
                         &exec_path,
                         plugin_lang,
-                        conf.clone(),
+                        None,
                         commit_id.clone(),
                         // The plugin for now is not installed, so it's
                         // neither enabled or disabled
@@ -213,7 +512,9 @@ impl Repository for Github {
             "initializing repository: {} {} > {}",
             self.name, &self.url.url_string, &self.url.path_string,
         );
-        let res = git2::Repository::clone(&self.url.url_string, &self.url.path_string);
+        // the full history is needed only to checkout a specific git reference
+        let shallow = self.url.git_ref.is_none();
+        let res = self.clone_with_mirrors(shallow).await;
         match res {
             Ok(repo) => {
                 self.branch = if repo.find_branch("master", git2::BranchType::Local).is_ok() {
@@ -221,21 +522,27 @@ impl Repository for Github {
                 } else {
                     "main".to_owned()
                 };
+                if let Some(git_ref) = &self.url.git_ref {
+                    debug!("pinning repository {} to {git_ref}", self.name);
+                    git_checkout(&repo, git_ref)?;
+                }
                 let (commit, date) = get_repo_info!(repo);
                 self.git_head = Some(commit.clone());
                 self.last_activity = Some(date.clone());
+                self.fetched_at = Some(now());
 
-                let clone = clone_recursive_fix(repo, &self.url).await;
+                let clone = clone_recursive_fix(repo, &self.url, self.proxy.as_deref()).await;
                 self.index_repository().await?;
                 clone
             }
-            Err(err) => Err(error!("{}", err.message())),
+            Err(err) => Err(err),
         }
     }
 
     async fn upgrade(
         &mut self,
         plugins: &Vec<Plugin>,
+        clean: bool,
         verbose: bool,
     ) -> Result<CoffeeUpgrade, CoffeeError> {
         // get the list of the plugins installed from this repository
@@ -258,17 +565,116 @@ impl Repository for Github {
                 plugins_effected.push(plugin_name.to_owned());
             }
         }
+        // the changes made by hand are lost with the upgrade, and the
+        // untracked files can make it fail, so we warn about them.
+        let dirty = git_dirty_files(&self.url.path_string)?;
+        if !dirty.is_empty() && !clean {
+            log::warn!(
+                "the local checkout of `{}` is dirty: {}",
+                self.name,
+                dirty.join(", ")
+            );
+        }
         // pull the changes from the repository
-        let status = git_upgrade(&self.url.path_string, &self.branch, verbose).await?;
-        self.git_head = Some(status.commit_id());
-        self.last_activity = Some(status.date());
+        let status = match self.pull(clean, verbose).await {
+            Ok(status) => status,
+            Err(err) if !dirty.is_empty() && !clean => {
+                return Err(error!(
+                    "{err}, the local checkout of `{}` is dirty, run the upgrade with `--clean` to reset it",
+                    self.name
+                ));
+            }
+            Err(err) => return Err(err),
+        };
+        let new_head = status.commit_id();
+        // only the plugins that have some changes inside their directory
+        // since the commit they were built from need to be rebuilt, the
+        // commit is older than the previous HEAD after a refresh.
+        plugins_effected.retain(|plugin_name| {
+            let Some(installed) = plugins.iter().find(|p| p.name() == *plugin_name) else {
+                return false;
+            };
+            // a pinned plugin is not upgraded with its repository
+            if installed.git_ref.is_some() {
+                return false;
+            }
+            let Some(commit) = &installed.commit else {
+                return true;
+            };
+            if *commit == new_head {
+                return false;
+            }
+            // e.g. the commit is not inside the shallow history
+            self.plugin_dir_changed(plugin_name, commit, &new_head)
+                .unwrap_or(true)
+        });
+        // the repository was already refreshed, but the plugins are still old
+        let status = match status {
+            UpgradeStatus::UpToDate(commit, date) if !plugins_effected.is_empty() => {
+                UpgradeStatus::Updated(commit, date)
+            }
+            status => status,
+        };
+
         Ok(CoffeeUpgrade {
             repo: self.name(),
             status,
             plugins_effected,
+            reloads: vec![],
+            dirty,
+            failed: HashMap::new(),
         })
     }
 
+    async fn refresh(&mut self) -> Result<UpgradeStatus, CoffeeError> {
+        self.pull(false, false).await
+    }
+
+    async fn latest_commit(&self) -> Result<Option<String>, CoffeeError> {
+        let commit = git_latest_commit(
+            &self.url.path_string,
+            &self.branch,
+            self.url.git_ref.as_deref(),
+            self.token(),
+            self.proxy.as_deref(),
+        )?;
+        Ok(Some(commit))
+    }
+
+    async fn plugin_changed(&self, name: &str, from: &str, to: &str) -> Result<bool, CoffeeError> {
+        self.plugin_dir_changed(name, from, to)
+    }
+
+    async fn read_file(&self, commit: &str, path: &str) -> Result<String, CoffeeError> {
+        git_read_file(&self.url.path_string, commit, path)
+    }
+
+    async fn log(&self, from: &str, to: &str) -> Result<Vec<CoffeeCommit>, CoffeeError> {
+        git_log(
+            &self.url.path_string,
+            from,
+            to,
+            self.token(),
+            self.proxy.as_deref(),
+        )
+    }
+
+    async fn checkout(&mut self, git_ref: Option<&str>) -> Result<String, CoffeeError> {
+        let git_ref = match git_ref {
+            Some(git_ref) => git_ref.to_owned(),
+            None => self
+                .git_head
+                .clone()
+                .ok_or_else(|| error!("repository `{}` has no head", self.name))?,
+        };
+        git_checkout_ref(
+            &self.url.path_string,
+            &git_ref,
+            self.token(),
+            self.proxy.as_deref(),
+        )
+    }
+
     async fn recover(&mut self) -> Result<(), CoffeeError> {
         let commit = self.git_head.clone();
 
@@ -279,7 +685,9 @@ impl Repository for Github {
             &self.url.path_string,
         );
         // recursively clone the repository
-        let res = git2::Repository::clone(&self.url.url_string, &self.url.path_string);
+        // the commit to recover can be older than the last one,
+        // so we need the full history.
+        let res = self.clone_with_mirrors(false).await;
         match res {
             Ok(repo) => {
                 // get the commit id
@@ -304,11 +712,34 @@ impl Repository for Github {
                         return Err(error!("{}", err.message()));
                     }
                 }
+                self.fetched_at = Some(now());
 
                 Ok(())
             }
-            Err(err) => Err(error!("{}", err.message())),
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn health_check(&self) -> Result<RepositoryHealth, CoffeeError> {
+        let path = &self.url.path_string;
+        let mut health = RepositoryHealth {
+            name: self.name.clone(),
+            exists: std::path::Path::new(path).is_dir(),
+            valid: false,
+            head: None,
+            expected: self.git_head.clone(),
+        };
+        if !health.exists {
+            return Ok(health);
+        }
+        match git_head(path) {
+            Ok(head) => {
+                health.valid = true;
+                health.head = head;
+            }
+            Err(err) => debug!("repository {} is not valid: {err}", self.name),
         }
+        Ok(health)
     }
 
     /// list of the plugin installed inside the repository.
@@ -326,6 +757,10 @@ impl Repository for Github {
         self.url.clone()
     }
 
+    fn fetched_at(&self) -> Option<String> {
+        self.fetched_at.clone()
+    }
+
     /// search inside the repository a plugin by name.
     fn get_plugin_by_name(&self, name: &str) -> Option<Plugin> {
         for plugin in &self.plugins {
@@ -336,6 +771,10 @@ impl Repository for Github {
         None
     }
 
+    fn to_info(&self) -> StorageRepository {
+        StorageRepository::from(self)
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -350,6 +789,15 @@ impl From<StorageRepository> for Github {
             branch: value.branch,
             git_head: value.git_head,
             last_activity: value.last_activity,
+            fetched_at: value.fetched_at,
+            token: None,
+            token_host: None,
+            clone_attempts: CLONE_ATTEMPTS,
+            clone_timeout: None,
+            progress: None,
+            git: None,
+            proxy: None,
+            mirrors: value.mirrors,
         }
     }
 }
@@ -363,6 +811,15 @@ impl From<&StorageRepository> for Github {
             branch: value.branch.to_owned(),
             git_head: value.git_head.to_owned(),
             last_activity: value.last_activity.to_owned(),
+            fetched_at: value.fetched_at.to_owned(),
+            token: None,
+            token_host: None,
+            clone_attempts: CLONE_ATTEMPTS,
+            clone_timeout: None,
+            progress: None,
+            git: None,
+            proxy: None,
+            mirrors: value.mirrors.to_owned(),
         }
     }
 }
@@ -377,6 +834,8 @@ impl From<Github> for StorageRepository {
             branch: value.branch,
             git_head: value.git_head,
             last_activity: value.last_activity,
+            fetched_at: value.fetched_at,
+            mirrors: value.mirrors,
         }
     }
 }
@@ -391,6 +850,8 @@ impl From<&Github> for StorageRepository {
             branch: value.branch.to_owned(),
             git_head: value.git_head.to_owned(),
             last_activity: value.last_activity.to_owned(),
+            fetched_at: value.fetched_at.to_owned(),
+            mirrors: value.mirrors.to_owned(),
         }
     }
 }
@@ -1,18 +1,61 @@
-use coffee_lib::errors::CoffeeError;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use coffee_lib::errors::{CoffeeError, CoffeeErrorKind};
 use coffee_lib::macros::error;
+use coffee_lib::progress::{emit, forward_lines, ProgressEvent, ProgressSender};
 use coffee_lib::url::URL;
+use coffee_lib::utils::{proxy_env, shell_quote, ScriptOptions};
 use coffee_lib::{commit_id, get_repo_info, sh};
 use log::debug;
 
-use coffee_lib::types::response::UpgradeStatus;
+use coffee_lib::types::response::{CoffeeCommit, UpgradeStatus};
+
+/// The current UTC time, formatted to be shown to the user.
+pub fn now() -> String {
+    format_now("%d/%m/%Y %H:%M")
+}
+
+/// The current UTC date, formatted like the date of a commit.
+pub fn today() -> String {
+    format_now("%d/%m/%Y")
+}
+
+/// Format the current UTC time with the `format` of chrono.
+///
+/// The time is taken from the system clock, because chrono is
+/// built without the `clock` feature.
+fn format_now(format: &str) -> String {
+    use chrono::TimeZone;
+
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or_default();
+    chrono::Utc
+        .timestamp_opt(timestamp, 0)
+        .single()
+        .map(|date| date.format(format).to_string())
+        .unwrap_or_default()
+}
 
-pub async fn clone_recursive_fix(repo: git2::Repository, url: &URL) -> Result<(), CoffeeError> {
+pub async fn clone_recursive_fix(
+    repo: git2::Repository,
+    url: &URL,
+    proxy: Option<&str>,
+) -> Result<(), CoffeeError> {
     let repository = repo.submodules().unwrap_or_default();
     debug!("submodule count: {}", repository.len());
     for (index, sub) in repository.iter().enumerate() {
         debug!("url {}: {}", index + 1, sub.url().unwrap());
         let path = format!("{}/{}", &url.path_string, sub.path().to_str().unwrap());
-        match git2::Repository::clone(sub.url().unwrap(), &path) {
+        match git2::build::RepoBuilder::new()
+            .fetch_options(fetch_options(None, proxy, false))
+            .clone(sub.url().unwrap(), Path::new(&path))
+        {
             // Fix error handling
             Ok(_) => {
                 debug!("added {}", sub.url().unwrap());
@@ -25,20 +68,466 @@ pub async fn clone_recursive_fix(repo: git2::Repository, url: &URL) -> Result<()
     Ok(())
 }
 
+/// Checkout the repository to a git reference, that can be
+/// a branch, a tag or a commit id.
+pub fn git_checkout(repo: &git2::Repository, git_ref: &str) -> Result<(), CoffeeError> {
+    // a branch is known only as remote branch after the clone
+    let object = repo
+        .revparse_single(&format!("origin/{git_ref}"))
+        .or_else(|_| repo.revparse_single(git_ref))
+        .map_err(|err| error!("git reference `{git_ref}` not found: {}", err.message()))?;
+    repo.checkout_tree(&object, Some(git2::build::CheckoutBuilder::new().force()))
+        .map_err(|err| error!("{}", err.message()))?;
+    let commit = object
+        .peel_to_commit()
+        .map_err(|err| error!("{}", err.message()))?;
+    repo.set_head_detached(commit.id())
+        .map_err(|err| error!("{}", err.message()))?;
+    Ok(())
+}
+
+/// Checkout the repository stored at `path` to the `git_ref` and
+/// return its commit id. When the reference is not available
+/// locally, e.g. inside a shallow clone, the whole history and
+/// the tags are fetched before trying again.
+pub fn git_checkout_ref(
+    path: &str,
+    git_ref: &str,
+    token: Option<&str>,
+    proxy: Option<&str>,
+) -> Result<String, CoffeeError> {
+    let repo = git2::Repository::open(path).map_err(|err| error!("{}", err.message()))?;
+    if git_checkout(&repo, git_ref).is_err() {
+        debug!("fetching the history of {path} to find the reference {git_ref}");
+        let mut remote = repo
+            .find_remote("origin")
+            .map_err(|err| error!("{}", err.message()))?;
+        let mut options = fetch_options(token, proxy, false);
+        // i32::MAX is GIT_FETCH_DEPTH_UNSHALLOW in libgit2
+        options.depth(i32::MAX);
+        let url = remote.url().unwrap_or_default().to_owned();
+        remote
+            .fetch::<&str>(&[], Some(&mut options), None)
+            .map_err(|err| fetch_error(&err, &url))?;
+        git_checkout(&repo, git_ref)?;
+    }
+    let (commit, _) = get_repo_info!(repo);
+    Ok(commit)
+}
+
+/// Build the git fetch options, that authenticate with
+/// the token when the repository is private.
+///
+/// With `proxy` the transfer goes through the proxy, otherwise
+/// the proxy is detected from the git configuration.
+///
+/// With `shallow` only the latest commit is fetched.
+pub fn fetch_options<'a>(
+    token: Option<&'a str>,
+    proxy: Option<&'a str>,
+    shallow: bool,
+) -> git2::FetchOptions<'a> {
+    fetch_options_until(token, proxy, shallow, None, None, None)
+}
+
+/// Build the git fetch options like `fetch_options`, but
+/// the transfer is aborted once the `deadline` is passed,
+/// or when `cancel` is set by who waits for the transfer.
+///
+/// With `progress` the output of the transfer is forwarded
+/// line by line to the listener.
+fn fetch_options_until<'a>(
+    token: Option<&'a str>,
+    proxy: Option<&'a str>,
+    shallow: bool,
+    deadline: Option<Instant>,
+    cancel: Option<Arc<AtomicBool>>,
+    progress: Option<ProgressSender>,
+) -> git2::FetchOptions<'a> {
+    let mut callbacks = git2::RemoteCallbacks::new();
+    if deadline.is_some() || cancel.is_some() || progress.is_some() {
+        // returning false from the callbacks aborts the transfer
+        let in_time = Arc::new(move || {
+            deadline.map_or(true, |deadline| Instant::now() < deadline)
+                && !cancel
+                    .as_ref()
+                    .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+        });
+        let sideband = progress.clone();
+        let sideband_in_time = in_time.clone();
+        callbacks.sideband_progress(move |data| {
+            let output = String::from_utf8_lossy(data);
+            for line in output.split(['\r', '\n']).map(str::trim) {
+                if !line.is_empty() {
+                    emit(sideband.as_ref(), ProgressEvent::Output(line.to_owned()));
+                }
+            }
+            sideband_in_time()
+        });
+        // the objects are reported only when the percentage changes
+        let mut last_percent = None;
+        callbacks.transfer_progress(move |stats| {
+            let (received, total) = (stats.received_objects(), stats.total_objects());
+            if progress.is_some() && total > 0 {
+                let percent = received * 100 / total;
+                if last_percent != Some(percent) {
+                    last_percent = Some(percent);
+                    let line = format!("Receiving objects: {percent}% ({received}/{total})");
+                    emit(progress.as_ref(), ProgressEvent::Output(line));
+                }
+            }
+            in_time()
+        });
+    }
+    if let Some(token) = token {
+        let mut attempted = false;
+        callbacks.credentials(move |_, _, _| {
+            // git asks again for the credentials when they are
+            // refused, so we fail instead of looping forever.
+            if attempted {
+                return Err(git2::Error::from_str(
+                    "authentication with the token failed",
+                ));
+            }
+            attempted = true;
+            git2::Cred::userpass_plaintext("oauth2", token)
+        });
+    }
+    let mut proxy_options = git2::ProxyOptions::new();
+    match proxy {
+        Some(proxy) => proxy_options.url(proxy),
+        None => proxy_options.auto(),
+    };
+    let mut options = git2::FetchOptions::new();
+    options.remote_callbacks(callbacks);
+    options.proxy_options(proxy_options);
+    options.download_tags(git2::AutotagOption::All);
+    if shallow {
+        options.depth(1);
+    }
+    options
+}
+
+/// Number of times that a clone is attempted by default,
+/// before returning the error.
+pub const CLONE_ATTEMPTS: u32 = 3;
+
+/// Check if the git error is caused by the network, so it is
+/// worth to try again. An authentication error or a bad url
+/// are going to fail again, so they are not.
+fn is_network_error(err: &git2::Error) -> bool {
+    if matches!(
+        err.code(),
+        git2::ErrorCode::Auth | git2::ErrorCode::Certificate
+    ) {
+        return false;
+    }
+    match err.class() {
+        git2::ErrorClass::Net | git2::ErrorClass::Ssl => true,
+        // a client error, e.g. 404, is not going to change
+        git2::ErrorClass::Http => !err.message().contains("status code: 4"),
+        _ => false,
+    }
+}
+
+/// Build the error of a fetch or a clone of `url` that failed,
+/// the failures of the network have the `Network` kind.
+fn fetch_error(err: &git2::Error, url: &str) -> CoffeeError {
+    let fetch_err = error!("{}", err.message());
+    if !is_network_error(err) {
+        return fetch_err;
+    }
+    fetch_err.with_kind(CoffeeErrorKind::Network {
+        url: url.to_owned(),
+    })
+}
+
+/// Clone the repository at `url` inside the `path` directory, using
+/// the token to authenticate when the repository is private.
+///
+/// A `shallow` clone contains only the latest commit, that is
+/// all coffee needs when the repository is not pinned.
+///
+/// A clone that fails for a network error is attempted again up to
+/// `attempts` times, waiting twice as much after each attempt.
+///
+/// An attempt that takes longer than `timeout` is aborted, what it
+/// downloaded is removed, and it is not attempted again, the error
+/// has the `CloneTimeout` kind.
+#[allow(clippy::too_many_arguments)]
+pub async fn git_clone(
+    url: &str,
+    path: &str,
+    token: Option<&str>,
+    proxy: Option<&str>,
+    shallow: bool,
+    attempts: u32,
+    timeout: Option<Duration>,
+    progress: Option<ProgressSender>,
+) -> Result<git2::Repository, CoffeeError> {
+    let mut backoff = Duration::from_secs(1);
+    let mut attempt = 1;
+    loop {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let cancel = Arc::new(AtomicBool::new(false));
+        // libgit2 blocks the thread, so the clone runs on a blocking
+        // thread to not stop the other clones running at the same time.
+        let clone = {
+            let (url, path) = (url.to_owned(), path.to_owned());
+            let token = token.map(str::to_owned);
+            let proxy = proxy.map(str::to_owned);
+            let progress = progress.clone();
+            let cancel = cancel.clone();
+            tokio::task::spawn_blocking(move || {
+                let result = git2::build::RepoBuilder::new()
+                    .fetch_options(fetch_options_until(
+                        token.as_deref(),
+                        proxy.as_deref(),
+                        shallow,
+                        deadline,
+                        Some(cancel.clone()),
+                        progress,
+                    ))
+                    .clone(&url, Path::new(&path));
+                // nobody waits for a clone that was cancelled, so
+                // we clean up what it left on disk.
+                let timed_out = deadline.is_some_and(|deadline| Instant::now() >= deadline);
+                if result.is_err() && (timed_out || cancel.load(Ordering::Relaxed)) {
+                    let _ = std::fs::remove_dir_all(&path);
+                }
+                result
+            })
+        };
+        // dropping the blocking task does not stop libgit2, so when we stop
+        // waiting for a stuck connection we cancel the transfer, that is
+        // aborted at the next callback, and the clone removes its directory.
+        let result = match timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, clone).await {
+                Ok(result) => result,
+                Err(_) => {
+                    cancel.store(true, Ordering::Relaxed);
+                    return Err(clone_timed_out(url, timeout));
+                }
+            },
+            None => clone.await,
+        }
+        .map_err(|err| error!("{err}"))?;
+        let err = match result {
+            Ok(repo) => return Ok(repo),
+            Err(err) => err,
+        };
+        if let (Some(timeout), Some(deadline)) = (timeout, deadline) {
+            if Instant::now() >= deadline {
+                return Err(clone_timed_out(url, timeout));
+            }
+        }
+        if attempt >= attempts || !is_network_error(&err) {
+            return Err(fetch_error(&err, url));
+        }
+        log::warn!(
+            "clone of {url} failed (attempt {attempt}/{attempts}): {}, retrying in {}s",
+            err.message(),
+            backoff.as_secs()
+        );
+        // git refuses to clone inside a directory that is not empty,
+        // so we remove what was left by the failed attempt.
+        if Path::new(path).exists() {
+            tokio::fs::remove_dir_all(path).await?;
+        }
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+        attempt += 1;
+    }
+}
+
+/// A git executable used in place of libgit2, for the
+/// environments where the clone needs a specific git.
+#[derive(Clone, Debug)]
+pub struct GitBinary {
+    pub path: String,
+    /// extra arguments of the `git clone` command.
+    pub clone_args: Vec<String>,
+}
+
+/// Check if the output of a `git` command that failed reports
+/// a failure of the network, as `is_network_error` does for libgit2.
+fn is_network_output(output: &str) -> bool {
+    const NETWORK_FAILURES: [&str; 7] = [
+        "Could not resolve host",
+        "Failed to connect",
+        "Connection timed out",
+        "Connection reset",
+        "Operation timed out",
+        "early EOF",
+        "The requested URL returned error: 5",
+    ];
+    NETWORK_FAILURES
+        .iter()
+        .any(|failure| output.contains(failure))
+}
+
+/// Clone the repository with the `git` executable, with the same
+/// behavior of `git_clone`: the clones that fail for a network error
+/// are attempted again, and the progress of git is forwarded.
+#[allow(clippy::too_many_arguments)]
+pub async fn git_clone_with(
+    git: &GitBinary,
+    url: &str,
+    path: &str,
+    proxy: Option<&str>,
+    shallow: bool,
+    attempts: u32,
+    timeout: Option<Duration>,
+    progress: Option<ProgressSender>,
+) -> Result<git2::Repository, CoffeeError> {
+    let mut backoff = Duration::from_secs(1);
+    let mut attempt = 1;
+    loop {
+        let mut cmd = tokio::process::Command::new(&git.path);
+        cmd.envs(proxy_env(proxy));
+        cmd.arg("clone");
+        if shallow {
+            cmd.args(["--depth", "1"]);
+        }
+        // git reports the progress only on a terminal, unless asked
+        if progress.is_some() {
+            cmd.arg("--progress");
+        }
+        cmd.args(&git.clone_args).arg(url).arg(path);
+        cmd.stdin(Stdio::null());
+        cmd.stdout(Stdio::null());
+        cmd.stderr(Stdio::piped());
+        // the command is killed when we stop waiting for it.
+        cmd.kill_on_drop(true);
+        let mut child = cmd
+            .spawn()
+            .map_err(|err| error!("unable to run `{}`: {err}", git.path))?;
+        let stderr = child.stderr.take();
+        let run = async {
+            let mut output = String::new();
+            forward_lines(stderr, progress.as_ref(), false, &mut output).await;
+            child.wait().await.map(|status| (status, output))
+        };
+        let result = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, run)
+                .await
+                .map_err(|_| timeout),
+            None => Ok(run.await),
+        };
+        let result = match result {
+            Ok(result) => result,
+            Err(timeout) => {
+                // nobody waits for the clone, so we clean up what it left on disk.
+                let _ = child.kill().await;
+                let _ = tokio::fs::remove_dir_all(path).await;
+                return Err(clone_timed_out(url, timeout));
+            }
+        };
+        let (status, stderr) =
+            result.map_err(|err| error!("unable to run `{}`: {err}", git.path))?;
+        if status.success() {
+            return git2::Repository::open(path).map_err(|err| error!("{}", err.message()));
+        }
+        let network = is_network_output(&stderr);
+        let err = error!(
+            "`{} clone {url}` exited with {status}: {}",
+            git.path,
+            stderr.trim()
+        );
+        if attempt >= attempts || !network {
+            if network {
+                return Err(err.with_kind(CoffeeErrorKind::Network {
+                    url: url.to_owned(),
+                }));
+            }
+            return Err(err);
+        }
+        log::warn!(
+            "clone of {url} failed (attempt {attempt}/{attempts}): {}, retrying in {}s",
+            stderr.trim(),
+            backoff.as_secs()
+        );
+        // git refuses to clone inside a directory that is not empty,
+        // so we remove what was left by the failed attempt.
+        if Path::new(path).exists() {
+            tokio::fs::remove_dir_all(path).await?;
+        }
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+        attempt += 1;
+    }
+}
+
+fn clone_timed_out(url: &str, timeout: Duration) -> CoffeeError {
+    error!("clone of {url} timed out after {}s", timeout.as_secs()).with_kind(
+        CoffeeErrorKind::CloneTimeout {
+            url: url.to_owned(),
+        },
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn git_upgrade(
     path: &str,
     branch: &str,
+    git_ref: Option<&str>,
+    token: Option<&str>,
+    proxy: Option<&str>,
+    git: Option<&GitBinary>,
+    clean: bool,
     verbose: bool,
 ) -> Result<UpgradeStatus, CoffeeError> {
-    use tokio::process::Command;
+    // the path of the executable can contain spaces
+    let git = shell_quote(git.map_or("git", |git| git.path.as_str()));
 
     let repo = git2::Repository::open(path).map_err(|err| error!("{}", err.message()))?;
 
     let (local_commit, _) = get_repo_info!(repo);
+    let shallow = repo.is_shallow();
 
-    let mut cmd = format!("git fetch origin\n");
-    cmd += &format!("git reset --hard origin/{branch}");
-    sh!(path, cmd, verbose);
+    let mut cmd = String::new();
+    match token {
+        // the token should never be part of a script, because
+        // the script is reported inside the logs.
+        Some(token) => {
+            let mut remote = repo
+                .find_remote("origin")
+                .map_err(|err| error!("{}", err.message()))?;
+            let url = remote.url().unwrap_or_default().to_owned();
+            remote
+                .fetch::<&str>(
+                    &[],
+                    Some(&mut fetch_options(Some(token), proxy, shallow)),
+                    None,
+                )
+                .map_err(|err| fetch_error(&err, &url))?;
+        }
+        // a shallow clone need to stay shallow, otherwise git
+        // fetches all the history that we skipped with the clone.
+        None if shallow => cmd += &format!("{git} fetch --depth 1 origin\n"),
+        None => cmd += &format!("{git} fetch origin --tags\n"),
+    }
+    // the hard reset restores the tracked files, but the untracked
+    // ones are left inside the working tree without cleaning it.
+    if clean {
+        cmd += &format!("{git} clean -fd\n");
+    }
+    match git_ref {
+        // the reference can be a branch, so we try first with the remote one
+        Some(git_ref) => {
+            let remote_ref = shell_quote(&format!("origin/{git_ref}"));
+            let git_ref = shell_quote(git_ref);
+            cmd += &format!("{git} reset --hard {remote_ref} || {git} reset --hard {git_ref}")
+        }
+        None => {
+            let remote_branch = shell_quote(&format!("origin/{branch}"));
+            cmd += &format!("{git} reset --hard {remote_branch}")
+        }
+    }
+    // the proxy is not part of the script, because it can
+    // contain the credentials and the script is logged.
+    let options = ScriptOptions::new().verbose(verbose).env(proxy_env(proxy));
+    sh!(path, cmd, options);
 
     let (upstream_commit, date) = get_repo_info!(repo);
 
@@ -48,3 +537,410 @@ pub async fn git_upgrade(
         Ok(UpgradeStatus::Updated(upstream_commit, date))
     }
 }
+
+/// Return the files changed, or not tracked, inside the working
+/// tree of the repository stored at `path`.
+pub fn git_dirty_files(path: &str) -> Result<Vec<String>, CoffeeError> {
+    let repo = git2::Repository::open(path).map_err(|err| error!("{}", err.message()))?;
+    let mut options = git2::StatusOptions::new();
+    options
+        .include_untracked(true)
+        .recurse_untracked_dirs(true)
+        .include_ignored(false);
+    let statuses = repo
+        .statuses(Some(&mut options))
+        .map_err(|err| error!("{}", err.message()))?;
+    Ok(statuses
+        .iter()
+        .filter_map(|entry| entry.path().map(str::to_owned))
+        .collect())
+}
+
+/// Return the commit checked out inside the repository stored at
+/// `path`, `None` when the repository does not have any commit yet.
+pub fn git_head(path: &str) -> Result<Option<String>, CoffeeError> {
+    let repo = git2::Repository::open(path).map_err(|err| error!("{}", err.message()))?;
+    let head = repo
+        .head()
+        .and_then(|head| head.peel_to_commit())
+        .map(|commit| commit.id().to_string())
+        .ok();
+    Ok(head)
+}
+
+/// Point the `origin` remote of the repository stored at `path` to `url`.
+pub fn git_set_origin(path: &str, url: &str) -> Result<(), CoffeeError> {
+    let repo = git2::Repository::open(path).map_err(|err| error!("{}", err.message()))?;
+    repo.remote_set_url("origin", url)
+        .map_err(|err| error!("{}", err.message()))
+}
+
+/// Fetch the remote of the repository stored at `path` and return
+/// the latest upstream commit, without touching the local checkout.
+pub fn git_latest_commit(
+    path: &str,
+    branch: &str,
+    git_ref: Option<&str>,
+    token: Option<&str>,
+    proxy: Option<&str>,
+) -> Result<String, CoffeeError> {
+    let repo = git2::Repository::open(path).map_err(|err| error!("{}", err.message()))?;
+    let shallow = repo.is_shallow();
+    let mut remote = repo
+        .find_remote("origin")
+        .map_err(|err| error!("{}", err.message()))?;
+    let url = remote.url().unwrap_or_default().to_owned();
+    remote
+        .fetch::<&str>(&[], Some(&mut fetch_options(token, proxy, shallow)), None)
+        .map_err(|err| fetch_error(&err, &url))?;
+    let git_ref = git_ref.unwrap_or(branch);
+    let object = repo
+        .revparse_single(&format!("origin/{git_ref}"))
+        .or_else(|_| repo.revparse_single(git_ref))
+        .map_err(|err| error!("git reference `{git_ref}` not found: {}", err.message()))?;
+    let commit = object
+        .peel_to_commit()
+        .map_err(|err| error!("{}", err.message()))?;
+    Ok(commit.id().to_string())
+}
+
+/// Read the file at the relative `file` path, as it is at the
+/// `commit` of the repository stored at `path`.
+pub fn git_read_file(path: &str, commit: &str, file: &str) -> Result<String, CoffeeError> {
+    let repo = git2::Repository::open(path).map_err(|err| error!("{}", err.message()))?;
+    let oid = git2::Oid::from_str(commit).map_err(|err| error!("{}", err.message()))?;
+    let tree = repo
+        .find_commit(oid)
+        .and_then(|commit| commit.tree())
+        .map_err(|err| error!("{}", err.message()))?;
+    let blob = tree
+        .get_path(Path::new(file))
+        .and_then(|entry| entry.to_object(&repo))
+        .and_then(|object| object.peel_to_blob())
+        .map_err(|err| error!("`{file}` not found at commit {commit}: {}", err.message()))?;
+    Ok(String::from_utf8_lossy(blob.content()).to_string())
+}
+
+/// Return the commits reachable from `to` and not from `from`, from
+/// the newest to the oldest, of the repository stored at `path`.
+///
+/// A shallow clone does not contain the commit `from`, so the
+/// whole history is fetched before walking it.
+pub fn git_log(
+    path: &str,
+    from: &str,
+    to: &str,
+    token: Option<&str>,
+    proxy: Option<&str>,
+) -> Result<Vec<CoffeeCommit>, CoffeeError> {
+    use chrono::TimeZone;
+
+    let repo = git2::Repository::open(path).map_err(|err| error!("{}", err.message()))?;
+    let from = git2::Oid::from_str(from).map_err(|err| error!("{}", err.message()))?;
+    let to = git2::Oid::from_str(to).map_err(|err| error!("{}", err.message()))?;
+    if repo.find_commit(from).is_err() || repo.find_commit(to).is_err() {
+        debug!("fetching the history of {path} to find the commit {from}");
+        let mut remote = repo
+            .find_remote("origin")
+            .map_err(|err| error!("{}", err.message()))?;
+        let mut options = fetch_options(token, proxy, false);
+        // i32::MAX is GIT_FETCH_DEPTH_UNSHALLOW in libgit2
+        options.depth(i32::MAX);
+        let url = remote.url().unwrap_or_default().to_owned();
+        remote
+            .fetch::<&str>(&[], Some(&mut options), None)
+            .map_err(|err| fetch_error(&err, &url))?;
+    }
+    let mut walk = repo.revwalk().map_err(|err| error!("{}", err.message()))?;
+    walk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::TIME)
+        .map_err(|err| error!("{}", err.message()))?;
+    walk.push(to).map_err(|err| error!("{}", err.message()))?;
+    walk.hide(from)
+        .map_err(|err| error!("commit {from} not found: {}", err.message()))?;
+
+    let mut commits = vec![];
+    for oid in walk {
+        let oid = oid.map_err(|err| error!("{}", err.message()))?;
+        let commit = repo
+            .find_commit(oid)
+            .map_err(|err| error!("{}", err.message()))?;
+        let date = chrono::Utc
+            .timestamp_opt(commit.time().seconds(), 0)
+            .single()
+            .map(|date| date.format("%d/%m/%Y %H:%M").to_string())
+            .unwrap_or_default();
+        commits.push(CoffeeCommit {
+            id: oid.to_string(),
+            summary: commit.summary().unwrap_or_default().to_owned(),
+            author: commit.author().name().unwrap_or_default().to_owned(),
+            date,
+        });
+    }
+    Ok(commits)
+}
+
+/// Return the list of paths that changed between two commits
+/// of the repository stored at `path`.
+pub fn git_changed_paths(path: &str, from: &str, to: &str) -> Result<Vec<PathBuf>, CoffeeError> {
+    let repo = git2::Repository::open(path).map_err(|err| error!("{}", err.message()))?;
+    let tree_of = |commit_id: &str| {
+        let oid = git2::Oid::from_str(commit_id).map_err(|err| error!("{}", err.message()))?;
+        repo.find_commit(oid)
+            .and_then(|commit| commit.tree())
+            .map_err(|err| error!("{}", err.message()))
+    };
+    let from_tree = tree_of(from)?;
+    let to_tree = tree_of(to)?;
+    let diff = repo
+        .diff_tree_to_tree(Some(&from_tree), Some(&to_tree), None)
+        .map_err(|err| error!("{}", err.message()))?;
+
+    let mut paths = vec![];
+    for delta in diff.deltas() {
+        for file in [delta.old_file(), delta.new_file()] {
+            if let Some(path) = file.path() {
+                paths.push(path.to_path_buf());
+            }
+        }
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use git2::{Error, ErrorClass, ErrorCode};
+
+    use coffee_lib::errors::CoffeeErrorKind;
+
+    use super::{
+        fetch_error, git_clone, git_clone_with, git_dirty_files, git_head, git_log,
+        is_network_error, is_network_output, GitBinary,
+    };
+
+    #[test]
+    fn test_is_network_error() {
+        let timeout = Error::new(ErrorCode::GenericError, ErrorClass::Net, "timed out");
+        assert!(is_network_error(&timeout));
+        let unavailable = Error::new(
+            ErrorCode::GenericError,
+            ErrorClass::Http,
+            "unexpected http status code: 503",
+        );
+        assert!(is_network_error(&unavailable));
+        let not_found = Error::new(
+            ErrorCode::GenericError,
+            ErrorClass::Http,
+            "unexpected http status code: 404",
+        );
+        assert!(!is_network_error(&not_found));
+        let auth = Error::new(ErrorCode::Auth, ErrorClass::Http, "authentication failed");
+        assert!(!is_network_error(&auth));
+    }
+
+    #[test]
+    fn test_fetch_error() {
+        let url = "https://github.com/lightningd/plugins";
+        let timeout = Error::new(ErrorCode::GenericError, ErrorClass::Net, "timed out");
+        let err = fetch_error(&timeout, url);
+        assert!(matches!(
+            err.kind(),
+            Some(CoffeeErrorKind::Network { url: unreachable }) if unreachable == url
+        ));
+        assert_eq!(err.exit_code(), 11);
+        let auth = Error::new(ErrorCode::Auth, ErrorClass::Http, "authentication failed");
+        assert!(fetch_error(&auth, url).kind().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_git_clone_timeout_removes_the_clone() {
+        // a server that accepts the connection and never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/plugins", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (_connection, _) = listener.accept().await.unwrap();
+            std::future::pending::<()>().await;
+        });
+        let path =
+            std::env::temp_dir().join(format!("coffee-clone-timeout-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        let root = path.to_str().unwrap();
+
+        let timeout = std::time::Duration::from_secs(1);
+        let err = git_clone(&url, root, None, None, true, 3, Some(timeout), None)
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err.kind(),
+            Some(CoffeeErrorKind::CloneTimeout { url: timed_out }) if *timed_out == url
+        ));
+        assert_eq!(err.exit_code(), 9);
+
+        // once libgit2 returns, the cancelled clone removes what it left
+        server.abort();
+        for _ in 0..50 {
+            if !path.exists() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_is_network_output() {
+        assert!(is_network_output(
+            "fatal: unable to access 'https://github.com/lightningd/plugins/': Could not resolve host: github.com"
+        ));
+        assert!(!is_network_output(
+            "fatal: repository 'https://github.com/lightningd/missing/' not found"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_git_clone_with() {
+        let path = std::env::temp_dir().join(format!("coffee-clone-with-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        let origin = path.join("origin");
+        let repo = git2::Repository::init(&origin).unwrap();
+        std::fs::write(origin.join("README.md"), "# plugins").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(std::path::Path::new("README.md")).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("coffee", "coffee@localhost").unwrap();
+        let commit = repo
+            .commit(Some("HEAD"), &signature, &signature, "init", &tree, &[])
+            .unwrap();
+
+        let git = GitBinary {
+            path: "git".to_owned(),
+            clone_args: vec![],
+        };
+        let url = format!("file://{}", origin.display());
+        let clone = path.join("clone");
+        let cloned = git_clone_with(
+            &git,
+            &url,
+            clone.to_str().unwrap(),
+            None,
+            true,
+            3,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(cloned.head().unwrap().target(), Some(commit));
+        assert!(clone.join("README.md").exists());
+
+        // a repository that does not exist is not a failure of the network
+        let url = format!("file://{}", path.join("missing").display());
+        let missing = path.join("missing-clone");
+        let err = git_clone_with(
+            &git,
+            &url,
+            missing.to_str().unwrap(),
+            None,
+            true,
+            3,
+            None,
+            None,
+        )
+        .await
+        .err()
+        .unwrap();
+        assert!(err.kind().is_none(), "{err}");
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn test_git_dirty_files() {
+        let path = std::env::temp_dir().join(format!("coffee-dirty-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        git2::Repository::init(&path).unwrap();
+        let root = path.to_str().unwrap();
+        assert!(git_dirty_files(root).unwrap().is_empty());
+
+        std::fs::create_dir_all(path.join("plugin")).unwrap();
+        std::fs::write(path.join("plugin/main.py"), "print()").unwrap();
+        assert_eq!(git_dirty_files(root).unwrap(), vec!["plugin/main.py"]);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn test_git_head() {
+        let path = std::env::temp_dir().join(format!("coffee-head-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        let root = path.to_str().unwrap();
+        assert!(git_head(root).is_err());
+
+        let repo = git2::Repository::init(&path).unwrap();
+        assert_eq!(git_head(root).unwrap(), None);
+
+        let signature = git2::Signature::now("coffee", "coffee@localhost").unwrap();
+        let tree = repo
+            .find_tree(repo.index().unwrap().write_tree().unwrap())
+            .unwrap();
+        let commit = repo
+            .commit(Some("HEAD"), &signature, &signature, "init", &tree, &[])
+            .unwrap();
+        assert_eq!(git_head(root).unwrap(), Some(commit.to_string()));
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn test_git_log() {
+        let path = std::env::temp_dir().join(format!("coffee-git-log-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        let repo = git2::Repository::init(&path).unwrap();
+        let signature = git2::Signature::now("coffee", "coffee@localhost").unwrap();
+        let mut commits = vec![];
+        for (i, summary) in ["init", "add the plugin", "fix the plugin"]
+            .iter()
+            .enumerate()
+        {
+            std::fs::write(path.join("README.md"), format!("# plugins {i}")).unwrap();
+            let mut index = repo.index().unwrap();
+            index.add_path(std::path::Path::new("README.md")).unwrap();
+            let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+            let parents = commits
+                .last()
+                .map(|commit| repo.find_commit(*commit).unwrap())
+                .into_iter()
+                .collect::<Vec<_>>();
+            let parents = parents.iter().collect::<Vec<_>>();
+            let commit = repo
+                .commit(
+                    Some("HEAD"),
+                    &signature,
+                    &signature,
+                    summary,
+                    &tree,
+                    &parents,
+                )
+                .unwrap();
+            commits.push(commit);
+        }
+        let path_str = path.to_str().unwrap();
+        let [first, second, third] = [0, 1, 2].map(|i| commits[i].to_string());
+
+        // the newest commit comes first, and `from` is not included
+        let log = git_log(path_str, &first, &third, None, None).unwrap();
+        let ids = log
+            .iter()
+            .map(|commit| commit.id.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![third.clone(), second.clone()]);
+        assert_eq!(log[0].summary, "fix the plugin");
+        assert_eq!(log[0].author, "coffee");
+        assert!(git_log(path_str, &third, &third, None, None)
+            .unwrap()
+            .is_empty());
+
+        // an unknown commit is fetched from the origin, that is missing here
+        let unknown = "0123456789012345678901234567890123456789";
+        assert!(git_log(path_str, unknown, &third, None, None).is_err());
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+}
@@ -10,7 +10,6 @@ pub mod httpd;
 
 #[actix_web::main]
 async fn main() -> Result<(), CoffeeError> {
-    env_logger::init();
     let cmd = cmd::HttpdArgs::parse();
     let mut coffee = CoffeeManager::new(&cmd).await?;
     coffee.link(&cmd.cln_path).await?;
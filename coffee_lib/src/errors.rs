@@ -1,10 +1,86 @@
 use std::fmt;
 
+use serde::Serialize;
+
+use crate::error;
+
+/// Code of the error returned when a remote with
+/// the same name already exists.
+pub const REMOTE_ALREADY_EXISTS: u64 = 409;
+/// Code of the error returned when a repository
+/// or a plugin can not be found.
+pub const NOT_FOUND: u64 = 404;
+/// Code of the error returned when the network
+/// is not supported.
+pub const UNSUPPORTED_NETWORK: u64 = 400;
+/// Code of the error returned when a remote
+/// repository can not be reached.
+pub const BAD_GATEWAY: u64 = 502;
+/// Code of the error returned when a plugin can not be built in time
+/// or started, or a configuration file can not be written.
+pub const INTERNAL_ERROR: u64 = 500;
+
 /// Specific repository error.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct CoffeeError {
     code: u64,
+    #[serde(rename = "cause")]
     msg: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kind: Option<CoffeeErrorKind>,
+}
+
+/// Errors that the caller may want to handle
+/// instead of just reporting them.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub enum CoffeeErrorKind {
+    /// A remote with the same name already exists,
+    /// with the url of the existing remote.
+    RemoteAlreadyExists { name: String, url: String },
+    /// There is no repository with the name.
+    RepositoryNotFound { name: String },
+    /// The plugin is not inside the repositories,
+    /// or it is not installed.
+    PluginNotFound { name: String },
+    /// The install script of the plugin failed.
+    BuildFailed { plugin: String },
+    /// The configuration file at the path can not be written.
+    ConfigWriteFailed { path: String },
+    /// The network is not supported by core lightning.
+    UnsupportedNetwork { network: String },
+    /// The remote repository at the url can not be reached,
+    /// e.g. the fetch or the clone failed for a network error.
+    Network { url: String },
+    /// Core lightning refused to run the plugin installed.
+    StartFailed { plugin: String },
+    /// The build of the plugin took longer than the install timeout.
+    BuildTimeout { plugin: String },
+    /// The clone of the repository at the url took
+    /// longer than the network timeout.
+    CloneTimeout { url: String },
+    /// The script took longer than the timeout given to it,
+    /// and it was killed together with its processes.
+    ScriptTimeout { script: String },
+}
+
+impl CoffeeErrorKind {
+    /// The code of the errors of this kind.
+    pub fn code(&self) -> u64 {
+        match self {
+            CoffeeErrorKind::RemoteAlreadyExists { .. } => REMOTE_ALREADY_EXISTS,
+            CoffeeErrorKind::RepositoryNotFound { .. } | CoffeeErrorKind::PluginNotFound { .. } => {
+                NOT_FOUND
+            }
+            CoffeeErrorKind::BuildFailed { .. }
+            | CoffeeErrorKind::ConfigWriteFailed { .. }
+            | CoffeeErrorKind::StartFailed { .. }
+            | CoffeeErrorKind::BuildTimeout { .. }
+            | CoffeeErrorKind::CloneTimeout { .. }
+            | CoffeeErrorKind::ScriptTimeout { .. } => INTERNAL_ERROR,
+            CoffeeErrorKind::UnsupportedNetwork { .. } => UNSUPPORTED_NETWORK,
+            CoffeeErrorKind::Network { .. } => BAD_GATEWAY,
+        }
+    }
 }
 
 impl CoffeeError {
@@ -14,6 +90,69 @@ impl CoffeeError {
         CoffeeError {
             code,
             msg: msg.to_string(),
+            kind: None,
+        }
+    }
+
+    /// Build the error returned when the remote `name`
+    /// already exists with the url `url`.
+    pub fn remote_already_exists(name: &str, url: &str) -> Self {
+        error!("repository with name: {name} already exists with url: {url}").with_kind(
+            CoffeeErrorKind::RemoteAlreadyExists {
+                name: name.to_owned(),
+                url: url.to_owned(),
+            },
+        )
+    }
+
+    /// Mark the error with the `kind`, the message is kept
+    /// while the code becomes the one of the kind.
+    pub fn with_kind(mut self, kind: CoffeeErrorKind) -> Self {
+        self.code = kind.code();
+        self.kind = Some(kind);
+        self
+    }
+
+    pub fn code(&self) -> u64 {
+        self.code
+    }
+
+    pub fn kind(&self) -> Option<&CoffeeErrorKind> {
+        self.kind.as_ref()
+    }
+
+    pub fn msg(&self) -> &str {
+        &self.msg
+    }
+
+    /// Exit code of the coffee process that failed with this
+    /// error, the mapping is stable so scripts can rely on it:
+    ///
+    /// - `1`: generic error, without a kind;
+    /// - `2`: the repository or the plugin is not found;
+    /// - `3`: the plugin build failed;
+    /// - `4`: the network is not supported;
+    /// - `5`: the remote already exists;
+    /// - `6`: the configuration can not be written;
+    /// - `7`: core lightning refused to run the plugin;
+    /// - `8`: the build of the plugin timed out;
+    /// - `9`: the clone of the repository timed out;
+    /// - `10`: a script timed out;
+    /// - `11`: the remote repository can not be reached.
+    pub fn exit_code(&self) -> i32 {
+        match self.kind() {
+            None => 1,
+            Some(CoffeeErrorKind::RepositoryNotFound { .. })
+            | Some(CoffeeErrorKind::PluginNotFound { .. }) => 2,
+            Some(CoffeeErrorKind::BuildFailed { .. }) => 3,
+            Some(CoffeeErrorKind::UnsupportedNetwork { .. }) => 4,
+            Some(CoffeeErrorKind::RemoteAlreadyExists { .. }) => 5,
+            Some(CoffeeErrorKind::ConfigWriteFailed { .. }) => 6,
+            Some(CoffeeErrorKind::StartFailed { .. }) => 7,
+            Some(CoffeeErrorKind::BuildTimeout { .. }) => 8,
+            Some(CoffeeErrorKind::CloneTimeout { .. }) => 9,
+            Some(CoffeeErrorKind::ScriptTimeout { .. }) => 10,
+            Some(CoffeeErrorKind::Network { .. }) => 11,
         }
     }
 }
@@ -43,6 +182,7 @@ impl From<std::io::Error> for CoffeeError {
         CoffeeError {
             code: 1,
             msg: format!("{}", err),
+            kind: None,
         }
     }
 }
@@ -52,6 +192,33 @@ impl From<String> for CoffeeError {
         CoffeeError {
             code: 1,
             msg: value,
+            kind: None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{CoffeeError, CoffeeErrorKind, NOT_FOUND};
+    use crate::error;
+
+    #[test]
+    fn test_error_with_kind() {
+        let err = error!("repository with name: lightningd not found").with_kind(
+            CoffeeErrorKind::RepositoryNotFound {
+                name: "lightningd".to_owned(),
+            },
+        );
+        assert_eq!(err.code(), NOT_FOUND);
+        assert!(matches!(
+            err.kind(),
+            Some(CoffeeErrorKind::RepositoryNotFound { name }) if name == "lightningd"
+        ));
+        assert_eq!(err.exit_code(), 2);
+        assert_eq!(error!("generic").exit_code(), 1);
+        assert_eq!(
+            format!("{err}"),
+            "code: 404, msg: repository with name: lightningd not found"
+        );
+    }
+}
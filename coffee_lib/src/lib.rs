@@ -10,6 +10,7 @@ pub mod macros;
 pub mod plugin;
 pub mod plugin_conf;
 pub mod plugin_manager;
+pub mod progress;
 pub mod repository;
 pub mod types;
 pub mod url;
@@ -11,36 +11,21 @@ macro_rules! error {
 
 /// sh macro is the macro that allow to run a
 /// script as a sequence of commands.
+///
+/// The script runs with `utils::run_script`, configured by the
+/// `utils::ScriptOptions` given as the last argument, e.g.
+/// `sh!(root, script, ScriptOptions::new().verbose(true))`.
+///
+/// When the command fails, or its timeout expires, the output
+/// of the command is reported inside the error.
 #[macro_export]
 macro_rules! sh {
-    ($root: expr, $script:expr, $verbose:expr) => {{
-        let script = $script.trim();
-        log::debug!("script: {:?}", script);
-
-        let mut cmd = Command::new("sh");
-        cmd.args(&["-c", &script]);
-        cmd.current_dir($root);
-
-        let command = if $verbose {
-            cmd.spawn()
-                .map_err(|_| error!("Unable to run the command"))?
-                .wait_with_output()
-                .await?
-        } else {
-            cmd.output().await?
-        };
-
-        if !command.status.success() {
-            let mut content = String::from_utf8(command.stderr).unwrap();
-            if content.trim().is_empty() {
-                content = String::from_utf8(command.stdout).unwrap();
-            }
-            return Err(CoffeeError::new(2, &content));
-        }
-    }};
+    ($root:expr, $script:expr, $options:expr) => {
+        $crate::utils::run_script($root, &$script, $options).await?
+    };
 
     ($root:expr, $script:expr) => {
-        sh!($root, $script, false)
+        sh!($root, $script, $crate::utils::ScriptOptions::new())
     };
 }
 
@@ -84,3 +69,166 @@ macro_rules! get_repo_info {
 }
 
 pub use {commit_id, error, get_repo_info, sh};
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::sync::mpsc::unbounded_channel;
+
+    use crate::errors::{CoffeeError, CoffeeErrorKind};
+    use crate::progress::{ProgressEvent, ProgressSender};
+    use crate::utils::ScriptOptions;
+
+    async fn run(script: &str, timeout: Option<Duration>) -> Result<(), CoffeeError> {
+        sh!("/tmp", script, ScriptOptions::new().timeout(timeout));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sh_failure_output() {
+        let err = run("echo coffee && echo espresso >&2 && exit 1", None)
+            .await
+            .unwrap_err();
+        let err = err.to_string();
+        assert!(err.starts_with("code: 2"), "{err}");
+        assert!(err.contains("coffee"), "{err}");
+        assert!(err.contains("espresso"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_sh_progress_output() {
+        async fn run(script: &str, progress: &ProgressSender) -> Result<(), CoffeeError> {
+            sh!(
+                "/tmp",
+                script,
+                ScriptOptions::new().progress(Some(progress))
+            );
+            Ok(())
+        }
+
+        let (sender, mut receiver) = unbounded_channel();
+        run("echo coffee && echo espresso >&2", &sender)
+            .await
+            .unwrap();
+        let mut events = vec![];
+        while let Ok(event) = receiver.try_recv() {
+            events.push(event);
+        }
+        assert!(
+            events.contains(&ProgressEvent::Output("coffee".to_owned())),
+            "{:?}",
+            events
+        );
+        assert!(
+            events.contains(&ProgressEvent::Output("espresso".to_owned())),
+            "{:?}",
+            events
+        );
+
+        let err = run("echo coffee && exit 1", &sender).await.unwrap_err();
+        assert!(err.to_string().contains("coffee"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_sh_progress_timeout() {
+        async fn run(script: &str, progress: &ProgressSender) -> Result<(), CoffeeError> {
+            let options = ScriptOptions::new()
+                .timeout(Some(Duration::from_millis(200)))
+                .progress(Some(progress));
+            sh!("/tmp", script, options);
+            Ok(())
+        }
+
+        let (sender, mut receiver) = unbounded_channel();
+        let err = run("echo coffee && sleep 5", &sender).await.unwrap_err();
+        assert!(
+            matches!(err.kind(), Some(CoffeeErrorKind::ScriptTimeout { .. })),
+            "{err}"
+        );
+        // the output read before the timeout is inside the error
+        assert!(err.to_string().contains("coffee"), "{err}");
+        assert_eq!(
+            receiver.try_recv().ok(),
+            Some(ProgressEvent::Output("coffee".to_owned()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sh_timeout_log() {
+        async fn run(script: &str, log: &str) -> Result<(), CoffeeError> {
+            let options = ScriptOptions::new()
+                .timeout(Some(Duration::from_millis(200)))
+                .log(Some(log));
+            sh!("/tmp", script, options);
+            Ok(())
+        }
+
+        let log = std::env::temp_dir().join(format!("coffee-sh-log-{}", std::process::id()));
+        let log = log.to_str().unwrap();
+        let _ = std::fs::remove_file(log);
+        let result = run("echo coffee && sleep 5", log).await;
+        assert!(result.is_err(), "{:?}", result);
+        let content = std::fs::read_to_string(log).unwrap();
+        assert!(content.contains("coffee"), "{content}");
+        assert!(content.contains("timed out"), "{content}");
+        std::fs::remove_file(log).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sh_env() {
+        async fn run(script: &str) -> Result<(), CoffeeError> {
+            let env = [("COFFEE_ROAST", "dark")];
+            sh!("/tmp", script, ScriptOptions::new().env(env));
+            Ok(())
+        }
+
+        let result = run("test \"$COFFEE_ROAST\" = dark").await;
+        assert!(result.is_ok(), "{:?}", result);
+        let result = run("test \"$COFFEE_ROAST\" = light").await;
+        assert!(result.is_err(), "{:?}", result);
+    }
+
+    #[tokio::test]
+    async fn test_sh_timeout() {
+        let err = run("sleep 5", Some(Duration::from_millis(100)))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                err.kind(),
+                Some(CoffeeErrorKind::ScriptTimeout { script }) if script == "sleep 5"
+            ),
+            "{err}"
+        );
+        assert_eq!(err.exit_code(), 10);
+
+        let result = run("true", Some(Duration::from_secs(5))).await;
+        assert!(result.is_ok(), "{:?}", result);
+    }
+
+    #[tokio::test]
+    async fn test_sh_timeout_kills_descendants() {
+        let marker = std::env::temp_dir().join(format!("coffee-sh-{}", std::process::id()));
+        let _ = std::fs::remove_file(&marker);
+        // the marker is touched by a grandchild of the script, the trailing
+        // `true` stops the shells from replacing themselves with the command.
+        let script = format!(
+            "sh -c 'sh -c \"sleep 1; touch {}\"; true'; true",
+            marker.display()
+        );
+        let result = run(&script, Some(Duration::from_millis(200))).await;
+        assert!(result.is_err(), "{:?}", result);
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert!(
+            !marker.exists(),
+            "a descendant of the script is still running"
+        );
+
+        // the same script without the timeout touches the marker
+        let result = run(&script, None).await;
+        assert!(result.is_ok(), "{:?}", result);
+        assert!(marker.exists());
+        std::fs::remove_file(&marker).unwrap();
+    }
+}
@@ -1,15 +1,23 @@
 //! Plugin module that abstract the concept of a cln plugin
 //! from a plugin manager point of view.
+use std::collections::BTreeMap;
 use std::fmt::{self, Display};
+use std::fs::Permissions;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
 
 use log;
 use serde::{Deserialize, Serialize};
-use tokio::process::Command;
+use tokio::fs;
 
 use crate::errors::CoffeeError;
 use crate::macros::error;
-use crate::plugin_conf::{Conf, Tipping};
+use crate::plugin_conf::{self, platform, Conf, Tipping};
+use crate::progress::ProgressSender;
 use crate::sh;
+use crate::utils::{
+    binary_in_path, expand_env, proxy_env, resolve_path, sha256sum, shell_quote, ScriptOptions,
+};
 
 /// Plugin language definition
 #[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
@@ -22,6 +30,8 @@ pub enum PluginLang {
     JVM,
     JavaScript,
     TypeScript,
+    /// A prebuilt executable downloaded from the release assets.
+    Binary,
     Unknown,
 }
 
@@ -35,6 +45,7 @@ impl Display for PluginLang {
             PluginLang::Go => "go",
             PluginLang::JVM => "jvm",
             PluginLang::TypeScript => "typescrip",
+            PluginLang::Binary => "binary",
             PluginLang::Unknown => "unknown",
         };
         write!(f, "{lang}")
@@ -42,46 +53,250 @@ impl Display for PluginLang {
 }
 
 impl PluginLang {
+    /// The language declared with `lang` inside the coffee manifest.
+    pub fn from_conf(lang: &str) -> Result<Self, CoffeeError> {
+        let lang = match lang {
+            "pypip" => PluginLang::PyPip,
+            "pypoetry" => PluginLang::PyPoetry,
+            "go" => PluginLang::Go,
+            "rs" | "rust" => PluginLang::Rust,
+            "dart" => PluginLang::Dart,
+            "js" => PluginLang::JavaScript,
+            "ts" => PluginLang::TypeScript,
+            "java" | "kotlin" | "scala" => PluginLang::JVM,
+            "binary" => PluginLang::Binary,
+            _ => return Err(error!("language {lang} not supported")),
+        };
+        Ok(lang)
+    }
+
+    /// The binaries needed to build and run a plugin
+    /// written in this language.
+    pub fn required_binaries(&self) -> Vec<&'static str> {
+        match self {
+            PluginLang::PyPip => vec!["python3"],
+            PluginLang::PyPoetry => vec!["python3"],
+            PluginLang::Go => vec!["go"],
+            PluginLang::Rust => vec!["cargo"],
+            PluginLang::JavaScript | PluginLang::TypeScript => vec!["node", "npm"],
+            PluginLang::Dart => vec!["dart"],
+            PluginLang::JVM => vec!["java"],
+            PluginLang::Binary => vec!["curl"],
+            PluginLang::Unknown => vec![],
+        }
+    }
+
+    /// The binaries of `required_binaries` that are used only by
+    /// the default install, and not by an `install` script.
+    pub fn default_install_binaries(&self) -> Vec<&'static str> {
+        match self {
+            PluginLang::JavaScript | PluginLang::TypeScript => vec!["npm"],
+            _ => vec![],
+        }
+    }
+
+    /// Infer the language of the plugin from the files
+    /// contained inside the plugin directory.
+    ///
+    /// `PluginLang::Unknown` is returned only when none
+    /// of the known project files is found.
+    pub fn detect(path: &Path) -> PluginLang {
+        let read = |file: &str| std::fs::read_to_string(path.join(file)).ok();
+        let exists = |file: &str| path.join(file).exists();
+
+        if exists("Cargo.toml") {
+            return PluginLang::Rust;
+        }
+        if exists("go.mod") || is_go_main_package(path) {
+            return PluginLang::Go;
+        }
+        let pyproject = read("pyproject.toml");
+        if pyproject
+            .as_ref()
+            .is_some_and(|content| content.contains("[tool.poetry]"))
+        {
+            return PluginLang::PyPoetry;
+        }
+        if exists("requirements.txt") {
+            return PluginLang::PyPip;
+        }
+        if pyproject.is_some() {
+            return PluginLang::PyPoetry;
+        }
+        if let Some(package) = read("package.json") {
+            if exists("tsconfig.json") || package.contains("\"typescript\"") {
+                return PluginLang::TypeScript;
+            }
+            return PluginLang::JavaScript;
+        }
+        if exists("pubspec.yaml") {
+            return PluginLang::Dart;
+        }
+        if ["pom.xml", "build.gradle", "build.gradle.kts", "build.sbt"]
+            .iter()
+            .any(|file| exists(file))
+        {
+            return PluginLang::JVM;
+        }
+        PluginLang::Unknown
+    }
+
+    /// The virtual environment used to install the plugin in
+    /// the directory `path`, only for the python plugins.
+    pub fn venv(&self, path: &str) -> Option<String> {
+        match self {
+            PluginLang::PyPip | PluginLang::PyPoetry => Some(format!("{path}/.venv")),
+            _ => None,
+        }
+    }
+
+    /// The directories created by the build of a plugin in
+    /// the directory `path`, that can be deleted to reclaim
+    /// disk space without touching the sources.
+    pub fn build_artifacts(&self, path: &str) -> Vec<String> {
+        let dirs: &[&str] = match self {
+            PluginLang::PyPip => &[".venv"],
+            PluginLang::PyPoetry => &[".venv", ".poetry"],
+            PluginLang::Rust => &["target"],
+            PluginLang::JavaScript | PluginLang::TypeScript => &["node_modules"],
+            PluginLang::Dart => &[".dart_tool"],
+            PluginLang::JVM => &["build", ".gradle"],
+            PluginLang::Go | PluginLang::Binary | PluginLang::Unknown => &[],
+        };
+        dirs.iter().map(|dir| format!("{path}/{dir}")).collect()
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub async fn default_install(
         &self,
         path: &str,
         name: &str,
         verbose: bool,
+        install_requirements: bool,
+        dry_run: bool,
+        progress: Option<&ProgressSender>,
+        log: Option<&str>,
+        cache: Option<&str>,
+        proxy: Option<&str>,
     ) -> Result<String, CoffeeError> {
+        let options = ScriptOptions::new()
+            .verbose(verbose)
+            .dry_run(dry_run)
+            .progress(progress)
+            .log(log)
+            .env(build_env(cache, proxy));
         match self {
             PluginLang::PyPip => {
-                /* 1. RUN PIP install or poetry install
-                 * 2. return the path of the main file */
-                let script = "pip3 install -r requirements.txt --break-system-packages";
-                sh!(path, script, verbose);
-                let main_file = format!("{path}/{name}.py");
-                Ok(main_file)
+                /* 1. RUN PIP install inside the plugin virtual environment
+                 * 2. return the path of the launcher */
+                if !install_requirements {
+                    return Ok(format!("{path}/{name}.py"));
+                }
+                let venv = format!("{path}/.venv");
+                let script = format!("python3 -m venv {}", shell_quote(&venv));
+                sh!(path, script, options.clone());
+                let pip = shell_quote(&format!("{venv}/bin/pip"));
+                let script = format!("{pip} install -r requirements.txt");
+                sh!(path, script, options.clone());
+                python_launcher(path, name, &venv, dry_run).await
             }
             PluginLang::PyPoetry => {
-                let mut script = "pip3 install poetry\n".to_string();
-                script += "poetry export -f requirements.txt --output requirements.txt\n";
-                script += "pip3 install -r requirements.txt";
-                sh!(path, script, verbose);
-                Ok(format!("{path}/{name}.py"))
+                if !install_requirements {
+                    return Ok(format!("{path}/{name}.py"));
+                }
+                // without poetry in the PATH it is installed inside its own virtual
+                // environment, so the system packages are not touched.
+                let poetry = if binary_in_path("poetry") {
+                    "poetry".to_owned()
+                } else {
+                    let poetry_venv = format!("{path}/.poetry");
+                    let script = format!("python3 -m venv {}", shell_quote(&poetry_venv));
+                    sh!(path, script, options.clone());
+                    let pip = shell_quote(&format!("{poetry_venv}/bin/pip"));
+                    let script = format!("{pip} install poetry");
+                    sh!(path, script, options.clone());
+                    shell_quote(&format!("{poetry_venv}/bin/poetry"))
+                };
+                // poetry manages the virtual environment of the plugin, and
+                // we keep it inside the plugin directory.
+                let script =
+                    format!("POETRY_VIRTUALENVS_IN_PROJECT=true {poetry} install --no-root");
+                sh!(path, script, options.clone());
+                let venv = format!("{path}/.venv");
+                python_launcher(path, name, &venv, dry_run).await
+            }
+            PluginLang::Go => {
+                if install_requirements {
+                    if !binary_in_path("go") {
+                        return Err(error!(
+                            "`go` not found in the PATH, it is required to build the plugin `{name}`"
+                        ));
+                    }
+                    let build = format!(
+                        "go build -o {} {}",
+                        shell_quote(name),
+                        shell_quote(&go_main_package(path, name))
+                    );
+                    // without the go.mod file we are in the old GOPATH mode,
+                    // so we need to tell to go to not look for a module.
+                    let script = if Path::new(&format!("{path}/go.mod")).exists() {
+                        build
+                    } else {
+                        format!("GO111MODULE=off {build}")
+                    };
+                    sh!(path, script, options.clone());
+                }
+                Ok(format!("{path}/{name}"))
+            }
+            PluginLang::Rust => {
+                if install_requirements {
+                    if !binary_in_path("cargo") {
+                        return Err(error!(
+                            "`cargo` not found in the PATH, it is required to build the plugin `{name}`"
+                        ));
+                    }
+                    let script = "cargo build --release";
+                    sh!(path, script, options.clone());
+                }
+                let binary = cargo_binary_name(path, name);
+                Ok(format!("{path}/target/release/{binary}"))
             }
-            PluginLang::Go => Err(error!(
-                "golang is not supported as default language, please us the coffee.yml manifest"
-            )),
-            PluginLang::Rust => Err(error!(
-                "rust is not supported as default language, please use the coffee.yml manifest"
-            )),
             PluginLang::Dart => Err(error!(
                 "dart is not supported as default language, please use the cofee.yml manifest"
             )),
-            PluginLang::JavaScript => Err(error!(
-                "js is not supported as default language, please use the coffee.yml manifest"
-            )),
-            PluginLang::TypeScript => Err(error!(
-                "ts is not supported as default language, please use the coffee.yml manifest"
-            )),
+            PluginLang::JavaScript | PluginLang::TypeScript => {
+                let Some(main) = package_json_main(path)? else {
+                    return Err(error!(
+                        "{self} plugin without a package.json, please use the coffee.yml manifest"
+                    ));
+                };
+                if install_requirements {
+                    if !binary_in_path("npm") {
+                        return Err(error!(
+                            "`npm` not found in the PATH, it is required to build the plugin `{name}`"
+                        ));
+                    }
+                    // `npm ci` installs the exact versions of the lock file,
+                    // that is not always shipped with the plugin.
+                    let script = if Path::new(&format!("{path}/package-lock.json")).exists() {
+                        "npm ci"
+                    } else {
+                        "npm install"
+                    };
+                    sh!(path, script, options.clone());
+                    if *self == PluginLang::TypeScript {
+                        sh!(path, "npm run build", options.clone());
+                    }
+                }
+                let main = resolve_path(path, &main)?;
+                launcher(path, name, &format!("node {}", shell_quote(&main)), dry_run).await
+            }
             PluginLang::JVM => Err(error!(
                 "JVM is not supported as default language, please use the coffee.yml manifest"
             )),
+            PluginLang::Binary => Err(error!(
+                "binary plugin without assets, please declare them in the coffee.yml manifest"
+            )),
             PluginLang::Unknown => {
                 /* 1. emit an error message  */
                 Err(error!(
@@ -92,6 +307,232 @@ impl PluginLang {
     }
 }
 
+/// Download the prebuilt executable of the plugin for the platform
+/// of the node, verify its checksum and return the path of the executable.
+///
+/// An archive (`.tar.gz` or `.tgz`) is extracted inside the plugin
+/// directory `path`, and it must contain the `main` of the plugin.
+async fn install_asset(
+    path: &str,
+    plugin: &plugin_conf::Plugin,
+    verbose: bool,
+    dry_run: bool,
+    progress: Option<&ProgressSender>,
+    log: Option<&str>,
+    proxy: Option<&str>,
+) -> Result<String, CoffeeError> {
+    let name = &plugin.name;
+    let platform = platform();
+    let Some(asset) = plugin
+        .assets
+        .as_ref()
+        .and_then(|assets| assets.get(&platform))
+    else {
+        return Err(error!(
+            "the plugin `{name}` has no executable for the platform `{platform}`"
+        ));
+    };
+    if !binary_in_path("curl") {
+        return Err(error!(
+            "`curl` not found in the PATH, it is required to download the plugin `{name}`"
+        ));
+    }
+    let url = asset.url(&plugin.version);
+    let download = format!("{path}/{name}.asset");
+    let script = format!(
+        "curl -fsSL -o {} {}",
+        shell_quote(&download),
+        shell_quote(&url)
+    );
+    let options = ScriptOptions::new()
+        .verbose(verbose)
+        .dry_run(dry_run)
+        .progress(progress)
+        .log(log);
+    sh!(path, script, options.clone().env(proxy_env(proxy)));
+    let exec_path = resolve_path(path, &plugin.main)?;
+    if dry_run {
+        log::info!("[dry-run] verify the sha256 of {url}");
+        return Ok(exec_path);
+    }
+    let checksum = sha256sum(&download).await?;
+    if !checksum.eq_ignore_ascii_case(&asset.sha256) {
+        let _ = fs::remove_file(&download).await;
+        return Err(error!(
+            "checksum mismatch for the asset `{url}` of plugin `{name}`: expected `{}` but found `{checksum}`",
+            asset.sha256
+        ));
+    }
+    if url.ends_with(".tar.gz") || url.ends_with(".tgz") {
+        let script = format!("tar -xzf {}", shell_quote(&download));
+        sh!(path, script, options);
+        fs::remove_file(&download).await?;
+    } else {
+        if let Some(parent) = Path::new(&exec_path).parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::rename(&download, &exec_path).await?;
+    }
+    if !Path::new(&exec_path).exists() {
+        return Err(error!(
+            "the asset `{url}` of plugin `{name}` does not contain `{}`",
+            plugin.main
+        ));
+    }
+    fs::set_permissions(&exec_path, Permissions::from_mode(0o755)).await?;
+    Ok(exec_path)
+}
+
+/// The environment that makes pip download the packages inside
+/// the shared `cache` directory, so they are reused by the next builds.
+fn pip_cache_env(cache: Option<&str>) -> Vec<(String, String)> {
+    cache
+        .map(|cache| ("PIP_CACHE_DIR".to_owned(), format!("{cache}/pip")))
+        .into_iter()
+        .collect()
+}
+
+/// The environment of the commands that build a plugin, with
+/// the shared `cache` and the `proxy` of the network operations.
+fn build_env(cache: Option<&str>, proxy: Option<&str>) -> Vec<(String, String)> {
+    pip_cache_env(cache)
+        .into_iter()
+        .chain(proxy_env(proxy))
+        .collect()
+}
+
+/// Write the script that core lightning runs to start a python
+/// plugin with the interpreter of the virtual environment `venv`,
+/// and return the path of the script.
+async fn python_launcher(
+    path: &str,
+    name: &str,
+    venv: &str,
+    dry_run: bool,
+) -> Result<String, CoffeeError> {
+    let command = format!(
+        "{} {}",
+        shell_quote(&format!("{venv}/bin/python3")),
+        shell_quote(&format!("{path}/{name}.py"))
+    );
+    launcher(path, name, &command, dry_run).await
+}
+
+/// Write the script that core lightning runs to start the plugin
+/// with the `command`, and return the path of the script.
+async fn launcher(
+    path: &str,
+    name: &str,
+    command: &str,
+    dry_run: bool,
+) -> Result<String, CoffeeError> {
+    let launcher = format!("{path}/{name}-launcher.sh");
+    let script = format!("#!/bin/sh\nexec {command} \"$@\"\n");
+    if dry_run {
+        log::info!("[dry-run] write {launcher}:\n{script}");
+        return Ok(launcher);
+    }
+    fs::write(&launcher, script).await?;
+    fs::set_permissions(&launcher, Permissions::from_mode(0o755)).await?;
+    Ok(launcher)
+}
+
+/// Read the entry point of a node plugin from the `main` field of
+/// its `package.json`, that is `index.js` when it is not declared.
+///
+/// Return `None` when the plugin has no `package.json`.
+fn package_json_main(path: &str) -> Result<Option<String>, CoffeeError> {
+    let Ok(content) = std::fs::read_to_string(format!("{path}/package.json")) else {
+        return Ok(None);
+    };
+    let package = serde_json::from_str::<serde_json::Value>(&content)
+        .map_err(|err| error!("invalid package.json in `{path}`: {err}"))?;
+    let main = package
+        .get("main")
+        .and_then(|main| main.as_str())
+        .unwrap_or("index.js");
+    Ok(Some(main.to_owned()))
+}
+
+/// Look for the go package that contains the `main` function
+/// of the plugin, and return it as a path relative to the plugin
+/// root directory.
+///
+/// The main package is usually in the root of the plugin, but
+/// it is also common to find it in the `cmd/<name>` directory.
+fn go_main_package(path: &str, name: &str) -> String {
+    for package in [".".to_owned(), format!("./cmd/{name}"), "./cmd".to_owned()] {
+        let package_path = format!("{path}/{package}");
+        if is_go_main_package(Path::new(&package_path)) {
+            return package;
+        }
+    }
+    ".".to_owned()
+}
+
+/// Check if the directory contains a go file with the `main` package.
+fn is_go_main_package(dir: &Path) -> bool {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return false;
+    };
+    entries.flatten().any(|entry| {
+        let file = entry.path();
+        file.extension().is_some_and(|ext| ext == "go")
+            && std::fs::read_to_string(&file)
+                .map(|content| content.contains("package main"))
+                .unwrap_or(false)
+    })
+}
+
+/// Read the `Cargo.toml` of a rust plugin to find the name of the
+/// binary built by cargo, because the crate name can be different
+/// from the plugin name.
+///
+/// An explicit `[[bin]]` target wins over the package name, and if
+/// nothing is found we fallback to the plugin name.
+fn cargo_binary_name(path: &str, name: &str) -> String {
+    let Ok(manifest) = std::fs::read_to_string(format!("{path}/Cargo.toml")) else {
+        return name.to_owned();
+    };
+    let mut section = "";
+    let mut package_name = None;
+    for line in manifest.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            section = line;
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        if key.trim() != "name" {
+            continue;
+        }
+        let value = value.split('"').nth(1).unwrap_or(value.trim()).to_owned();
+        match section {
+            "[[bin]]" => return value,
+            "[package]" => package_name = Some(value),
+            _ => {}
+        }
+    }
+    package_name.unwrap_or(name.to_owned())
+}
+
+/// The abbreviated form of a git commit id.
+fn short_commit(commit: &str) -> String {
+    commit.chars().take(7).collect()
+}
+
+/// Where the plugin is installed.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum PluginScope {
+    /// Installed only for the network of coffee.
+    #[default]
+    Network,
+    /// Installed once and shared by all the networks managed by coffee.
+    Global,
+}
+
 /// Plugin struct definition
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct Plugin {
@@ -107,6 +548,27 @@ pub struct Plugin {
     // Optional for now to be backward compatible
     /// If the plugin is enabled or not
     pub enabled: Option<bool>,
+    /// sha256 of the plugin executable, computed
+    /// when the plugin is configured.
+    pub checksum: Option<String>,
+    /// version of the plugin, as declared in the coffee manifest
+    /// or the git short commit when it is not declared.
+    pub version: Option<String>,
+    /// path of the virtual environment of a python plugin.
+    pub venv: Option<String>,
+    /// git reference that the plugin is pinned to, the
+    /// upgrades of its repository do not rebuild it.
+    pub git_ref: Option<String>,
+    /// the plugins stored before the scope existed are per network.
+    #[serde(default)]
+    pub scope: PluginScope,
+    /// fingerprint of the trusted key that signed the
+    /// manifest of the plugin, when it was verified.
+    pub signed_by: Option<String>,
+    /// unix time in seconds of the install, missing for
+    /// the plugins installed before it was recorded.
+    #[serde(default)]
+    pub installed_at: Option<u64>,
 }
 
 impl Plugin {
@@ -120,6 +582,10 @@ impl Plugin {
         commit_id: Option<String>,
         enabled: Option<bool>,
     ) -> Self {
+        let version = config
+            .as_ref()
+            .map(|conf| conf.plugin.version.clone())
+            .or(commit_id.as_ref().map(|commit| short_commit(commit)));
         Plugin {
             name: name.to_owned(),
             root_path: root_path.to_owned(),
@@ -128,28 +594,155 @@ impl Plugin {
             conf: config,
             commit: commit_id,
             enabled,
+            checksum: None,
+            version,
+            venv: None,
+            git_ref: None,
+            scope: PluginScope::Network,
+            signed_by: None,
+            installed_at: None,
         }
     }
 
     /// configure the plugin in order to work with cln.
     ///
     /// In case of success return the path of the executable.
-    pub async fn configure(&mut self, verbose: bool) -> Result<String, CoffeeError> {
+    ///
+    /// With `dry_run` the commands are only printed, and the
+    /// resolved executable path is returned without building it.
+    ///
+    /// With `log` the output of the build is appended to the file.
+    ///
+    /// With `cache` the package managers that support it download
+    /// the dependencies inside the shared directory.
+    ///
+    /// With `proxy` the build commands reach the network through it.
+    ///
+    /// With `offline` the dependencies are not installed and nothing is
+    /// built or downloaded, the executable must be already there.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn configure(
+        &mut self,
+        verbose: bool,
+        dry_run: bool,
+        progress: Option<&ProgressSender>,
+        log: Option<&str>,
+        cache: Option<&str>,
+        proxy: Option<&str>,
+        offline: bool,
+    ) -> Result<String, CoffeeError> {
         log::debug!("install plugin inside from root dir {}", self.root_path);
         let exec_path = if let Some(conf) = &self.conf {
-            if let Some(script) = &conf.plugin.install {
-                sh!(self.root_path.clone(), script, verbose);
+            if offline && (self.lang == PluginLang::Binary || conf.plugin.install.is_some()) {
+                log::info!(
+                    "offline, skipping the install of the plugin `{}`",
+                    self.name
+                );
+                self.exec_path.clone()
+            } else if self.lang == PluginLang::Binary {
+                install_asset(
+                    &self.root_path,
+                    &conf.plugin,
+                    verbose,
+                    dry_run,
+                    progress,
+                    log,
+                    proxy,
+                )
+                .await?
+            } else if let Some(script) = &conf.plugin.install {
+                // the variables of the manifest override the cache
+                let env = build_env(cache, proxy)
+                    .into_iter()
+                    .chain(
+                        conf.plugin
+                            .env
+                            .iter()
+                            .flatten()
+                            .map(|(key, value)| (key.clone(), expand_env(value))),
+                    )
+                    .collect::<Vec<_>>();
+                let options = ScriptOptions::new()
+                    .verbose(verbose)
+                    .dry_run(dry_run)
+                    .progress(progress)
+                    .log(log)
+                    .env(env);
+                sh!(self.root_path.clone(), script, options);
                 self.exec_path.clone()
             } else {
                 self.lang
-                    .default_install(&self.root_path, &self.name, verbose)
+                    .default_install(
+                        &self.root_path,
+                        &self.name,
+                        verbose,
+                        !offline,
+                        dry_run,
+                        progress,
+                        log,
+                        cache,
+                        proxy,
+                    )
                     .await?
             }
         } else {
             self.lang
-                .default_install(&self.root_path, &self.name, verbose)
+                .default_install(
+                    &self.root_path,
+                    &self.name,
+                    verbose,
+                    !offline,
+                    dry_run,
+                    progress,
+                    log,
+                    cache,
+                    proxy,
+                )
                 .await?
         };
+
+        if dry_run {
+            log::info!("[dry-run] executable path: {exec_path}");
+            return Ok(exec_path);
+        }
+        if !Path::new(&exec_path).exists() && offline {
+            return Err(error!(
+                "the main file `{exec_path}` of plugin `{}` does not exist, and it can not be built offline",
+                self.name
+            ));
+        }
+        if !Path::new(&exec_path).exists() {
+            return Err(error!(
+                "the main file `{exec_path}` of plugin `{}` does not exist after the install",
+                self.name
+            ));
+        }
+        // the install procedure can run the plugin with a launcher,
+        // so the executable is the one returned by the install.
+        self.exec_path = exec_path.clone();
+        self.venv = self
+            .lang
+            .venv(&self.root_path)
+            .filter(|venv| Path::new(venv).exists());
+
+        let expected = self
+            .conf
+            .as_ref()
+            .and_then(|conf| conf.plugin.sha256.clone());
+        self.checksum = match expected {
+            Some(expected) => {
+                let checksum = sha256sum(&exec_path).await?;
+                if !checksum.eq_ignore_ascii_case(&expected) {
+                    return Err(error!(
+                        "checksum mismatch for plugin `{}`: expected `{expected}` but found `{checksum}`",
+                        self.name
+                    ));
+                }
+                Some(checksum)
+            }
+            // the checksum is verified only when the manifest declares it
+            None => None,
+        };
         Ok(exec_path)
     }
 
@@ -162,10 +755,34 @@ impl Plugin {
         self.name.clone()
     }
 
+    /// The binaries needed to build and run the plugin, that
+    /// depend on how the manifest declares to install it.
+    pub fn required_binaries(&self) -> Vec<&'static str> {
+        let custom_install = self
+            .conf
+            .as_ref()
+            .is_some_and(|conf| conf.plugin.install.is_some());
+        let default_install = self.lang.default_install_binaries();
+        self.lang
+            .required_binaries()
+            .into_iter()
+            .filter(|binary| !custom_install || !default_install.contains(binary))
+            .collect()
+    }
+
     pub fn tipping_info(&self) -> Option<Tipping> {
         self.conf.as_ref().and_then(|conf| conf.tipping.clone())
     }
 
+    /// return the lightning address or the BOLT12 offer where
+    /// the author accepts tips, falling back to the `tipping` offer.
+    pub fn funding_info(&self) -> Option<String> {
+        let conf = self.conf.as_ref()?;
+        conf.funding
+            .clone()
+            .or_else(|| conf.tipping.as_ref().map(|tipping| tipping.bolt12.clone()))
+    }
+
     pub fn important(&self) -> bool {
         if let Some(config) = &self.conf {
             if let Some(important) = config.plugin.important {
@@ -177,6 +794,70 @@ impl Plugin {
             false
         }
     }
+
+    /// short description of the plugin, as declared in the coffee manifest.
+    pub fn description(&self) -> Option<String> {
+        self.conf
+            .as_ref()
+            .and_then(|conf| conf.plugin.description.clone())
+    }
+
+    /// author of the plugin, as declared in the coffee manifest.
+    pub fn author(&self) -> Option<String> {
+        self.conf
+            .as_ref()
+            .and_then(|conf| conf.plugin.author.clone())
+    }
+
+    /// license of the plugin, as declared in the coffee manifest.
+    pub fn license(&self) -> Option<String> {
+        self.conf
+            .as_ref()
+            .and_then(|conf| conf.plugin.license.clone())
+    }
+
+    /// minimum version of core lightning needed by the
+    /// plugin, as declared in the coffee manifest.
+    pub fn min_cln_version(&self) -> Option<String> {
+        self.conf
+            .as_ref()
+            .and_then(|conf| conf.plugin.min_cln_version.clone())
+    }
+
+    /// homepage of the plugin, as declared in the coffee manifest.
+    pub fn homepage(&self) -> Option<String> {
+        self.conf
+            .as_ref()
+            .and_then(|conf| conf.plugin.homepage.clone())
+    }
+
+    /// return the version of the plugin.
+    ///
+    /// The plugins stored by an older version of coffee do not
+    /// have the version, so we fallback to the git short commit.
+    pub fn version(&self) -> Option<String> {
+        self.version
+            .clone()
+            .or(self.commit.as_ref().map(|commit| short_commit(commit)))
+    }
+
+    /// name of the plugins that need to be installed
+    /// before this plugin, as declared in the coffee manifest.
+    pub fn dependencies(&self) -> Vec<String> {
+        self.conf
+            .as_ref()
+            .and_then(|conf| conf.plugin.dependencies.clone())
+            .unwrap_or_default()
+    }
+
+    /// options that the plugin needs inside the cln
+    /// configuration, as declared in the coffee manifest.
+    pub fn cln_options(&self) -> BTreeMap<String, String> {
+        self.conf
+            .as_ref()
+            .and_then(|conf| conf.plugin.cln_options.clone())
+            .unwrap_or_default()
+    }
 }
 
 impl fmt::Display for Plugin {
@@ -184,3 +865,276 @@ impl fmt::Display for Plugin {
         write!(f, "name: {}, path: {}", self.name, self.exec_path)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::{
+        cargo_binary_name, go_main_package, install_asset, package_json_main, pip_cache_env,
+        python_launcher, Plugin, PluginLang,
+    };
+    use crate::plugin_conf::{platform, Conf, Manifest};
+    use crate::utils::sha256sum;
+
+    #[test]
+    fn test_pip_cache_env() {
+        assert!(pip_cache_env(None).is_empty());
+        assert_eq!(
+            pip_cache_env(Some("/home/alice/.coffee/cache")),
+            vec![(
+                "PIP_CACHE_DIR".to_owned(),
+                "/home/alice/.coffee/cache/pip".to_owned()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_cargo_binary_name() {
+        let dir = std::env::temp_dir().join("coffee-cargo-binary-name");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.to_str().unwrap();
+
+        // without a manifest we use the plugin name
+        assert_eq!(cargo_binary_name(path, "folgore"), "folgore");
+
+        let manifest = "[package]\nname = \"folgore-plugin\"\nversion = \"0.1.0\"\n";
+        fs::write(dir.join("Cargo.toml"), manifest).unwrap();
+        assert_eq!(cargo_binary_name(path, "folgore"), "folgore-plugin");
+
+        let manifest = format!("{manifest}\n[[bin]]\nname = \"folgore\" # the plugin\n");
+        fs::write(dir.join("Cargo.toml"), manifest).unwrap();
+        assert_eq!(cargo_binary_name(path, "folgore"), "folgore");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_go_main_package() {
+        let dir = std::env::temp_dir().join("coffee-go-main-package");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("cmd").join("folgore")).unwrap();
+        let path = dir.to_str().unwrap();
+
+        // without a main package we fallback to the root
+        assert_eq!(go_main_package(path, "folgore"), ".");
+
+        fs::write(dir.join("cmd").join("main.go"), "package main\n").unwrap();
+        assert_eq!(go_main_package(path, "folgore"), "./cmd");
+
+        let main = dir.join("cmd").join("folgore").join("main.go");
+        fs::write(main, "package main\n").unwrap();
+        assert_eq!(go_main_package(path, "folgore"), "./cmd/folgore");
+
+        // a go file of another package is not the main package
+        fs::write(dir.join("lib.go"), "package folgore\n").unwrap();
+        assert_eq!(go_main_package(path, "folgore"), "./cmd/folgore");
+
+        fs::write(dir.join("main.go"), "package main\n").unwrap();
+        assert_eq!(go_main_package(path, "folgore"), ".");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_package_json_main() {
+        let dir = std::env::temp_dir().join("coffee-package-json-main");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.to_str().unwrap();
+
+        assert_eq!(package_json_main(path).unwrap(), None);
+
+        fs::write(dir.join("package.json"), r#"{"name": "plugin"}"#).unwrap();
+        assert_eq!(package_json_main(path).unwrap().unwrap(), "index.js");
+
+        fs::write(dir.join("package.json"), r#"{"main": "dist/app.js"}"#).unwrap();
+        assert_eq!(package_json_main(path).unwrap().unwrap(), "dist/app.js");
+
+        fs::write(dir.join("package.json"), "{").unwrap();
+        assert!(package_json_main(path).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_detect_plugin_lang() {
+        let dir = std::env::temp_dir().join("coffee-detect-plugin-lang");
+        let fixtures: &[(&[(&str, &str)], PluginLang)] = &[
+            (&[], PluginLang::Unknown),
+            (&[("README.md", "# plugin")], PluginLang::Unknown),
+            (&[("Cargo.toml", "[package]")], PluginLang::Rust),
+            (&[("go.mod", "module plugin")], PluginLang::Go),
+            (&[("main.go", "package main")], PluginLang::Go),
+            (&[("requirements.txt", "pyln-client")], PluginLang::PyPip),
+            (
+                &[("pyproject.toml", "[tool.poetry]\nname = \"plugin\"")],
+                PluginLang::PyPoetry,
+            ),
+            (
+                &[
+                    ("pyproject.toml", "[project]\nname = \"plugin\""),
+                    ("requirements.txt", "pyln-client"),
+                ],
+                PluginLang::PyPip,
+            ),
+            (&[("package.json", "{}")], PluginLang::JavaScript),
+            (
+                &[(
+                    "package.json",
+                    r#"{"devDependencies": {"typescript": "^5"}}"#,
+                )],
+                PluginLang::TypeScript,
+            ),
+            (
+                &[("package.json", "{}"), ("tsconfig.json", "{}")],
+                PluginLang::TypeScript,
+            ),
+            (&[("pubspec.yaml", "name: plugin")], PluginLang::Dart),
+            (&[("build.gradle", "")], PluginLang::JVM),
+        ];
+
+        for (files, lang) in fixtures {
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            for (name, content) in files.iter() {
+                fs::write(dir.join(name), content).unwrap();
+            }
+            assert_eq!(PluginLang::detect(&dir), *lang, "{:?}", files);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_plugin_version() {
+        let commit =
+            Some("37290d74ac4d186e3a8e5785d259d2ec04fac91ae28092e7620ec8bc99e830aa".to_owned());
+        let plugin = Plugin::new(
+            "folgore",
+            "/tmp/folgore",
+            "/tmp/folgore/folgore",
+            PluginLang::Rust,
+            None,
+            commit.clone(),
+            None,
+        );
+        assert_eq!(plugin.version(), Some("37290d7".to_owned()));
+
+        let conf = r#"{"plugin": {"name": "folgore", "version": "0.1.0", "lang": "rust", "main": "folgore"}}"#;
+        let conf = serde_json::from_str::<Conf>(conf).unwrap();
+        let plugin = Plugin::new(
+            "folgore",
+            "/tmp/folgore",
+            "/tmp/folgore/folgore",
+            PluginLang::Rust,
+            Some(conf),
+            commit,
+            None,
+        );
+        assert_eq!(plugin.version(), Some("0.1.0".to_owned()));
+    }
+
+    #[test]
+    fn test_required_binaries() {
+        let plugin = |conf: Option<&str>| {
+            Plugin::new(
+                "hello",
+                "/tmp/hello",
+                "/tmp/hello/index.js",
+                PluginLang::JavaScript,
+                conf.map(|conf| serde_json::from_str::<Conf>(conf).unwrap()),
+                None,
+                None,
+            )
+        };
+        assert_eq!(plugin(None).required_binaries(), vec!["node", "npm"]);
+
+        // the install script replaces the default install with npm
+        let conf = r#"{"plugin": {"name": "hello", "version": "0.1.0", "lang": "javascript", "main": "index.js", "install": "yarn install"}}"#;
+        assert_eq!(plugin(Some(conf)).required_binaries(), vec!["node"]);
+    }
+
+    #[tokio::test]
+    async fn test_python_launcher() {
+        let dir = std::env::temp_dir().join("coffee-python-launcher");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.to_str().unwrap();
+        let venv = format!("{path}/.venv");
+
+        let launcher = python_launcher(path, "summary", &venv, true).await.unwrap();
+        assert_eq!(launcher, format!("{path}/summary-launcher.sh"));
+        assert!(!dir.join("summary-launcher.sh").exists());
+
+        let launcher = python_launcher(path, "summary", &venv, false)
+            .await
+            .unwrap();
+        let script = fs::read_to_string(&launcher).unwrap();
+        assert!(
+            script.contains(&format!("exec '{venv}/bin/python3' '{path}/summary.py'")),
+            "{script}"
+        );
+        let mode = fs::metadata(&launcher).unwrap().permissions().mode();
+        assert_eq!(mode & 0o111, 0o111, "{mode:o}");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_install_asset() {
+        let dir = std::env::temp_dir().join("coffee-install-asset");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("plugin")).unwrap();
+        let asset = dir.join("folgore-release");
+        fs::write(&asset, "#!/bin/sh\necho folgore\n").unwrap();
+        let sha256 = sha256sum(asset.to_str().unwrap()).await.unwrap();
+        let path = dir.join("plugin");
+        let path = path.to_str().unwrap();
+
+        let manifest = format!(
+            "plugin:\n  name: folgore\n  version: 0.1.0\n  lang: binary\n  main: bin/folgore\n  assets:\n    {}:\n      url: file://{}\n      sha256: {sha256}\n",
+            platform(),
+            asset.display()
+        );
+        let mut plugin = Manifest::parse(&manifest).unwrap().plugin.unwrap();
+        let exec_path = install_asset(path, &plugin, false, false, None, None, None)
+            .await
+            .unwrap();
+        assert_eq!(exec_path, format!("{path}/bin/folgore"));
+        let mode = fs::metadata(&exec_path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o111, 0o111, "{mode:o}");
+
+        // an asset that does not match the checksum is refused
+        let assets = plugin.assets.as_mut().unwrap();
+        assets.get_mut(&platform()).unwrap().sha256 = "0".repeat(64);
+        let err = install_asset(path, &plugin, false, false, None, None, None)
+            .await
+            .unwrap_err();
+        assert!(err.msg().contains("checksum mismatch"), "{err}");
+
+        // the url of the manifest is a single argument of curl
+        let asset = dir.join("folgore's-release");
+        fs::write(&asset, "#!/bin/sh\necho folgore\n").unwrap();
+        let sha256 = sha256sum(asset.to_str().unwrap()).await.unwrap();
+        let assets = plugin.assets.as_mut().unwrap();
+        let entry = assets.get_mut(&platform()).unwrap();
+        entry.url = format!("file://{}", asset.display());
+        entry.sha256 = sha256;
+        let exec_path = install_asset(path, &plugin, false, false, None, None, None)
+            .await
+            .unwrap();
+        assert_eq!(exec_path, format!("{path}/bin/folgore"));
+
+        let pwned = dir.join("pwned");
+        let assets = plugin.assets.as_mut().unwrap();
+        assets.get_mut(&platform()).unwrap().url =
+            format!("file:///nowhere'; touch '{}", pwned.display());
+        assert!(install_asset(path, &plugin, false, false, None, None, None)
+            .await
+            .is_err());
+        assert!(!pwned.exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,22 +1,277 @@
 //! Coffee configuration serialization file.
+use std::collections::BTreeMap;
+use std::path::Path;
+
 use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+use tokio::fs;
+
+use crate::errors::CoffeeError;
+use crate::macros::error;
+use crate::plugin::PluginLang;
+
+/// The file names of the coffee manifest, in order of precedence.
+pub const MANIFEST_FILES: [&str; 2] = ["coffee.yaml", "coffee.yml"];
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub struct Conf {
     pub plugin: Plugin,
     pub tipping: Option<Tipping>,
+    /// lightning address or BOLT12 offer of the author.
+    pub funding: Option<String>,
+}
+
+/// The coffee manifest, that can define a single plugin
+/// with `plugin` or a collection of plugins with `plugins`.
+///
+/// The values shared by the plugins can be defined once inside
+/// the `defaults` section, that is merged inside each plugin
+/// while the manifest is parsed.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Manifest {
+    pub plugin: Option<Plugin>,
+    pub plugins: Option<Vec<Plugin>>,
+    pub tipping: Option<Tipping>,
+    pub funding: Option<String>,
+}
+
+impl Manifest {
+    /// Parse the manifest, when the content does not match the
+    /// manifest schema the error reports the offending field and line.
+    pub fn parse(content: &str) -> Result<Self, CoffeeError> {
+        let malformed = |err: serde_path_to_error::Error<serde_yaml::Error>| {
+            let field = err.path().to_string();
+            let err = err.into_inner();
+            if field == "." {
+                error!("Coffee manifest malformed: {err}")
+            } else {
+                error!("Coffee manifest malformed: `{field}`: {err}")
+            }
+        };
+        let value = serde_yaml::from_str::<Value>(content)
+            .map_err(|err| error!("Coffee manifest malformed: {err}"))?;
+        // without shared values the manifest is deserialized from the
+        // text, so the errors report also the line of the field.
+        if value.get("defaults").is_none() && !has_merge_keys(&value) {
+            let deserializer = serde_yaml::Deserializer::from_str(content);
+            return serde_path_to_error::deserialize(deserializer).map_err(malformed);
+        }
+        let value = apply_defaults(value)?;
+        serde_path_to_error::deserialize(value).map_err(malformed)
+    }
+
+    /// Check the values that the schema can not express, like
+    /// the language of the plugins or the empty fields.
+    pub fn validate(&self) -> Result<(), CoffeeError> {
+        let plugins = self
+            .plugin
+            .iter()
+            .map(|plugin| ("plugin".to_owned(), plugin))
+            .chain(
+                self.plugins
+                    .iter()
+                    .flatten()
+                    .enumerate()
+                    .map(|(index, plugin)| (format!("plugins[{index}]"), plugin)),
+            )
+            .collect::<Vec<_>>();
+        if plugins.is_empty() {
+            return Err(error!(
+                "Coffee manifest malformed: no plugin defined, add a `plugin` or a `plugins` section"
+            ));
+        }
+        for (field, plugin) in plugins {
+            for (name, value) in [
+                ("name", &plugin.name),
+                ("version", &plugin.version),
+                ("main", &plugin.main),
+            ] {
+                if value.trim().is_empty() {
+                    return Err(error!(
+                        "Coffee manifest malformed: `{field}.{name}` can not be empty"
+                    ));
+                }
+            }
+            let lang = PluginLang::from_conf(&plugin.lang)
+                .map_err(|err| error!("Coffee manifest malformed: `{field}.lang`: {err}"))?;
+            let assets = plugin.assets.iter().flatten().collect::<Vec<_>>();
+            if lang == PluginLang::Binary && assets.is_empty() {
+                return Err(error!(
+                    "Coffee manifest malformed: `{field}.assets` is required by a binary plugin"
+                ));
+            }
+            for (platform, asset) in assets {
+                let sha256 = &asset.sha256;
+                if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(error!(
+                        "Coffee manifest malformed: `{field}.assets.{platform}.sha256` is not a sha256"
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Split the manifest in one configuration for each plugin.
+    pub fn confs(&self) -> Vec<Conf> {
+        self.plugin
+            .iter()
+            .chain(self.plugins.iter().flatten())
+            .map(|plugin| Conf {
+                plugin: plugin.clone(),
+                tipping: self.tipping.clone(),
+                funding: self.funding.clone(),
+            })
+            .collect()
+    }
+}
+
+/// Check if the yaml `value` uses the merge keys, e.g. `<<: *base`.
+fn has_merge_keys(value: &Value) -> bool {
+    match value {
+        Value::Mapping(mapping) => mapping
+            .iter()
+            .any(|(key, value)| key.as_str() == Some("<<") || has_merge_keys(value)),
+        Value::Sequence(sequence) => sequence.iter().any(has_merge_keys),
+        Value::Tagged(tagged) => has_merge_keys(&tagged.value),
+        _ => false,
+    }
+}
+
+/// Resolve the merge keys of the manifest, and merge its `defaults`
+/// section inside each plugin.
+fn apply_defaults(mut value: Value) -> Result<Value, CoffeeError> {
+    value
+        .apply_merge()
+        .map_err(|err| error!("Coffee manifest malformed: {err}"))?;
+    let Some(manifest) = value.as_mapping_mut() else {
+        return Ok(value);
+    };
+    let defaults = match manifest.remove("defaults") {
+        Some(Value::Mapping(defaults)) => defaults,
+        None | Some(Value::Null) => Mapping::new(),
+        Some(_) => {
+            return Err(error!(
+                "Coffee manifest malformed: `defaults` must be a mapping of plugin fields"
+            ))
+        }
+    };
+    if let Some(plugin) = manifest.get_mut("plugin") {
+        merge_defaults(plugin, &defaults);
+    }
+    if let Some(Value::Sequence(plugins)) = manifest.get_mut("plugins") {
+        for plugin in plugins {
+            merge_defaults(plugin, &defaults);
+        }
+    }
+    Ok(value)
+}
+
+/// Copy inside `value` the fields of `defaults` that it does not define.
+///
+/// The mappings present on both sides, e.g. `env`, are merged key by key,
+/// any other value of the plugin (also `null` or a list) wins as it is.
+fn merge_defaults(value: &mut Value, defaults: &Mapping) {
+    let Value::Mapping(mapping) = value else {
+        return;
+    };
+    for (key, default) in defaults {
+        match mapping.get_mut(key) {
+            None => {
+                mapping.insert(key.clone(), default.clone());
+            }
+            Some(value) => {
+                if let Value::Mapping(default) = default {
+                    merge_defaults(value, default);
+                }
+            }
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub struct Plugin {
     pub name: String,
     pub version: String,
+    pub description: Option<String>,
     pub lang: String,
     pub deprecated: Option<()>,
     pub dependencies: Option<Vec<String>>,
     pub install: Option<String>,
+    /// environment variables of the install script, the values
+    /// can reference the coffee environment with `${VAR}`.
+    pub env: Option<BTreeMap<String, String>>,
     pub main: String,
     pub important: Option<bool>,
+    /// options that the plugin needs inside the cln
+    /// configuration, added next to the `plugin` line.
+    pub cln_options: Option<BTreeMap<String, String>>,
+    /// sha256 of the executable produced by the install
+    /// procedure, used to verify the plugin integrity.
+    pub sha256: Option<String>,
+    /// minimum version of core lightning needed by the plugin.
+    pub min_cln_version: Option<String>,
+    /// prebuilt executables of a `binary` plugin, by platform
+    /// (e.g. `linux-x86_64`), downloaded instead of building it.
+    pub assets: Option<BTreeMap<String, Asset>>,
+    /// name (and optionally the contact) of the author.
+    pub author: Option<String>,
+    /// SPDX identifier of the license, e.g. `BSD-3-Clause`.
+    pub license: Option<String>,
+    /// url of the project page or of the documentation.
+    pub homepage: Option<String>,
+}
+
+/// A prebuilt executable of the plugin, published as a release asset.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Asset {
+    /// where the asset is downloaded from, `{version}`, `{os}` and
+    /// `{arch}` are replaced with the plugin version and the platform.
+    pub url: String,
+    /// sha256 of the downloaded asset.
+    pub sha256: String,
+}
+
+impl Asset {
+    /// The url of the asset for the `version` of the plugin.
+    pub fn url(&self, version: &str) -> String {
+        self.url
+            .replace("{version}", version)
+            .replace("{os}", std::env::consts::OS)
+            .replace("{arch}", std::env::consts::ARCH)
+    }
+}
+
+/// The platform of the node, in the form used by the
+/// `assets` of the manifest (e.g. `linux-x86_64`).
+pub fn platform() -> String {
+    format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH)
+}
+
+/// Parse and validate the coffee manifest at `path`, that can be
+/// the manifest itself or the directory of the plugin.
+pub async fn validate_manifest(path: &str) -> Result<Manifest, CoffeeError> {
+    let manifest_path = if Path::new(path).is_dir() {
+        let Some(manifest_path) = MANIFEST_FILES
+            .iter()
+            .map(|file| format!("{path}/{file}"))
+            .find(|file| Path::new(file).exists())
+        else {
+            return Err(error!("no coffee manifest found inside `{path}`"));
+        };
+        manifest_path
+    } else {
+        path.to_owned()
+    };
+    let content = fs::read_to_string(&manifest_path)
+        .await
+        .map_err(|err| error!("unable to read `{manifest_path}`: {err}"))?;
+    let manifest =
+        Manifest::parse(&content).map_err(|err| error!("{manifest_path}: {}", err.msg()))?;
+    manifest
+        .validate()
+        .map_err(|err| error!("{manifest_path}: {}", err.msg()))?;
+    Ok(manifest)
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
@@ -28,3 +283,161 @@ pub struct Deprecaterd {
 pub struct Tipping {
     pub bolt12: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Manifest;
+
+    #[test]
+    fn test_manifest_confs() {
+        let manifest = r#"{"plugin": {"name": "folgore", "version": "0.1.0", "lang": "rust", "main": "folgore"}}"#;
+        let manifest = serde_json::from_str::<Manifest>(manifest).unwrap();
+        let confs = manifest.confs();
+        assert_eq!(confs.len(), 1);
+        assert_eq!(confs[0].plugin.name, "folgore");
+        assert_eq!(confs[0].plugin.author, None);
+        assert_eq!(confs[0].plugin.cln_options, None);
+        assert_eq!(confs[0].plugin.min_cln_version, None);
+        assert_eq!(confs[0].funding, None);
+
+        let manifest = r#"{
+            "plugins": [
+                {"name": "summary", "version": "0.1.0", "lang": "pypip", "main": "summary.py", "license": "BSD-3-Clause"},
+                {"name": "helpme", "version": "0.2.0", "lang": "pypip", "main": "helpme/helpme.py", "cln_options": {"helpme-verbose": "true"}}
+            ],
+            "tipping": {"bolt12": "lno1"},
+            "funding": "author@getalby.com"
+        }"#;
+        let manifest = serde_json::from_str::<Manifest>(manifest).unwrap();
+        let confs = manifest.confs();
+        assert_eq!(confs.len(), 2);
+        assert_eq!(confs[0].plugin.name, "summary");
+        assert_eq!(confs[0].plugin.license.as_deref(), Some("BSD-3-Clause"));
+        assert_eq!(confs[1].plugin.main, "helpme/helpme.py");
+        let options = confs[1].plugin.cln_options.as_ref().unwrap();
+        assert_eq!(options["helpme-verbose"], "true");
+        assert!(confs.iter().all(|conf| conf.tipping.is_some()));
+        assert!(confs
+            .iter()
+            .all(|conf| conf.funding.as_deref() == Some("author@getalby.com")));
+    }
+
+    #[test]
+    fn test_manifest_errors() {
+        let manifest =
+            "plugin:\n  name: folgore\n  version: 0.1.0\n  lang: rust\n  main: folgore\n";
+        let manifest = Manifest::parse(manifest).unwrap();
+        assert!(manifest.validate().is_ok());
+
+        // the error points to the field and the line
+        let manifest = "plugins:\n  - name: folgore\n    version: 0.1.0\n    lang: rust\n    main: [folgore]\n";
+        let err = Manifest::parse(manifest).unwrap_err();
+        assert!(err.msg().contains("`plugins[0].main`"), "{err}");
+        assert!(err.msg().contains("line 5"), "{err}");
+
+        let manifest = "plugin:\n  name: folgore\n  lang: rust\n  main: folgore\n";
+        let err = Manifest::parse(manifest).unwrap_err();
+        assert!(err.msg().contains("missing field `version`"), "{err}");
+
+        let manifest =
+            "plugins:\n  - name: folgore\n    version: 0.1.0\n    lang: cobol\n    main: folgore\n";
+        let err = Manifest::parse(manifest).unwrap().validate().unwrap_err();
+        assert!(err.msg().contains("`plugins[0].lang`"), "{err}");
+
+        let manifest = "funding: author@getalby.com\n";
+        assert!(Manifest::parse(manifest).unwrap().validate().is_err());
+
+        // a binary plugin needs the assets, with a valid checksum
+        let manifest =
+            "plugin:\n  name: folgore\n  version: 0.1.0\n  lang: binary\n  main: folgore\n";
+        let err = Manifest::parse(manifest).unwrap().validate().unwrap_err();
+        assert!(err.msg().contains("`plugin.assets`"), "{err}");
+        let manifest = format!(
+            "{manifest}  assets:\n    linux-x86_64:\n      url: https://example.com/v{{version}}/folgore-{{os}}-{{arch}}\n      sha256: {}\n",
+            "a".repeat(64)
+        );
+        let parsed = Manifest::parse(&manifest).unwrap();
+        assert!(parsed.validate().is_ok());
+        let asset = &parsed.plugin.unwrap().assets.unwrap()["linux-x86_64"];
+        assert_eq!(
+            asset.url("0.1.0"),
+            format!(
+                "https://example.com/v0.1.0/folgore-{}-{}",
+                std::env::consts::OS,
+                std::env::consts::ARCH
+            )
+        );
+        let manifest = manifest.replace(&"a".repeat(64), "abc");
+        let err = Manifest::parse(&manifest).unwrap().validate().unwrap_err();
+        assert!(
+            err.msg().contains("`plugin.assets.linux-x86_64.sha256`"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_manifest_defaults() {
+        let manifest = r#"
+defaults:
+  version: 0.1.0
+  lang: pypip
+  license: BSD-3-Clause
+  env:
+    PIP_NO_CACHE_DIR: "1"
+    LANG: C
+plugins:
+  - name: summary
+    main: summary.py
+  - name: helpme
+    version: 0.2.0
+    main: helpme.py
+    license: null
+    env:
+      LANG: C.UTF-8
+"#;
+        let manifest = Manifest::parse(manifest).unwrap();
+        assert!(manifest.validate().is_ok());
+        let confs = manifest.confs();
+        let summary = &confs[0].plugin;
+        assert_eq!(summary.version, "0.1.0");
+        assert_eq!(summary.lang, "pypip");
+        assert_eq!(summary.license.as_deref(), Some("BSD-3-Clause"));
+        // the values of the plugin win, and the mappings are merged
+        let helpme = &confs[1].plugin;
+        assert_eq!(helpme.version, "0.2.0");
+        assert_eq!(helpme.license, None);
+        let env = helpme.env.as_ref().unwrap();
+        assert_eq!(env["LANG"], "C.UTF-8");
+        assert_eq!(env["PIP_NO_CACHE_DIR"], "1");
+
+        // the yaml merge keys are resolved too
+        let manifest = r#"
+base: &base
+  version: 0.1.0
+  lang: rust
+plugins:
+  - <<: *base
+    name: folgore
+    main: folgore
+"#;
+        let manifest = Manifest::parse(manifest).unwrap();
+        assert_eq!(manifest.confs()[0].plugin.lang, "rust");
+
+        let manifest = "defaults: [rust]
+plugin:
+  name: folgore
+  main: folgore
+";
+        let err = Manifest::parse(manifest).unwrap_err();
+        assert!(err.msg().contains("`defaults`"), "{err}");
+        // a field missing also inside the defaults is reported
+        let manifest = "defaults:
+  lang: rust
+plugin:
+  name: folgore
+  main: folgore
+";
+        let err = Manifest::parse(manifest).unwrap_err();
+        assert!(err.msg().contains("missing field `version`"), "{err}");
+    }
+}
@@ -1,6 +1,9 @@
 //! Plugin manager module definition.
+use std::collections::HashMap;
+
 use async_trait::async_trait;
 
+use crate::progress::ProgressSender;
 use crate::{errors::CoffeeError, types::response::*};
 
 /// Plugin manager traits that define the API a generic
@@ -10,29 +13,151 @@ pub trait PluginManager {
     /// configure the plugin manger.
     async fn configure(&mut self) -> Result<(), CoffeeError>;
 
+    /// set the listener of the progress of the long operations, like
+    /// the output of the clones and of the builds line by line, so a
+    /// terminal UI can render it. With `None` nothing is sent.
+    fn set_progress(&mut self, progress: Option<ProgressSender>);
+
+    /// compute the disk space used by each plugin and repository
+    /// inside `list` and `list_remotes`, it is disabled by default
+    /// because walking large directories is slow.
+    fn set_disk_usage(&mut self, enabled: bool);
+
+    /// set the order of the plugins returned by `list`
+    /// and `list_available`, by name when not set.
+    fn set_list_order(&mut self, order: ListOrder);
+
+    /// wait until core lightning runs each plugin installed, and roll back
+    /// the install when it refuses it. It needs the rpc socket of the
+    /// node, and it is disabled by default.
+    fn set_verify_start(&mut self, enabled: bool);
+
+    /// refuse to add the remotes that do not contain any plugin,
+    /// by default they are added with a warning.
+    fn set_require_plugins(&mut self, enabled: bool);
+
     /// install a plugin by name, return an error if some error happens.
+    ///
+    /// When `plugins` is a git url, the plugin inside the repository
+    /// is installed, and the repository is added as a remote.
+    ///
+    /// With `dry_run` the commands that would be run are printed,
+    /// without changing the plugin manager state.
+    ///
+    /// With `force` a plugin already installed is built again,
+    /// instead of returning an error.
     async fn install(
         &mut self,
         plugins: &str,
         verbose: bool,
         try_dynamic: bool,
-    ) -> Result<(), CoffeeError>;
+        dry_run: bool,
+        force: bool,
+    ) -> Result<CoffeeInstall, CoffeeError>;
+
+    /// install a plugin by name once for all the networks managed by
+    /// coffee, its files are shared and it is added to the cln
+    /// configuration of each network.
+    async fn install_global(
+        &mut self,
+        plugin: &str,
+        verbose: bool,
+        dry_run: bool,
+    ) -> Result<CoffeeInstall, CoffeeError>;
+
+    /// install multiple plugins by name, building them concurrently.
+    ///
+    /// An error while installing a plugin does not stop the installation
+    /// of the others, so the result of each plugin is returned.
+    async fn install_many(
+        &mut self,
+        plugins: &[&str],
+        verbose: bool,
+        try_dynamic: bool,
+    ) -> Result<HashMap<String, Result<(), CoffeeError>>, CoffeeError>;
+
+    /// build the plugin and start it inside the running core lightning
+    /// node, without installing it. The plugin is stopped and its build
+    /// removed afterwards, and an error is returned when core lightning
+    /// refuses to run it.
+    async fn try_plugin(&mut self, plugin: &str, verbose: bool)
+        -> Result<CoffeeTrial, CoffeeError>;
+
+    /// build again all the installed plugins from their directory, e.g.
+    /// after an upgrade of coffee that changed the build environment.
+    ///
+    /// A plugin that fails is reported without stopping the others.
+    async fn repair_all(&mut self, verbose: bool) -> Result<CoffeeRepair, CoffeeError>;
 
     // remove a plugin by name, return an error if some error happens.
     async fn remove(&mut self, plugin: &str) -> Result<CoffeeRemove, CoffeeError>;
 
+    /// remove a plugin by name without leaving any trace: together with
+    /// the build, the launcher and the build log are deleted. With
+    /// `keep_config` the cln options of the plugin stay in the configuration.
+    async fn purge(&mut self, plugin: &str, keep_config: bool)
+        -> Result<CoffeeRemove, CoffeeError>;
+
+    /// remove a plugin from the cln configuration and from the plugins
+    /// installed, leaving its files on disk. A later install of the
+    /// plugin reuses the build when nothing changed.
+    async fn detach(&mut self, plugin: &str) -> Result<CoffeeRemove, CoffeeError>;
+
     /// return the list of plugins installed by the plugin manager.
     async fn list(&mut self) -> Result<CoffeeList, CoffeeError>;
 
-    /// upgrade a single or multiple repositories.
-    async fn upgrade(&mut self, repo: &str, verbose: bool) -> Result<CoffeeUpgrade, CoffeeError>;
+    /// return the list of plugins installed by the plugin manager
+    /// together with all the plugins available across the remote
+    /// repositories, each one flagged when it is installed.
+    async fn list_available(&mut self) -> Result<CoffeeList, CoffeeError>;
+
+    /// return the output of the last build of the plugin, that is
+    /// stored also when the build fails.
+    async fn logs(&self, plugin: &str) -> Result<CoffeeBuildLog, CoffeeError>;
+
+    /// return the commits between the installed plugin and the
+    /// latest commit of its repository, without upgrading it.
+    async fn changelog(&self, plugin: &str) -> Result<CoffeeChangelog, CoffeeError>;
+
+    /// return the status of a single plugin, or None when the
+    /// plugin is not installed and not inside any repository.
+    async fn status(&self, plugin: &str) -> Result<Option<CoffeeStatus>, CoffeeError>;
+
+    /// upgrade a single or multiple repositories, with `clean`
+    /// the local checkout is reset to a pristine state before.
+    async fn upgrade(
+        &mut self,
+        repo: &str,
+        clean: bool,
+        verbose: bool,
+    ) -> Result<CoffeeUpgrade, CoffeeError>;
 
-    /// add the remote repository to the plugin manager.
-    async fn add_remote(&mut self, name: &str, url: &str) -> Result<(), CoffeeError>;
+    /// fetch the remote repositories and return the installed plugins
+    /// that have newer commits upstream, without upgrading anything.
+    async fn check_updates(&self) -> Result<CoffeeCheckUpdates, CoffeeError>;
 
-    /// remove the remote repository from the plugin manager.
+    /// add the remote repository to the plugin manager, and
+    /// return the plugins found inside it.
+    async fn add_remote(&mut self, name: &str, url: &str) -> Result<CoffeeAddRemote, CoffeeError>;
+
+    /// remove the remote repository from the plugin manager,
+    /// together with the plugins installed from it.
     async fn rm_remote(&mut self, name: &str) -> Result<(), CoffeeError>;
 
+    /// return the plugins that `rm_remote` would remove together
+    /// with the remote repository, without removing anything.
+    async fn rm_remote_preview(&self, name: &str) -> Result<CoffeeRemoteRemoval, CoffeeError>;
+
+    /// fetch the remote repository and look for new plugins,
+    /// without upgrading the plugins installed from it.
+    async fn refresh_remote(&mut self, name: &str) -> Result<CoffeeRefresh, CoffeeError>;
+
+    /// refresh all the remote repositories, an error while refreshing
+    /// a repository does not stop the refresh of the others.
+    async fn refresh_all(
+        &mut self,
+    ) -> Result<HashMap<String, Result<CoffeeRefresh, CoffeeError>>, CoffeeError>;
+
     /// list the remote repositories for the plugin manager.
     async fn list_remotes(&mut self) -> Result<CoffeeRemote, CoffeeError>;
 
@@ -45,15 +170,57 @@ pub trait PluginManager {
     /// Unlink coffee from CLN configuration file
     async fn unlink(&mut self, cln_conf_path: &str) -> Result<(), CoffeeError>;
 
+    /// link coffee to the core lightning node at `cln_dir` as
+    /// the target `name`, without switching to it.
+    async fn add_target(&mut self, name: &str, cln_dir: &str) -> Result<(), CoffeeError>;
+
+    /// switch the core lightning target where `install`
+    /// and `remove` write the plugins.
+    async fn use_target(&mut self, name: &str) -> Result<(), CoffeeError>;
+
+    /// return the core lightning targets known by coffee.
+    async fn list_targets(&self) -> Result<CoffeeTargets, CoffeeError>;
+
+    /// show the core lightning configuration written by coffee,
+    /// merged with the one of the node.
+    async fn show_config(&self) -> Result<CoffeeClnConfig, CoffeeError>;
+
+    /// return the remotes and the plugins installed, so the same
+    /// setup can be imported by coffee on another machine.
+    async fn export_state(&self) -> Result<CoffeeState, CoffeeError>;
+
+    /// add the remotes and install the plugins of the `state`, building
+    /// them again. What is already present with a different
+    /// configuration is reported as a conflict and left untouched.
+    async fn import_state(
+        &mut self,
+        state: &CoffeeState,
+        verbose: bool,
+    ) -> Result<CoffeeImport, CoffeeError>;
+
     /// show the README file of the plugin
     async fn show(&mut self, plugin: &str) -> Result<CoffeeShow, CoffeeError>;
 
-    /// search remote repositories for a plugin by name
+    /// search remote repositories for the plugins that
+    /// contains the term in the name or in the description.
     async fn search(&mut self, plugin: &str) -> Result<CoffeeSearch, CoffeeError>;
 
+    /// explain why the plugin can not be installed, with
+    /// the plugins of the remotes that have a similar name.
+    async fn explain(&self, plugin: &str) -> Result<CoffeeExplain, CoffeeError>;
+
     /// clean up storage information about the remote repositories of the plugin manager.
     async fn nurse(&mut self) -> Result<CoffeeNurse, CoffeeError>;
 
+    /// check that the environment is able to run the plugins
+    /// installed, without taking any action.
+    async fn diagnose(&self) -> Result<CoffeeDiagnose, CoffeeError>;
+
+    /// remove the directories of the repositories and of the plugins that
+    /// coffee does not track, with `cache` also the downloads shared by
+    /// the builds. With `dry_run` the directories are only listed.
+    async fn prune(&mut self, dry_run: bool, cache: bool) -> Result<CoffeePrune, CoffeeError>;
+
     /// verify that coffee configuration is sane without taking any action.
     async fn nurse_verify(&self) -> Result<ChainOfResponsibilityStatus, CoffeeError>;
 
@@ -64,6 +231,20 @@ pub trait PluginManager {
         repos: Vec<String>,
     ) -> Result<Vec<NurseStatus>, CoffeeError>;
 
+    /// patch coffee configuration in the case that a repository is stored
+    /// but coffee is not able to load it, removing it.
+    async fn patch_repository_corrupted(
+        &mut self,
+        repos: Vec<String>,
+    ) -> Result<Vec<NurseStatus>, CoffeeError>;
+
+    /// patch coffee configuration in the case that a plugin is present in the core
+    /// lightning configuration managed by coffee but its executable is absent.
+    async fn patch_plugin_executable_absent(
+        &mut self,
+        plugins: Vec<String>,
+    ) -> Result<Vec<NurseStatus>, CoffeeError>;
+
     /// tip a specific plugins of the following amount
     ///
     /// The tip command required that the receiver of the
@@ -72,6 +253,10 @@ pub trait PluginManager {
     /// P.S: only Bitcoin ofc
     async fn tip(&mut self, plugin: &str, amount_msat: u64) -> Result<CoffeeTip, CoffeeError>;
 
+    /// return where the author of the plugin accepts tips,
+    /// looking at the plugins installed and then at the repositories.
+    async fn funding(&self, plugin: &str) -> Result<CoffeeFunding, CoffeeError>;
+
     /// disable a plugin by name
     async fn disable(&mut self, plugin: &str) -> Result<(), CoffeeError>;
 
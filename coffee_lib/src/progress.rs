@@ -0,0 +1,102 @@
+//! Progress events emitted by the plugin manager during
+//! the long operations, so a client can render them.
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc::UnboundedSender;
+
+pub type ProgressSender = UnboundedSender<ProgressEvent>;
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ProgressEvent {
+    /// The repository at the url is being cloned
+    Cloning(String),
+    /// The plugin is copied from the repository
+    Copying(String),
+    /// The plugin is being built
+    Building(String),
+    /// (dependency, plugin) the dependency is installed before the plugin
+    InstallingDependency(String, String),
+    /// A line of the output of a command
+    Output(String),
+    /// The plugin is installed
+    Done(String),
+}
+
+impl fmt::Display for ProgressEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProgressEvent::Cloning(url) => write!(f, "Cloning {url}"),
+            ProgressEvent::Copying(plugin) => write!(f, "Copying {plugin}"),
+            ProgressEvent::Building(plugin) => write!(f, "Building {plugin}"),
+            ProgressEvent::InstallingDependency(dependency, plugin) => {
+                write!(f, "Installing {dependency} required by {plugin}")
+            }
+            ProgressEvent::Output(line) => write!(f, "{line}"),
+            ProgressEvent::Done(plugin) => write!(f, "Installed {plugin}"),
+        }
+    }
+}
+
+/// Send the event when there is someone listening, the
+/// events are best effort so a closed channel is ignored.
+pub fn emit(progress: Option<&ProgressSender>, event: ProgressEvent) {
+    if let Some(progress) = progress {
+        let _ = progress.send(event);
+    }
+}
+
+/// Read the `pipe` line by line forwarding each line to `progress`,
+/// and append the whole output read to `output`.
+///
+/// The output is appended while it is read, so the lines read are
+/// kept also when the future is dropped before the end of the pipe.
+///
+/// With `log_lines` each line is also logged, the library never
+/// prints on the terminal, that belongs to the embedder.
+pub async fn forward_lines<R>(
+    pipe: Option<R>,
+    progress: Option<&ProgressSender>,
+    log_lines: bool,
+    output: &mut String,
+) where
+    R: AsyncRead + Unpin,
+{
+    let Some(pipe) = pipe else {
+        return;
+    };
+    let mut lines = BufReader::new(pipe).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        *output += &line;
+        output.push('\n');
+        if log_lines {
+            log::info!("{line}");
+        }
+        emit(progress, ProgressEvent::Output(line));
+    }
+}
+
+/// Append the `script` and its output to the log file at `path`.
+///
+/// The log is best effort, so a failure is only reported in the logs.
+pub async fn append_log(path: &str, script: &str, stdout: &str, stderr: &str, status: &str) {
+    let content = format!("$ {script}\n{stdout}{stderr}[{status}]\n");
+    let file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await;
+    let result = match file {
+        // a tokio file writes in the background, so it is flushed
+        // to have the content on the disk once we return.
+        Ok(mut file) => match file.write_all(content.as_bytes()).await {
+            Ok(()) => file.flush().await,
+            Err(err) => Err(err),
+        },
+        Err(err) => Err(err),
+    };
+    if let Err(err) = result {
+        log::warn!("unable to write the build log `{path}`: {err}");
+    }
+}
@@ -6,9 +6,37 @@ use crate::errors::CoffeeError;
 use crate::plugin::Plugin;
 use crate::url::URL;
 
-use crate::types::response::CoffeeUpgrade;
+use crate::types::response::{CoffeeCommit, CoffeeUpgrade, RepositoryHealth, UpgradeStatus};
 
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+/// The kind of repository, used to build back
+/// the right implementation from the storage.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Kind {
+    Git,
+    GitLab,
+    Local,
+}
+
+/// Minimal information to make
+/// a repository persistent.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RepositoryInfo {
+    pub kind: Kind,
+    pub name: String,
+    pub url: URL,
+    pub plugins: Vec<Plugin>,
+    pub branch: String,
+    pub git_head: Option<String>,
+    pub last_activity: Option<String>,
+    /// the last time that the repository was fetched
+    pub fetched_at: Option<String>,
+    /// the urls tried in order when the `url` is not reachable
+    #[serde(default)]
+    pub mirrors: Vec<String>,
+}
 
 #[async_trait]
 pub trait Repository: Any {
@@ -24,21 +52,62 @@ pub trait Repository: Any {
     /// return the list of plugin that are register contained inside the repository.
     async fn list(&self) -> Result<Vec<Plugin>, CoffeeError>;
 
-    /// upgrade the repository
+    /// upgrade the repository, with `clean` the untracked files
+    /// are removed from the local checkout before the upgrade.
     async fn upgrade(
         &mut self,
         plugins: &Vec<Plugin>,
+        clean: bool,
         verbose: bool,
     ) -> Result<CoffeeUpgrade, CoffeeError>;
 
+    /// fetch the remote and index the plugins again, without
+    /// rebuilding the plugins installed from the repository.
+    async fn refresh(&mut self) -> Result<UpgradeStatus, CoffeeError>;
+
+    /// fetch the remote and return its latest commit, without
+    /// touching the local checkout. `None` when the repository
+    /// does not have any upstream.
+    async fn latest_commit(&self) -> Result<Option<String>, CoffeeError>;
+
+    /// return true when the directory of the plugin `name` has
+    /// some changes between the commits `from` and `to`.
+    async fn plugin_changed(&self, name: &str, from: &str, to: &str) -> Result<bool, CoffeeError>;
+
+    /// read the file at the `path` relative to the repository
+    /// root, as it is at the `commit`.
+    async fn read_file(&self, commit: &str, path: &str) -> Result<String, CoffeeError>;
+
+    /// return the commits reachable from `to` and not from `from`,
+    /// fetching the history when `from` is not available locally.
+    async fn log(&self, from: &str, to: &str) -> Result<Vec<CoffeeCommit>, CoffeeError>;
+
+    /// check out the `git_ref` in the local repository, fetching it
+    /// when it is not available, and return its commit id.
+    ///
+    /// The head of the repository is not updated, so `None`
+    /// checks out the head again.
+    async fn checkout(&mut self, git_ref: Option<&str>) -> Result<String, CoffeeError>;
+
     /// recover the repository from the commit id.
     async fn recover(&mut self) -> Result<(), CoffeeError>;
 
+    /// check that the local checkout of the repository exists,
+    /// it is valid and it is at the commit stored, without
+    /// changing anything.
+    async fn health_check(&self) -> Result<RepositoryHealth, CoffeeError>;
+
     /// return the name of the repository.
     fn name(&self) -> String;
 
     /// return the url of the repository.
     fn url(&self) -> URL;
 
+    /// return the last time that the repository was fetched.
+    fn fetched_at(&self) -> Option<String>;
+
+    /// return the information to store the repository.
+    fn to_info(&self) -> RepositoryInfo;
+
     fn as_any(&self) -> &dyn Any;
 }
@@ -65,15 +65,45 @@ pub mod request {
 
 // Definition of the response types.
 pub mod response {
+    use std::collections::{BTreeMap, HashMap};
     use std::fmt;
 
     use serde::{Deserialize, Serialize};
 
-    use crate::plugin::Plugin;
+    use crate::plugin::{Plugin, PluginLang, PluginScope};
+
+    /// The plugin installed by `install`.
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct CoffeeInstall {
+        pub plugin: String,
+        /// The executable of the plugin, with a dry run the
+        /// one that the build would produce.
+        pub exec_path: String,
+        pub lang: PluginLang,
+    }
+
+    impl CoffeeInstall {
+        pub fn new(plugin: &Plugin, exec_path: &str) -> Self {
+            Self {
+                plugin: plugin.name(),
+                exec_path: exec_path.to_owned(),
+                lang: plugin.lang.clone(),
+            }
+        }
+    }
 
     #[derive(Debug, Serialize, Deserialize)]
     pub struct CoffeeRemove {
         pub plugin: Plugin,
+        /// The disk space reclaimed by the remove, in bytes.
+        #[serde(default)]
+        pub freed_bytes: u64,
+        /// The files and the directories deleted.
+        #[serde(default)]
+        pub removed_paths: Vec<String>,
+        /// The options of the plugin removed from the cln configuration.
+        #[serde(default)]
+        pub reverted_options: BTreeMap<String, String>,
     }
 
     // This struct is used to represent the list of plugins
@@ -83,6 +113,46 @@ pub mod response {
     #[derive(Debug, Serialize, Deserialize)]
     pub struct CoffeeList {
         pub plugins: Vec<Plugin>,
+        /// The plugins available across the remote repositories,
+        /// filled only when the available plugins are requested.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub available: Vec<CoffeeAvailablePlugin>,
+        /// The disk space used by each installed plugin, in bytes,
+        /// filled only when the disk usage is requested.
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        pub disk_usage: HashMap<String, u64>,
+    }
+
+    /// The order of the plugins installed inside `CoffeeList`,
+    /// the plugins with the same key are ordered by name.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+    pub enum ListOrder {
+        #[default]
+        Name,
+        /// By the name of the repository that contains the plugin.
+        Repository,
+        /// From the oldest install to the newest one.
+        InstallDate,
+    }
+
+    /// A plugin available in a remote repository, flagged
+    /// when it is already installed.
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct CoffeeAvailablePlugin {
+        pub repository: String,
+        pub plugin: Plugin,
+        pub installed: bool,
+    }
+
+    /// The remote repository added, or updated.
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct CoffeeAddRemote {
+        pub name: String,
+        /// The url cloned, that can be one of the mirrors.
+        pub url: String,
+        /// The plugins found inside the remote, a remote without
+        /// plugins is kept unless they are required.
+        pub plugins: Vec<String>,
     }
 
     #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -97,6 +167,78 @@ pub mod response {
         pub plugins: Vec<Plugin>,
         pub commit_id: Option<String>,
         pub date: Option<String>,
+        /// The git reference where the repository is pinned
+        pub git_ref: Option<String>,
+        /// The last time that the repository was fetched
+        pub fetched_at: Option<String>,
+        /// The urls tried in order when `url` is not reachable
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub mirrors: Vec<String>,
+        /// The disk space used by the clone, in bytes,
+        /// available only when the disk usage is requested.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub disk_usage: Option<u64>,
+    }
+
+    /// An installed plugin that has newer commits upstream.
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct CoffeePluginUpdate {
+        pub plugin: String,
+        /// The commit of the installed plugin
+        pub current_commit: Option<String>,
+        /// The latest commit of the remote repository
+        pub latest_commit: String,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct CoffeeCheckUpdates {
+        pub updates: Vec<CoffeePluginUpdate>,
+    }
+
+    /// The output of the last build of a plugin.
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct CoffeeBuildLog {
+        pub plugin: String,
+        /// The path of the log file
+        pub path: String,
+        pub log: String,
+    }
+
+    /// A single commit of a repository.
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct CoffeeCommit {
+        pub id: String,
+        /// The first line of the commit message
+        pub summary: String,
+        pub author: String,
+        pub date: String,
+    }
+
+    /// The commits between the installed plugin
+    /// and the latest commit of its repository.
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct CoffeeChangelog {
+        pub plugin: String,
+        pub current_commit: String,
+        pub latest_commit: String,
+        /// The new commits, from the newest to the oldest.
+        pub commits: Vec<CoffeeCommit>,
+    }
+
+    /// The status of a single plugin, installed or
+    /// available in one of the remote repositories.
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct CoffeeStatus {
+        pub plugin: String,
+        pub installed: bool,
+        /// A plugin that is not installed is never enabled.
+        pub enabled: bool,
+        pub version: Option<String>,
+        pub commit: Option<String>,
+        pub lang: PluginLang,
+        /// The path of the executable, available
+        /// only when the plugin is installed.
+        pub exec_path: Option<String>,
     }
 
     #[derive(Debug, Serialize, Deserialize)]
@@ -130,17 +272,197 @@ pub mod response {
         /// alterate we return the list of plugin
         /// that are effected and need to be recompiled.
         pub plugins_effected: Vec<String>,
+        /// The outcome of the reload of the plugins rebuilt inside
+        /// the running core lightning, empty without the rpc socket.
+        #[serde(default)]
+        pub reloads: Vec<ReloadStatus>,
+        /// The files changed by hand inside the local checkout
+        /// that were found before the upgrade.
+        #[serde(default)]
+        pub dirty: Vec<String>,
+        /// The error of each plugin that could not be rebuilt, the
+        /// installed build of these plugins is left as it was.
+        #[serde(default)]
+        pub failed: HashMap<String, String>,
+    }
+
+    /// The outcome of the refresh of a repository, that
+    /// does not touch the plugins installed.
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct CoffeeRefresh {
+        pub repo: String,
+        pub status: UpgradeStatus,
+        /// The plugins that the repository did not contain before.
+        pub new_plugins: Vec<String>,
+    }
+
+    /// A plugin that core lightning accepted to run during a trial.
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct CoffeeTrial {
+        pub plugin: String,
+        /// The executable started by core lightning, removed after the trial.
+        pub exec_path: String,
+    }
+
+    /// The outcome of the build of all the installed plugins.
+    #[derive(Debug, Default, Serialize, Deserialize)]
+    pub struct CoffeeRepair {
+        /// The plugins built again.
+        pub repaired: Vec<CoffeeInstall>,
+        /// The error of each plugin that could not be built again.
+        pub failed: HashMap<String, String>,
+    }
+
+    /// The core lightning configuration managed by coffee,
+    /// and the options that core lightning reads with it.
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct CoffeeClnConfig {
+        /// The core lightning configuration, missing
+        /// when coffee is not linked yet.
+        pub cln_config_path: Option<String>,
+        /// The configuration written by coffee.
+        pub coffee_config_path: String,
+        /// Whether the core lightning configuration
+        /// includes the one written by coffee.
+        pub included: bool,
+        /// The options written by coffee.
+        pub coffee_config: BTreeMap<String, Vec<String>>,
+        /// The options of the core lightning configuration
+        /// together with the ones written by coffee.
+        pub merged: BTreeMap<String, Vec<String>>,
+    }
+
+    /// A remote repository inside the state of coffee.
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    pub struct CoffeeStateRemote {
+        pub name: String,
+        pub url: String,
+        /// The git reference where the repository is pinned
+        pub git_ref: Option<String>,
+        /// The urls tried in order when `url` is not reachable
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub mirrors: Vec<String>,
+    }
+
+    /// A plugin installed inside the state of coffee.
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    pub struct CoffeeStatePlugin {
+        pub name: String,
+        /// The remote that contains the plugin, missing
+        /// when it is not inside any remote.
+        pub remote: Option<String>,
+        /// The git reference where the plugin is pinned
+        pub git_ref: Option<String>,
+        pub enabled: bool,
+        #[serde(default)]
+        pub scope: PluginScope,
+    }
+
+    /// The portable state of coffee, the remotes and the plugins
+    /// installed from them, without the artifacts of the builds
+    /// that are built again when the state is imported.
+    #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+    pub struct CoffeeState {
+        pub remotes: Vec<CoffeeStateRemote>,
+        pub plugins: Vec<CoffeeStatePlugin>,
+    }
+
+    /// The outcome of the import of a state of coffee.
+    #[derive(Debug, Default, Serialize, Deserialize)]
+    pub struct CoffeeImport {
+        /// The remotes added.
+        pub remotes_added: Vec<String>,
+        /// The plugins installed.
+        pub installed: Vec<String>,
+        /// The remotes and the plugins already present.
+        pub skipped: Vec<String>,
+        /// The remotes and the plugins that are present with
+        /// a different configuration, left untouched.
+        pub conflicts: Vec<String>,
+        /// The error of each remote that could not be added.
+        pub remotes_failed: HashMap<String, String>,
+        /// The error of each plugin that could not be installed.
+        pub plugins_failed: HashMap<String, String>,
+    }
+
+    /// A core lightning node where coffee installs the plugins.
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct CoffeeTarget {
+        pub name: String,
+        /// The core lightning root directory, missing
+        /// when the target is not linked yet.
+        pub cln_root: Option<String>,
+        /// The configuration file written by coffee.
+        pub config_path: String,
+        /// The number of plugins installed on the target.
+        pub plugins: usize,
+        /// Whether the install and the remove write to this target.
+        pub active: bool,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct CoffeeTargets {
+        pub targets: Vec<CoffeeTarget>,
+    }
+
+    /// The plugins installed from a remote repository,
+    /// that are removed together with it.
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct CoffeeRemoteRemoval {
+        pub remote: String,
+        pub plugins: Vec<Plugin>,
+    }
+
+    /// The outcome of the reload of a plugin after the upgrade.
+    #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+    pub enum ReloadStatus {
+        /// (plugin) the new executable is running
+        Reloaded(String),
+        /// (plugin) core lightning needs a restart to run the new executable
+        RestartNeeded(String),
     }
 
     #[derive(Clone, Debug, Serialize, Deserialize)]
     pub struct CoffeeShow {
         pub readme: String,
+        /// The commit of the README, that is the installed
+        /// one when the plugin is installed.
+        pub commit: Option<String>,
+        /// The author declared in the manifest of the plugin
+        pub author: Option<String>,
+        /// The license declared in the manifest of the plugin
+        pub license: Option<String>,
+        /// The homepage declared in the manifest of the plugin
+        pub homepage: Option<String>,
+        /// sha256 of the plugin executable, available
+        /// only when the plugin is installed.
+        pub checksum: Option<String>,
+        /// fingerprint of the key that signed the manifest,
+        /// when the installed plugin was verified.
+        #[serde(default)]
+        pub signed_by: Option<String>,
+        /// minimum version of core lightning needed by the plugin.
+        #[serde(default)]
+        pub min_cln_version: Option<String>,
     }
 
     #[derive(Clone, Debug, Serialize, Deserialize)]
     pub struct CoffeeSearch {
+        /// The url of the repository of the best match
         pub repository_url: String,
+        /// The best match
         pub plugin: Plugin,
+        /// All the plugins that match the search,
+        /// sorted from the best to the worst match.
+        pub matches: Vec<CoffeeSearchMatch>,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct CoffeeSearchMatch {
+        pub name: String,
+        pub repository: String,
+        pub repository_url: String,
+        pub description: Option<String>,
     }
 
     /// This struct is used to represent a defect
@@ -150,6 +472,12 @@ pub mod response {
         // A patch operation when a git repository is present in the coffee configuration
         // but is absent from the local storage.
         RepositoryLocallyAbsent(Vec<String>),
+        // A patch operation when a plugin is present in the core lightning
+        // configuration managed by coffee but its executable is absent.
+        PluginExecutableAbsent(Vec<String>),
+        // A patch operation when a repository is stored but coffee
+        // is not able to load it, e.g: the stored entry is damaged.
+        RepositoryCorrupted(Vec<String>),
         // TODO: Add more patch operations
     }
 
@@ -178,6 +506,18 @@ pub mod response {
                                 write!(f, " {}", repo)?;
                             }
                         }
+                        Defect::PluginExecutableAbsent(plugins) => {
+                            write!(f, "{}. Plugin executable missing locally: ", i + 1)?;
+                            for plugin in plugins {
+                                write!(f, " {}", plugin)?;
+                            }
+                        }
+                        Defect::RepositoryCorrupted(repos) => {
+                            write!(f, "{}. Repository not loadable: ", i + 1)?;
+                            for repo in repos {
+                                write!(f, " {}", repo)?;
+                            }
+                        }
                     }
                 }
                 Ok(())
@@ -192,6 +532,32 @@ pub mod response {
     pub enum NurseStatus {
         RepositoryLocallyRestored(Vec<String>),
         RepositoryLocallyRemoved(Vec<String>),
+        PluginConfigurationRemoved(Vec<String>),
+        /// The repositories that coffee was not able to load,
+        /// removed together with their clone.
+        CorruptedRepositoryRemoved(Vec<String>),
+    }
+
+    /// A directory that coffee does not use anymore.
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct CoffeePrunedPath {
+        pub path: String,
+        /// The size in bytes of the files inside the directory
+        pub size: u64,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct CoffeePrune {
+        pub paths: Vec<CoffeePrunedPath>,
+        /// When true the directories are only listed, not removed.
+        pub dry_run: bool,
+    }
+
+    impl CoffeePrune {
+        /// The bytes reclaimed by removing the directories.
+        pub fn reclaimed(&self) -> u64 {
+            self.paths.iter().map(|path| path.size).sum()
+        }
     }
 
     #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -210,6 +576,8 @@ pub mod response {
             let mut new_status: Vec<NurseStatus> = vec![];
             let mut repositories_locally_removed: Vec<String> = vec![];
             let mut repositories_locally_restored: Vec<String> = vec![];
+            let mut plugins_configuration_removed: Vec<String> = vec![];
+            let mut corrupted_repositories_removed: Vec<String> = vec![];
             for repo in self.status.iter() {
                 match repo {
                     NurseStatus::RepositoryLocallyRemoved(repos) => {
@@ -218,6 +586,12 @@ pub mod response {
                     NurseStatus::RepositoryLocallyRestored(repos) => {
                         repositories_locally_restored.append(&mut repos.clone())
                     }
+                    NurseStatus::PluginConfigurationRemoved(plugins) => {
+                        plugins_configuration_removed.append(&mut plugins.clone())
+                    }
+                    NurseStatus::CorruptedRepositoryRemoved(repos) => {
+                        corrupted_repositories_removed.append(&mut repos.clone())
+                    }
                 }
             }
             if !repositories_locally_removed.is_empty() {
@@ -230,6 +604,16 @@ pub mod response {
                     repositories_locally_restored,
                 ));
             }
+            if !plugins_configuration_removed.is_empty() {
+                new_status.push(NurseStatus::PluginConfigurationRemoved(
+                    plugins_configuration_removed,
+                ));
+            }
+            if !corrupted_repositories_removed.is_empty() {
+                new_status.push(NurseStatus::CorruptedRepositoryRemoved(
+                    corrupted_repositories_removed,
+                ));
+            }
             self.status = new_status;
         }
     }
@@ -243,10 +627,184 @@ pub mod response {
                 NurseStatus::RepositoryLocallyRemoved(val) => {
                     write!(f, "Repositories removed locally: {}", val.join(" "))
                 }
+                NurseStatus::PluginConfigurationRemoved(val) => {
+                    write!(f, "Plugins removed from configuration: {}", val.join(" "))
+                }
+                NurseStatus::CorruptedRepositoryRemoved(val) => {
+                    write!(f, "Corrupted repositories removed: {}", val.join(" "))
+                }
             }
         }
     }
 
+    /// An issue found by the diagnose, that
+    /// coffee is not able to fix by itself.
+    #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+    pub enum DiagnoseIssue {
+        /// (binary, plugins that need it)
+        MissingBinary(String, Vec<String>),
+        /// The configuration file can not be written by coffee
+        ConfigNotWritable(String),
+        /// (plugin, executable path)
+        PluginNotExecutable(String, String),
+        /// The local checkout of the repository is missing
+        RepositoryAbsent(String),
+        /// The local checkout of the repository is not a git repository
+        RepositoryNotValid(String),
+        /// (repository, commit stored, commit checked out)
+        RepositoryHeadMismatch(String, String, String),
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct CoffeeDiagnose {
+        pub issues: Vec<DiagnoseIssue>,
+    }
+
+    impl CoffeeDiagnose {
+        pub fn is_healthy(&self) -> bool {
+            self.issues.is_empty()
+        }
+    }
+
+    impl fmt::Display for DiagnoseIssue {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                DiagnoseIssue::MissingBinary(binary, plugins) => write!(
+                    f,
+                    "`{binary}` not found in the PATH, required by: {}",
+                    plugins.join(" ")
+                ),
+                DiagnoseIssue::ConfigNotWritable(path) => {
+                    write!(f, "configuration file `{path}` is not writable")
+                }
+                DiagnoseIssue::PluginNotExecutable(plugin, path) => {
+                    write!(f, "plugin `{plugin}` executable `{path}` is not executable")
+                }
+                DiagnoseIssue::RepositoryAbsent(repo) => write!(
+                    f,
+                    "repository `{repo}` is missing locally, run `coffee nurse` to restore it"
+                ),
+                DiagnoseIssue::RepositoryNotValid(repo) => {
+                    write!(f, "repository `{repo}` is not a valid git repository")
+                }
+                DiagnoseIssue::RepositoryHeadMismatch(repo, expected, head) => write!(
+                    f,
+                    "repository `{repo}` is at commit {head}, but coffee stored {expected}"
+                ),
+            }
+        }
+    }
+
+    /// The local checkout of a repository, compared
+    /// with the one stored by coffee.
+    #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+    pub struct RepositoryHealth {
+        pub name: String,
+        /// The local checkout exists
+        pub exists: bool,
+        /// The local checkout is a valid git repository
+        pub valid: bool,
+        /// The commit checked out, None when it can not be read
+        pub head: Option<String>,
+        /// The commit stored by coffee, None when the
+        /// repository does not track any commit
+        pub expected: Option<String>,
+    }
+
+    impl RepositoryHealth {
+        pub fn head_matches(&self) -> bool {
+            self.expected.is_none() || self.head == self.expected
+        }
+
+        pub fn is_healthy(&self) -> bool {
+            self.exists && self.valid && self.head_matches()
+        }
+    }
+
+    /// A reason why the install of a plugin would fail.
+    #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+    pub enum ExplainReason {
+        /// No remote repository is added
+        NoRemotes,
+        /// No remote repository has a plugin with the name
+        NotFound,
+        /// The plugin is already installed
+        AlreadyInstalled,
+        /// (repository, error) the coffee manifest of the plugin can not be used
+        InvalidManifest(String, String),
+        /// (repository, language) the exec path of the plugin can
+        /// not be guessed without the coffee manifest
+        MissingManifest(String, PluginLang),
+        /// (repository) the language of the plugin is not detected
+        UnknownLang(String),
+        /// (binary, language) the binary is not found in the PATH
+        MissingBinary(String, PluginLang),
+        /// The dependencies of the plugin can not be resolved
+        Dependencies(String),
+        /// The node runs an older core lightning version
+        IncompatibleCln(String),
+    }
+
+    impl fmt::Display for ExplainReason {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                ExplainReason::NoRemotes => {
+                    write!(f, "no remote is added, use `coffee remote add` to add one")
+                }
+                ExplainReason::NotFound => {
+                    write!(f, "the plugin is not present inside the remotes")
+                }
+                ExplainReason::AlreadyInstalled => write!(f, "the plugin is already installed"),
+                ExplainReason::InvalidManifest(repo, err) => {
+                    write!(f, "the coffee manifest inside `{repo}` is not valid: {err}")
+                }
+                ExplainReason::MissingManifest(repo, lang) => write!(
+                    f,
+                    "the plugin inside `{repo}` is written in {lang}, but it has no coffee manifest to find its executable"
+                ),
+                ExplainReason::UnknownLang(repo) => write!(
+                    f,
+                    "the language of the plugin inside `{repo}` is unknown, and it has no coffee manifest"
+                ),
+                ExplainReason::MissingBinary(binary, lang) => write!(
+                    f,
+                    "`{binary}` not found in the PATH, required by the {lang} plugins"
+                ),
+                ExplainReason::Dependencies(err) => write!(f, "{err}"),
+                ExplainReason::IncompatibleCln(err) => write!(f, "{err}"),
+            }
+        }
+    }
+
+    /// Why a plugin can not be installed.
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct CoffeeExplain {
+        pub plugin: String,
+        /// The language detected, None when the plugin is not found.
+        pub lang: Option<PluginLang>,
+        /// The remote that contains the plugin.
+        pub repository: Option<String>,
+        /// The plugins of the remotes with a similar name,
+        /// sorted from the most to the least similar.
+        pub suggestions: Vec<CoffeeSearchMatch>,
+        pub reasons: Vec<ExplainReason>,
+    }
+
+    impl CoffeeExplain {
+        pub fn is_installable(&self) -> bool {
+            self.reasons.is_empty()
+        }
+    }
+
+    /// Where the author of a plugin accepts tips.
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub struct CoffeeFunding {
+        pub plugin: String,
+        /// Lightning address or BOLT12 offer, None when
+        /// the plugin does not define any tipping info.
+        pub funding: Option<String>,
+    }
+
     #[derive(Clone, Debug, Serialize, Deserialize)]
     pub struct CoffeeTip {
         pub for_plugin: String,
@@ -2,6 +2,9 @@ use std::fmt;
 
 use serde::{Deserialize, Serialize};
 
+use crate::errors::CoffeeError;
+use crate::macros::error;
+
 /// This struct will make sure our URLs are of the
 /// correct format and will also check correctness
 /// of associated fields
@@ -15,6 +18,9 @@ pub struct URL {
     pub path_string: String,
     /// the repo name associated with the url
     pub repo_name: String,
+    /// the git reference (branch, tag or commit) where
+    /// the repository is pinned, if any.
+    pub git_ref: Option<String>,
 }
 
 /// Handle GitHub HTTP links
@@ -25,12 +31,49 @@ pub(crate) fn remove_dot_git_from_url(url: &str) -> &str {
     }
 }
 
-/// Handle URLs with a trailing "/"
+/// Handle URLs with one or more trailing "/"
 fn remove_trailing_slash_from_url(url: &str) -> &str {
-    match url.strip_suffix('/') {
-        Some(s) => s,
-        None => url,
+    url.trim_end_matches('/')
+}
+
+/// Reject the urls that git would fail to clone, so the user
+/// gets the error before any network operation.
+fn validate_url(url: &str) -> Result<(), CoffeeError> {
+    if url.is_empty() {
+        return Err(error!("the repository url is empty"));
+    }
+    if url.contains(char::is_whitespace) {
+        return Err(error!("the repository url `{url}` contains spaces"));
+    }
+    match url.split_once("://") {
+        Some(("file", path)) => {
+            if path.is_empty() {
+                return Err(error!("the repository url `{url}` has no path"));
+            }
+        }
+        Some(("https" | "http" | "ssh" | "git", rest)) => {
+            let host = rest.split('/').next().unwrap_or_default();
+            let host = host.rsplit('@').next().unwrap_or_default();
+            if host.is_empty() || host.starts_with(':') {
+                return Err(error!("the repository url `{url}` has no host"));
+            }
+        }
+        Some((scheme, _)) => {
+            return Err(error!(
+                "the repository url `{url}` has an unsupported scheme `{scheme}`"
+            ));
+        }
+        // the scp-like syntax used by ssh, e.g: `git@github.com:user/repo.git`
+        None => match url.split_once(':') {
+            Some((authority, path)) if authority.contains('@') && !path.is_empty() => {}
+            _ => {
+                return Err(error!(
+                    "the repository url `{url}` is missing the scheme, e.g: `https://`"
+                ));
+            }
+        },
     }
+    Ok(())
 }
 
 /// Handle coffee non-compliant URLs
@@ -47,15 +90,53 @@ fn get_repo_name_from_url(url: &str) -> String {
     repo_name.to_owned()
 }
 
+/// Check if the argument is a git url, instead of a plugin name.
+pub fn is_git_url(value: &str) -> bool {
+    match value.split_once("://") {
+        Some(_) => true,
+        // the scp-like syntax used by ssh, e.g: `git@github.com:user/repo.git`
+        None => matches!(value.split_once(':'), Some((authority, _)) if authority.contains('@')),
+    }
+}
+
 impl URL {
     /// Build a new URL and initialize its fields
-    pub fn new(local_path: &str, url: &str, remote_name: &str) -> Self {
-        URL {
+    ///
+    /// The url can contains a git reference where the repository
+    /// needs to be pinned, in the form of `<url>#<git_ref>`.
+    ///
+    /// An error is returned when the url is not a valid git url.
+    pub fn new(local_path: &str, url: &str, remote_name: &str) -> Result<Self, CoffeeError> {
+        let (url, git_ref) = match url.split_once('#') {
+            Some((url, git_ref)) if !git_ref.is_empty() => (url, Some(git_ref.to_owned())),
+            Some((url, _)) => (url, None),
+            None => (url, None),
+        };
+        validate_url(url)?;
+        let url = handle_incorrect_url(url);
+        Ok(URL {
             name: remote_name.to_owned(),
-            url_string: handle_incorrect_url(url),
+            repo_name: get_repo_name_from_url(&url),
+            url_string: url,
             path_string: format!("{local_path}/repositories/{remote_name}"),
-            repo_name: get_repo_name_from_url(url),
+            git_ref,
+        })
+    }
+
+    /// Return the host of the url, without credentials and port.
+    ///
+    /// Both the `https://host/...` and the `git@host:...` forms
+    /// are supported.
+    pub fn host(&self) -> Option<String> {
+        let authority = match self.url_string.split_once("://") {
+            Some((_, rest)) => rest.split('/').next()?,
+            None => self.url_string.split(':').next()?,
+        };
+        let host = authority.rsplit('@').next()?.split(':').next()?;
+        if host.is_empty() {
+            return None;
         }
+        Some(host.to_lowercase())
     }
 }
 
@@ -72,12 +153,12 @@ impl fmt::Display for URL {
 #[cfg(test)]
 mod tests {
     use super::remove_dot_git_from_url;
-    use super::URL;
+    use super::{is_git_url, URL};
 
     #[test]
     fn test_remote() {
         let u = "https://github.com/lightningd/plugins";
-        let url = URL::new("/tmp/", u, "lightningd_plugins");
+        let url = URL::new("/tmp/", u, "lightningd_plugins").unwrap();
         assert_eq!(url.repo_name, "plugins");
         assert_eq!(url.url_string, u);
     }
@@ -85,8 +166,73 @@ mod tests {
     #[test]
     fn test_remote_git_prefix() {
         let u = "https://github.com/lightningd/plugins.git";
-        let url = URL::new("/tmp/", u, "lightningd_plugins");
+        let url = URL::new("/tmp/", u, "lightningd_plugins").unwrap();
+        assert_eq!(url.repo_name, "plugins");
+        assert_eq!(url.url_string, remove_dot_git_from_url(u));
+    }
+
+    #[test]
+    fn test_remote_host() {
+        let url = URL::new("/tmp/", "https://gitlab.com/x/plugins", "x").unwrap();
+        assert_eq!(url.host(), Some("gitlab.com".to_owned()));
+
+        let url = URL::new("/tmp/", "https://user@GitHub.com:443/x/plugins", "x").unwrap();
+        assert_eq!(url.host(), Some("github.com".to_owned()));
+
+        let url = URL::new("/tmp/", "git@gitlab.com:x/plugins.git", "x").unwrap();
+        assert_eq!(url.host(), Some("gitlab.com".to_owned()));
+    }
+
+    #[test]
+    fn test_remote_git_ref() {
+        let u = "https://github.com/lightningd/plugins.git";
+        let url = URL::new("/tmp/", u, "lightningd_plugins").unwrap();
+        assert_eq!(url.git_ref, None);
+
+        let url = URL::new("/tmp/", &format!("{u}#v1.0.0"), "lightningd_plugins").unwrap();
         assert_eq!(url.repo_name, "plugins");
         assert_eq!(url.url_string, remove_dot_git_from_url(u));
+        assert_eq!(url.git_ref, Some("v1.0.0".to_owned()));
+    }
+
+    #[test]
+    fn test_is_git_url() {
+        assert!(is_git_url("https://github.com/coffee-tools/folgore.git"));
+        assert!(is_git_url("git@github.com:coffee-tools/folgore.git"));
+        assert!(is_git_url("file:///home/alice/folgore"));
+        assert!(!is_git_url("folgore"));
+        assert!(!is_git_url("summary"));
+    }
+
+    #[test]
+    fn test_remote_trailing_slashes() {
+        let url = URL::new("/tmp/", "https://github.com/lightningd/plugins.git//", "x").unwrap();
+        assert_eq!(url.url_string, "https://github.com/lightningd/plugins");
+        assert_eq!(url.repo_name, "plugins");
+    }
+
+    #[test]
+    fn test_invalid_url() {
+        for url in [
+            "",
+            "github.com/lightningd/plugins",
+            "https://github.com/lightningd/plugins ",
+            " https://github.com/lightningd/plugins",
+            "https:///lightningd/plugins",
+            "ftp://github.com/lightningd/plugins",
+            "file://",
+        ] {
+            let result = URL::new("/tmp/", url, "x");
+            assert!(result.is_err(), "`{url}` is valid: {:?}", result);
+        }
+
+        for url in [
+            "git@github.com:lightningd/plugins.git",
+            "ssh://git@github.com/lightningd/plugins.git",
+            "file:///home/alice/plugins",
+        ] {
+            let result = URL::new("/tmp/", url, "x");
+            assert!(result.is_ok(), "`{url}` is not valid: {:?}", result);
+        }
     }
 }
@@ -1,9 +1,15 @@
 use super::macros::error;
-use std::path::Path;
+use std::ffi::{OsStr, OsString};
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
 
-use tokio::fs::create_dir;
+use sha2::{Digest, Sha256};
+use tokio::fs::{create_dir, File};
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
 
-use crate::errors::CoffeeError;
+use crate::errors::{CoffeeError, CoffeeErrorKind};
+use crate::progress::{append_log, forward_lines, ProgressSender};
 
 pub fn get_plugin_info_from_path(path: &Path) -> Result<(String, String), CoffeeError> {
     match path.parent() {
@@ -28,6 +34,426 @@ pub async fn check_dir_or_make_if_missing(path: String) -> Result<(), CoffeeErro
     Ok(())
 }
 
+/// Check if a binary is available inside one of the
+/// directories listed in the `PATH` environment variable.
+pub fn binary_in_path(binary: &str) -> bool {
+    let Some(paths) = std::env::var_os("PATH") else {
+        return false;
+    };
+    std::env::split_paths(&paths).any(|dir| dir.join(binary).is_file())
+}
+
+/// Resolve the `relative` path inside the `root` directory, normalizing
+/// the `.` and `..` components without touching the file system.
+///
+/// A path that is absolute or that escapes from `root` is an error.
+pub fn resolve_path(root: &str, relative: &str) -> Result<String, CoffeeError> {
+    let mut resolved = PathBuf::new();
+    for component in Path::new(relative).components() {
+        match component {
+            Component::Normal(part) => resolved.push(part),
+            Component::CurDir => {}
+            Component::ParentDir if resolved.pop() => {}
+            _ => {
+                return Err(error!(
+                    "path `{relative}` is not inside the directory `{root}`"
+                ))
+            }
+        }
+    }
+    if resolved.as_os_str().is_empty() {
+        return Err(error!("path `{relative}` does not point to a file"));
+    }
+    Ok(Path::new(root).join(resolved).to_string_lossy().to_string())
+}
+
+/// Replace each `${VAR}` inside `value` with the value of the
+/// environment variable `VAR`, or an empty string when missing.
+pub fn expand_env(value: &str) -> String {
+    let mut expanded = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        expanded += &rest[..start];
+        let name = &rest[start + 2..start + len];
+        expanded += &std::env::var(name).unwrap_or_default();
+        rest = &rest[start + len + 1..];
+    }
+    expanded + rest
+}
+
+/// The environment variables that make the commands run by coffee,
+/// like git, pip or npm, reach the network through the `proxy`.
+pub fn proxy_env(proxy: Option<&str>) -> Vec<(String, String)> {
+    let Some(proxy) = proxy else {
+        return vec![];
+    };
+    ["HTTP_PROXY", "HTTPS_PROXY", "http_proxy", "https_proxy"]
+        .iter()
+        .map(|key| (key.to_string(), proxy.to_owned()))
+        .collect()
+}
+
+/// Compute the size in bytes of the file, or of all the files
+/// inside the directory, without following the symbolic links.
+pub fn disk_usage(path: &Path) -> u64 {
+    let Ok(metadata) = std::fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    std::fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| disk_usage(&entry.path()))
+                .sum()
+        })
+        .unwrap_or_default()
+}
+
+/// Verify the detached `signature` of the `file` with gpg, and return the
+/// fingerprints of the keys of the valid signatures.
+///
+/// The keys need to be already inside the gpg keyring of the user.
+pub async fn gpg_verify(file: &str, signature: &str) -> Result<Vec<String>, CoffeeError> {
+    let output = Command::new("gpg")
+        .args(["--batch", "--status-fd", "1", "--verify", signature, file])
+        .output()
+        .await
+        .map_err(|err| error!("unable to run gpg to verify `{file}`: {err}"))?;
+    let fingerprints = valid_signatures(&String::from_utf8_lossy(&output.stdout));
+    if !output.status.success() || fingerprints.is_empty() {
+        return Err(error!(
+            "invalid signature `{signature}` of `{file}`: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(fingerprints)
+}
+
+/// Read the fingerprints of the valid signatures from the
+/// machine readable status printed by `gpg --status-fd`.
+///
+/// Each signature reports the fingerprint of the key that signed,
+/// and the fingerprint of its primary key when it is a subkey.
+fn valid_signatures(status: &str) -> Vec<String> {
+    status
+        .lines()
+        .filter_map(|line| line.strip_prefix("[GNUPG:] VALIDSIG "))
+        .flat_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            [fields.first(), fields.get(9)]
+                .into_iter()
+                .flatten()
+                .map(|fingerprint| fingerprint.to_string())
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Parse a core lightning version, like `v23.08.1` or
+/// `v24.02rc1-modded`, in its numeric components.
+pub fn parse_cln_version(version: &str) -> Option<Vec<u64>> {
+    let version = version.trim().trim_start_matches('v');
+    let end = version
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(version.len());
+    let components = version[..end]
+        .split('.')
+        .filter(|component| !component.is_empty())
+        .map(|component| component.parse::<u64>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    (!components.is_empty()).then_some(components)
+}
+
+/// Check if the `running` core lightning version is at least the
+/// `required` one, `None` when one of them can not be parsed.
+pub fn cln_version_satisfies(required: &str, running: &str) -> Option<bool> {
+    let mut required = parse_cln_version(required)?;
+    let mut running = parse_cln_version(running)?;
+    let len = required.len().max(running.len());
+    required.resize(len, 0);
+    running.resize(len, 0);
+    Some(running >= required)
+}
+
+/// Ask the version to the `lightningd` binary inside the `PATH`.
+pub async fn lightningd_version() -> Option<String> {
+    if !binary_in_path("lightningd") {
+        return None;
+    }
+    let output = Command::new("lightningd")
+        .arg("--version")
+        .output()
+        .await
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let version = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    (!version.is_empty()).then_some(version)
+}
+
+/// Kill the process of a script, and the processes started by it, when
+/// it is dropped before being disarmed. This way the tools started by a
+/// build, e.g. `cargo`, do not keep running when we stop waiting for it.
+///
+/// The script must be spawned as the leader of its own process group,
+/// e.g. with `process_group(0)`, so all its descendants are in the group.
+pub struct KillOnDrop {
+    pid: Option<u32>,
+}
+
+impl KillOnDrop {
+    pub fn new(pid: Option<u32>) -> Self {
+        Self { pid }
+    }
+
+    /// The process terminated, so there is nothing left to kill.
+    pub fn disarm(&mut self) {
+        self.pid = None;
+    }
+}
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        let Some(pid) = self.pid else {
+            return;
+        };
+        // the group id is the pid of its leader, and a negative
+        // pid sends the signal to the whole group.
+        #[cfg(unix)]
+        unsafe {
+            libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+        }
+    }
+}
+
+/// Quote `value` to be used as a single word inside a shell script.
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// The options of a script run with `run_script`, they
+/// start from the defaults of `ScriptOptions::new`.
+#[derive(Clone, Default)]
+pub struct ScriptOptions<'a> {
+    verbose: bool,
+    dry_run: bool,
+    timeout: Option<Duration>,
+    progress: Option<&'a ProgressSender>,
+    log: Option<&'a str>,
+    env: Vec<(OsString, OsString)>,
+}
+
+impl<'a> ScriptOptions<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Without a listener or a log file, a verbose
+    /// script writes directly on the terminal.
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// A dry run only logs the script, without running it.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// When the timeout expires the script, and the processes that
+    /// it started, are killed and an error with the `ScriptTimeout`
+    /// kind is returned.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Each line of the output is forwarded to the
+    /// listener as a `ProgressEvent::Output`.
+    pub fn progress(mut self, progress: Option<&'a ProgressSender>) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// The script and its output are appended to the file at
+    /// `path`, also when the script is not verbose.
+    pub fn log(mut self, path: Option<&'a str>) -> Self {
+        self.log = path;
+        self
+    }
+
+    /// The `(key, value)` pairs are added to the environment of the script.
+    pub fn env<I, K, V>(mut self, env: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        self.env.extend(
+            env.into_iter()
+                .map(|(key, value)| (key.as_ref().to_os_string(), value.as_ref().to_os_string())),
+        );
+        self
+    }
+}
+
+/// Append the output of a script to the `content` of its error.
+fn with_output(mut content: String, stdout: &str, stderr: &str) -> String {
+    for output in [stderr, stdout] {
+        if !output.trim().is_empty() {
+            content += &format!("\n{}", output.trim());
+        }
+    }
+    content
+}
+
+/// Run the `script` with `sh` inside `root`, this is what the `sh!`
+/// macro expands to, so all its forms behave in the same way.
+///
+/// When the script fails or times out, the output read so far is
+/// reported inside the error, and it is written to the log file.
+pub async fn run_script(
+    root: impl AsRef<Path>,
+    script: &str,
+    options: ScriptOptions<'_>,
+) -> Result<(), CoffeeError> {
+    let script = script.trim();
+    if options.dry_run {
+        log::info!("[dry-run] {}: {script}", root.as_ref().display());
+        return Ok(());
+    }
+    log::debug!("script: {:?}", script);
+
+    let ScriptOptions {
+        verbose,
+        timeout,
+        progress,
+        log: log_path,
+        env,
+        ..
+    } = options;
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", script]);
+    cmd.current_dir(root);
+    cmd.envs(env);
+    // the script leads its own process group, so
+    // its descendants can be killed together with it.
+    #[cfg(unix)]
+    cmd.process_group(0);
+    let inherit = verbose && progress.is_none() && log_path.is_none();
+    if !verbose {
+        cmd.stdin(std::process::Stdio::null());
+    }
+    if !inherit {
+        cmd.stdout(std::process::Stdio::piped());
+        cmd.stderr(std::process::Stdio::piped());
+    }
+    let mut child = cmd
+        .spawn()
+        .map_err(|err| error!("Unable to run the command: {err}"))?;
+    // the script is killed when we stop waiting for it.
+    let mut guard = KillOnDrop::new(child.id());
+    // without a listener the output of a verbose script is logged
+    let log_lines = progress.is_none() && verbose;
+    let (stdout, stderr) = (child.stdout.take(), child.stderr.take());
+    let (mut stdout_output, mut stderr_output) = (String::new(), String::new());
+    let run = async {
+        tokio::join!(
+            forward_lines(stdout, progress, log_lines, &mut stdout_output),
+            forward_lines(stderr, progress, log_lines, &mut stderr_output),
+        );
+        child.wait().await
+    };
+    let result = match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, run).await.ok(),
+        None => Some(run.await),
+    };
+    let Some(result) = result else {
+        let timeout = timeout.unwrap_or_default();
+        if let Some(log_path) = log_path {
+            let status = format!("timed out after {}s", timeout.as_secs());
+            append_log(log_path, script, &stdout_output, &stderr_output, &status).await;
+        }
+        let content = format!("`{script}` timed out after {}s", timeout.as_secs());
+        let content = with_output(content, &stdout_output, &stderr_output);
+        return Err(
+            error!("{content}").with_kind(CoffeeErrorKind::ScriptTimeout {
+                script: script.to_owned(),
+            }),
+        );
+    };
+    guard.disarm();
+    let status = result.map_err(|err| error!("Unable to run the command: {err}"))?;
+    if let Some(log_path) = log_path {
+        append_log(
+            log_path,
+            script,
+            &stdout_output,
+            &stderr_output,
+            &status.to_string(),
+        )
+        .await;
+    }
+
+    if !status.success() {
+        let content = format!("`{script}` exited with {}", status);
+        let content = with_output(content, &stdout_output, &stderr_output);
+        return Err(CoffeeError::new(2, &content));
+    }
+    Ok(())
+}
+
+/// Copy the directory `from` inside the new directory `to`, like
+/// `cp -r`, keeping the symbolic links as they are.
+pub async fn copy_dir(from: &Path, to: &Path) -> Result<(), CoffeeError> {
+    let mut dirs = vec![(from.to_path_buf(), to.to_path_buf())];
+    while let Some((from, to)) = dirs.pop() {
+        tokio::fs::create_dir_all(&to).await?;
+        let mut entries = tokio::fs::read_dir(&from).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let (source, target) = (entry.path(), to.join(entry.file_name()));
+            let file_type = entry.file_type().await?;
+            if file_type.is_dir() {
+                dirs.push((source, target));
+            } else if file_type.is_symlink() {
+                tokio::fs::symlink(tokio::fs::read_link(&source).await?, &target).await?;
+            } else {
+                tokio::fs::copy(&source, &target).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Compute the sha256 of a file, as a lowercase hex string.
+pub async fn sha256sum(path: &str) -> Result<String, CoffeeError> {
+    let mut file = File::open(path)
+        .await
+        .map_err(|err| error!("unable to compute the sha256 of `{path}`: {err}"))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs::create_dir_all;
@@ -37,6 +463,11 @@ mod tests {
     use std::path::Path;
     use std::sync::Once;
 
+    use super::{
+        binary_in_path, cln_version_satisfies, copy_dir, disk_usage, expand_env, parse_cln_version,
+        proxy_env, resolve_path, sha256sum, shell_quote, valid_signatures,
+    };
+
     static INIT: Once = Once::new();
 
     fn init() {
@@ -67,4 +498,132 @@ mod tests {
         assert!(Path::new(&path).exists());
         remove_dir_all(path).unwrap();
     }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("git"), "'git'");
+        assert_eq!(shell_quote("/opt/my git/git"), "'/opt/my git/git'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+    }
+
+    #[test]
+    fn test_proxy_env() {
+        assert!(proxy_env(None).is_empty());
+        let env = proxy_env(Some("http://proxy.local:3128"));
+        assert_eq!(env.len(), 4);
+        assert!(env
+            .iter()
+            .any(|(key, value)| key == "HTTPS_PROXY" && value == "http://proxy.local:3128"));
+    }
+
+    #[test]
+    fn test_binary_in_path() {
+        init();
+        assert!(binary_in_path("sh"));
+        assert!(!binary_in_path("coffee-binary-that-does-not-exist"));
+    }
+
+    #[test]
+    fn test_expand_env() {
+        init();
+        env::set_var("COFFEE_EXPAND_ROOT", "/coffee");
+        assert_eq!(expand_env("${COFFEE_EXPAND_ROOT}/target"), "/coffee/target");
+        assert_eq!(expand_env("${COFFEE_EXPAND_MISSING}target"), "target");
+        assert_eq!(expand_env("no variables"), "no variables");
+        assert_eq!(expand_env("${unterminated"), "${unterminated");
+    }
+
+    #[test]
+    fn test_valid_signatures() {
+        init();
+        let status = "[GNUPG:] NEWSIG
+[GNUPG:] GOODSIG 5678EF90 Alice <alice@coffee.dev>
+[GNUPG:] VALIDSIG 1234ABCD5678EF90 2024-01-01 1704067200 0 4 0 1 10 00 AAAABBBBCCCCDDDD
+[GNUPG:] TRUST_UNDEFINED 0 pgp
+";
+        assert_eq!(
+            valid_signatures(status),
+            vec!["1234ABCD5678EF90".to_owned(), "AAAABBBBCCCCDDDD".to_owned()]
+        );
+        assert!(valid_signatures("[GNUPG:] BADSIG 5678EF90 Alice").is_empty());
+    }
+
+    #[test]
+    fn test_cln_version_satisfies() {
+        init();
+        assert_eq!(parse_cln_version("v23.08.1"), Some(vec![23, 8, 1]));
+        assert_eq!(parse_cln_version("v24.02rc1-modded"), Some(vec![24, 2]));
+        assert_eq!(parse_cln_version("unknown"), None);
+        assert_eq!(cln_version_satisfies("23.08", "v23.08.1"), Some(true));
+        assert_eq!(cln_version_satisfies("v23.08.1", "v23.08"), Some(false));
+        assert_eq!(cln_version_satisfies("v24.02", "v23.11.2"), Some(false));
+        assert_eq!(cln_version_satisfies("v24.02", "v24.02"), Some(true));
+        assert_eq!(cln_version_satisfies("v24.02", "unknown"), None);
+    }
+
+    #[test]
+    fn test_disk_usage() {
+        init();
+        let path = env::temp_dir().join("coffee-disk-usage");
+        create_dir_all(path.join("nested")).unwrap();
+        std::fs::write(path.join("plugin.py"), "coffee").unwrap();
+        std::fs::write(path.join("nested/requirements.txt"), "pyln-client").unwrap();
+        assert_eq!(disk_usage(&path), 17);
+        assert_eq!(disk_usage(&path.join("missing")), 0);
+        remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_path() {
+        init();
+        let root = "/coffee/plugins/folgore";
+        assert_eq!(
+            resolve_path(root, "dist/app.js").unwrap(),
+            "/coffee/plugins/folgore/dist/app.js"
+        );
+        assert_eq!(
+            resolve_path(root, "./dist/../bin//folgore").unwrap(),
+            "/coffee/plugins/folgore/bin/folgore"
+        );
+        assert!(resolve_path(root, "../other/plugin.py").is_err());
+        assert!(resolve_path(root, "/usr/bin/plugin").is_err());
+        assert!(resolve_path(root, "./").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_copy_dir() {
+        let dir = env::temp_dir().join(format!("coffee-copy-dir-{}", std::process::id()));
+        let _ = remove_dir_all(&dir);
+        let from = dir.join("my plugin");
+        create_dir_all(from.join("src")).unwrap();
+        std::fs::write(from.join("src").join("main.py"), "print('coffee')").unwrap();
+        std::os::unix::fs::symlink("src/main.py", from.join("main.py")).unwrap();
+
+        let to = dir.join("network").join("my plugin");
+        copy_dir(&from, &to).await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(to.join("src").join("main.py")).unwrap(),
+            "print('coffee')"
+        );
+        assert_eq!(
+            std::fs::read_link(to.join("main.py")).unwrap(),
+            Path::new("src/main.py")
+        );
+        // the source is left untouched
+        assert!(from.join("src").join("main.py").exists());
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sha256sum() {
+        init();
+        let path = env::temp_dir().join("coffee-sha256sum");
+        std::fs::write(&path, "coffee").unwrap();
+        let checksum = sha256sum(path.to_str().unwrap()).await.unwrap();
+        assert_eq!(
+            checksum,
+            "37290d74ac4d186e3a8e5785d259d2ec04fac91ae28092e7620ec8bc99e830aa"
+        );
+        std::fs::remove_file(path).unwrap();
+    }
 }
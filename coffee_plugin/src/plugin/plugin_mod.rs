@@ -91,9 +91,10 @@ fn coffee_install(plugin: &mut Plugin<State>, request: Value) -> Result<Value, P
     let rt = Runtime::new().unwrap();
 
     let request: InstallReq = serde_json::from_value(request)?;
-    rt.block_on(coffee.install(&request.name, false, true))
+    let result = rt
+        .block_on(coffee.install(&request.name, false, true, false, false))
         .map_err(from)?;
-    Ok(json!({}))
+    Ok(serde_json::to_value(result)?)
 }
 
 #[rpc_method(
@@ -119,7 +120,10 @@ fn coffee_remote(plugin: &mut Plugin<State>, request: Value) -> Result<Value, Pl
             let mut coffee = coffee.lock().unwrap();
             let cmd = request.cmd().unwrap();
             match cmd {
-                RemoteCmd::Add => coffee.add_remote(&request.name, &request.url()).await,
+                RemoteCmd::Add => coffee
+                    .add_remote(&request.name, &request.url())
+                    .await
+                    .map(|_| ()),
                 RemoteCmd::Rm => coffee.rm_remote(&request.name).await,
             }
         })
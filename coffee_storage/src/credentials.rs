@@ -0,0 +1,318 @@
+//! credentials is a module to keep the secrets used
+//! by the plugin manager (e.g: the token of a private
+//! repository) encrypted on disk.
+//!
+//! The secrets are encrypted with AES-256-GCM, with a key derived
+//! with PBKDF2 from a passphrase given by the user, that is never
+//! stored. Only the random salt of the derivation is stored inside
+//! the coffee root directory.
+//!
+//! The previous versions of coffee stored the key in the clear,
+//! the secrets encrypted with it are migrated to the passphrase.
+//!
+//! The token of a repository is encrypted together with the host
+//! where it is valid, so it is never sent to another host.
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+
+use openssl::hash::MessageDigest;
+use openssl::pkcs5::pbkdf2_hmac;
+use openssl::rand::rand_bytes;
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+use serde::{Deserialize, Serialize};
+
+use coffee_lib::error;
+use coffee_lib::errors::CoffeeError;
+
+const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+const IV_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+/// Iterations of PBKDF2, to make the guess of the passphrase slow.
+const KDF_ITERATIONS: usize = 200_000;
+
+/// Key stored in the clear by the previous versions of coffee.
+const LEGACY_KEY_FILE: &str = "credentials.key";
+
+pub struct Credentials {
+    key: Vec<u8>,
+}
+
+/// The token of a private repository, with the host where it is valid.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RepositoryToken {
+    pub token: String,
+    /// `None` when the token was stored before its host was recorded.
+    pub host: Option<String>,
+}
+
+impl RepositoryToken {
+    pub fn new(token: &str, host: Option<String>) -> Self {
+        Self {
+            token: token.to_owned(),
+            host,
+        }
+    }
+
+    /// The token, when it is valid for `host`. A token stored
+    /// without its host is valid only for `stored_host`, the
+    /// host of the remote when the token was stored.
+    pub fn for_host(&self, host: Option<&str>, stored_host: Option<&str>) -> Option<String> {
+        let valid_host = self.host.as_deref().or(stored_host);
+        match (valid_host, host) {
+            (Some(valid_host), Some(host)) if valid_host == host => Some(self.token.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl Credentials {
+    /// Derive the encryption key from the `passphrase`, with the salt
+    /// stored inside `path`, that is generated if it is missing.
+    pub async fn new(path: &str, passphrase: &str) -> Result<Self, CoffeeError> {
+        if passphrase.is_empty() {
+            return Err(error!("the passphrase of the credentials is empty"));
+        }
+        let salt_path = format!("{path}/credentials.salt");
+        let salt = match tokio::fs::read(&salt_path).await {
+            Ok(salt) if salt.len() == SALT_LEN => salt,
+            Ok(_) => return Err(error!("credentials salt `{salt_path}` is corrupted")),
+            Err(_) => {
+                let mut salt = vec![0; SALT_LEN];
+                rand_bytes(&mut salt).map_err(|err| error!("{err}"))?;
+                let mut options = OpenOptions::new();
+                options.write(true).create_new(true);
+                #[cfg(unix)]
+                options.mode(0o600);
+                options.open(&salt_path)?.write_all(&salt)?;
+                salt
+            }
+        };
+        let mut key = vec![0; KEY_LEN];
+        pbkdf2_hmac(
+            passphrase.as_bytes(),
+            &salt,
+            KDF_ITERATIONS,
+            MessageDigest::sha256(),
+            &mut key,
+        )
+        .map_err(|err| error!("{err}"))?;
+        Ok(Self { key })
+    }
+
+    /// Load the key stored in the clear inside `path` by the
+    /// previous versions of coffee, `None` when there is not.
+    pub async fn legacy(path: &str) -> Result<Option<Self>, CoffeeError> {
+        let key_path = format!("{path}/{LEGACY_KEY_FILE}");
+        if !tokio::fs::try_exists(&key_path).await? {
+            return Ok(None);
+        }
+        let key = tokio::fs::read(&key_path).await?;
+        if key.len() != KEY_LEN {
+            return Err(error!("credentials key `{key_path}` is corrupted"));
+        }
+        Ok(Some(Self { key }))
+    }
+
+    /// Remove the key stored in the clear inside `path`, once
+    /// the secrets encrypted with it are migrated.
+    pub async fn remove_legacy(path: &str) -> Result<(), CoffeeError> {
+        tokio::fs::remove_file(format!("{path}/{LEGACY_KEY_FILE}")).await?;
+        Ok(())
+    }
+
+    /// Encrypt again with this key the `secrets` encrypted with the
+    /// `legacy` key, a secret that this key already decrypts is kept.
+    ///
+    /// All the secrets are migrated or none, so the legacy key must
+    /// be removed only when this succeeds.
+    pub fn migrate(
+        &self,
+        legacy: &Credentials,
+        secrets: &HashMap<String, String>,
+    ) -> Result<HashMap<String, String>, CoffeeError> {
+        let mut migrated = HashMap::new();
+        for (name, secret) in secrets {
+            let secret = match self.decrypt(secret) {
+                Ok(_) => secret.to_owned(),
+                Err(_) => {
+                    let secret = legacy
+                        .decrypt(secret)
+                        .map_err(|err| error!("secret of `{name}`: {err}"))?;
+                    self.encrypt(&secret)?
+                }
+            };
+            migrated.insert(name.to_owned(), secret);
+        }
+        Ok(migrated)
+    }
+
+    /// Encrypt the token of a repository together with its host.
+    pub fn encrypt_token(&self, token: &RepositoryToken) -> Result<String, CoffeeError> {
+        let token = serde_json::to_string(token).map_err(|err| error!("{err}"))?;
+        self.encrypt(&token)
+    }
+
+    /// Decrypt a token encrypted with `encrypt_token`, a token
+    /// stored before its host was recorded has no host.
+    pub fn decrypt_token(&self, secret: &str) -> Result<RepositoryToken, CoffeeError> {
+        let token = self.decrypt(secret)?;
+        match serde_json::from_str(&token) {
+            Ok(token) => Ok(token),
+            Err(_) => Ok(RepositoryToken { token, host: None }),
+        }
+    }
+
+    /// Encrypt the secret, and return it hex encoded.
+    pub fn encrypt(&self, secret: &str) -> Result<String, CoffeeError> {
+        let mut iv = [0; IV_LEN];
+        rand_bytes(&mut iv).map_err(|err| error!("{err}"))?;
+        let mut tag = [0; TAG_LEN];
+        let ciphertext = encrypt_aead(
+            Cipher::aes_256_gcm(),
+            &self.key,
+            Some(&iv),
+            &[],
+            secret.as_bytes(),
+            &mut tag,
+        )
+        .map_err(|err| error!("{err}"))?;
+        let data = [&iv[..], &tag[..], &ciphertext[..]].concat();
+        Ok(data.iter().map(|byte| format!("{byte:02x}")).collect())
+    }
+
+    /// Decrypt a secret encrypted with `encrypt`.
+    pub fn decrypt(&self, secret: &str) -> Result<String, CoffeeError> {
+        let data = (0..secret.len())
+            .step_by(2)
+            .map(|i| {
+                secret
+                    .get(i..i + 2)
+                    .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+            })
+            .collect::<Option<Vec<u8>>>()
+            .filter(|data| data.len() >= IV_LEN + TAG_LEN)
+            .ok_or(error!("encrypted secret malformed"))?;
+        let (iv, data) = data.split_at(IV_LEN);
+        let (tag, ciphertext) = data.split_at(TAG_LEN);
+        let secret = decrypt_aead(
+            Cipher::aes_256_gcm(),
+            &self.key,
+            Some(iv),
+            &[],
+            ciphertext,
+            tag,
+        )
+        .map_err(|_| error!("unable to decrypt the secret, is the passphrase correct?"))?;
+        String::from_utf8(secret).map_err(|err| error!("{err}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{Credentials, RepositoryToken, KEY_LEN, LEGACY_KEY_FILE};
+
+    #[tokio::test]
+    async fn test_encrypt_decrypt() {
+        let dir = std::env::temp_dir().join(format!("coffee-credentials-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.to_str().unwrap();
+
+        let credentials = Credentials::new(path, "passphrase").await.unwrap();
+        let encrypted = credentials.encrypt("glpat-secret").unwrap();
+        assert!(!encrypted.contains("glpat-secret"));
+        assert_eq!(credentials.decrypt(&encrypted).unwrap(), "glpat-secret");
+
+        // the salt is reused, so the same passphrase decrypts it again
+        let credentials = Credentials::new(path, "passphrase").await.unwrap();
+        assert_eq!(credentials.decrypt(&encrypted).unwrap(), "glpat-secret");
+
+        let credentials = Credentials::new(path, "wrong").await.unwrap();
+        assert!(credentials.decrypt(&encrypted).is_err());
+        assert!(credentials.decrypt("00ff").is_err());
+        assert!(Credentials::new(path, "").await.is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_token_host() {
+        let dir = std::env::temp_dir().join(format!("coffee-token-host-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.to_str().unwrap();
+        let credentials = Credentials::new(path, "passphrase").await.unwrap();
+
+        let token = RepositoryToken::new("glpat-secret", Some("gitlab.com".to_owned()));
+        let encrypted = credentials.encrypt_token(&token).unwrap();
+        let token = credentials.decrypt_token(&encrypted).unwrap();
+        assert_eq!(token.host.as_deref(), Some("gitlab.com"));
+        assert_eq!(
+            token.for_host(Some("gitlab.com"), None).as_deref(),
+            Some("glpat-secret")
+        );
+        // the host of the remote does not move the token to another host
+        assert!(token
+            .for_host(Some("evil.example.org"), Some("evil.example.org"))
+            .is_none());
+
+        // a token stored without its host stays on the host of the remote
+        let encrypted = credentials.encrypt("glpat-secret").unwrap();
+        let token = credentials.decrypt_token(&encrypted).unwrap();
+        assert_eq!(token.host, None);
+        assert!(token.for_host(Some("gitlab.com"), None).is_none());
+        assert!(token
+            .for_host(Some("evil.example.org"), Some("gitlab.com"))
+            .is_none());
+        assert_eq!(
+            token
+                .for_host(Some("gitlab.com"), Some("gitlab.com"))
+                .as_deref(),
+            Some("glpat-secret")
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_migrate_legacy() {
+        let dir = std::env::temp_dir().join(format!("coffee-legacy-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.to_str().unwrap();
+        assert!(Credentials::legacy(path).await.unwrap().is_none());
+
+        std::fs::write(dir.join(LEGACY_KEY_FILE), [7; KEY_LEN]).unwrap();
+        let legacy = Credentials::legacy(path).await.unwrap().unwrap();
+        let credentials = Credentials::new(path, "passphrase").await.unwrap();
+        // the legacy key is kept until the secrets are migrated
+        assert!(dir.join(LEGACY_KEY_FILE).exists());
+
+        let secrets = HashMap::from([
+            ("gitlab".to_owned(), legacy.encrypt("glpat-secret").unwrap()),
+            (
+                "github".to_owned(),
+                credentials.encrypt("ghp-secret").unwrap(),
+            ),
+        ]);
+        let migrated = credentials.migrate(&legacy, &secrets).unwrap();
+        assert_eq!(
+            credentials.decrypt(&migrated["gitlab"]).unwrap(),
+            "glpat-secret"
+        );
+        assert_eq!(migrated["github"], secrets["github"]);
+
+        // a secret that none of the keys decrypts can not be migrated
+        let other = Credentials::new(path, "other").await.unwrap();
+        let secrets = HashMap::from([("other".to_owned(), other.encrypt("secret").unwrap())]);
+        assert!(credentials.migrate(&legacy, &secrets).is_err());
+
+        Credentials::remove_legacy(path).await.unwrap();
+        assert!(Credentials::legacy(path).await.unwrap().is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -5,12 +5,16 @@
 //! This will work for the initial version
 //! of it, but maybe in the future it is needed
 //! a more smart version of storage manager
-use crate::storage::StorageManager;
+use crate::migration;
+use crate::storage::{StorageManager, REPOSITORIES_KEY};
 use async_trait::async_trait;
+use coffee_lib::error;
 use coffee_lib::errors::CoffeeError;
+use coffee_lib::repository::RepositoryInfo;
 use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
 use tokio::{
-    fs::File,
+    fs::{self, File},
     io::{AsyncReadExt, AsyncWriteExt},
 };
 
@@ -31,6 +35,46 @@ impl FileStorage {
     pub fn get_path(&self) -> String {
         format!("{}/{}", self.path, self.name_file)
     }
+
+    /// path of the copy of the last successful store.
+    pub fn get_backup_path(&self) -> String {
+        format!("{}.bak", self.get_path())
+    }
+
+    async fn load_from<T>(path: &str) -> Result<T, CoffeeError>
+    where
+        T: DeserializeOwned + Send + Sync,
+    {
+        let mut content = String::new();
+        File::open(path).await?.read_to_string(&mut content).await?;
+        migration::decode(&content)
+    }
+
+    /// the file is a full dump, so a change of the repositories
+    /// rewrites it with the change applied to the stored value.
+    async fn update_repositories<F>(&self, update: F) -> Result<(), CoffeeError>
+    where
+        F: FnOnce(&mut serde_json::Map<String, Value>) + Send,
+    {
+        let mut value: Value = self.load(REPOSITORIES_KEY).await?;
+        let Some(store) = value.as_object_mut() else {
+            return Err(error!(
+                "the storage file {} is not an object",
+                self.get_path()
+            ));
+        };
+        let repositories = store
+            .entry(REPOSITORIES_KEY)
+            .or_insert_with(|| Value::Object(Default::default()));
+        let Some(repositories) = repositories.as_object_mut() else {
+            return Err(error!(
+                "the repositories inside {} are not an object",
+                self.get_path()
+            ));
+        };
+        update(repositories);
+        self.store(REPOSITORIES_KEY, &value).await
+    }
 }
 
 #[async_trait]
@@ -41,24 +85,142 @@ impl StorageManager for FileStorage {
     where
         T: DeserializeOwned + Send + Sync,
     {
-        let mut content = String::new();
-        File::open(self.get_path())
-            .await?
-            .read_to_string(&mut content)
-            .await?;
-        let val = serde_json::from_str::<T>(&content).unwrap();
-        Ok(val)
+        match Self::load_from(&self.get_path()).await {
+            Ok(val) => Ok(val),
+            Err(err) => {
+                // the storage file can be truncated by a crash,
+                // so we try to recover from the previous store.
+                Self::load_from(&self.get_backup_path())
+                    .await
+                    .map_err(|_| err)
+            }
+        }
     }
 
     async fn store<T>(&self, _: &str, to_store: &T) -> Result<(), Self::Err>
     where
         T: Serialize + Send + Sync,
     {
-        let content = serde_json::to_string(to_store).unwrap();
-        File::create(self.get_path())
-            .await?
-            .write_all(content.as_bytes())
-            .await?;
+        let content = migration::encode(to_store)?;
+        // write the content in a temporary file in the same directory,
+        // so the rename over the storage file is atomic.
+        let tmp_path = format!("{}.tmp", self.get_path());
+        let mut file = File::create(&tmp_path).await?;
+        file.write_all(content.as_bytes()).await?;
+        file.sync_all().await?;
+
+        // a corrupted storage file must not replace the last
+        // good backup, so it is parsed before the rotation.
+        if fs::try_exists(self.get_path()).await? {
+            match Self::load_from::<Value>(&self.get_path()).await {
+                Ok(_) => {
+                    fs::copy(self.get_path(), self.get_backup_path()).await?;
+                }
+                Err(err) => log::warn!(
+                    "storage file {} is corrupted, keeping the previous backup: {err}",
+                    self.get_path()
+                ),
+            }
+        }
+        fs::rename(&tmp_path, self.get_path()).await?;
         Ok(())
     }
+
+    async fn upsert_repository(&self, repo: &RepositoryInfo) -> Result<(), Self::Err> {
+        let value = serde_json::to_value(repo).map_err(|err| error!("{err}"))?;
+        self.update_repositories(|repositories| {
+            repositories.insert(repo.name.clone(), value);
+        })
+        .await
+    }
+
+    async fn remove_repository(&self, name: &str) -> Result<(), Self::Err> {
+        self.update_repositories(|repositories| {
+            repositories.remove(name);
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde_json::{json, Value};
+
+    use coffee_lib::repository::RepositoryInfo;
+
+    use super::FileStorage;
+    use crate::storage::testing::{repository, test_dir};
+    use crate::storage::{StorageManager, REPOSITORIES_KEY};
+
+    async fn repositories(storage: &FileStorage) -> HashMap<String, RepositoryInfo> {
+        let value: Value = storage.load(REPOSITORIES_KEY).await.unwrap();
+        serde_json::from_value(value[REPOSITORIES_KEY].clone()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_upsert_remove_repository() {
+        let path = test_dir("file-repositories");
+        let storage = FileStorage::new(&path);
+        storage
+            .store(REPOSITORIES_KEY, &json!({"network": "regtest"}))
+            .await
+            .unwrap();
+
+        let lightningd = repository("lightningd", "https://github.com/lightningd/plugins");
+        storage.upsert_repository(&lightningd).await.unwrap();
+        let other = repository("other", "https://github.com/other/plugins");
+        storage.upsert_repository(&other).await.unwrap();
+        let moved = repository("lightningd", "https://github.com/moved/plugins");
+        storage.upsert_repository(&moved).await.unwrap();
+
+        let stored = repositories(&storage).await;
+        assert_eq!(stored.len(), 2);
+        assert_eq!(
+            stored["lightningd"].url.url_string,
+            "https://github.com/moved/plugins"
+        );
+        // the rest of the dump is untouched
+        let value: Value = storage.load(REPOSITORIES_KEY).await.unwrap();
+        assert_eq!(value["network"], "regtest");
+
+        storage.remove_repository("other").await.unwrap();
+        // removing a missing repository is not an error
+        storage.remove_repository("other").await.unwrap();
+        let stored = repositories(&storage).await;
+        assert_eq!(stored.keys().collect::<Vec<_>>(), vec!["lightningd"]);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_store_keeps_the_backup_of_a_corrupted_file() {
+        let path = test_dir("file-backup");
+        let storage = FileStorage::new(&path);
+        storage
+            .store(REPOSITORIES_KEY, &json!({"network": "regtest"}))
+            .await
+            .unwrap();
+        storage
+            .store(REPOSITORIES_KEY, &json!({"network": "testnet"}))
+            .await
+            .unwrap();
+
+        // a crash truncated the storage file
+        std::fs::write(storage.get_path(), "{\"network\": ").unwrap();
+        let value: Value = storage.load(REPOSITORIES_KEY).await.unwrap();
+        assert_eq!(value["network"], "regtest");
+
+        storage
+            .store(REPOSITORIES_KEY, &json!({"network": "signet"}))
+            .await
+            .unwrap();
+        let backup: Value = FileStorage::load_from(&storage.get_backup_path())
+            .await
+            .unwrap();
+        assert_eq!(backup["network"], "regtest");
+        let value: Value = storage.load(REPOSITORIES_KEY).await.unwrap();
+        assert_eq!(value["network"], "signet");
+        std::fs::remove_dir_all(&path).unwrap();
+    }
 }
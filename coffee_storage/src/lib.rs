@@ -2,7 +2,10 @@
 //! define a storage for the plugin manager in
 //! order to define the interface to manage the
 //! information to store on disk.
+pub mod credentials;
 pub mod file;
+pub mod migration;
 pub mod model;
 pub mod nosql_db;
+pub mod sqlite;
 pub mod storage;
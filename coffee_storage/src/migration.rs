@@ -0,0 +1,183 @@
+//! migration is a module to bring the values stored
+//! by an older coffee to the current shape.
+//!
+//! Each stored value is wrapped with the version of the
+//! schema, as `{"version": N, "data": ...}`, so on load we
+//! know which migrations to run without mixing the version
+//! with the keys of the stored value.
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Map, Value};
+
+use coffee_lib::error;
+use coffee_lib::errors::CoffeeError;
+
+/// Version of the schema of the stored values, it must be increased
+/// with a new migration when a stored struct changes its shape.
+pub const SCHEMA_VERSION: u64 = 1;
+
+/// Key of the envelope where the schema version is stored.
+const VERSION_KEY: &str = "version";
+
+/// Key of the envelope where the stored value is.
+const DATA_KEY: &str = "data";
+
+/// Key where the schema version was stored inside the values,
+/// before they were wrapped in the envelope.
+const INLINE_VERSION_KEY: &str = "schema_version";
+
+/// Migrations from each version to the next one, the
+/// migration at index `n` brings a value from `n` to `n + 1`.
+const MIGRATIONS: [fn(Value) -> Value; SCHEMA_VERSION as usize] = [plugins_out_of_config];
+
+/// Check if the value is the storage of a network, the only
+/// stored value that has a `config` with the `network` inside.
+fn is_storage_info(store: &Map<String, Value>) -> bool {
+    store
+        .get("repositories")
+        .is_some_and(|repositories| repositories.is_object())
+        && store
+            .get("config")
+            .and_then(|config| config.as_object())
+            .is_some_and(|config| config.contains_key("network"))
+}
+
+/// Version 1 stores the installed plugins next to the
+/// configuration, instead of inside it.
+fn plugins_out_of_config(mut value: Value) -> Value {
+    let Some(store) = value.as_object_mut() else {
+        return value;
+    };
+    if !is_storage_info(store) {
+        return value;
+    }
+    let plugins = store
+        .get_mut("config")
+        .and_then(|config| config.as_object_mut())
+        .and_then(|config| config.remove("plugins"));
+    if let Some(plugins) = plugins {
+        store.entry("plugins").or_insert(plugins);
+    }
+    value
+}
+
+/// Split the stored value in its schema version and its data.
+///
+/// A value without the envelope was stored before it, so the
+/// version is the one inside the value, when it is a number,
+/// or zero when it was stored before the versioning.
+fn unwrap(value: Value) -> (u64, Value) {
+    match value {
+        Value::Object(mut object) => {
+            let version = object.get(VERSION_KEY).and_then(|version| version.as_u64());
+            if let Some(version) = version {
+                if object.len() == 2 && object.contains_key(DATA_KEY) {
+                    let data = object.remove(DATA_KEY).unwrap_or_default();
+                    return (version, data);
+                }
+            }
+            let version = object
+                .get(INLINE_VERSION_KEY)
+                .and_then(|version| version.as_u64());
+            if let Some(version) = version {
+                object.remove(INLINE_VERSION_KEY);
+                return (version, Value::Object(object));
+            }
+            (0, Value::Object(object))
+        }
+        value => (0, value),
+    }
+}
+
+/// Run the migrations needed to bring the stored value to the
+/// current schema, and return the data without the envelope.
+pub fn migrate(value: Value) -> Result<Value, CoffeeError> {
+    let (version, mut value) = unwrap(value);
+    if version > SCHEMA_VERSION {
+        return Err(error!(
+            "storage schema version {version} is newer than the supported {SCHEMA_VERSION}, please upgrade coffee"
+        ));
+    }
+    for migration in &MIGRATIONS[version as usize..] {
+        value = migration(value);
+    }
+    Ok(value)
+}
+
+/// Serialize the value wrapped with the current schema version.
+pub fn encode<T: Serialize>(value: &T) -> Result<String, CoffeeError> {
+    let value = serde_json::to_value(value).map_err(|err| error!("{err}"))?;
+    let value = json!({ VERSION_KEY: SCHEMA_VERSION, DATA_KEY: value });
+    serde_json::to_string(&value).map_err(|err| error!("{err}"))
+}
+
+/// Deserialize a stored value, migrating it to the current schema.
+pub fn decode<T: DeserializeOwned>(content: &str) -> Result<T, CoffeeError> {
+    let value = serde_json::from_str::<Value>(content).map_err(|err| error!("{err}"))?;
+    let value = migrate(value)?;
+    serde_json::from_value(value).map_err(|err| error!("{err}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde_json::{json, Value};
+
+    use super::{decode, encode, migrate, SCHEMA_VERSION};
+
+    #[test]
+    fn test_migrate_unversioned_storage() {
+        let old = json!({
+            "config": {"network": "regtest", "plugins": [{"name": "summary"}]},
+            "repositories": {},
+        });
+        let value = migrate(old).unwrap();
+        assert_eq!(value["plugins"], json!([{"name": "summary"}]));
+        assert!(value["config"].get("plugins").is_none());
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let mut repositories = HashMap::new();
+        repositories.insert("lightningd".to_owned(), 1);
+        let content = encode(&repositories).unwrap();
+        assert!(content.contains(&format!("\"version\":{SCHEMA_VERSION}")));
+        let decoded: HashMap<String, u64> = decode(&content).unwrap();
+        assert_eq!(decoded, repositories);
+    }
+
+    #[test]
+    fn test_repository_named_like_the_version() {
+        let repositories = json!({
+            "schema_version": {"name": "schema_version", "plugins": []},
+            "version": {"name": "version", "plugins": []},
+        });
+        let content = encode(&repositories).unwrap();
+        let decoded: Value = decode(&content).unwrap();
+        assert_eq!(decoded, repositories);
+
+        // the values stored with the version inside keep the repository
+        let inline = json!({
+            "schema_version": {"name": "schema_version", "plugins": []},
+        });
+        assert_eq!(migrate(inline.clone()).unwrap(), inline);
+    }
+
+    #[test]
+    fn test_migrate_unversioned_repositories() {
+        // a repository named `config` is not the configuration
+        let old = json!({
+            "config": {"name": "config", "plugins": [{"name": "summary"}]},
+            "repositories": {"name": "repositories", "plugins": []},
+        });
+        assert_eq!(migrate(old.clone()).unwrap(), old);
+    }
+
+    #[test]
+    fn test_newer_schema() {
+        let value = json!({"version": SCHEMA_VERSION + 1, "data": {}});
+        assert!(migrate(value).is_err());
+        let value = json!({"schema_version": SCHEMA_VERSION + 1});
+        assert!(migrate(value).is_err());
+    }
+}
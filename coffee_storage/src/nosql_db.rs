@@ -1,4 +1,7 @@
+use std::collections::HashMap;
+
 use async_trait::async_trait;
+use coffee_lib::repository::RepositoryInfo;
 use coffee_lib::utils::check_dir_or_make_if_missing;
 use nosql_db::NoSQL;
 use nosql_sled::sled;
@@ -7,7 +10,8 @@ use nosql_sled::SledDB;
 use coffee_lib::error;
 use coffee_lib::errors::CoffeeError;
 
-use crate::storage::StorageManager;
+use crate::migration;
+use crate::storage::{StorageManager, REPOSITORIES_KEY};
 
 /// No SQL database
 pub struct NoSQlStorage {
@@ -22,6 +26,15 @@ impl NoSQlStorage {
         let db = SledDB::try_from(config).map_err(|err| error!("{err}"))?;
         Ok(Self { inner: db })
     }
+
+    /// the repositories are stored under a single key, so a
+    /// change rewrites the value with all the repositories.
+    async fn repositories(&self) -> Result<HashMap<String, serde_json::Value>, CoffeeError> {
+        if !self.inner.contains(REPOSITORIES_KEY) {
+            return Ok(HashMap::new());
+        }
+        self.load(REPOSITORIES_KEY).await
+    }
 }
 
 #[async_trait]
@@ -38,16 +51,69 @@ impl StorageManager for NoSQlStorage {
             ));
         }
         let value = self.inner.get(key).map_err(|err| error!("{err}"))?;
-        let value: T = serde_json::from_str(&value).map_err(|err| error!("{err}"))?;
-        Ok(value)
+        migration::decode(&value)
     }
 
     async fn store<T>(&self, key: &str, to_store: &T) -> Result<(), Self::Err>
     where
         T: serde::Serialize + Send + Sync,
     {
-        let value = serde_json::to_string(to_store).map_err(|err| error!("{err}"))?;
+        let value = migration::encode(to_store)?;
         self.inner.put(key, &value).map_err(|err| error!("{err}"))?;
         Ok(())
     }
+
+    async fn upsert_repository(&self, repo: &RepositoryInfo) -> Result<(), Self::Err> {
+        let mut repositories = self.repositories().await?;
+        let value = serde_json::to_value(repo).map_err(|err| error!("{err}"))?;
+        repositories.insert(repo.name.clone(), value);
+        self.store(REPOSITORIES_KEY, &repositories).await
+    }
+
+    async fn remove_repository(&self, name: &str) -> Result<(), Self::Err> {
+        let mut repositories = self.repositories().await?;
+        if repositories.remove(name).is_some() {
+            self.store(REPOSITORIES_KEY, &repositories).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use coffee_lib::repository::RepositoryInfo;
+
+    use super::NoSQlStorage;
+    use crate::storage::testing::{repository, test_dir};
+    use crate::storage::{StorageManager, REPOSITORIES_KEY};
+
+    #[tokio::test]
+    async fn test_upsert_remove_repository() {
+        let path = test_dir("nosql-repositories");
+        let storage = NoSQlStorage::new(&path).await.unwrap();
+
+        let lightningd = repository("lightningd", "https://github.com/lightningd/plugins");
+        storage.upsert_repository(&lightningd).await.unwrap();
+        let other = repository("other", "https://github.com/other/plugins");
+        storage.upsert_repository(&other).await.unwrap();
+        let moved = repository("lightningd", "https://github.com/moved/plugins");
+        storage.upsert_repository(&moved).await.unwrap();
+
+        let stored: HashMap<String, RepositoryInfo> = storage.load(REPOSITORIES_KEY).await.unwrap();
+        assert_eq!(stored.len(), 2);
+        assert_eq!(
+            stored["lightningd"].url.url_string,
+            "https://github.com/moved/plugins"
+        );
+
+        storage.remove_repository("other").await.unwrap();
+        // removing a missing repository is not an error
+        storage.remove_repository("other").await.unwrap();
+        let stored: HashMap<String, RepositoryInfo> = storage.load(REPOSITORIES_KEY).await.unwrap();
+        assert_eq!(stored.keys().collect::<Vec<_>>(), vec!["lightningd"]);
+        drop(storage);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
 }
@@ -0,0 +1,388 @@
+//! sqlite is a module to store the plugin manager
+//! status inside a SQLite database.
+//!
+//! Each key is stored as a row of the `storage` table,
+//! so storing a key does not rewrite the others. The
+//! repositories are stored as rows of the `repositories`
+//! table, so a single repository can be changed alone.
+//!
+//! Every value, including the rows of the repositories, is
+//! wrapped with the schema version by the `migration` module.
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use rusqlite::{Connection, OptionalExtension};
+use serde::{de::DeserializeOwned, Serialize};
+
+use coffee_lib::error;
+use coffee_lib::errors::CoffeeError;
+use coffee_lib::repository::RepositoryInfo;
+use coffee_lib::utils::check_dir_or_make_if_missing;
+
+use crate::migration;
+use crate::storage::{StorageManager, REPOSITORIES_KEY};
+
+/// SQL database
+pub struct SqliteStorage {
+    inner: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    pub async fn new(path: &str) -> Result<Self, CoffeeError> {
+        check_dir_or_make_if_missing(path.to_owned()).await?;
+        let conn =
+            Connection::open(format!("{path}/storage.sqlite")).map_err(|err| error!("{err}"))?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS storage (key TEXT PRIMARY KEY, value TEXT NOT NULL)",
+            (),
+        )
+        .map_err(|err| error!("{err}"))?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS repositories (name TEXT PRIMARY KEY, value TEXT NOT NULL)",
+            (),
+        )
+        .map_err(|err| error!("{err}"))?;
+        let storage = Self {
+            inner: Mutex::new(conn),
+        };
+        storage.migrate_repositories()?;
+        Ok(storage)
+    }
+
+    /// Move the repositories stored as a single row of the
+    /// `storage` table inside the `repositories` table.
+    fn migrate_repositories(&self) -> Result<(), CoffeeError> {
+        let mut conn = self.inner.lock().map_err(|err| error!("{err}"))?;
+        let value = conn
+            .query_row(
+                "SELECT value FROM storage WHERE key = ?1",
+                [REPOSITORIES_KEY],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()
+            .map_err(|err| error!("{err}"))?;
+        let Some(value) = value else {
+            return Ok(());
+        };
+        let repositories: HashMap<String, serde_json::Value> = migration::decode(&value)?;
+        let tx = conn.transaction().map_err(|err| error!("{err}"))?;
+        Self::insert_repositories(&tx, &repositories)?;
+        tx.execute("DELETE FROM storage WHERE key = ?1", [REPOSITORIES_KEY])
+            .map_err(|err| error!("{err}"))?;
+        tx.commit().map_err(|err| error!("{err}"))?;
+        Ok(())
+    }
+
+    fn insert_repositories(
+        conn: &Connection,
+        repositories: &HashMap<String, serde_json::Value>,
+    ) -> Result<(), CoffeeError> {
+        for (name, repo) in repositories {
+            let value = migration::encode(repo)?;
+            conn.execute(
+                "INSERT INTO repositories (name, value) VALUES (?1, ?2) \
+                 ON CONFLICT(name) DO UPDATE SET value = excluded.value",
+                (name, &value),
+            )
+            .map_err(|err| error!("{err}"))?;
+        }
+        Ok(())
+    }
+
+    fn load_repositories(&self) -> Result<HashMap<String, serde_json::Value>, CoffeeError> {
+        let conn = self.inner.lock().map_err(|err| error!("{err}"))?;
+        let mut stmt = conn
+            .prepare("SELECT name, value FROM repositories")
+            .map_err(|err| error!("{err}"))?;
+        let rows = stmt
+            .query_map((), |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })
+            .map_err(|err| error!("{err}"))?;
+        let mut repositories = HashMap::new();
+        for row in rows {
+            let (name, value) = row.map_err(|err| error!("{err}"))?;
+            repositories.insert(name, migration::decode(&value)?);
+        }
+        Ok(repositories)
+    }
+
+    fn store_repositories(
+        &self,
+        repositories: HashMap<String, serde_json::Value>,
+    ) -> Result<(), CoffeeError> {
+        let mut conn = self.inner.lock().map_err(|err| error!("{err}"))?;
+        let tx = conn.transaction().map_err(|err| error!("{err}"))?;
+        tx.execute("DELETE FROM repositories", ())
+            .map_err(|err| error!("{err}"))?;
+        Self::insert_repositories(&tx, &repositories)?;
+        tx.commit().map_err(|err| error!("{err}"))?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StorageManager for SqliteStorage {
+    type Err = CoffeeError;
+
+    async fn load<T>(&self, key: &str) -> Result<T, Self::Err>
+    where
+        T: DeserializeOwned + Send + Sync,
+    {
+        if key == REPOSITORIES_KEY {
+            let repositories = self.load_repositories()?;
+            return serde_json::from_value(
+                serde_json::to_value(repositories).map_err(|err| error!("{err}"))?,
+            )
+            .map_err(|err| error!("{err}"));
+        }
+        let value = {
+            let conn = self.inner.lock().map_err(|err| error!("{err}"))?;
+            conn.query_row("SELECT value FROM storage WHERE key = ?1", [key], |row| {
+                row.get::<_, String>(0)
+            })
+            .optional()
+            .map_err(|err| error!("{err}"))?
+        };
+        let Some(value) = value else {
+            return Err(error!(
+                "value with key `{key}` not found inside the database"
+            ));
+        };
+        migration::decode(&value)
+    }
+
+    async fn store<T>(&self, key: &str, to_store: &T) -> Result<(), Self::Err>
+    where
+        T: Serialize + Send + Sync,
+    {
+        if key == REPOSITORIES_KEY {
+            let value = serde_json::to_value(to_store).map_err(|err| error!("{err}"))?;
+            let repositories = serde_json::from_value(value).map_err(|err| error!("{err}"))?;
+            return self.store_repositories(repositories);
+        }
+        let value = migration::encode(to_store)?;
+        let conn = self.inner.lock().map_err(|err| error!("{err}"))?;
+        conn.execute(
+            "INSERT INTO storage (key, value) VALUES (?1, ?2) \
+             ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+            (key, &value),
+        )
+        .map_err(|err| error!("{err}"))?;
+        Ok(())
+    }
+
+    async fn upsert_repository(&self, repo: &RepositoryInfo) -> Result<(), Self::Err> {
+        let value = migration::encode(repo)?;
+        let conn = self.inner.lock().map_err(|err| error!("{err}"))?;
+        conn.execute(
+            "INSERT INTO repositories (name, value) VALUES (?1, ?2) \
+             ON CONFLICT(name) DO UPDATE SET value = excluded.value",
+            (&repo.name, &value),
+        )
+        .map_err(|err| error!("{err}"))?;
+        Ok(())
+    }
+
+    async fn remove_repository(&self, name: &str) -> Result<(), Self::Err> {
+        let conn = self.inner.lock().map_err(|err| error!("{err}"))?;
+        conn.execute("DELETE FROM repositories WHERE name = ?1", [name])
+            .map_err(|err| error!("{err}"))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rusqlite::Connection;
+    use serde_json::{json, Value};
+
+    use coffee_lib::repository::RepositoryInfo;
+
+    use super::SqliteStorage;
+    use crate::migration;
+    use crate::storage::testing::{repository, test_dir};
+    use crate::storage::{StorageManager, REPOSITORIES_KEY};
+
+    #[tokio::test]
+    async fn test_store_load() {
+        let path = test_dir("sqlite-store");
+        let storage = SqliteStorage::new(&path).await.unwrap();
+        assert!(storage.load::<Value>("regtest").await.is_err());
+
+        let config = json!({"network": "regtest", "plugins": [{"name": "summary"}]});
+        storage.store("regtest", &config).await.unwrap();
+        storage
+            .store("testnet", &json!({"network": "testnet"}))
+            .await
+            .unwrap();
+        assert_eq!(storage.load::<Value>("regtest").await.unwrap(), config);
+
+        // storing a key again replaces only its value
+        let config = json!({"network": "regtest", "plugins": []});
+        storage.store("regtest", &config).await.unwrap();
+        assert_eq!(storage.load::<Value>("regtest").await.unwrap(), config);
+        assert_eq!(
+            storage.load::<Value>("testnet").await.unwrap()["network"],
+            "testnet"
+        );
+        drop(storage);
+
+        // the values survive when the database is opened again
+        let storage = SqliteStorage::new(&path).await.unwrap();
+        assert_eq!(storage.load::<Value>("regtest").await.unwrap(), config);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_store_load_repositories() {
+        let path = test_dir("sqlite-store-repositories");
+        let storage = SqliteStorage::new(&path).await.unwrap();
+        let stored: HashMap<String, RepositoryInfo> = storage.load(REPOSITORIES_KEY).await.unwrap();
+        assert!(stored.is_empty());
+
+        let repositories = HashMap::from([
+            (
+                "lightningd".to_owned(),
+                repository("lightningd", "https://github.com/lightningd/plugins"),
+            ),
+            (
+                "other".to_owned(),
+                repository("other", "https://github.com/other/plugins"),
+            ),
+        ]);
+        storage
+            .store(REPOSITORIES_KEY, &repositories)
+            .await
+            .unwrap();
+        let stored: HashMap<String, RepositoryInfo> = storage.load(REPOSITORIES_KEY).await.unwrap();
+        assert_eq!(stored.len(), 2);
+
+        // a store of the repositories replaces all of them
+        let repositories = HashMap::from([(
+            "other".to_owned(),
+            repository("other", "https://github.com/other/plugins"),
+        )]);
+        storage
+            .store(REPOSITORIES_KEY, &repositories)
+            .await
+            .unwrap();
+        let stored: HashMap<String, RepositoryInfo> = storage.load(REPOSITORIES_KEY).await.unwrap();
+        assert_eq!(stored.keys().collect::<Vec<_>>(), vec!["other"]);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_upsert_remove_repository() {
+        let path = test_dir("sqlite-repositories");
+        let storage = SqliteStorage::new(&path).await.unwrap();
+
+        let lightningd = repository("lightningd", "https://github.com/lightningd/plugins");
+        storage.upsert_repository(&lightningd).await.unwrap();
+        let other = repository("other", "https://github.com/other/plugins");
+        storage.upsert_repository(&other).await.unwrap();
+        let moved = repository("lightningd", "https://github.com/moved/plugins");
+        storage.upsert_repository(&moved).await.unwrap();
+
+        let stored: HashMap<String, RepositoryInfo> = storage.load(REPOSITORIES_KEY).await.unwrap();
+        assert_eq!(stored.len(), 2);
+        assert_eq!(
+            stored["lightningd"].url.url_string,
+            "https://github.com/moved/plugins"
+        );
+
+        storage.remove_repository("other").await.unwrap();
+        // removing a missing repository is not an error
+        storage.remove_repository("other").await.unwrap();
+        let stored: HashMap<String, RepositoryInfo> = storage.load(REPOSITORIES_KEY).await.unwrap();
+        assert_eq!(stored.keys().collect::<Vec<_>>(), vec!["lightningd"]);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_migrate_repositories() {
+        let path = test_dir("sqlite-migration");
+        // the old schema stored all the repositories as a single row
+        {
+            let conn = Connection::open(format!("{path}/storage.sqlite")).unwrap();
+            conn.execute(
+                "CREATE TABLE storage (key TEXT PRIMARY KEY, value TEXT NOT NULL)",
+                (),
+            )
+            .unwrap();
+            let repositories = HashMap::from([(
+                "lightningd".to_owned(),
+                repository("lightningd", "https://github.com/lightningd/plugins"),
+            )]);
+            let value = migration::encode(&repositories).unwrap();
+            conn.execute(
+                "INSERT INTO storage (key, value) VALUES (?1, ?2)",
+                (REPOSITORIES_KEY, &value),
+            )
+            .unwrap();
+        }
+
+        let storage = SqliteStorage::new(&path).await.unwrap();
+        let stored: HashMap<String, RepositoryInfo> = storage.load(REPOSITORIES_KEY).await.unwrap();
+        assert_eq!(stored.keys().collect::<Vec<_>>(), vec!["lightningd"]);
+        let rows: i64 = storage
+            .inner
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT COUNT(*) FROM storage WHERE key = ?1",
+                [REPOSITORIES_KEY],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(rows, 0);
+        drop(storage);
+
+        // the migration runs once, so opening it again keeps the repositories
+        let storage = SqliteStorage::new(&path).await.unwrap();
+        let stored: HashMap<String, RepositoryInfo> = storage.load(REPOSITORIES_KEY).await.unwrap();
+        assert_eq!(stored.len(), 1);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_load_unversioned_repository_row() {
+        let path = test_dir("sqlite-unversioned-row");
+        let storage = SqliteStorage::new(&path).await.unwrap();
+        // the rows of the repositories were stored without the schema version
+        let lightningd = repository("lightningd", "https://github.com/lightningd/plugins");
+        storage
+            .inner
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT INTO repositories (name, value) VALUES (?1, ?2)",
+                ("lightningd", serde_json::to_string(&lightningd).unwrap()),
+            )
+            .unwrap();
+        let stored: HashMap<String, RepositoryInfo> = storage.load(REPOSITORIES_KEY).await.unwrap();
+        assert_eq!(
+            stored["lightningd"].url.url_string,
+            "https://github.com/lightningd/plugins"
+        );
+
+        // the row is written again with the schema version
+        storage.upsert_repository(&lightningd).await.unwrap();
+        let value: String = storage
+            .inner
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT value FROM repositories WHERE name = ?1",
+                ["lightningd"],
+                |row| row.get(0),
+            )
+            .unwrap();
+        let value: Value = serde_json::from_str(&value).unwrap();
+        assert_eq!(value["version"], migration::SCHEMA_VERSION);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+}
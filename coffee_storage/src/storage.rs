@@ -5,6 +5,12 @@
 use async_trait::async_trait;
 use serde::{de::DeserializeOwned, Serialize};
 
+use coffee_lib::errors::CoffeeError;
+use coffee_lib::repository::RepositoryInfo;
+
+use crate::nosql_db::NoSQlStorage;
+use crate::sqlite::SqliteStorage;
+
 #[async_trait]
 pub trait StorageManager {
     type Err;
@@ -20,4 +26,109 @@ pub trait StorageManager {
     async fn load<T>(&self, key: &str) -> Result<T, Self::Err>
     where
         T: DeserializeOwned + Send + Sync;
+
+    /// async call to add or update a single repository
+    /// without storing again all the others.
+    async fn upsert_repository(&self, repo: &RepositoryInfo) -> Result<(), Self::Err>;
+
+    /// async call to remove a single repository
+    /// without storing again all the others.
+    async fn remove_repository(&self, name: &str) -> Result<(), Self::Err>;
+}
+
+/// Key where the repositories shared between
+/// the networks are stored.
+pub const REPOSITORIES_KEY: &str = "repositories";
+
+/// The storage backends that can be used
+/// by the plugin manager.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StorageBackend {
+    #[default]
+    NoSQL,
+    Sqlite,
+}
+
+/// Storage that dispatch the calls to the
+/// backend selected at runtime.
+pub enum Storage {
+    NoSQL(NoSQlStorage),
+    Sqlite(SqliteStorage),
+}
+
+impl Storage {
+    pub async fn new(backend: StorageBackend, path: &str) -> Result<Self, CoffeeError> {
+        let storage = match backend {
+            StorageBackend::NoSQL => Storage::NoSQL(NoSQlStorage::new(path).await?),
+            StorageBackend::Sqlite => Storage::Sqlite(SqliteStorage::new(path).await?),
+        };
+        Ok(storage)
+    }
+}
+
+#[async_trait]
+impl StorageManager for Storage {
+    type Err = CoffeeError;
+
+    async fn store<T>(&self, key: &str, to_store: &T) -> Result<(), Self::Err>
+    where
+        T: Serialize + Send + Sync,
+    {
+        match self {
+            Storage::NoSQL(storage) => storage.store(key, to_store).await,
+            Storage::Sqlite(storage) => storage.store(key, to_store).await,
+        }
+    }
+
+    async fn load<T>(&self, key: &str) -> Result<T, Self::Err>
+    where
+        T: DeserializeOwned + Send + Sync,
+    {
+        match self {
+            Storage::NoSQL(storage) => storage.load(key).await,
+            Storage::Sqlite(storage) => storage.load(key).await,
+        }
+    }
+
+    async fn upsert_repository(&self, repo: &RepositoryInfo) -> Result<(), Self::Err> {
+        match self {
+            Storage::NoSQL(storage) => storage.upsert_repository(repo).await,
+            Storage::Sqlite(storage) => storage.upsert_repository(repo).await,
+        }
+    }
+
+    async fn remove_repository(&self, name: &str) -> Result<(), Self::Err> {
+        match self {
+            Storage::NoSQL(storage) => storage.remove_repository(name).await,
+            Storage::Sqlite(storage) => storage.remove_repository(name).await,
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use coffee_lib::repository::{Kind, RepositoryInfo};
+    use coffee_lib::url::URL;
+
+    /// Create an empty directory for the storage of a test.
+    pub(crate) fn test_dir(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("coffee-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.to_str().unwrap().to_owned()
+    }
+
+    pub(crate) fn repository(name: &str, url: &str) -> RepositoryInfo {
+        RepositoryInfo {
+            kind: Kind::Git,
+            name: name.to_owned(),
+            url: URL::new("/tmp/coffee", url, name).unwrap(),
+            plugins: vec![],
+            branch: "master".to_owned(),
+            git_head: None,
+            last_activity: None,
+            fetched_at: None,
+            mirrors: vec![],
+        }
+    }
 }
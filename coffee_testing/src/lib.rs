@@ -76,9 +76,6 @@ pub struct CoffeeTestingArgs {
     pub data_dir: String,
 }
 
-unsafe impl Send for CoffeeTestingArgs {}
-unsafe impl Sync for CoffeeTestingArgs {}
-
 impl coffee_core::CoffeeArgs for CoffeeTestingArgs {
     fn command(&self) -> coffee_core::CoffeeOperation {
         unimplemented!()
@@ -1,11 +1,19 @@
+use std::collections::HashMap;
+use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
 use std::sync::Arc;
 use tokio::fs;
 
 use serde_json::json;
 
+use coffee_lib::errors::CoffeeErrorKind;
+use coffee_lib::plugin::PluginScope;
 use coffee_lib::plugin_manager::PluginManager;
-use coffee_lib::types::response::{Defect, NurseStatus};
+use coffee_lib::progress::ProgressEvent;
+use coffee_lib::types::response::{
+    CoffeeStatePlugin, Defect, DiagnoseIssue, ExplainReason, ListOrder, NurseStatus, ReloadStatus,
+};
+use coffee_storage::storage::StorageManager;
 use coffee_testing::cln::Node;
 use coffee_testing::prelude::tempfile;
 use coffee_testing::{CoffeeTesting, CoffeeTestingArgs};
@@ -38,11 +46,26 @@ pub async fn init_coffee_test_cmd() -> anyhow::Result<()> {
     };
     let mut manager = CoffeeTesting::tmp_with_args(&args, dir.clone()).await?;
     let root_path = manager.root_path().to_owned();
+    // the output of the clone is streamed to the listener
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    manager.coffee().set_progress(Some(sender));
     manager
         .coffee()
         .add_remote("folgore", "https://github.com/coffee-tools/folgore.git")
         .await
         .unwrap();
+    manager.coffee().set_progress(None);
+    let mut events = vec![];
+    while let Ok(event) = receiver.try_recv() {
+        events.push(event);
+    }
+    assert!(
+        events
+            .iter()
+            .any(|event| matches!(event, ProgressEvent::Output(_))),
+        "{:?}",
+        events
+    );
 
     // dropping the first coffee instance, but without delete the dir
     drop(manager);
@@ -55,7 +78,7 @@ pub async fn init_coffee_test_cmd() -> anyhow::Result<()> {
     let new_root_path = manager.root_path().to_owned();
     assert_eq!(root_path.path(), new_root_path.path());
 
-    let actual_network = manager.coffee().storage_info().config.network;
+    let actual_network = manager.coffee().storage_info().unwrap().config.network;
     let expected_network = "testnet".to_string();
 
     assert_eq!(
@@ -86,6 +109,69 @@ pub async fn init_coffee_test_with_cln() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+pub async fn init_coffee_test_link_twice() -> anyhow::Result<()> {
+    init();
+    let cln = Node::tmp("regtest").await?;
+
+    let mut manager = CoffeeTesting::tmp().await?;
+    let lightning_dir = cln.rpc().getinfo()?.ligthning_dir;
+    let lightning_dir = lightning_dir.strip_suffix("/regtest").unwrap();
+    log::info!("lightning path: {lightning_dir}");
+
+    manager.coffee().link(&lightning_dir).await?;
+    manager.coffee().link(&lightning_dir).await?;
+
+    // the coffee configuration must be included only once
+    let cln_conf = fs::read_to_string(format!("{lightning_dir}/regtest/config")).await?;
+    let includes = cln_conf
+        .lines()
+        .filter(|line| line.starts_with("include") && line.contains("coffee.conf"))
+        .count();
+    assert_eq!(includes, 1, "{cln_conf}");
+
+    Ok(())
+}
+
+#[tokio::test]
+pub async fn init_coffee_test_switch_target() -> anyhow::Result<()> {
+    init();
+    let alice = Node::tmp("regtest").await?;
+    let bob = Node::tmp("regtest").await?;
+
+    let mut manager = CoffeeTesting::tmp().await?;
+    let alice_dir = alice.rpc().getinfo()?.ligthning_dir;
+    let alice_dir = alice_dir.strip_suffix("/regtest").unwrap();
+    let bob_dir = bob.rpc().getinfo()?.ligthning_dir;
+    let bob_dir = bob_dir.strip_suffix("/regtest").unwrap();
+
+    manager.coffee().link(alice_dir).await?;
+    manager.coffee().add_target("bob", bob_dir).await?;
+    assert!(manager.coffee().add_target("bob", bob_dir).await.is_err());
+    assert!(manager.coffee().use_target("carol").await.is_err());
+
+    // bob includes its own coffee configuration
+    let cln_conf = fs::read_to_string(format!("{bob_dir}/regtest/config")).await?;
+    assert!(cln_conf.contains("targets/bob.conf"), "{cln_conf}");
+
+    manager.coffee().use_target("bob").await?;
+    let targets = manager.coffee().list_targets().await?.targets;
+    let names = targets
+        .iter()
+        .map(|target| (target.name.as_str(), target.active))
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec![("bob", true), ("default", false)]);
+    assert_eq!(targets[0].cln_root.as_deref(), Some(bob_dir));
+
+    manager.coffee().use_target("default").await?;
+    let targets = manager.coffee().list_targets().await?.targets;
+    assert!(targets
+        .iter()
+        .all(|target| target.active == (target.name == "default")));
+
+    Ok(())
+}
+
 #[tokio::test]
 #[ntest::timeout(560000)]
 pub async fn init_coffee_test_add_remote() {
@@ -110,9 +196,27 @@ pub async fn init_coffee_test_add_remote() {
         .add_remote("lightningd", "https://github.com/lightningd/plugins.git")
         .await
         .unwrap();
+    let err = manager
+        .coffee()
+        .add_remote("lightningd", "https://github.com/lightningd/plugins.git")
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.kind(),
+        Some(&CoffeeErrorKind::RemoteAlreadyExists {
+            name: "lightningd".to_owned(),
+            url: "https://github.com/lightningd/plugins".to_owned(),
+        }),
+        "{err}"
+    );
+    // the remote was just cloned, so the refresh does not find anything new
+    let refresh = manager.coffee().refresh_remote("lightningd").await.unwrap();
+    assert!(refresh.new_plugins.is_empty(), "{:?}", refresh);
+    let result = manager.coffee().refresh_remote("unknown").await;
+    assert!(result.is_err(), "{:?}", result);
     manager
         .coffee()
-        .install("summary", true, true)
+        .install("summary", true, true, false, false)
         .await
         .unwrap();
 
@@ -123,6 +227,58 @@ pub async fn init_coffee_test_add_remote() {
     cln.stop().await.unwrap();
 }
 
+#[tokio::test]
+#[ntest::timeout(120000)]
+pub async fn try_plugin_in_regtest() {
+    init();
+
+    let mut cln = Node::tmp("regtest").await.unwrap();
+    let mut manager = CoffeeTesting::tmp().await.unwrap();
+    manager
+        .coffee()
+        .add_remote("lightningd", "https://github.com/lightningd/plugins.git")
+        .await
+        .unwrap();
+
+    // without the rpc socket of the node the trial can not run
+    let result = manager.coffee().try_plugin("summary", false).await;
+    assert!(result.is_err(), "{:?}", result);
+
+    let lightning_dir = cln.rpc().getinfo().unwrap().ligthning_dir;
+    let lightning_dir = lightning_dir.strip_suffix("/regtest").unwrap();
+    manager.coffee().link(lightning_dir).await.unwrap();
+    let trial = manager.coffee().try_plugin("summary", false).await.unwrap();
+    // the plugin is not installed, and its build is removed
+    let plugins = manager.coffee().list().await.unwrap().plugins;
+    assert!(plugins.is_empty(), "{:?}", plugins);
+    assert!(!Path::new(&trial.exec_path).exists(), "{:?}", trial);
+
+    cln.stop().await.unwrap();
+}
+
+#[tokio::test]
+#[ntest::timeout(120000)]
+pub async fn test_add_remote_without_plugins() {
+    init();
+
+    let mut manager = CoffeeTesting::tmp().await.unwrap();
+    let empty = tempfile::tempdir().unwrap();
+    let url = format!("file://{}", empty.path().display());
+
+    // without plugins the remote is refused only when required
+    manager.coffee().set_require_plugins(true);
+    let result = manager.coffee().add_remote("empty", &url).await;
+    assert!(result.is_err(), "{:?}", result);
+    let result = manager.coffee().get_plugins_in_remote("empty").await;
+    assert!(result.is_err(), "{:?}", result);
+
+    manager.coffee().set_require_plugins(false);
+    let remote = manager.coffee().add_remote("empty", &url).await.unwrap();
+    assert!(remote.plugins.is_empty(), "{:?}", remote);
+    let result = manager.coffee().get_plugins_in_remote("empty").await;
+    assert!(result.unwrap().plugins.is_empty());
+}
+
 #[tokio::test]
 #[ntest::timeout(120000)]
 pub async fn test_add_remove_plugins() {
@@ -166,16 +322,119 @@ pub async fn test_add_remove_plugins() {
         "Plugin 'helpme' not found"
     );
 
+    // Installing summary from an unknown git reference fails
+    let result = manager
+        .coffee()
+        .install("summary@not-a-git-ref", true, false, false, false)
+        .await;
+    assert!(result.is_err(), "{:?}", result);
+
     // Install summary plugin
-    let result = manager.coffee().install("summary", true, false).await;
+    let result = manager
+        .coffee()
+        .install("summary", true, false, false, false)
+        .await;
     assert!(result.is_ok(), "{:?}", result);
+    // the executable returned is the one registered inside core lightning
+    let install = result.unwrap();
+    assert_eq!(install.plugin, "summary");
+    assert!(std::path::Path::new(&install.exec_path).exists());
 
-    // Install helpme plugin
+    // Install helpme plugin, waiting until core lightning runs it
+    manager.coffee().set_verify_start(true);
+    manager
+        .coffee()
+        .install("helpme", true, false, false, false)
+        .await
+        .unwrap();
+    manager.coffee().set_verify_start(false);
+    cln.rpc()
+        .call::<serde_json::Value, serde_json::Value>("helpme", json!({}))
+        .unwrap();
+
+    // The available plugins flag the installed ones
+    let available = manager.coffee().list_available().await.unwrap();
+    assert_eq!(available.plugins.len(), 2, "{:?}", available.plugins);
+    assert!(
+        available
+            .available
+            .iter()
+            .any(|entry| entry.plugin.name() == "summary" && entry.installed),
+        "{:?}",
+        available.available
+    );
+    assert!(
+        available.available.iter().any(|entry| !entry.installed),
+        "{:?}",
+        available.available
+    );
+
+    // The plugins are just installed, so there are no updates
+    let updates = manager.coffee().check_updates().await.unwrap();
+    assert!(updates.updates.is_empty(), "{:?}", updates);
+    let changelog = manager.coffee().changelog("summary").await.unwrap();
+    assert!(changelog.commits.is_empty(), "{:?}", changelog);
+
+    // The status of an installed plugin has its executable
+    let status = manager.coffee().status("summary").await.unwrap().unwrap();
+    assert!(status.installed && status.enabled, "{:?}", status);
+    assert!(status.exec_path.is_some(), "{:?}", status);
+    let status = manager.coffee().status("unknown-plugin").await.unwrap();
+    assert!(status.is_none(), "{:?}", status);
+
+    // The output of the build is stored, also without verbose
+    let logs = manager.coffee().logs("helpme").await.unwrap();
+    assert_eq!(logs.plugin, "helpme");
+    assert!(logs.path.ends_with("/logs/helpme.log"), "{:?}", logs);
+    // each script is followed by its output and its exit status
+    assert!(logs.log.starts_with("$ "), "{:?}", logs);
+    assert!(logs.log.trim_end().ends_with(']'), "{:?}", logs);
+    assert_eq!(fs::read_to_string(&logs.path).await.unwrap(), logs.log);
+    let err = manager.coffee().logs("unknown-plugin").await.unwrap_err();
+    assert!(
+        matches!(err.kind(), Some(CoffeeErrorKind::PluginNotFound { name }) if name == "unknown-plugin"),
+        "{err}"
+    );
+
+    // Installing again summary fails without force
+    let result = manager
+        .coffee()
+        .install("summary", true, false, false, false)
+        .await;
+    assert!(result.is_err(), "{:?}", result);
+
+    // Reinstall summary plugin, keeping the same configuration
+    let conf_before = manager.coffee().coffee_cln_config.fields.clone();
     manager
         .coffee()
-        .install("helpme", true, false)
+        .install("summary", true, false, false, true)
         .await
         .unwrap();
+    let plugins = manager.coffee().list().await.unwrap().plugins;
+    assert_eq!(
+        plugins
+            .iter()
+            .filter(|plugin| plugin.name() == "summary")
+            .count(),
+        1,
+        "{:?}",
+        plugins
+    );
+    assert_eq!(
+        manager.coffee().coffee_cln_config.fields,
+        conf_before,
+        "the cln configuration changed after the reinstall"
+    );
+
+    // The configuration written by coffee is included by the node
+    let config = manager.coffee().show_config().await.unwrap();
+    assert!(config.included, "{:?}", config);
+    let merged_plugins = config.merged.get("plugin").cloned().unwrap_or_default();
+    assert!(
+        merged_plugins.iter().any(|path| path.contains("summary")),
+        "{:?}",
+        config
+    );
 
     // Ensure that the list of remotes is correct
     let result = manager.coffee().list_remotes().await;
@@ -202,10 +461,97 @@ pub async fn test_add_remove_plugins() {
         plugins.iter().any(|plugin| plugin.name() == "helpme"),
         "Plugin 'helpme' not found"
     );
+    // the plugins are sorted by name, and summary is the last install
+    let names = plugins
+        .iter()
+        .map(|plugin| plugin.name())
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec!["helpme", "summary"]);
+    assert!(plugins.iter().all(|plugin| plugin.installed_at.is_some()));
+    manager.coffee().set_list_order(ListOrder::InstallDate);
+    let plugins = manager.coffee().list().await.unwrap().plugins;
+    assert_eq!(
+        plugins.last().map(|plugin| plugin.name()).as_deref(),
+        Some("summary")
+    );
+    manager.coffee().set_list_order(ListOrder::Name);
+
+    // building again the plugins keeps them installed, also when repeated
+    for _ in 0..2 {
+        let repair = manager.coffee().repair_all(false).await;
+        assert!(repair.is_ok(), "{:?}", repair);
+        let repair = repair.unwrap();
+        assert!(repair.failed.is_empty(), "{:?}", repair.failed);
+        let repaired = repair
+            .repaired
+            .iter()
+            .map(|install| install.plugin.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(repaired, vec!["helpme", "summary"]);
+    }
+
+    // the exported state contains the remote and both the plugins
+    let state = manager.coffee().export_state().await.unwrap();
+    assert_eq!(state.remotes.len(), 1, "{:?}", state);
+    assert_eq!(state.remotes[0].name, repo_name);
+    let names = state
+        .plugins
+        .iter()
+        .map(|plugin| plugin.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec!["helpme", "summary"]);
+    // importing it again does not change anything
+    let import = manager.coffee().import_state(&state, false).await.unwrap();
+    assert!(import.remotes_added.is_empty(), "{:?}", import);
+    assert!(import.installed.is_empty(), "{:?}", import);
+    assert!(import.conflicts.is_empty(), "{:?}", import);
+    assert_eq!(import.skipped.len(), 3, "{:?}", import);
+    // a remote with the same name and another url is a conflict
+    let mut conflicting = state.clone();
+    conflicting.remotes[0].url = "https://github.com/coffee-tools/plugins".to_owned();
+    conflicting.plugins.clear();
+    let import = manager
+        .coffee()
+        .import_state(&conflicting, false)
+        .await
+        .unwrap();
+    assert_eq!(import.conflicts.len(), 1, "{:?}", import);
+
+    // the disk usage is computed only when requested
+    assert!(manager.coffee().list().await.unwrap().disk_usage.is_empty());
+    manager.coffee().set_disk_usage(true);
+    let disk_usage = manager.coffee().list().await.unwrap().disk_usage;
+    assert!(
+        disk_usage.get("summary").is_some_and(|size| *size > 0),
+        "{:?}",
+        disk_usage
+    );
+    let remotes = manager
+        .coffee()
+        .list_remotes()
+        .await
+        .unwrap()
+        .remotes
+        .unwrap();
+    assert!(
+        remotes
+            .iter()
+            .all(|remote| remote.disk_usage.is_some_and(|size| size > 0)),
+        "{:?}",
+        remotes
+    );
+    manager.coffee().set_disk_usage(false);
+
+    // the installed plugins and the remote are tracked, so nothing is pruned
+    let result = manager.coffee().prune(true, false).await;
+    assert!(result.is_ok(), "{:?}", result);
+    assert!(result.unwrap().paths.is_empty());
 
     // Remove summary plugin
     let result = manager.coffee().remove("summary").await;
     assert!(result.is_ok(), "{:?}", result);
+    // the plugin directory and its venv are deleted
+    assert!(result.unwrap().freed_bytes > 0);
 
     // Ensure that the list of plugins is correct
     let result = manager.coffee().list().await;
@@ -218,6 +564,21 @@ pub async fn test_add_remove_plugins() {
         "Plugin 'helpme' not found"
     );
 
+    // The preview lists the helpme plugin, without removing it
+    let removal = manager.coffee().rm_remote_preview(repo_name).await.unwrap();
+    let removed = removal
+        .plugins
+        .iter()
+        .map(|plugin| plugin.name())
+        .collect::<Vec<_>>();
+    assert_eq!(removed, vec!["helpme".to_owned()], "{:?}", removal);
+    let plugins = manager.coffee().list().await.unwrap().plugins;
+    assert!(
+        plugins.iter().any(|plugin| plugin.name() == "helpme"),
+        "{:?}",
+        plugins
+    );
+
     // Remove lightningd remote repository
     // This should also remove the helpme plugin
     let result = manager.coffee().rm_remote(repo_name).await;
@@ -275,17 +636,60 @@ pub async fn test_errors_and_show() {
         repo_url
     );
 
+    // Search for a plugin with a partial name
+    let result = manager.coffee().search("sum").await;
+    assert!(result.is_ok(), "{:?}", result);
+    let result = result.unwrap();
+    assert_eq!(result.plugin.name(), "summary", "{:?}", result.matches);
+    assert!(
+        result.matches.iter().any(|plugin| plugin.name == "summary"),
+        "{:?}",
+        result.matches
+    );
+
+    // The repository just cloned is at the commit stored
+    let health = manager.coffee().repositories_health().await.unwrap();
+    assert!(health.iter().all(|repo| repo.is_healthy()), "{:?}", health);
+
+    // Explain a typo in the plugin name
+    let result = manager.coffee().explain("summry").await;
+    assert!(result.is_ok(), "{:?}", result);
+    let result = result.unwrap();
+    assert!(!result.is_installable(), "{:?}", result);
+    assert!(
+        result.reasons.contains(&ExplainReason::NotFound),
+        "{:?}",
+        result.reasons
+    );
+    assert_eq!(
+        result
+            .suggestions
+            .first()
+            .map(|plugin| plugin.name.as_str()),
+        Some("summary"),
+        "{:?}",
+        result.suggestions
+    );
+
     // Install summary plugin
-    let result = manager.coffee().install("summary", true, false).await;
+    let result = manager
+        .coffee()
+        .install("summary", true, false, false, false)
+        .await;
     assert!(result.is_ok(), "{:?}", result);
 
     // Get the README file for a plugin that is not installed
     let result = manager.coffee().show("helpme").await.unwrap();
     let val = result.readme.as_str();
     assert!(val.starts_with("# Helpme plugin"));
+    // the README is the one of the repository HEAD
+    assert!(result.commit.is_some(), "{:?}", result);
 
     // Install a plugin that is not in the repository
-    let result = manager.coffee().install("x", true, false).await;
+    let result = manager
+        .coffee()
+        .install("x", true, false, false, false)
+        .await;
     assert!(result.is_err(), "{:?}", result);
 
     // Remove helpme plugin
@@ -297,6 +701,8 @@ pub async fn test_errors_and_show() {
     // This should also fail
     let result = manager.coffee().rm_remote("folgore").await;
     assert!(result.is_err(), "{:?}", result);
+    let result = manager.coffee().rm_remote_preview("folgore").await;
+    assert!(result.is_err(), "{:?}", result);
 
     // Ensure that the list of remotes is correct
     let result = manager.coffee().list_remotes().await;
@@ -322,6 +728,34 @@ pub async fn test_errors_and_show() {
         plugins
     );
 
+    // Purge summary plugin, leaving no build log behind
+    let purge = manager.coffee().purge("summary", false).await;
+    assert!(purge.is_ok(), "{:?}", purge);
+    let purge = purge.unwrap();
+    assert!(
+        purge
+            .removed_paths
+            .iter()
+            .any(|path| path.ends_with("logs/summary.log")),
+        "{:?}",
+        purge.removed_paths
+    );
+    let plugins = manager.coffee().list().await.unwrap().plugins;
+    assert!(plugins.is_empty(), "{:?}", plugins);
+    let result = manager.coffee().purge("summary", false).await;
+    assert!(result.is_err(), "{:?}", result);
+
+    // Offline the plugin runs without installing its requirements
+    manager.coffee().config.startup.offline = true;
+    let install = manager
+        .coffee()
+        .install("summary", true, false, false, false)
+        .await;
+    assert!(install.is_ok(), "{:?}", install);
+    let install = install.unwrap();
+    assert!(install.exec_path.ends_with("summary.py"), "{:?}", install);
+    manager.coffee().config.startup.offline = false;
+
     cln.stop().await.unwrap();
 }
 
@@ -353,7 +787,7 @@ pub async fn install_plugin_in_two_networks() -> anyhow::Result<()> {
     // This should install summary plugin for regtest network
     manager
         .coffee()
-        .install("summary", true, true)
+        .install("summary", true, true, false, false)
         .await
         .unwrap();
     // Ensure that summary is installed for regtest network
@@ -389,7 +823,7 @@ pub async fn install_plugin_in_two_networks() -> anyhow::Result<()> {
     // This should install summary plugin for testnet network
     manager
         .coffee()
-        .install("summary", true, true)
+        .install("summary", true, true, false, false)
         .await
         .unwrap();
     // Ensure that summary is installed for testnet network
@@ -401,6 +835,67 @@ pub async fn install_plugin_in_two_networks() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+#[ntest::timeout(560000)]
+pub async fn install_global_plugin_in_two_networks() -> anyhow::Result<()> {
+    init();
+    let mut regtest = Node::tmp("regtest").await.unwrap();
+    let lightning_dir = regtest.rpc().getinfo().unwrap().ligthning_dir;
+    let lightning_regtest_dir = lightning_dir.strip_suffix("/regtest").unwrap();
+    let mut testnet = Node::tmp("testnet").await.unwrap();
+    let lightning_dir = testnet.rpc().getinfo().unwrap().ligthning_dir;
+    let lightning_testnet_dir = lightning_dir.strip_suffix("/testnet").unwrap();
+
+    let dir = Arc::new(tempfile::tempdir()?);
+    let regtest_args = CoffeeTestingArgs {
+        conf: None,
+        data_dir: dir.path().to_str().unwrap().to_owned(),
+        network: "regtest".to_string(),
+    };
+    let testnet_args = CoffeeTestingArgs {
+        conf: None,
+        data_dir: dir.path().to_str().unwrap().to_owned(),
+        network: "testnet".to_string(),
+    };
+    let mut manager = CoffeeTesting::tmp_with_args(&regtest_args, dir.clone()).await?;
+    manager.coffee().link(&lightning_regtest_dir).await.unwrap();
+    drop(manager);
+
+    let mut manager = CoffeeTesting::tmp_with_args(&testnet_args, dir.clone()).await?;
+    manager.coffee().link(&lightning_testnet_dir).await.unwrap();
+    manager
+        .coffee()
+        .add_remote("lightningd", "https://github.com/lightningd/plugins.git")
+        .await
+        .unwrap();
+    // Install summary once for testnet and regtest
+    let install = manager
+        .coffee()
+        .install_global("summary", true, false)
+        .await
+        .unwrap();
+    assert!(install.exec_path.contains("/global/plugins/summary"));
+    drop(manager);
+
+    // Ensure that summary is installed also for regtest, with the same executable
+    let mut manager = CoffeeTesting::tmp_with_args(&regtest_args, dir.clone()).await?;
+    let plugins = manager.coffee().list().await.unwrap().plugins;
+    assert_eq!(plugins.len(), 1, "{:?}", plugins);
+    assert_eq!(plugins[0].scope, PluginScope::Global);
+    assert_eq!(plugins[0].exec_path, install.exec_path);
+
+    // Removing summary from regtest removes it also from testnet
+    manager.coffee().remove("summary").await.unwrap();
+    drop(manager);
+    let mut manager = CoffeeTesting::tmp_with_args(&testnet_args, dir.clone()).await?;
+    let plugins = manager.coffee().list().await.unwrap().plugins;
+    assert!(plugins.is_empty(), "{:?}", plugins);
+
+    regtest.stop().await.unwrap();
+    testnet.stop().await.unwrap();
+    Ok(())
+}
+
 #[tokio::test]
 #[ntest::timeout(560000)]
 pub async fn test_double_slash() {
@@ -424,13 +919,16 @@ pub async fn test_double_slash() {
         .unwrap();
 
     // Install summary plugin
-    let result = manager.coffee().install("summary", true, false).await;
+    let result = manager
+        .coffee()
+        .install("summary", true, false, false, false)
+        .await;
     assert!(result.is_ok(), "{:?}", result);
 
     // Install helpme plugin
     manager
         .coffee()
-        .install("helpme", true, false)
+        .install("helpme", true, false, false, false)
         .await
         .unwrap();
 
@@ -489,7 +987,7 @@ pub async fn test_plugin_installation_path() {
     // Install summary plugin for regtest network
     manager
         .coffee()
-        .install("summary", true, false)
+        .install("summary", true, false, false, false)
         .await
         .unwrap();
 
@@ -556,6 +1054,14 @@ pub async fn test_plugin_installation_path() {
         exec_path
     );
 
+    // Check that the cln configuration does not reference
+    // the removed plugin anymore
+    let regtest_config_file_content = fs::read_to_string(&regtest_config_file).await.unwrap();
+    assert!(
+        !regtest_config_file_content.contains(exec_path.to_str().unwrap()),
+        "{regtest_config_file_content}"
+    );
+
     // Construct the path of the summary plugin cloned version
     let summary_exec_path = format!(
         "{}/.coffee/repositories/lightningd/summary/summary.py",
@@ -696,3 +1202,890 @@ pub async fn test_nurse_repository_missing_on_disk() {
 
     cln.stop().await.unwrap();
 }
+
+#[tokio::test]
+#[ntest::timeout(560000)]
+pub async fn test_install_rollback() {
+    init();
+
+    let mut cln = Node::tmp("regtest").await.unwrap();
+    let mut manager = CoffeeTesting::tmp().await.unwrap();
+
+    let lightning_dir = cln.rpc().getinfo().unwrap().ligthning_dir;
+    let lightning_dir = lightning_dir.strip_suffix("/regtest").unwrap();
+    log::info!("lightning path: {lightning_dir}");
+
+    manager.coffee().link(&lightning_dir).await.unwrap();
+    manager
+        .coffee()
+        .add_remote("lightningd", "https://github.com/lightningd/plugins.git")
+        .await
+        .unwrap();
+
+    // Make the flush of the cln configuration fail, after
+    // that the storage is already updated.
+    let conf_path = manager.coffee().coffee_cln_config.path.clone();
+    manager.coffee().coffee_cln_config.path = format!("{conf_path}.missing/coffee.conf");
+    let result = manager
+        .coffee()
+        .install("summary", true, false, false, false)
+        .await;
+    assert!(result.is_err(), "{:?}", result);
+
+    // Assert that no partial state remains
+    let plugins = manager.coffee().list().await.unwrap().plugins;
+    assert!(
+        !plugins.iter().any(|plugin| plugin.name() == "summary"),
+        "{:?}",
+        plugins
+    );
+    let fields = &manager.coffee().coffee_cln_config.fields;
+    assert!(
+        !fields
+            .get("plugin")
+            .is_some_and(|paths| paths.iter().any(|path| path.contains("summary"))),
+        "{:?}",
+        fields
+    );
+
+    // Restore the configuration and install the plugin
+    manager.coffee().coffee_cln_config.path = conf_path;
+    manager
+        .coffee()
+        .install("summary", true, false, false, false)
+        .await
+        .unwrap();
+    let plugins = manager.coffee().list().await.unwrap().plugins;
+    assert!(
+        plugins.iter().any(|plugin| plugin.name() == "summary"),
+        "{:?}",
+        plugins
+    );
+
+    cln.stop().await.unwrap();
+}
+
+#[tokio::test]
+#[ntest::timeout(120000)]
+pub async fn test_install_verify_rollback() {
+    init();
+
+    let mut cln = Node::tmp("regtest").await.unwrap();
+    let mut manager = CoffeeTesting::tmp().await.unwrap();
+
+    // a plugin that exits before core lightning is able to run it
+    let repo = tempfile::tempdir().unwrap();
+    let plugin_dir = repo.path().join("broken");
+    fs::create_dir_all(&plugin_dir).await.unwrap();
+    fs::write(
+        plugin_dir.join("coffee.yml"),
+        "plugin:\n  name: broken\n  version: 0.0.1\n  lang: pypip\n  install: chmod +x broken.sh\n  main: broken.sh\n",
+    )
+    .await
+    .unwrap();
+    fs::write(plugin_dir.join("broken.sh"), "#!/bin/sh\nexit 1\n")
+        .await
+        .unwrap();
+    let url = format!("file://{}", repo.path().display());
+    manager.coffee().add_remote("local", &url).await.unwrap();
+
+    // without the rpc socket the start can not be verified
+    manager.coffee().set_verify_start(true);
+    let result = manager
+        .coffee()
+        .install("broken", false, false, false, false)
+        .await;
+    assert!(result.is_err(), "{:?}", result);
+
+    let lightning_dir = cln.rpc().getinfo().unwrap().ligthning_dir;
+    let lightning_dir = lightning_dir.strip_suffix("/regtest").unwrap();
+    manager.coffee().link(lightning_dir).await.unwrap();
+    let err = manager
+        .coffee()
+        .install("broken", false, false, false, false)
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(err.kind(), Some(CoffeeErrorKind::StartFailed { plugin }) if plugin == "broken"),
+        "{err}"
+    );
+
+    // the install is rolled back, together with the build
+    let plugins = manager.coffee().list().await.unwrap().plugins;
+    assert!(plugins.is_empty(), "{:?}", plugins);
+    let fields = &manager.coffee().coffee_cln_config.fields;
+    assert!(
+        !fields
+            .get("plugin")
+            .is_some_and(|paths| paths.iter().any(|path| path.contains("broken"))),
+        "{:?}",
+        fields
+    );
+    let build = manager
+        .root_path()
+        .path()
+        .join(".coffee/regtest/plugins/broken");
+    assert!(!build.exists(), "{}", build.display());
+
+    cln.stop().await.unwrap();
+}
+
+/// A core lightning plugin that answers only to the handshake,
+/// so it can be started without any dependency.
+const DYNAMIC_PLUGIN: &str = r#"#!/usr/bin/env python3
+import json
+import sys
+
+decoder = json.JSONDecoder()
+buffer = ""
+for line in sys.stdin:
+    buffer += line
+    while buffer.strip():
+        try:
+            request, end = decoder.raw_decode(buffer.lstrip())
+        except ValueError:
+            break
+        buffer = buffer.lstrip()[end:]
+        if "id" not in request:
+            continue
+        result = {}
+        if request["method"] == "getmanifest":
+            result = {"options": [], "rpcmethods": [], "dynamic": True}
+        response = {"jsonrpc": "2.0", "id": request["id"], "result": result}
+        print(json.dumps(response) + "\n", flush=True)
+"#;
+
+#[tokio::test]
+#[ntest::timeout(120000)]
+pub async fn test_start_stop_plugin() {
+    init();
+
+    let mut cln = Node::tmp("regtest").await.unwrap();
+    let mut manager = CoffeeTesting::tmp().await.unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("dynamic.py");
+    fs::write(&path, DYNAMIC_PLUGIN).await.unwrap();
+    fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))
+        .await
+        .unwrap();
+    let path = path.to_str().unwrap();
+
+    // without the link there is no rpc socket to use
+    let err = manager.coffee().start_plugin(path).await.unwrap_err();
+    assert!(err.msg().contains("not available"), "{err}");
+
+    let lightning_dir = cln.rpc().getinfo().unwrap().ligthning_dir;
+    let lightning_dir = lightning_dir.strip_suffix("/regtest").unwrap();
+    manager.coffee().link(lightning_dir).await.unwrap();
+
+    async fn plugin_running(manager: &mut CoffeeTesting, path: &str) -> bool {
+        let list: serde_json::Value = manager
+            .coffee()
+            .cln("plugin", json!({ "subcommand": "list" }))
+            .await
+            .unwrap();
+        list["plugins"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|plugin| plugin["name"] == path)
+    }
+
+    manager.coffee().start_plugin(path).await.unwrap();
+    assert!(plugin_running(&mut manager, path).await);
+
+    manager.coffee().stop_plugin(path).await.unwrap();
+    assert!(!plugin_running(&mut manager, path).await);
+
+    // the plugin is not running anymore, so cln refuses to stop it
+    let result = manager.coffee().stop_plugin(path).await;
+    assert!(result.is_err(), "{:?}", result);
+
+    cln.stop().await.unwrap();
+}
+
+#[tokio::test]
+#[ntest::timeout(120000)]
+pub async fn test_upgrade_reload() {
+    init();
+
+    let mut cln = Node::tmp("regtest").await.unwrap();
+    let mut manager = CoffeeTesting::tmp().await.unwrap();
+    let repo = tempfile::tempdir().unwrap();
+    let plugin_dir = repo.path().join("dynamic");
+    fs::create_dir_all(&plugin_dir).await.unwrap();
+    fs::write(
+        plugin_dir.join("coffee.yml"),
+        "plugin:\n  name: dynamic\n  version: 0.0.1\n  lang: pypip\n  install: chmod +x dynamic.py\n  main: dynamic.py\n",
+    )
+    .await
+    .unwrap();
+    fs::write(plugin_dir.join("dynamic.py"), DYNAMIC_PLUGIN)
+        .await
+        .unwrap();
+    let url = format!("file://{}", repo.path().display());
+    manager.coffee().add_remote("local", &url).await.unwrap();
+
+    // without the rpc socket the upgrade does not touch core lightning
+    manager
+        .coffee()
+        .install("dynamic", false, false, false, false)
+        .await
+        .unwrap();
+    fs::write(plugin_dir.join("dynamic.py"), DYNAMIC_PLUGIN)
+        .await
+        .unwrap();
+    let upgrade = manager
+        .coffee()
+        .upgrade("local", false, false)
+        .await
+        .unwrap();
+    assert_eq!(upgrade.plugins_effected, vec!["dynamic".to_owned()]);
+    assert!(upgrade.reloads.is_empty(), "{:?}", upgrade.reloads);
+
+    let lightning_dir = cln.rpc().getinfo().unwrap().ligthning_dir;
+    manager.coffee().config.startup.rpc_path = Some(format!("{lightning_dir}/lightning-rpc"));
+    let lightning_dir = lightning_dir.strip_suffix("/regtest").unwrap();
+    manager.coffee().link(lightning_dir).await.unwrap();
+
+    // the new executable is started by the upgrade
+    fs::write(plugin_dir.join("dynamic.py"), DYNAMIC_PLUGIN)
+        .await
+        .unwrap();
+    let upgrade = manager
+        .coffee()
+        .upgrade("local", false, false)
+        .await
+        .unwrap();
+    assert_eq!(
+        upgrade.reloads,
+        vec![ReloadStatus::Reloaded("dynamic".to_owned())]
+    );
+
+    // a new executable that does not start needs a restart
+    fs::write(plugin_dir.join("dynamic.py"), "#!/bin/sh\nexit 1\n")
+        .await
+        .unwrap();
+    let upgrade = manager
+        .coffee()
+        .upgrade("local", false, false)
+        .await
+        .unwrap();
+    assert_eq!(
+        upgrade.reloads,
+        vec![ReloadStatus::RestartNeeded("dynamic".to_owned())]
+    );
+
+    cln.stop().await.unwrap();
+}
+
+#[tokio::test]
+#[ntest::timeout(560000)]
+pub async fn test_detach_plugin() {
+    init();
+
+    let mut cln = Node::tmp("regtest").await.unwrap();
+    let mut manager = CoffeeTesting::tmp().await.unwrap();
+
+    let lightning_dir = cln.rpc().getinfo().unwrap().ligthning_dir;
+    let lightning_dir = lightning_dir.strip_suffix("/regtest").unwrap();
+    log::info!("lightning path: {lightning_dir}");
+
+    manager.coffee().link(&lightning_dir).await.unwrap();
+    manager
+        .coffee()
+        .add_remote("lightningd", "https://github.com/lightningd/plugins.git")
+        .await
+        .unwrap();
+    manager
+        .coffee()
+        .install("summary", true, false, false, false)
+        .await
+        .unwrap();
+
+    // Detach the plugin, and make sure that the files are still there
+    let detached = manager.coffee().detach("summary").await.unwrap().plugin;
+    let plugins = manager.coffee().list().await.unwrap().plugins;
+    assert!(
+        !plugins.iter().any(|plugin| plugin.name() == "summary"),
+        "{:?}",
+        plugins
+    );
+    let fields = &manager.coffee().coffee_cln_config.fields;
+    assert!(
+        !fields
+            .get("plugin")
+            .is_some_and(|paths| paths.contains(&detached.exec_path)),
+        "{:?}",
+        fields
+    );
+    let exec_path = Path::new(&detached.exec_path);
+    assert!(exec_path.exists(), "{:?} does not exist", exec_path);
+
+    // Install it again, reusing the build
+    manager
+        .coffee()
+        .install("summary", true, false, false, false)
+        .await
+        .unwrap();
+    let plugins = manager.coffee().list().await.unwrap().plugins;
+    let summary = plugins
+        .iter()
+        .find(|plugin| plugin.name() == "summary")
+        .unwrap();
+    assert_eq!(summary.exec_path, detached.exec_path);
+    let note = Path::new(&detached.root_path).join(".coffee-detached.json");
+    assert!(!note.exists(), "{:?} still exists", note);
+
+    cln.stop().await.unwrap();
+}
+
+#[tokio::test]
+#[ntest::timeout(560000)]
+pub async fn test_plugins_restored_after_restart() {
+    init();
+
+    let dir = Arc::new(tempfile::tempdir().unwrap());
+    let args = CoffeeTestingArgs {
+        conf: None,
+        data_dir: dir.path().to_str().unwrap().to_owned(),
+        network: "regtest".to_string(),
+    };
+    let mut manager = CoffeeTesting::tmp_with_args(&args, dir.clone())
+        .await
+        .unwrap();
+    manager
+        .coffee()
+        .add_remote("lightningd", "https://github.com/lightningd/plugins.git")
+        .await
+        .unwrap();
+    manager
+        .coffee()
+        .install("summary", true, false, false, false)
+        .await
+        .unwrap();
+    drop(manager);
+
+    // the installed plugins are restored by the inventory
+    let mut manager = CoffeeTesting::tmp_with_args(&args, dir.clone())
+        .await
+        .unwrap();
+    let plugins = manager.coffee().list().await.unwrap().plugins;
+    assert_eq!(plugins.len(), 1, "{:?}", plugins);
+    assert_eq!(plugins[0].name(), "summary");
+
+    manager.coffee().remove("summary").await.unwrap();
+    drop(manager);
+
+    let mut manager = CoffeeTesting::tmp_with_args(&args, dir.clone())
+        .await
+        .unwrap();
+    let plugins = manager.coffee().list().await.unwrap().plugins;
+    assert!(plugins.is_empty(), "{:?}", plugins);
+}
+
+#[tokio::test]
+#[ntest::timeout(560000)]
+pub async fn test_install_from_url() {
+    init();
+
+    let mut manager = CoffeeTesting::tmp().await.unwrap();
+
+    // The repository contains more plugins, so the install fails
+    // and the remote added for the install is removed.
+    let err = manager
+        .coffee()
+        .install(
+            "https://github.com/lightningd/plugins.git",
+            true,
+            false,
+            true,
+            false,
+        )
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("summary"), "{err}");
+    let remotes = manager.coffee().list_remotes().await.unwrap();
+    assert!(
+        remotes.remotes.unwrap_or_default().is_empty(),
+        "the remote was not removed"
+    );
+
+    // A remote with the same url is reused, instead of added again
+    manager
+        .coffee()
+        .add_remote("lightningd", "https://github.com/lightningd/plugins.git")
+        .await
+        .unwrap();
+    let err = manager
+        .coffee()
+        .install(
+            "https://github.com/lightningd/plugins.git",
+            true,
+            false,
+            true,
+            false,
+        )
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("lightningd"), "{err}");
+    let remotes = manager.coffee().list_remotes().await.unwrap();
+    assert_eq!(remotes.remotes.unwrap_or_default().len(), 1);
+}
+
+#[tokio::test]
+#[ntest::timeout(120000)]
+pub async fn test_install_from_url_name_collision() {
+    init();
+
+    let mut manager = CoffeeTesting::tmp().await.unwrap();
+
+    // two repositories with the same name, so the same derived remote name
+    let dirs = [tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap()];
+    let mut urls = vec![];
+    for (dir, plugin) in dirs.iter().zip(["first", "second"]) {
+        let plugin_dir = dir.path().join("hello").join(plugin);
+        fs::create_dir_all(&plugin_dir).await.unwrap();
+        fs::write(
+            plugin_dir.join("coffee.yml"),
+            format!("plugin:\n  name: {plugin}\n  version: 0.0.1\n  lang: pypip\n  install: chmod +x {plugin}.sh\n  main: {plugin}.sh\n"),
+        )
+        .await
+        .unwrap();
+        fs::write(plugin_dir.join(format!("{plugin}.sh")), "#!/bin/sh\n")
+            .await
+            .unwrap();
+        urls.push(format!("file://{}", dir.path().join("hello").display()));
+    }
+    let remote = manager
+        .coffee()
+        .add_remote("hello", &urls[0])
+        .await
+        .unwrap();
+    assert_eq!(remote.plugins, vec!["first"]);
+
+    // the remote of the install does not replace the one with the same name
+    let install = manager
+        .coffee()
+        .install(&urls[1], false, false, false, false)
+        .await
+        .unwrap();
+    assert_eq!(install.plugin, "second");
+    let remotes = manager.coffee().list_remotes().await.unwrap();
+    let mut names = remotes
+        .remotes
+        .unwrap_or_default()
+        .into_iter()
+        .map(|remote| remote.local_name)
+        .collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(names, vec!["hello", "hello-2"]);
+
+    // the plugin is installed again only with `force`
+    let err = manager
+        .coffee()
+        .install(&urls[1], false, false, false, false)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("already installed"), "{err}");
+    manager
+        .coffee()
+        .install(&urls[1], false, false, false, true)
+        .await
+        .unwrap();
+    let plugins = manager.coffee().list().await.unwrap().plugins;
+    assert_eq!(plugins.len(), 1, "{:?}", plugins);
+}
+
+#[tokio::test]
+#[ntest::timeout(120000)]
+pub async fn test_import_state_from_the_remote() {
+    init();
+
+    let mut manager = CoffeeTesting::tmp().await.unwrap();
+
+    // two remotes that contain a plugin with the same name
+    let dirs = [tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap()];
+    for (dir, remote) in dirs.iter().zip(["first", "second"]) {
+        let plugin_dir = dir.path().join("hello");
+        fs::create_dir_all(&plugin_dir).await.unwrap();
+        fs::write(
+            plugin_dir.join("coffee.yml"),
+            "plugin:\n  name: hello\n  version: 0.0.1\n  lang: pypip\n  install: chmod +x hello.sh\n  main: hello.sh\n",
+        )
+        .await
+        .unwrap();
+        fs::write(
+            plugin_dir.join("hello.sh"),
+            format!("#!/bin/sh\n# {remote}\n"),
+        )
+        .await
+        .unwrap();
+        let url = format!("file://{}", dir.path().display());
+        manager.coffee().add_remote(remote, &url).await.unwrap();
+    }
+
+    let mut state = manager.coffee().export_state().await.unwrap();
+    for (name, remote) in [("hello", "second"), ("missing", "first")] {
+        state.plugins.push(CoffeeStatePlugin {
+            name: name.to_owned(),
+            remote: Some(remote.to_owned()),
+            git_ref: None,
+            enabled: true,
+            scope: PluginScope::default(),
+        });
+    }
+    let import = manager.coffee().import_state(&state, false).await.unwrap();
+    assert_eq!(import.installed, vec!["hello"], "{:?}", import);
+    assert!(import.remotes_failed.is_empty(), "{:?}", import);
+    assert!(
+        import.plugins_failed.contains_key("missing"),
+        "{:?}",
+        import
+    );
+
+    // the plugin is the one of the remote inside the state
+    let plugins = manager.coffee().list().await.unwrap().plugins;
+    let exec = fs::read_to_string(&plugins[0].exec_path).await.unwrap();
+    assert!(exec.contains("# second"), "{exec}");
+}
+
+#[tokio::test]
+#[ntest::timeout(120000)]
+pub async fn test_disable_plugin_entries_removed() {
+    init();
+
+    let mut manager = CoffeeTesting::tmp().await.unwrap();
+    let repo = tempfile::tempdir().unwrap();
+    let plugin_dir = repo.path().join("hello");
+    fs::create_dir_all(&plugin_dir).await.unwrap();
+    fs::write(
+        plugin_dir.join("coffee.yml"),
+        "plugin:\n  name: hello\n  version: 0.0.1\n  lang: pypip\n  install: chmod +x hello.sh\n  main: hello.sh\n",
+    )
+    .await
+    .unwrap();
+    fs::write(plugin_dir.join("hello.sh"), "#!/bin/sh\n")
+        .await
+        .unwrap();
+    let url = format!("file://{}", repo.path().display());
+    manager.coffee().add_remote("local", &url).await.unwrap();
+
+    let disabled = |manager: &mut CoffeeTesting| {
+        manager
+            .coffee()
+            .coffee_cln_config
+            .fields
+            .get("disable-plugin")
+            .cloned()
+            .unwrap_or_default()
+    };
+
+    // enable removes the entry written by disable
+    manager
+        .coffee()
+        .install("hello", false, false, false, false)
+        .await
+        .unwrap();
+    manager.coffee().disable("hello").await.unwrap();
+    assert_eq!(disabled(&mut manager).len(), 1);
+    manager.coffee().enable("hello").await.unwrap();
+    assert!(disabled(&mut manager).is_empty());
+
+    // remove does the same with a disabled plugin
+    manager.coffee().disable("hello").await.unwrap();
+    manager.coffee().remove("hello").await.unwrap();
+    assert!(disabled(&mut manager).is_empty());
+
+    // and nurse with a disabled plugin whose executable is missing
+    let install = manager
+        .coffee()
+        .install("hello", false, false, false, false)
+        .await
+        .unwrap();
+    manager.coffee().disable("hello").await.unwrap();
+    fs::remove_file(&install.exec_path).await.unwrap();
+    let nurse = manager.coffee().nurse().await.unwrap();
+    assert_eq!(
+        nurse.status,
+        vec![NurseStatus::PluginConfigurationRemoved(vec![install
+            .exec_path
+            .clone()])]
+    );
+    assert!(disabled(&mut manager).is_empty());
+}
+
+#[tokio::test]
+#[ntest::timeout(120000)]
+pub async fn test_remove_last_plugin_frees_the_cache() {
+    init();
+
+    let mut manager = CoffeeTesting::tmp().await.unwrap();
+    let repo = tempfile::tempdir().unwrap();
+    for name in ["hello", "world"] {
+        let plugin_dir = repo.path().join(name);
+        fs::create_dir_all(&plugin_dir).await.unwrap();
+        fs::write(
+            plugin_dir.join("coffee.yml"),
+            format!("plugin:\n  name: {name}\n  version: 0.0.1\n  lang: pypip\n  install: chmod +x {name}.sh\n  main: {name}.sh\n"),
+        )
+        .await
+        .unwrap();
+        fs::write(plugin_dir.join(format!("{name}.sh")), "#!/bin/sh\n")
+            .await
+            .unwrap();
+    }
+    let url = format!("file://{}", repo.path().display());
+    manager.coffee().add_remote("local", &url).await.unwrap();
+    for name in ["hello", "world"] {
+        manager
+            .coffee()
+            .install(name, false, false, false, false)
+            .await
+            .unwrap();
+    }
+    // a download left in the shared cache by the builds
+    let cache = format!("{}/cache", manager.coffee().config.root_path);
+    fs::create_dir_all(format!("{cache}/pip")).await.unwrap();
+    fs::write(format!("{cache}/pip/package.whl"), vec![0u8; 4096])
+        .await
+        .unwrap();
+
+    // the cache is still used by the other plugin
+    let remove = manager.coffee().remove("hello").await.unwrap();
+    assert!(!remove.removed_paths.contains(&cache), "{remove:?}");
+    assert!(fs::try_exists(&cache).await.unwrap());
+
+    let remove = manager.coffee().remove("world").await.unwrap();
+    assert!(remove.removed_paths.contains(&cache), "{remove:?}");
+    assert!(remove.freed_bytes >= 4096, "{remove:?}");
+    assert!(!fs::try_exists(&cache).await.unwrap());
+}
+
+#[tokio::test]
+#[ntest::timeout(120000)]
+pub async fn test_diagnose() {
+    init();
+
+    let mut manager = CoffeeTesting::tmp().await.unwrap();
+    let repo = tempfile::tempdir().unwrap();
+    let plugin_dir = repo.path().join("hello");
+    fs::create_dir_all(&plugin_dir).await.unwrap();
+    fs::write(
+        plugin_dir.join("coffee.yml"),
+        "plugin:\n  name: hello\n  version: 0.0.1\n  lang: pypip\n  install: chmod +x hello.sh\n  main: hello.sh\n",
+    )
+    .await
+    .unwrap();
+    fs::write(plugin_dir.join("hello.sh"), "#!/bin/sh\n")
+        .await
+        .unwrap();
+    let url = format!("file://{}", repo.path().display());
+    manager.coffee().add_remote("local", &url).await.unwrap();
+    let install = manager
+        .coffee()
+        .install("hello", false, false, false, false)
+        .await
+        .unwrap();
+
+    // the binaries depend on the machine that runs the tests
+    async fn issues(manager: &mut CoffeeTesting) -> Vec<DiagnoseIssue> {
+        manager
+            .coffee()
+            .diagnose()
+            .await
+            .unwrap()
+            .issues
+            .into_iter()
+            .filter(|issue| !matches!(issue, DiagnoseIssue::MissingBinary(..)))
+            .collect()
+    }
+    assert!(issues(&mut manager).await.is_empty());
+
+    // the executable lost its permissions
+    let mut permissions = fs::metadata(&install.exec_path)
+        .await
+        .unwrap()
+        .permissions();
+    permissions.set_mode(0o644);
+    fs::set_permissions(&install.exec_path, permissions)
+        .await
+        .unwrap();
+    assert_eq!(
+        issues(&mut manager).await,
+        vec![DiagnoseIssue::PluginNotExecutable(
+            "hello".to_owned(),
+            install.exec_path.clone()
+        )]
+    );
+
+    // and the local repository is gone
+    fs::remove_dir_all(repo.path()).await.unwrap();
+    assert_eq!(
+        issues(&mut manager).await,
+        vec![
+            DiagnoseIssue::PluginNotExecutable("hello".to_owned(), install.exec_path.clone()),
+            DiagnoseIssue::RepositoryAbsent("local".to_owned()),
+        ]
+    );
+}
+
+#[tokio::test]
+#[ntest::timeout(120000)]
+pub async fn test_install_cln_options() {
+    init();
+
+    let mut manager = CoffeeTesting::tmp().await.unwrap();
+    let repo = tempfile::tempdir().unwrap();
+    let plugin_dir = repo.path().join("hello");
+    fs::create_dir_all(&plugin_dir).await.unwrap();
+    fs::write(
+        plugin_dir.join("coffee.yml"),
+        "plugin:\n  name: hello\n  version: 0.0.1\n  lang: pypip\n  install: chmod +x hello.sh\n  main: hello.sh\n  cln_options:\n    alias: hello-node\n    hello-greeting: \"ciao\"\n",
+    )
+    .await
+    .unwrap();
+    fs::write(plugin_dir.join("hello.sh"), "#!/bin/sh\n")
+        .await
+        .unwrap();
+    let url = format!("file://{}", repo.path().display());
+    manager.coffee().add_remote("local", &url).await.unwrap();
+
+    // an option set by the user is not overridden by the plugin
+    manager
+        .coffee()
+        .coffee_cln_config
+        .add_conf("alias", "my-node")
+        .map_err(|err| err.cause)
+        .unwrap();
+    let field = |manager: &mut CoffeeTesting, key: &str| {
+        manager
+            .coffee()
+            .coffee_cln_config
+            .fields
+            .get(key)
+            .cloned()
+            .unwrap_or_default()
+    };
+
+    manager
+        .coffee()
+        .install("hello", false, false, false, false)
+        .await
+        .unwrap();
+    assert_eq!(
+        field(&mut manager, "hello-greeting"),
+        vec!["ciao".to_owned()]
+    );
+    assert_eq!(field(&mut manager, "alias"), vec!["my-node".to_owned()]);
+    let config = fs::read_to_string(&manager.coffee().coffee_cln_config.path)
+        .await
+        .unwrap();
+    assert!(config.contains("hello-greeting=ciao"), "{config}");
+
+    // the remove reverts only the options added by the plugin
+    let remove = manager.coffee().remove("hello").await.unwrap();
+    assert_eq!(
+        remove.reverted_options,
+        [("hello-greeting".to_owned(), "ciao".to_owned())].into()
+    );
+    assert!(field(&mut manager, "hello-greeting").is_empty());
+    assert_eq!(field(&mut manager, "alias"), vec!["my-node".to_owned()]);
+}
+
+#[tokio::test]
+#[ntest::timeout(560000)]
+pub async fn test_add_remotes() {
+    init();
+
+    let mut manager = CoffeeTesting::tmp().await.unwrap();
+    let results = manager
+        .coffee()
+        .add_remotes(&[
+            ("lightningd", "https://github.com/lightningd/plugins.git"),
+            ("folgore", "https://github.com/coffee-tools/folgore.git"),
+            ("invalid", "github.com/coffee-tools/folgore"),
+        ])
+        .await
+        .unwrap();
+    assert!(results["lightningd"].is_ok(), "{:?}", results);
+    assert!(results["folgore"].is_ok(), "{:?}", results);
+    assert!(results["invalid"].is_err(), "{:?}", results);
+
+    let remotes = manager.coffee().list_remotes().await.unwrap();
+    assert_eq!(remotes.remotes.unwrap_or_default().len(), 2);
+
+    // the mirror is cloned when the url is not reachable, and it
+    // becomes the url of the repository
+    let result = manager
+        .coffee()
+        .add_remote(
+            "mirrored",
+            "https://localhost:1/coffee-tools/folgore.git, https://github.com/coffee-tools/folgore.git",
+        )
+        .await;
+    assert!(result.is_ok(), "{:?}", result);
+    let remotes = manager.coffee().list_remotes().await.unwrap();
+    let remote = remotes
+        .remotes
+        .unwrap_or_default()
+        .into_iter()
+        .find(|remote| remote.local_name == "mirrored")
+        .unwrap();
+    assert_eq!(remote.url, "https://github.com/coffee-tools/folgore");
+    assert_eq!(
+        remote.mirrors,
+        vec!["https://localhost:1/coffee-tools/folgore".to_owned()]
+    );
+}
+
+#[tokio::test]
+#[ntest::timeout(560000)]
+pub async fn test_corrupted_repository() {
+    init();
+
+    let dir = Arc::new(tempfile::tempdir().unwrap());
+    let args = CoffeeTestingArgs {
+        conf: None,
+        data_dir: dir.path().to_str().unwrap().to_owned(),
+        network: "regtest".to_string(),
+    };
+    let mut manager = CoffeeTesting::tmp_with_args(&args, dir.clone())
+        .await
+        .unwrap();
+    manager
+        .coffee()
+        .add_remote("folgore", "https://github.com/coffee-tools/folgore.git")
+        .await
+        .unwrap();
+    // damage the stored entry of a second repository
+    let storage = &manager.coffee().storage;
+    let mut repositories = storage
+        .load::<HashMap<String, serde_json::Value>>("repositories")
+        .await
+        .unwrap();
+    repositories.insert("broken".to_owned(), json!({ "name": 42 }));
+    storage.store("repositories", &repositories).await.unwrap();
+    drop(manager);
+
+    // the other repositories are still loaded
+    let mut manager = CoffeeTesting::tmp_with_args(&args, dir.clone())
+        .await
+        .unwrap();
+    assert!(manager.coffee().repos.contains_key("folgore"));
+    let status = manager.coffee().nurse_verify().await.unwrap();
+    assert_eq!(
+        status.defects,
+        vec![Defect::RepositoryCorrupted(vec!["broken".to_owned()])]
+    );
+
+    let nurse = manager.coffee().nurse().await.unwrap();
+    assert_eq!(
+        nurse.status,
+        vec![NurseStatus::CorruptedRepositoryRemoved(vec![
+            "broken".to_owned()
+        ])]
+    );
+    let status = manager.coffee().nurse_verify().await.unwrap();
+    assert!(status.is_sane(), "{status}");
+}